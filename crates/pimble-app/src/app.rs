@@ -7,7 +7,7 @@ use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Instant;
 
-use pimble_core::NodeId;
+use pimble_core::{EditKind, NodeId};
 use rinch::prelude::*;
 use rinch::core::ce::with_active_ce_api;
 use rinch::core::{request_focus, set_keyboard_interceptor, clear_keyboard_interceptor};
@@ -17,7 +17,7 @@ use rinch_editor_components::{render_toolbar, ToolbarConfig};
 use rinch_tabler_icons::{TablerIcon, TablerIconStyle, render_tabler_icon};
 
 use crate::backend::{BackendCommand, BackendEvent, BackendHandle};
-use crate::state::{get_node_content_text, AppState, ConnectionState};
+use crate::state::{get_node_content_text, AppState, ConnectionState, NodeHistory};
 
 /// Editor content styles for the contenteditable area.
 ///
@@ -198,6 +198,16 @@ struct DeferredUpdates {
     expand_nodes: Vec<String>,
     node_title: Option<String>,
     editor_content: Option<Vec<u8>>,
+    history_data: Option<Vec<TreeNodeData>>,
+    history_preview: Option<Vec<u8>>,
+}
+
+/// Handles for the version history panel
+#[derive(Clone)]
+struct HistoryPanel {
+    open: Signal<bool>,
+    data: Signal<Vec<TreeNodeData>>,
+    preview: Rc<RefCell<Option<NodeHandle>>>,
 }
 
 /// Process backend events and update signals
@@ -213,6 +223,7 @@ fn process_backend_events(
     tree_state: UseTreeReturn,
     node_title: Signal<String>,
     ce_div_cell: &Rc<RefCell<Option<NodeHandle>>>,
+    history_panel: &HistoryPanel,
 ) {
     let (_events, deferred) = {
         let events: Vec<BackendEvent> = {
@@ -239,6 +250,7 @@ fn process_backend_events(
                 BackendEvent::Connected => {
                     tracing::info!("Connected to backend");
                     state.connection = ConnectionState::Connected;
+                    state.watched.clear();
                     deferred.connection_status = Some("Connected".to_string());
 
                     // Auto-open previously loaded stores
@@ -254,6 +266,7 @@ fn process_backend_events(
                 BackendEvent::Disconnected => {
                     tracing::info!("Disconnected from backend");
                     state.connection = ConnectionState::Disconnected;
+                    state.watched.clear();
                     deferred.connection_status = Some("Disconnected".to_string());
                 }
 
@@ -287,6 +300,32 @@ fn process_backend_events(
                     save_app_state_file(&paths);
                 }
 
+                BackendEvent::StoreWatched { store_id, watched } => {
+                    if *watched {
+                        state.watched.insert(*store_id);
+                    } else {
+                        tracing::info!("Not following changes to store {:?}; re-fetching after edits", store_id);
+                        state.watched.remove(store_id);
+                    }
+                }
+
+                BackendEvent::StoreStale { store_id } => {
+                    // Reload every cached children list, and the node in the editor
+                    tracing::info!("Reloading store {:?}", store_id);
+                    if let Some(backend) = &state.backend {
+                        for (s, parent_id) in state.children.keys() {
+                            if s == store_id {
+                                backend.send(BackendCommand::GetChildren { store_id: *store_id, node_id: *parent_id });
+                            }
+                        }
+                        if let Some((sel_store_id, sel_node_id)) = state.selected_store_and_node() {
+                            if sel_store_id == *store_id {
+                                backend.send(BackendCommand::GetNode { store_id: *store_id, node_id: sel_node_id });
+                            }
+                        }
+                    }
+                }
+
                 BackendEvent::StoreCreated { store_id, root_node_id } => {
                     tracing::info!("Store created: {:?} with root {:?}", store_id, root_node_id);
                     if let Some(path) = state.pending_create_path.take() {
@@ -370,36 +409,146 @@ fn process_backend_events(
                         deferred.expand_nodes.push(format!("node_{}_{}", store_id, new_parent_id));
                     }
 
-                    // Rebuild tree immediately with correct data
+                    // Rebuild tree immediately; the store's change events
+                    // bring the authoritative children lists
                     deferred.tree_data = Some(state.build_tree_data());
 
-                    // Also re-fetch from server for authoritative data
-                    if let Some(backend) = &state.backend {
+                    // Without change events, re-fetch them from the server
+                    if let Some(backend) = state.unwatched_backend(*store_id) {
                         backend.send(BackendCommand::GetChildren { store_id: *store_id, node_id: *old_parent_id });
                         backend.send(BackendCommand::GetChildren { store_id: *store_id, node_id: *new_parent_id });
                     }
                 }
 
+                BackendEvent::NodeMovedAcrossStores { source_store_id, old_parent_id, target_store_id, new_parent_id, node_id } => {
+                    tracing::info!(
+                        "Node moved: {:?} from store {:?} to {:?} in store {:?}",
+                        node_id, source_store_id, new_parent_id, target_store_id
+                    );
+
+                    // Drop the node from the source store's caches
+                    if let Some(old_children) = state.children.get_mut(&(*source_store_id, *old_parent_id)) {
+                        old_children.retain(|&id| id != *node_id);
+                    }
+                    state.nodes.remove(&(*source_store_id, *node_id));
+
+                    // The subtree now lives in the target store; expand its new parent
+                    state.expanded.insert((*target_store_id, *new_parent_id));
+                    let is_root = state.stores.get(target_store_id)
+                        .map_or(false, |s| s.root_node_id == *new_parent_id);
+                    if !is_root {
+                        deferred.expand_nodes.push(format!("node_{}_{}", target_store_id, new_parent_id));
+                    }
+
+                    deferred.tree_data = Some(state.build_tree_data());
+
+                    if let Some(backend) = state.unwatched_backend(*source_store_id) {
+                        backend.send(BackendCommand::GetChildren { store_id: *source_store_id, node_id: *old_parent_id });
+                    }
+                    if let Some(backend) = state.unwatched_backend(*target_store_id) {
+                        backend.send(BackendCommand::GetChildren { store_id: *target_store_id, node_id: *new_parent_id });
+                    }
+                }
+
+                BackendEvent::NodeCopied { store_id, parent_id, node_id } => {
+                    tracing::info!("Node copied: {:?} under {:?}", node_id, parent_id);
+                    state.expanded.insert((*store_id, *parent_id));
+                    if let Some(backend) = state.unwatched_backend(*store_id) {
+                        backend.send(BackendCommand::GetChildren { store_id: *store_id, node_id: *parent_id });
+                    }
+                }
+
                 BackendEvent::NodeContentUpdated { store_id, node_id } => {
+                    // The node's change event reloads it, and the tree
+                    // rebuild picks up the new excerpt for auto-titled nodes
                     tracing::info!("Node content updated: {:?}/{:?}", store_id, node_id);
-                    // Re-fetch the node so cached content updates;
-                    // tree rebuild will pick up the new excerpt for auto-titled nodes.
-                    if let Some(backend) = &state.backend {
+                    if let Some(backend) = state.unwatched_backend(*store_id) {
                         backend.send(BackendCommand::GetNode { store_id: *store_id, node_id: *node_id });
                     }
                 }
 
                 BackendEvent::NodeRenamed { store_id, node_id } => {
                     tracing::info!("Node renamed: {:?}/{:?}", store_id, node_id);
-                    // Re-fetch the node to update cached metadata, then rebuild tree
-                    if let Some(backend) = &state.backend {
+                    if let Some(backend) = state.unwatched_backend(*store_id) {
                         backend.send(BackendCommand::GetNode { store_id: *store_id, node_id: *node_id });
                     }
                 }
 
+                BackendEvent::NodeHistoryLoaded { store_id, node_id, changes } => {
+                    tracing::info!("History loaded for {:?}: {} changes", node_id, changes.len());
+                    // Ignore history for a node that's no longer shown
+                    if let Some(history) = state.history.as_mut() {
+                        if history.store_id == *store_id && history.node_id == *node_id {
+                            history.changes = changes.clone();
+                            history.selected = None;
+                            deferred.history_data = Some(history.build_list_data());
+                            deferred.history_preview = Some(Vec::new());
+                        }
+                    }
+                }
+
+                BackendEvent::NodeVersionLoaded { store_id, node_id, hash, content } => {
+                    if let Some(history) = &state.history {
+                        let is_shown = history.store_id == *store_id
+                            && history.node_id == *node_id
+                            && history.selected.as_deref() == Some(hash.as_str());
+                        if is_shown {
+                            deferred.history_preview = Some(content.clone());
+                        }
+                    }
+                }
+
+                BackendEvent::NodeVersionRestored { store_id, node_id } => {
+                    tracing::info!("Node restored: {:?}/{:?}", store_id, node_id);
+                    // The node's change event reloads it into the editor;
+                    // re-fetch the history so the restore shows up in it
+                    if let Some(backend) = state.unwatched_backend(*store_id) {
+                        backend.send(BackendCommand::GetNode { store_id: *store_id, node_id: *node_id });
+                    }
+                    if let Some(backend) = &state.backend {
+                        if state.history.is_some() {
+                            backend.send(BackendCommand::GetNodeHistory { store_id: *store_id, node_id: *node_id });
+                        }
+                    }
+                }
+
+                BackendEvent::EditReverted { edit, parent_id } => {
+                    tracing::info!("Reverted {:?} edit of {:?}/{:?}", edit.kind, edit.store_id, edit.node_id);
+                    // Change events reload the node and the children lists
+                    // it moved between; only the history needs asking for
+                    if edit.kind == EditKind::Content && state.history.is_some() {
+                        if let Some(backend) = &state.backend {
+                            backend.send(BackendCommand::GetNodeHistory { store_id: edit.store_id, node_id: edit.node_id });
+                        }
+                    }
+
+                    if let Some(backend) = state.unwatched_backend(edit.store_id) {
+                        if edit.kind == EditKind::Content {
+                            backend.send(BackendCommand::GetNode { store_id: edit.store_id, node_id: edit.node_id });
+                        } else {
+                            // Re-fetch the children lists the node left and the
+                            // one it's in now
+                            let mut parents: Vec<NodeId> = state.children.iter()
+                                .filter(|((s, _), ids)| *s == edit.store_id && ids.contains(&edit.node_id))
+                                .map(|((_, p), _)| *p)
+                                .collect();
+                            if let Some(pid) = parent_id {
+                                if !parents.contains(pid) {
+                                    parents.push(*pid);
+                                }
+                                backend.send(BackendCommand::GetNode { store_id: edit.store_id, node_id: edit.node_id });
+                            }
+                            for pid in parents {
+                                backend.send(BackendCommand::GetChildren { store_id: edit.store_id, node_id: pid });
+                            }
+                        }
+                    }
+                }
+
                 BackendEvent::StoreClosed { store_id } => {
                     tracing::info!("Store closed: {:?}", store_id);
                     state.stores.remove(store_id);
+                    state.watched.remove(store_id);
                     deferred.sidebar_heading = Some(state.sidebar_heading());
                     deferred.tree_data = Some(state.build_tree_data());
 
@@ -438,6 +587,43 @@ fn process_backend_events(
             load_content_into_ce(&content_bytes, ce_div);
         }
     }
+    if let Some(v) = deferred.history_data {
+        history_panel.data.set(v);
+    }
+    if let Some(content_bytes) = deferred.history_preview {
+        if let Some(preview) = history_panel.preview.borrow().as_ref() {
+            preview.set_inner_html(&content_bytes_to_html(&content_bytes));
+        }
+    }
+}
+
+/// Point the history panel at the selected node and load its versions
+fn request_history(state: &Rc<RefCell<AppState>>, history: &HistoryPanel) {
+    {
+        let mut st = state.borrow_mut();
+        st.history = st
+            .selected_store_and_node()
+            .map(|(store_id, node_id)| NodeHistory::new(store_id, node_id));
+        if let (Some(history), Some(backend)) = (&st.history, &st.backend) {
+            backend.send(BackendCommand::GetNodeHistory {
+                store_id: history.store_id,
+                node_id: history.node_id,
+            });
+        }
+    }
+    // Cleared until the new history arrives
+    history.data.set(Vec::new());
+}
+
+/// Open or close the history panel
+fn toggle_history(state: &Rc<RefCell<AppState>>, history: &HistoryPanel) {
+    let open = !history.open.get();
+    if open {
+        request_history(state, history);
+    } else {
+        state.borrow_mut().history = None;
+    }
+    history.open.set(open);
 }
 
 /// Save current CE content as Automerge bytes.
@@ -501,6 +687,14 @@ pub fn run() {
     let tree_data: Signal<Vec<TreeNodeData>> = Signal::new(Vec::new());
     let node_title: Signal<String> = Signal::new(String::new());
 
+    // History panel state
+    let history = HistoryPanel {
+        open: Signal::new(false),
+        data: Signal::new(Vec::new()),
+        preview: Rc::new(RefCell::new(None)),
+    };
+    let history_tree_state = UseTreeReturn::new(UseTreeOptions::default());
+
     // Inline rename state
     let renaming_node: Signal<Option<String>> = Signal::new(None);
     let rename_text: Signal<String> = Signal::new(String::new());
@@ -521,6 +715,7 @@ pub fn run() {
     // can trigger it without capturing non-Send types.
     let state_for_events = state.clone();
     let ce_div_for_events = ce_div_cell.clone();
+    let history_for_events = history.clone();
     EVENT_PROCESSOR.with(|cell| {
         *cell.borrow_mut() = Some(Box::new(move || {
             process_backend_events(
@@ -531,6 +726,7 @@ pub fn run() {
                 tree_state,
                 node_title,
                 &ce_div_for_events,
+                &history_for_events,
             );
         }));
     });
@@ -552,6 +748,10 @@ pub fn run() {
     let state_for_new = state.clone();
     let state_for_open = state.clone();
     let state_for_close = state.clone();
+    let state_for_history = state.clone();
+    let state_for_undo = state.clone();
+    let state_for_redo = state.clone();
+    let history_for_menu = history.clone();
 
     let file_menu = Menu::new()
         .item(MenuItem::new("New Store...").shortcut("Ctrl+N").on_click(move || {
@@ -608,8 +808,18 @@ pub fn run() {
         }));
 
     let edit_menu = Menu::new()
-        .item(MenuItem::new("Undo").shortcut("Ctrl+Z").enabled(false).on_click(|| {}))
-        .item(MenuItem::new("Redo").shortcut("Ctrl+Y").enabled(false).on_click(|| {}))
+        .item(MenuItem::new("Undo").shortcut("Ctrl+Z").on_click(move || {
+            let st = state_for_undo.borrow();
+            if let Some(backend) = &st.backend {
+                backend.send(BackendCommand::Undo);
+            }
+        }))
+        .item(MenuItem::new("Redo").shortcut("Ctrl+Y").on_click(move || {
+            let st = state_for_redo.borrow();
+            if let Some(backend) = &st.backend {
+                backend.send(BackendCommand::Redo);
+            }
+        }))
         .separator()
         .item(MenuItem::new("Cut").shortcut("Ctrl+X").enabled(false).on_click(|| {}))
         .item(MenuItem::new("Copy").shortcut("Ctrl+C").enabled(false).on_click(|| {}))
@@ -619,6 +829,9 @@ pub fn run() {
         .item(MenuItem::new("Toggle Sidebar").shortcut("Ctrl+\\").on_click(|| {
             tracing::info!("Toggle sidebar");
         }))
+        .item(MenuItem::new("Version History").shortcut("Ctrl+H").on_click(move || {
+            toggle_history(&state_for_history, &history_for_menu);
+        }))
         .separator()
         .item(MenuItem::new("Zoom In").shortcut("Ctrl+=").on_click(|| {}))
         .item(MenuItem::new("Zoom Out").shortcut("Ctrl+-").on_click(|| {}))
@@ -695,12 +908,14 @@ pub fn run() {
     // Build app component - parameter must be named __scope for the rsx! macro
     let app_state = state.clone();
     let ce_div_for_app = ce_div_cell.clone();
+    let history_for_app = history.clone();
     let app_component = move |__scope: &mut RenderScope| -> NodeHandle {
         // Tree callbacks
         let select_state = app_state.clone();
         let select_title = node_title;
 
         let select_ce_div = ce_div_for_app.clone();
+        let select_history = history_for_app.clone();
         let select_last_click = last_click.clone();
         let on_tree_select = ValueCallback::new(move |value: String| {
             tracing::info!("Tree node selected: {}", value);
@@ -796,6 +1011,11 @@ pub fn run() {
             if let Some(ce_div) = select_ce_div.borrow().as_ref() {
                 load_content_into_ce(&content_bytes, ce_div);
             }
+
+            // The history panel follows the selection
+            if select_history.open.get() {
+                request_history(&select_state, &select_history);
+            }
         });
 
         let expand_state = app_state.clone();
@@ -885,32 +1105,38 @@ pub fn run() {
                         return;
                     };
                     // Parse drop target — determine new parent
-                    let new_parent_id = if let Some((target_store_id, target_node_id_opt)) = st.parse_tree_value(&nv_drop) {
-                        if drag_store_id != target_store_id {
-                            return; // Can't move across stores
-                        }
-                        match target_node_id_opt {
-                            Some(nid) => nid, // Drop on node → make child of that node
-                            None => {
-                                // Drop on store root → use root_node_id
-                                if let Some(store) = st.stores.get(&target_store_id) {
-                                    store.root_node_id
-                                } else {
-                                    return;
-                                }
+                    let Some((target_store_id, target_node_id_opt)) = st.parse_tree_value(&nv_drop) else {
+                        return;
+                    };
+                    let new_parent_id = match target_node_id_opt {
+                        Some(nid) => nid, // Drop on node → make child of that node
+                        None => {
+                            // Drop on store root → use root_node_id
+                            if let Some(store) = st.stores.get(&target_store_id) {
+                                store.root_node_id
+                            } else {
+                                return;
                             }
                         }
-                    } else {
-                        return;
                     };
 
                     if let Some(backend) = &st.backend {
-                        backend.send(BackendCommand::MoveNode {
-                            store_id: drag_store_id,
-                            node_id: drag_node_id,
-                            new_parent_id,
-                            position: None,
-                        });
+                        if drag_store_id == target_store_id {
+                            backend.send(BackendCommand::MoveNode {
+                                store_id: drag_store_id,
+                                node_id: drag_node_id,
+                                new_parent_id,
+                                position: None,
+                            });
+                        } else {
+                            backend.send(BackendCommand::MoveNodeAcrossStores {
+                                source_store_id: drag_store_id,
+                                node_id: drag_node_id,
+                                target_store_id,
+                                new_parent_id,
+                                position: None,
+                            });
+                        }
                     }
                 }
             });
@@ -1068,8 +1294,101 @@ pub fn run() {
             }
         });
 
+        // History panel: the selected node's versions, a preview of the
+        // picked version, and a button to restore it
+        let history_select_state = app_state.clone();
+        let on_history_select = ValueCallback::new(move |hash: String| {
+            let mut st = history_select_state.borrow_mut();
+            let Some(history) = st.history.as_mut() else {
+                return;
+            };
+            history.selected = Some(hash.clone());
+            let (store_id, node_id) = (history.store_id, history.node_id);
+            if let Some(backend) = &st.backend {
+                backend.send(BackendCommand::GetNodeAtVersion { store_id, node_id, hash });
+            }
+        });
+
+        let history_data = history_for_app.data;
+        let history_source: Rc<dyn Fn() -> Vec<TreeNodeData>> =
+            Rc::new(move || history_data.get());
+        let history_list = Tree {
+            data: history_data.get(),
+            tree: Some(history_tree_state),
+            data_source: Some(history_source),
+            select_on_click: true,
+            onselect: Some(on_history_select),
+            ..Default::default()
+        };
+        let history_list_handle = history_list.render(__scope, &[]);
+        let history_scroll = __scope.create_element("div");
+        history_scroll.set_attribute("style", "flex: 1; overflow-y: auto; padding: 0 4px;");
+        history_scroll.append_child(&history_list_handle);
+
+        let restore_state = app_state.clone();
+        let restore_handler = __scope.register_handler(move || {
+            let bytes = save_content_via_ce_api();
+            let st = restore_state.borrow();
+            let Some(history) = &st.history else {
+                return;
+            };
+            let Some(hash) = history.selected.clone() else {
+                return;
+            };
+            if let Some(backend) = &st.backend {
+                // Save pending edits first so they stay in the history
+                backend.send(BackendCommand::SetNodeContent {
+                    store_id: history.store_id,
+                    node_id: history.node_id,
+                    content: bytes,
+                });
+                backend.send(BackendCommand::RestoreNodeVersion {
+                    store_id: history.store_id,
+                    node_id: history.node_id,
+                    hash,
+                });
+            }
+        });
+        let restore_button = __scope.create_element("button");
+        restore_button.set_attribute("title", "Restore the selected version");
+        restore_button.set_attribute("style", "font-size: 12px; padding: 2px 8px; cursor: pointer;");
+        restore_button.set_attribute("data-onclick", &restore_handler.0.to_string());
+        restore_button.append_child(&__scope.create_text("Restore"));
+
+        let history_heading = __scope.create_element("span");
+        history_heading.set_attribute("style", "flex: 1; font-weight: 600;");
+        history_heading.append_child(&__scope.create_text("History"));
+
+        let history_header = __scope.create_element("div");
+        history_header.set_attribute("style", "display: flex; align-items: center; padding: 4px 8px; border-bottom: 1px solid var(--rinch-color-default-border);");
+        history_header.append_child(&history_heading);
+        history_header.append_child(&restore_button);
+
+        let history_preview = __scope.create_element("div");
+        history_preview.set_attribute("class", "editor-content");
+        history_preview.set_attribute("style", "max-height: 45%; overflow-y: auto; padding: 8px 12px; border-top: 1px solid var(--rinch-color-default-border);");
+        history_preview.set_inner_html("<p><br></p>");
+        *history_for_app.preview.borrow_mut() = Some(history_preview.clone());
+
+        let history_panel = __scope.create_element("div");
+        history_panel.append_child(&history_header);
+        history_panel.append_child(&history_scroll);
+        history_panel.append_child(&history_preview);
+        let history_panel_effect = history_panel.clone();
+        let history_open = history_for_app.open;
+        Effect::new(move || {
+            if history_open.get() {
+                history_panel_effect.set_attribute("style", "width: 280px; min-width: 220px; border-left: 1px solid var(--rinch-color-default-border); display: flex; flex-direction: column; background: var(--rinch-color-body);");
+            } else {
+                history_panel_effect.set_attribute("style", "display: none;");
+            }
+        });
+
         // Toolbar button handlers
         let new_node_state = app_state.clone();
+        let duplicate_state = app_state.clone();
+        let history_toggle_state = app_state.clone();
+        let history_toggle = history_for_app.clone();
 
         // Rich text editor setup
         let on_editor_change: Rc<dyn Fn()> = Rc::new(|| {
@@ -1157,6 +1476,44 @@ pub fn run() {
                                 }),
                             }
 
+                            // Duplicate the selected node next to itself
+                            ActionIcon {
+                                icon: TablerIcon::Copy,
+                                variant: "subtle",
+                                size: "sm",
+                                onclick: Callback::new(move || {
+                                    let st = duplicate_state.borrow();
+                                    let Some(selected_id) = &st.selected_id else {
+                                        return;
+                                    };
+                                    let Some((store_id, Some(node_id))) = st.parse_tree_value(selected_id) else {
+                                        return;
+                                    };
+                                    let Some(parent_id) = st.nodes.get(&(store_id, node_id)).and_then(|n| n.parent_id) else {
+                                        return;
+                                    };
+                                    if let Some(backend) = &st.backend {
+                                        backend.send(BackendCommand::CopyNode {
+                                            source_store_id: store_id,
+                                            node_id,
+                                            target_store_id: store_id,
+                                            new_parent_id: parent_id,
+                                            position: None,
+                                        });
+                                    }
+                                }),
+                            }
+
+                            // Show the selected node's versions
+                            ActionIcon {
+                                icon: TablerIcon::History,
+                                variant: "subtle",
+                                size: "sm",
+                                onclick: Callback::new(move || {
+                                    toggle_history(&history_toggle_state, &history_toggle);
+                                }),
+                            }
+
                             // Spacer
                             div { style: "flex: 1;", }
 
@@ -1196,6 +1553,9 @@ pub fn run() {
                             {ce_div}
                         }
                     }
+
+                    // History panel
+                    {history_panel}
                 }
             }
         }
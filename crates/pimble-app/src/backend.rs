@@ -4,14 +4,23 @@
 //! 1. Spawn a background thread with a tokio runtime
 //! 2. Use channels to communicate between Makepad UI and async code
 //! 3. Signal Makepad to redraw when data arrives
+//!
+//! Caches are kept fresh by subscribing to each open store's changes: every
+//! change, whoever made it, reloads the nodes and children lists it touched.
+//! Stores that can't be followed, such as remote ones, are re-fetched by the
+//! UI after its own edits instead.
 
+use std::collections::HashMap;
 use std::thread;
 
 use crossbeam_channel::{bounded, Receiver, Sender};
 use pimble_client::PimbleClient;
-use pimble_core::{Node, NodeId, Store, StoreId, Workspace};
-use pimble_server::PimbleServer;
+use pimble_core::{ChangeType, ContentFormat, EditKind, HistoryEntry, Node, NodeChange, NodeId, Store, StoreId, UndoneEdit, Workspace};
+use pimble_crdt::{CrdtDocument, DeviceId, MetadataContent, EDITOR_FORMAT};
+use pimble_server::{PimbleServer, ServerConfig};
 use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
 /// Commands sent from UI to backend
 #[derive(Debug)]
@@ -32,6 +41,17 @@ pub enum BackendCommand {
     SetNodeContent { store_id: StoreId, node_id: NodeId, content: Vec<u8> },
     RenameNode { store_id: StoreId, node_id: NodeId, title: String },
     MoveNode { store_id: StoreId, node_id: NodeId, new_parent_id: NodeId, position: Option<usize> },
+    MoveNodeAcrossStores { source_store_id: StoreId, node_id: NodeId, target_store_id: StoreId, new_parent_id: NodeId, position: Option<usize> },
+    CopyNode { source_store_id: StoreId, node_id: NodeId, target_store_id: StoreId, new_parent_id: NodeId, position: Option<usize> },
+
+    // History operations
+    GetNodeHistory { store_id: StoreId, node_id: NodeId },
+    GetNodeAtVersion { store_id: StoreId, node_id: NodeId, hash: String },
+    RestoreNodeVersion { store_id: StoreId, node_id: NodeId, hash: String },
+
+    // Undo operations
+    Undo,
+    Redo,
 
     // Workspace operations
     CreateWorkspace { name: String, path: String },
@@ -51,6 +71,10 @@ pub enum BackendEvent {
     StoreOpened { store: Store },
     StoreClosed { store_id: StoreId },
     StoreList { stores: Vec<Store> },
+    /// Whether the store's changes are followed, keeping its caches fresh
+    StoreWatched { store_id: StoreId, watched: bool },
+    /// Changes to the store may have been missed; reload what's cached
+    StoreStale { store_id: StoreId },
 
     // Node events
     NodeCreated { store_id: StoreId, parent_id: Option<NodeId>, node_id: NodeId },
@@ -59,12 +83,61 @@ pub enum BackendEvent {
     NodeContentUpdated { store_id: StoreId, node_id: NodeId },
     NodeRenamed { store_id: StoreId, node_id: NodeId },
     NodeMoved { store_id: StoreId, node_id: NodeId, old_parent_id: NodeId, new_parent_id: NodeId },
+    NodeMovedAcrossStores { source_store_id: StoreId, old_parent_id: NodeId, target_store_id: StoreId, new_parent_id: NodeId, node_id: NodeId },
+    NodeCopied { store_id: StoreId, parent_id: NodeId, node_id: NodeId },
+
+    // History events
+    NodeHistoryLoaded { store_id: StoreId, node_id: NodeId, changes: Vec<HistoryEntry> },
+    NodeVersionLoaded { store_id: StoreId, node_id: NodeId, hash: String, content: Vec<u8> },
+    NodeVersionRestored { store_id: StoreId, node_id: NodeId },
+
+    // Undo events
+    EditReverted { edit: UndoneEdit, parent_id: Option<NodeId> },
 
     // Workspace events
     WorkspaceLoaded { workspace: Workspace },
     WorkspaceSaved,
 }
 
+/// What a store subscription passes to the backend loop
+enum Watched {
+    Change(NodeChange),
+    /// The subscription ended without being asked to, possibly missing changes
+    Ended(StoreId),
+}
+
+/// A cache to reload after a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    Node { store_id: StoreId, node_id: NodeId },
+    Children { store_id: StoreId, parent_id: NodeId },
+}
+
+impl Refresh {
+    /// What a change makes stale: the node itself, or the children lists
+    /// it entered or left
+    fn for_change(change: &NodeChange) -> Vec<Self> {
+        let store_id = change.store_id;
+        let mut refreshes = Vec::new();
+        if matches!(change.change_type, ChangeType::Updated | ChangeType::Moved) {
+            refreshes.push(Refresh::Node { store_id, node_id: change.node_id });
+        }
+        if change.change_type != ChangeType::Updated {
+            for parent_id in change.parent_id.into_iter().chain(change.old_parent_id) {
+                refreshes.push(Refresh::Children { store_id, parent_id });
+            }
+        }
+        refreshes
+    }
+
+    fn command(self) -> BackendCommand {
+        match self {
+            Refresh::Node { store_id, node_id } => BackendCommand::GetNode { store_id, node_id },
+            Refresh::Children { store_id, parent_id } => BackendCommand::GetChildren { store_id, node_id: parent_id },
+        }
+    }
+}
+
 /// Handle to communicate with the backend
 #[derive(Clone)]
 pub struct BackendHandle {
@@ -108,15 +181,41 @@ async fn backend_loop(
     signal_ui: impl Fn(),
 ) {
     let mut client: Option<PimbleClient> = None;
+    // Documents as last loaded or saved, so saves send only new changes
+    let mut documents: HashMap<(StoreId, NodeId), CrdtDocument> = HashMap::new();
+
+    let config_dir = dirs::config_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("pimble");
+
+    // Attribute the editor's edits to this device; the embedded server
+    // attributes its own to the id in its state directory
+    let device_path = config_dir.join("device_id");
+    let device = match DeviceId::load_or_create(&device_path) {
+        Ok(device) => {
+            tracing::info!("Device id {}", device);
+            Some(device)
+        }
+        Err(e) => {
+            tracing::error!("Failed to load device id from {:?}: {}", device_path, e);
+            None
+        }
+    };
 
     // Start embedded server and auto-connect
-    let mut server = PimbleServer::new();
+    let mut server = PimbleServer::with_config(ServerConfig {
+        state_dir: Some(config_dir.join("server")),
+        ..Default::default()
+    });
     match server.start().await {
         Ok(()) => {
             let url = format!("http://{}", server.addr());
             tracing::info!("Embedded server started on {}", server.addr());
             match PimbleClient::connect(&url).await {
-                Ok(c) => {
+                Ok(mut c) => {
+                    if let Some(device) = device {
+                        c.set_device(device);
+                    }
                     client = Some(c);
                     let _ = event_tx.try_send(BackendEvent::Connected);
                     signal_ui();
@@ -139,21 +238,93 @@ async fn backend_loop(
         }
     }
 
-    loop {
-        // Block waiting for commands
-        let cmd = match cmd_rx.recv() {
-            Ok(cmd) => cmd,
-            Err(_) => break, // Channel closed, exit
-        };
+    // Commands arrive on a blocking channel; hand them to the async loop
+    let (async_cmd_tx, mut commands) = mpsc::channel::<BackendCommand>(100);
+    tokio::task::spawn_blocking(move || {
+        while let Ok(cmd) = cmd_rx.recv() {
+            if async_cmd_tx.blocking_send(cmd).is_err() {
+                break;
+            }
+        }
+    });
 
-        let event = process_command(&mut client, cmd).await;
+    // Changes to open stores, forwarded from one subscription per store
+    let (watch_tx, mut changes) = mpsc::unbounded_channel::<Watched>();
+    let mut watches: HashMap<StoreId, JoinHandle<()>> = HashMap::new();
 
-        if let Some(event) = event {
-            let _ = event_tx.try_send(event);
-            signal_ui();
+    loop {
+        tokio::select! {
+            cmd = commands.recv() => {
+                let Some(cmd) = cmd else {
+                    break; // Channel closed, exit
+                };
+                let event = process_command(&mut client, device, &mut documents, cmd).await;
+
+                if let Some(event) = event {
+                    let watched = update_watches(client.as_ref(), &mut watches, &watch_tx, &event).await;
+                    let _ = event_tx.try_send(event);
+                    if let Some(watched) = watched {
+                        let _ = event_tx.try_send(watched);
+                    }
+                    signal_ui();
+                }
+            }
+            Some(watched) = changes.recv() => {
+                // Take every change that has piled up, reloading each cache once
+                let mut refreshes: Vec<Refresh> = Vec::new();
+                let mut ended: Vec<StoreId> = Vec::new();
+                let mut pending = Some(watched);
+                while let Some(watched) = pending.take().or_else(|| changes.try_recv().ok()) {
+                    let change = match watched {
+                        Watched::Change(change) => change,
+                        Watched::Ended(store_id) => {
+                            ended.push(store_id);
+                            continue;
+                        }
+                    };
+                    if change.change_type == ChangeType::Deleted {
+                        documents.remove(&(change.store_id, change.node_id));
+                    }
+                    for refresh in Refresh::for_change(&change) {
+                        if !refreshes.contains(&refresh) {
+                            refreshes.push(refresh);
+                        }
+                    }
+                }
+                for refresh in refreshes {
+                    if let Some(event) = process_command(&mut client, device, &mut documents, refresh.command()).await {
+                        let _ = event_tx.try_send(event);
+                    }
+                }
+
+                // Follow stores whose subscription ended again, then have
+                // the UI reload them, so nothing missed in between is lost
+                for store_id in ended {
+                    // A store closed since is no longer followed
+                    if watches.remove(&store_id).is_none() {
+                        continue;
+                    }
+                    let watch = match client.as_ref() {
+                        Some(c) => watch_store(c, store_id, &watch_tx).await,
+                        None => None,
+                    };
+                    let watched = watch.is_some();
+                    if let Some(watch) = watch {
+                        watches.insert(store_id, watch);
+                    }
+                    documents.retain(|(s, _), _| *s != store_id);
+                    let _ = event_tx.try_send(BackendEvent::StoreWatched { store_id, watched });
+                    let _ = event_tx.try_send(BackendEvent::StoreStale { store_id });
+                }
+                signal_ui();
+            }
         }
     }
 
+    for (_, watch) in watches {
+        watch.abort();
+    }
+
     // Cleanup: stop server and flush stores
     let store_manager = server.store_manager();
     let _ = server.stop().await;
@@ -161,14 +332,130 @@ async fn backend_loop(
     let _ = manager.flush_all().await;
 }
 
+/// Start or stop following a store's changes as it is opened or closed
+///
+/// Subscriptions live on the client's connection, so a new connection
+/// starts over; stores are reopened, and watched again, once it's made.
+/// Returns whether an opened store is being followed.
+async fn update_watches(
+    client: Option<&PimbleClient>,
+    watches: &mut HashMap<StoreId, JoinHandle<()>>,
+    watch_tx: &mpsc::UnboundedSender<Watched>,
+    event: &BackendEvent,
+) -> Option<BackendEvent> {
+    match event {
+        BackendEvent::StoreOpened { store } => {
+            let c = client?;
+            if watches.contains_key(&store.id) {
+                return None;
+            }
+            let watch = watch_store(c, store.id, watch_tx).await;
+            let watched = watch.is_some();
+            if let Some(watch) = watch {
+                watches.insert(store.id, watch);
+            }
+            Some(BackendEvent::StoreWatched { store_id: store.id, watched })
+        }
+        BackendEvent::StoreClosed { store_id } => {
+            if let Some(watch) = watches.remove(store_id) {
+                watch.abort();
+            }
+            None
+        }
+        BackendEvent::Connected | BackendEvent::Disconnected => {
+            for (_, watch) in watches.drain() {
+                watch.abort();
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Subscribe to a store's changes and forward them to the backend loop
+///
+/// Servers refuse subscriptions to stores they reach remotely; those get
+/// `None` and are left to the UI's re-fetches.
+async fn watch_store(
+    client: &PimbleClient,
+    store_id: StoreId,
+    watch_tx: &mpsc::UnboundedSender<Watched>,
+) -> Option<JoinHandle<()>> {
+    let mut subscription = match client.subscribe_store(store_id).await {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            tracing::warn!("Failed to follow changes to store {}: {}", store_id, e);
+            return None;
+        }
+    };
+    let watch_tx = watch_tx.clone();
+    Some(tokio::spawn(async move {
+        while let Some(change) = subscription.next().await {
+            match change {
+                Ok(change) => {
+                    if watch_tx.send(Watched::Change(change)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => tracing::warn!("Bad change notification for store {}: {}", store_id, e),
+            }
+        }
+        // Lagging behind ends the subscription too
+        tracing::info!("Stopped following changes to store {}", store_id);
+        let _ = watch_tx.send(Watched::Ended(store_id));
+    }))
+}
+
+/// Fold an edited copy into a tracked document and exchange changes with the server
+async fn save_document(
+    client: &PimbleClient,
+    store_id: StoreId,
+    node_id: NodeId,
+    doc: &mut CrdtDocument,
+    edited: &CrdtDocument,
+) -> pimble_client::Result<()> {
+    doc.update_from(edited, MetadataContent::is_metadata_key)?;
+    doc.set_content_format(&ContentFormat::new(EDITOR_FORMAT, 1))?;
+    client.push_document(store_id, node_id, doc).await?;
+    client.pull_document(store_id, node_id, doc).await?;
+    Ok(())
+}
+
+/// Undo or redo this client's latest edit
+///
+/// Followed stores reload the nodes it changed from their change events.
+/// For the others, a reverted move reports the parent the node is under
+/// now, so the UI knows which children list to re-fetch.
+async fn revert_edit(client: Option<&PimbleClient>, undo: bool) -> Option<BackendEvent> {
+    let Some(c) = client else {
+        return Some(BackendEvent::Error { message: "Not connected".into() });
+    };
+    let result = if undo { c.undo().await } else { c.redo().await };
+    let edit = match result {
+        Ok(response) => response.edit?,
+        Err(e) => return Some(BackendEvent::Error { message: e.to_string() }),
+    };
+    let parent_id = if edit.kind == EditKind::Content {
+        None
+    } else {
+        c.get_node(edit.store_id, edit.node_id).await.ok().and_then(|n| n.parent_id)
+    };
+    Some(BackendEvent::EditReverted { edit, parent_id })
+}
+
 async fn process_command(
     client: &mut Option<PimbleClient>,
+    device: Option<DeviceId>,
+    documents: &mut HashMap<(StoreId, NodeId), CrdtDocument>,
     cmd: BackendCommand,
 ) -> Option<BackendEvent> {
     match cmd {
         BackendCommand::Connect { url } => {
             match PimbleClient::connect(&url).await {
-                Ok(c) => {
+                Ok(mut c) => {
+                    if let Some(device) = device {
+                        c.set_device(device);
+                    }
                     *client = Some(c);
                     Some(BackendEvent::Connected)
                 }
@@ -177,7 +464,12 @@ async fn process_command(
         }
 
         BackendCommand::Disconnect => {
-            *client = None;
+            if let Some(c) = client.take() {
+                if let Err(e) = c.close_session().await {
+                    tracing::warn!("Failed to close session: {}", e);
+                }
+            }
+            documents.clear();
             Some(BackendEvent::Disconnected)
         }
 
@@ -238,7 +530,13 @@ async fn process_command(
                 return Some(BackendEvent::Error { message: "Not connected".into() });
             };
             match c.get_node(store_id, node_id).await {
-                Ok(node) => Some(BackendEvent::NodeLoaded { store_id, node }),
+                Ok(node) => {
+                    if let Ok(mut doc) = CrdtDocument::load(&node.content) {
+                        c.track_document(store_id, node_id, &mut doc);
+                        documents.insert((store_id, node_id), doc);
+                    }
+                    Some(BackendEvent::NodeLoaded { store_id, node })
+                }
                 Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
             }
         }
@@ -261,7 +559,14 @@ async fn process_command(
             let Some(c) = client.as_ref() else {
                 return Some(BackendEvent::Error { message: "Not connected".into() });
             };
-            match c.set_node_content_bytes(store_id, node_id, content).await {
+            // The editor rebuilds its document, so fold it into the one
+            // loaded from the server and send only the new changes; the
+            // server merges them with any concurrent edits
+            let saved = match (documents.get_mut(&(store_id, node_id)), CrdtDocument::load(&content)) {
+                (Some(doc), Ok(edited)) => save_document(c, store_id, node_id, doc, &edited).await,
+                _ => c.set_node_content_bytes(store_id, node_id, content).await,
+            };
+            match saved {
                 Ok(()) => Some(BackendEvent::NodeContentUpdated { store_id, node_id }),
                 Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
             }
@@ -271,18 +576,14 @@ async fn process_command(
             let Some(c) = client.as_ref() else {
                 return Some(BackendEvent::Error { message: "Not connected".into() });
             };
-            match c.get_node(store_id, node_id).await {
-                Ok(mut node) => {
-                    node.metadata.title = title;
-                    node.metadata.custom.insert(
-                        "explicit_title".to_string(),
-                        serde_json::Value::Bool(true),
-                    );
-                    match c.update_node_metadata(store_id, node_id, node.metadata).await {
-                        Ok(()) => Some(BackendEvent::NodeRenamed { store_id, node_id }),
-                        Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
-                    }
-                }
+            // Field edits, so a concurrent tag or field change isn't lost
+            let renamed = async {
+                c.set_title(store_id, node_id, title).await?;
+                c.set_custom_field(store_id, node_id, "explicit_title", Some(serde_json::Value::Bool(true)))
+                    .await
+            };
+            match renamed.await {
+                Ok(_) => Some(BackendEvent::NodeRenamed { store_id, node_id }),
                 Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
             }
         }
@@ -302,6 +603,78 @@ async fn process_command(
             }
         }
 
+        BackendCommand::MoveNodeAcrossStores { source_store_id, node_id, target_store_id, new_parent_id, position } => {
+            let Some(c) = client.as_ref() else {
+                return Some(BackendEvent::Error { message: "Not connected".into() });
+            };
+            let old_parent_id = match c.get_node(source_store_id, node_id).await {
+                Ok(node) => node.parent_id.unwrap_or(NodeId(uuid::Uuid::nil())),
+                Err(e) => return Some(BackendEvent::Error { message: e.to_string() }),
+            };
+            match c.move_node_across_stores(source_store_id, node_id, target_store_id, new_parent_id, position).await {
+                Ok(()) => Some(BackendEvent::NodeMovedAcrossStores {
+                    source_store_id,
+                    old_parent_id,
+                    target_store_id,
+                    new_parent_id,
+                    node_id,
+                }),
+                Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
+            }
+        }
+
+        BackendCommand::CopyNode { source_store_id, node_id, target_store_id, new_parent_id, position } => {
+            let Some(c) = client.as_ref() else {
+                return Some(BackendEvent::Error { message: "Not connected".into() });
+            };
+            match c.copy_node(source_store_id, node_id, target_store_id, new_parent_id, position).await {
+                Ok(copy_id) => Some(BackendEvent::NodeCopied {
+                    store_id: target_store_id,
+                    parent_id: new_parent_id,
+                    node_id: copy_id,
+                }),
+                Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
+            }
+        }
+
+        BackendCommand::GetNodeHistory { store_id, node_id } => {
+            let Some(c) = client.as_ref() else {
+                return Some(BackendEvent::Error { message: "Not connected".into() });
+            };
+            match c.get_node_history(store_id, node_id).await {
+                Ok(changes) => Some(BackendEvent::NodeHistoryLoaded { store_id, node_id, changes }),
+                Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
+            }
+        }
+
+        BackendCommand::GetNodeAtVersion { store_id, node_id, hash } => {
+            let Some(c) = client.as_ref() else {
+                return Some(BackendEvent::Error { message: "Not connected".into() });
+            };
+            match c.get_node_at_version(store_id, node_id, vec![hash.clone()]).await {
+                Ok((node, _text)) => Some(BackendEvent::NodeVersionLoaded {
+                    store_id,
+                    node_id,
+                    hash,
+                    content: node.content,
+                }),
+                Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
+            }
+        }
+
+        BackendCommand::RestoreNodeVersion { store_id, node_id, hash } => {
+            let Some(c) = client.as_ref() else {
+                return Some(BackendEvent::Error { message: "Not connected".into() });
+            };
+            match c.restore_node_version(store_id, node_id, vec![hash]).await {
+                Ok(_) => Some(BackendEvent::NodeVersionRestored { store_id, node_id }),
+                Err(e) => Some(BackendEvent::Error { message: e.to_string() }),
+            }
+        }
+
+        BackendCommand::Undo => revert_edit(client.as_ref(), true).await,
+        BackendCommand::Redo => revert_edit(client.as_ref(), false).await,
+
         BackendCommand::CreateWorkspace { name, path } => {
             let Some(c) = client.as_ref() else {
                 return Some(BackendEvent::Error { message: "Not connected".into() });
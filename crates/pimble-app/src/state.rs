@@ -2,8 +2,8 @@
 
 use std::collections::{HashMap, HashSet};
 
-use pimble_core::{Node, NodeId, Store, StoreId, Workspace};
-use pimble_crdt::DocumentContent;
+use pimble_core::{HistoryEntry, Node, NodeId, Store, StoreId, Workspace};
+use pimble_crdt::{identify_format, short_hash, DocumentContent, DOCUMENT_FORMAT};
 use rinch::components::TreeNodeData;
 use rinch_editor::EditorDocument;
 
@@ -11,20 +11,26 @@ use crate::backend::BackendHandle;
 
 /// Extract text content from node content bytes.
 ///
-/// Tries new format (EditorDocument) first, falls back to old format (DocumentContent).
+/// Content recorded as document format is read as DocumentContent; anything
+/// else tries the editor's format (EditorDocument) first, falls back to
+/// DocumentContent.
 pub fn get_node_content_text(content: &[u8]) -> String {
     if content.is_empty() {
         return String::new();
     }
 
-    // Try new format first (EditorDocument with rich blocks)
-    if let Ok(doc) = EditorDocument::from_bytes(content) {
-        return doc.to_markdown();
+    // Try new format first (EditorDocument with rich blocks), unless the
+    // content is known to be a plain document
+    let is_document = identify_format(content, "document").is_some_and(|format| format.id == DOCUMENT_FORMAT);
+    if !is_document {
+        if let Ok(doc) = EditorDocument::from_bytes(content) {
+            return doc.to_markdown();
+        }
     }
 
-    // Fall back to old format (DocumentContent with flat text)
+    // Fall back to DocumentContent, rendering any blocks and marks
     match DocumentContent::load(content) {
-        Ok(doc) => doc.get_text().unwrap_or_else(|_| {
+        Ok(doc) => doc.to_markdown().unwrap_or_else(|_| {
             String::from_utf8_lossy(content).to_string()
         }),
         Err(_) => String::from_utf8_lossy(content).to_string(),
@@ -48,6 +54,10 @@ pub struct AppState {
     /// Open stores (loaded from workspace or opened manually)
     pub stores: HashMap<StoreId, Store>,
 
+    /// Stores whose changes the backend follows; the rest are re-fetched
+    /// after each edit
+    pub watched: HashSet<StoreId>,
+
     /// Cached nodes by (store_id, node_id)
     pub nodes: HashMap<(StoreId, NodeId), Node>,
 
@@ -65,6 +75,9 @@ pub struct AppState {
 
     /// Error message to display
     pub error: Option<String>,
+
+    /// Version history of the selected node, while the history panel is open
+    pub history: Option<NodeHistory>,
 }
 
 impl AppState {
@@ -75,12 +88,14 @@ impl AppState {
             pending_create_path: None,
             workspace: None,
             stores: HashMap::new(),
+            watched: HashSet::new(),
             nodes: HashMap::new(),
             children: HashMap::new(),
             selected_id: None,
             expanded: HashSet::new(),
             loading: LoadingState::default(),
             error: None,
+            history: None,
         }
     }
 
@@ -103,6 +118,15 @@ impl AppState {
         }
     }
 
+    /// The backend, if a store's caches must be re-fetched by hand because
+    /// its changes aren't followed
+    pub fn unwatched_backend(&self, store_id: StoreId) -> Option<&BackendHandle> {
+        if self.watched.contains(&store_id) {
+            return None;
+        }
+        self.backend.as_ref()
+    }
+
     /// Compute the display label for a node in the tree.
     ///
     /// - If `explicit_title` custom flag is set and title is non-empty → use title
@@ -211,6 +235,52 @@ impl AppState {
     }
 }
 
+/// Versions of a node shown in the history panel
+#[derive(Debug, Clone)]
+pub struct NodeHistory {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+
+    /// Changes to the node's content, oldest first
+    pub changes: Vec<HistoryEntry>,
+
+    /// Hash of the version being previewed
+    pub selected: Option<String>,
+}
+
+impl NodeHistory {
+    pub fn new(store_id: StoreId, node_id: NodeId) -> Self {
+        Self {
+            store_id,
+            node_id,
+            changes: Vec::new(),
+            selected: None,
+        }
+    }
+
+    /// Build list items for the history panel, newest first
+    ///
+    /// Each item's value is the change hash naming that version.
+    pub fn build_list_data(&self) -> Vec<TreeNodeData> {
+        self.changes
+            .iter()
+            .rev()
+            .map(|change| {
+                let when = change
+                    .timestamp
+                    .map(|ts| ts.with_timezone(&chrono::Local).format("%b %e, %H:%M").to_string())
+                    .unwrap_or_else(|| short_hash(&change.hash).to_string());
+                let who = change.author.as_deref().unwrap_or_else(|| short_hash(&change.actor));
+                let label = match &change.message {
+                    Some(message) => format!("{} · {} · {}", when, who, message),
+                    None => format!("{} · {}", when, who),
+                };
+                TreeNodeData::new(change.hash.clone(), &label)
+            })
+            .collect()
+    }
+}
+
 impl Default for AppState {
     fn default() -> Self {
         Self::new()
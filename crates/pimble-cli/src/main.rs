@@ -2,8 +2,10 @@
 
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use pimble_client::PimbleClient;
+use pimble_core::{HistoryEntry, HistoryMode, NodeId};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -25,7 +27,7 @@ async fn main() -> Result<()> {
 
     match command.as_str() {
         "help" | "--help" | "-h" => print_help(),
-        "server" => run_server().await?,
+        "server" => run_server(flag_value(&args[2..], "--state-dir")).await?,
         "create-store" => {
             if args.len() < 4 {
                 eprintln!("Usage: pimble-cli create-store <path> <name>");
@@ -41,6 +43,108 @@ async fn main() -> Result<()> {
             }
             open_store(&args[2]).await?;
         }
+        "export-markdown" => {
+            if args.len() < 4 {
+                eprintln!("Usage: pimble-cli export-markdown <store-path> <output-dir> [--node <id>] [--incremental]");
+                return Ok(());
+            }
+            let node = flag_value(&args[4..], "--node")
+                .map(|id| NodeId::parse(&id))
+                .transpose()?;
+            let incremental = args[4..].iter().any(|a| a == "--incremental");
+            export_markdown(&args[2], &args[3], node, incremental).await?;
+        }
+        "export-html" => {
+            if args.len() < 4 {
+                eprintln!("Usage: pimble-cli export-html <store-path> <output-dir> [--node <id>] [--title <title>] [--incremental]");
+                return Ok(());
+            }
+            let node = flag_value(&args[4..], "--node")
+                .map(|id| NodeId::parse(&id))
+                .transpose()?;
+            let title = flag_value(&args[4..], "--title");
+            let incremental = args[4..].iter().any(|a| a == "--incremental");
+            export_html(&args[2], &args[3], node, title, incremental).await?;
+        }
+        "export-opml" => {
+            if args.len() < 3 {
+                eprintln!("Usage: pimble-cli export-opml <store-path> [output-file] [--node <id>]");
+                return Ok(());
+            }
+            let node = flag_value(&args[3..], "--node")
+                .map(|id| NodeId::parse(&id))
+                .transpose()?;
+            let output = args.get(3).filter(|a| !a.starts_with("--"));
+            export_opml(&args[2], output.map(String::as_str), node).await?;
+        }
+        "import-opml" => {
+            if args.len() < 4 {
+                eprintln!("Usage: pimble-cli import-opml <store-path> <opml-file> [--parent <id>]");
+                return Ok(());
+            }
+            let parent = flag_value(&args[4..], "--parent")
+                .map(|id| NodeId::parse(&id))
+                .transpose()?;
+            import_opml(&args[2], &args[3], parent).await?;
+        }
+        "diff" => {
+            if args.len() < 4 {
+                eprintln!("Usage: pimble-cli diff <store-path> <node-id> [--from <time|hash>] [--to <time|hash>]");
+                return Ok(());
+            }
+            let node = NodeId::parse(&args[3])?;
+            let from = flag_value(&args[4..], "--from");
+            let to = flag_value(&args[4..], "--to");
+            diff(&args[2], node, from.as_deref(), to.as_deref()).await?;
+        }
+        "migrate-store" => {
+            if args.len() < 3 {
+                eprintln!("Usage: pimble-cli migrate-store <store-path>");
+                return Ok(());
+            }
+            migrate_store(&args[2]).await?;
+        }
+        "compact-store" => {
+            if args.len() < 3 {
+                eprintln!("Usage: pimble-cli compact-store <store-path>");
+                return Ok(());
+            }
+            compact_store(&args[2]).await?;
+        }
+        "set-history-mode" => {
+            if args.len() < 5 {
+                eprintln!("Usage: pimble-cli set-history-mode <store-path> <node-id> <full|truncate>");
+                return Ok(());
+            }
+            let node = NodeId::parse(&args[3])?;
+            let mode = match args[4].as_str() {
+                "full" => HistoryMode::Full,
+                "truncate" => HistoryMode::Truncate,
+                other => bail!("Unknown history mode '{}'; expected full or truncate", other),
+            };
+            set_history_mode(&args[2], node, mode).await?;
+        }
+        "set-identity" => {
+            if args.len() < 4 {
+                eprintln!("Usage: pimble-cli set-identity <store-path> <device-id> [name]");
+                return Ok(());
+            }
+            set_identity(&args[2], &args[3], args.get(4).cloned()).await?;
+        }
+        "identities" => {
+            if args.len() < 3 {
+                eprintln!("Usage: pimble-cli identities <store-path>");
+                return Ok(());
+            }
+            identities(&args[2]).await?;
+        }
+        "presence" => {
+            if args.len() < 4 {
+                eprintln!("Usage: pimble-cli presence <store-path> <node-id>");
+                return Ok(());
+            }
+            presence(&args[2], NodeId::parse(&args[3])?).await?;
+        }
         _ => {
             eprintln!("Unknown command: {}", command);
             print_help();
@@ -63,21 +167,54 @@ COMMANDS:
     create-store    Create a new store
     open-store      Open an existing store
     list-stores     List all open stores
+    export-markdown Export a store (or subtree) to a folder of markdown files
+    export-html     Publish a store (or subtree) as a static HTML site
+    export-opml     Export a store (or subtree) as an OPML outline
+    import-opml     Import an OPML outline into a store
+    diff            Show how a node's text changed between two versions
+    migrate-store   Upgrade every node in a store to current content formats
+    compact-store   Rewrite a store's content files, dropping history where asked
+    set-history-mode
+                    Keep a node's full history, or drop it on compaction
+    set-identity    Name a device's changes in a store (omit the name to remove it)
+    identities      List the devices named in a store
+    presence        List the clients viewing a node right now
 
 EXAMPLES:
     pimble-cli server
+    pimble-cli server --state-dir ~/.pimble/server
     pimble-cli create-store ./my-notes.pimble "My Notes"
     pimble-cli open-store ./my-notes.pimble
     pimble-cli list-stores
+    pimble-cli export-markdown ./my-notes.pimble ./export --incremental
+    pimble-cli export-html ./my-notes.pimble ./site --title "My Notes"
+    pimble-cli export-opml ./my-notes.pimble ./outline.opml
+    pimble-cli import-opml ./my-notes.pimble ./outline.opml
+    pimble-cli diff ./my-notes.pimble <node-id> --from 2026-10-01T00:00:00Z --to 2026-10-15T00:00:00Z
+    pimble-cli migrate-store ./my-notes.pimble
+    pimble-cli set-history-mode ./my-notes.pimble <node-id> truncate
+    pimble-cli compact-store ./my-notes.pimble
+    pimble-cli set-identity ./my-notes.pimble <device-id> "Work laptop"
+    pimble-cli presence ./my-notes.pimble <node-id>
 "#
     );
 }
 
-async fn run_server() -> Result<()> {
+async fn run_server(state_dir: Option<String>) -> Result<()> {
     use pimble_server::{run_server, ServerConfig};
 
+    // The server's id lives here, so peers recognise it after a restart and
+    // its edits are attributed to it
+    let state_dir = state_dir
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".pimble").join("server")));
+
     println!("Starting Pimble server on 127.0.0.1:9876...");
-    run_server(ServerConfig::default()).await?;
+    run_server(ServerConfig {
+        state_dir,
+        ..Default::default()
+    })
+    .await?;
     Ok(())
 }
 
@@ -113,6 +250,230 @@ async fn list_stores() -> Result<()> {
     Ok(())
 }
 
+async fn export_markdown(
+    store_path: &str,
+    output_dir: &str,
+    node: Option<NodeId>,
+    incremental: bool,
+) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let report = client
+        .export_markdown(store.id, node, PathBuf::from(output_dir), incremental)
+        .await?;
+
+    println!("Exported '{}' to {}", store.name, output_dir);
+    println!("  {} files written", report.written.len());
+    if incremental {
+        println!("  {} files unchanged", report.unchanged);
+        println!("  {} stale files removed", report.removed.len());
+    }
+    println!("  {} assets copied", report.assets_copied);
+    Ok(())
+}
+
+async fn export_html(
+    store_path: &str,
+    output_dir: &str,
+    node: Option<NodeId>,
+    title: Option<String>,
+    incremental: bool,
+) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let report = client
+        .export_html(store.id, node, PathBuf::from(output_dir), title, incremental)
+        .await?;
+
+    println!("Published '{}' to {}", store.name, output_dir);
+    println!("  {} files written", report.written.len());
+    if incremental {
+        println!("  {} files unchanged", report.unchanged);
+        println!("  {} stale files removed", report.removed.len());
+    }
+    println!("  {} assets copied", report.assets_copied);
+    Ok(())
+}
+
+async fn export_opml(store_path: &str, output: Option<&str>, node: Option<NodeId>) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let opml = client.export_opml(store.id, node).await?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, opml)?;
+            println!("Exported '{}' to {}", store.name, path);
+        }
+        None => print!("{}", opml),
+    }
+    Ok(())
+}
+
+async fn import_opml(store_path: &str, opml_path: &str, parent: Option<NodeId>) -> Result<()> {
+    let opml = std::fs::read_to_string(opml_path)?;
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let node_ids = client.import_opml(store.id, parent, opml).await?;
+
+    println!("Imported {} top-level outlines into '{}'", node_ids.len(), store.name);
+    for id in node_ids {
+        println!("  {}", id);
+    }
+    Ok(())
+}
+
+async fn diff(store_path: &str, node_id: NodeId, from: Option<&str>, to: Option<&str>) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let history = client.get_node_history(store.id, node_id).await?;
+
+    let from = match from {
+        Some(version) => resolve_version(&history, version)?,
+        None => Vec::new(),
+    };
+    let to = to.map(|version| resolve_version(&history, version)).transpose()?;
+    let diff = client.diff_node_versions(store.id, node_id, from, to).await?;
+
+    if diff.unified.is_empty() {
+        println!("No changes");
+    } else {
+        print!("{}", diff.unified);
+    }
+    Ok(())
+}
+
+/// Heads of the version named by an RFC 3339 time or a change hash prefix
+///
+/// A time names the version with every change made up to then. Changes
+/// without a recorded time count as made when the change before them was.
+/// History is in causal order, and clocks differ between devices, so each
+/// change is judged by its own time rather than assuming times only grow.
+fn resolve_version(history: &[HistoryEntry], version: &str) -> Result<Vec<String>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(version) {
+        if history.iter().all(|entry| entry.timestamp.is_none()) {
+            bail!("No change to this node records when it was made; name a change hash instead");
+        }
+        let time = time.with_timezone(&Utc);
+        let mut last = None;
+        let mut heads = Vec::new();
+        for entry in history {
+            last = entry.timestamp.or(last);
+            if !matches!(last, Some(t) if t > time) {
+                heads.push(entry.hash.clone());
+            }
+        }
+        return Ok(heads);
+    }
+
+    let matches: Vec<&HistoryEntry> = history.iter().filter(|entry| entry.hash.starts_with(version)).collect();
+    match matches.as_slice() {
+        [entry] => Ok(vec![entry.hash.clone()]),
+        [] => bail!("No change or time matches '{}'", version),
+        _ => bail!("'{}' matches more than one change", version),
+    }
+}
+
+async fn migrate_store(store_path: &str) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let report = client.migrate_store(store.id).await?;
+
+    println!("Migrated '{}'", store.name);
+    println!("  {} nodes upgraded", report.migrated.len());
+    for migration in &report.migrated {
+        println!("    {}: {} -> {}", migration.node_id, migration.from, migration.to);
+    }
+    println!("  {} nodes already current", report.current);
+    if !report.unrecognized.is_empty() {
+        println!("  {} nodes in unrecognized formats, left as they were:", report.unrecognized.len());
+        for node_id in &report.unrecognized {
+            println!("    {}", node_id);
+        }
+    }
+    Ok(())
+}
+
+async fn compact_store(store_path: &str) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let report = client.compact_store(store.id, false).await?;
+
+    println!("Compacted '{}'", store.name);
+    println!("  {} content files rewritten", report.compacted);
+    println!("  {} bytes down to {}", report.bytes_before, report.bytes_after);
+    if !report.truncated.is_empty() {
+        println!("  {} nodes had their history dropped:", report.truncated.len());
+        for node_id in &report.truncated {
+            println!("    {}", node_id);
+        }
+    }
+    if !report.kept.is_empty() {
+        println!("  {} nodes kept their history, which peers still build on", report.kept.len());
+    }
+    Ok(())
+}
+
+async fn set_history_mode(store_path: &str, node_id: NodeId, mode: HistoryMode) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    client.set_history_mode(store.id, node_id, mode).await?;
+
+    match mode {
+        HistoryMode::Full => println!("Node {} keeps its full history", node_id),
+        HistoryMode::Truncate => println!("Node {} drops its history when '{}' is compacted", node_id, store.name),
+    }
+    Ok(())
+}
+
+async fn set_identity(store_path: &str, device_id: &str, name: Option<String>) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    client.set_identity(store.id, device_id.to_string(), name.clone()).await?;
+
+    match name {
+        Some(name) => println!("Device {} is now '{}' in '{}'", device_id, name, store.name),
+        None => println!("Removed the name of device {} in '{}'", device_id, store.name),
+    }
+    Ok(())
+}
+
+async fn identities(store_path: &str) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let identities = client.list_identities(store.id).await?;
+
+    if identities.is_empty() {
+        println!("No devices named in '{}'", store.name);
+    }
+    for identity in identities {
+        println!("{}  {}", identity.device_id, identity.name);
+    }
+    Ok(())
+}
+
+async fn presence(store_path: &str, node_id: NodeId) -> Result<()> {
+    let client = connect().await?;
+    let store = client.open_store(PathBuf::from(store_path)).await?;
+    let present = client.get_presence(store.id, node_id).await?;
+
+    if present.is_empty() {
+        println!("Nobody is on node {}", node_id);
+    }
+    for presence in present {
+        println!("{}  {}  (seen {})", presence.client_id, presence.name, presence.last_seen.to_rfc3339());
+    }
+    Ok(())
+}
+
+/// Get the value following a `--flag` in the argument list
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
 async fn connect() -> Result<PimbleClient> {
     let url = std::env::var("PIMBLE_SERVER").unwrap_or_else(|_| "http://127.0.0.1:9876".to_string());
     let client = PimbleClient::connect(&url).await?;
@@ -1,41 +1,135 @@
 //! RPC client implementation
 
-use std::path::Path;
-
-use jsonrpsee::http_client::{HttpClient, HttpClientBuilder};
-use pimble_core::{Node, NodeId, Store, StoreId, Workspace};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use jsonrpsee::http_client::{HeaderMap, HeaderValue, HttpClient, HttpClientBuilder};
+use jsonrpsee::ws_client::{WsClient, WsClientBuilder};
+use pimble_core::{
+    AuthMethod, CompactionReport, ConflictInfo, HistoryEntry, HistoryMode, Identity, LinkResolution, LinkTarget, MetadataEdit, MigrationReport, Node,
+    NodeDiff, NodeId, NodeMetadata, Store, StoreId, Tombstone, Workspace,
+};
+use pimble_crdt::{CrdtDocument, DeviceId};
 use pimble_rpc::{
-    CloseStoreRequest, CreateNodeRequest, CreateStoreRequest, CreateWorkspaceRequest,
-    DeleteNodeRequest, GetChildrenRequest, GetNodeRequest, GetNodesRequest,
-    LoadWorkspaceRequest, MoveNodeRequest, OpenStoreRequest, PimbleApiClient, SaveWorkspaceRequest,
-    SearchRequest, SearchResultItem, SetNodeTextRequest, UpdateNodeContentRequest, UpdateNodeMetadataRequest,
+    ApplyNodeChangesRequest, ApplyNodeRecordsRequest, CloneStoreRequest, CloseSessionRequest, CloseStoreRequest,
+    CompactStoreRequest,
+    CopyNodeRequest, CreateNodeRequest, CreateStoreRequest, CreateWorkspaceRequest,
+    DeleteNodeRequest, DiffNodeVersionsRequest, ExportHtmlRequest, ExportMarkdownRequest,
+    ExportOpmlRequest, ExportResponse, GetChildrenRequest, GetNodeChangesSinceRequest,
+    GetNodeHistoryRequest, GetNodeRequest, GetNodesRequest, GetPresenceRequest, HelloRequest, ImportOpmlRequest,
+    LeavePresenceRequest, ListConflictsRequest, ListIdentitiesRequest, LoadWorkspaceRequest, MergeStoreTreeRequest, MigrateStoreRequest,
+    MoveNodeAcrossStoresRequest, MoveNodeRequest, NodeChangedNotification, NodeVersionRequest, OpenRemoteStoreRequest,
+    OpenStoreRequest, PairStoreRequest, PimbleApiClient, Presence, PresenceNotification,
+    PublishPresenceRequest, ReplicateStoreRequest,
+    ReplicateStoreResponse, ReplicationManifestRequest, ReplicationManifestResponse,
+    ResolveConflictRequest, ResolveLinkRequest, RpcError, SaveWorkspaceRequest, SearchRequest,
+    SearchResultItem, ServerInfo, SetCustomFieldRequest, SetHistoryModeRequest, SetIdentityRequest, SetNodeTextRequest, SetTitleRequest,
+    SubscribeNodeRequest, SubscribePresenceRequest, SubscribeStoreRequest, SyncNodeContentRequest, TagRequest, UndoRequest, UndoResponse, UnpairStoreRequest,
+    UpdateNodeContentRequest, UpdateNodeMetadataRequest, PROTOCOL_VERSION,
 };
+use tokio::sync::OnceCell;
 use tracing::debug;
 use url::Url;
 
 use crate::error::{ClientError, Result};
 
+pub use jsonrpsee::core::client::Subscription;
+
+/// Request headers carrying an authentication method's credentials
+fn auth_headers(auth: &AuthMethod) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let invalid = |e: &dyn std::fmt::Display| ClientError::Connection(format!("Invalid credentials: {}", e));
+    match auth {
+        AuthMethod::None => {}
+        AuthMethod::ApiKey { key } => {
+            headers.insert("x-api-key", HeaderValue::from_str(key).map_err(|e| invalid(&e))?);
+        }
+        AuthMethod::Bearer { token } => {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|e| invalid(&e))?;
+            headers.insert("authorization", value);
+        }
+        AuthMethod::OAuth2 { .. } => {
+            return Err(ClientError::Connection(
+                "OAuth2 authentication is not supported yet".into(),
+            ));
+        }
+    }
+    Ok(headers)
+}
+
 /// Client for connecting to a Pimble server
+///
+/// Each client is its own undo session: [`PimbleClient::undo`] reverses
+/// edits made through this client and no other.
+///
+/// For documents opened with [`PimbleClient::open_document`] the client
+/// remembers which heads the server is known to have, so saves and
+/// refreshes exchange only the changes in between.
 pub struct PimbleClient {
     client: HttpClient,
     base_url: Url,
+    headers: HeaderMap,
+    /// WebSocket connection for subscriptions, opened on first use
+    ws: OnceCell<WsClient>,
+    /// What the server supports, once the handshake succeeded
+    info: OnceCell<ServerInfo>,
+    known_heads: Mutex<HashMap<(StoreId, NodeId), Vec<String>>>,
+    session_id: String,
+    /// Identifies this client's presence to others, unlike the session id
+    presence_id: String,
+    /// Device edits to tracked documents are attributed to
+    device: Option<DeviceId>,
 }
 
 impl PimbleClient {
     /// Connect to a Pimble server
     pub async fn connect(url: impl AsRef<str>) -> Result<Self> {
+        Self::connect_with_auth(url, &AuthMethod::None).await
+    }
+
+    /// Connect to a Pimble server, authenticating every request
+    ///
+    /// API keys are sent in an `X-API-Key` header and bearer tokens in an
+    /// `Authorization` header. OAuth2 needs a token exchange that isn't
+    /// supported yet and is rejected.
+    ///
+    /// Fails if the server can't be reached or speaks an incompatible
+    /// protocol; see [`PimbleClient::handshake`].
+    pub async fn connect_with_auth(url: impl AsRef<str>, auth: &AuthMethod) -> Result<Self> {
+        let client = Self::new_with_auth(url, auth)?;
+        client.handshake().await?;
+        Ok(client)
+    }
+
+    /// Create a client without contacting the server
+    ///
+    /// For servers that may be offline. Nothing is checked until the first
+    /// request, and capabilities are unknown until
+    /// [`PimbleClient::handshake`] succeeds.
+    pub fn new_with_auth(url: impl AsRef<str>, auth: &AuthMethod) -> Result<Self> {
         let base_url: Url = url
             .as_ref()
             .parse()
             .map_err(|e| ClientError::Connection(format!("Invalid URL: {}", e)))?;
 
+        let headers = auth_headers(auth)?;
         let client = HttpClientBuilder::default()
+            .set_headers(headers.clone())
             .build(&base_url)
             .map_err(|e| ClientError::Connection(e.to_string()))?;
 
-        debug!("Connected to Pimble server at {}", base_url);
-
-        Ok(Self { client, base_url })
+        Ok(Self {
+            client,
+            base_url,
+            headers,
+            ws: OnceCell::new(),
+            info: OnceCell::new(),
+            known_heads: Mutex::new(HashMap::new()),
+            session_id: uuid::Uuid::new_v4().to_string(),
+            presence_id: uuid::Uuid::new_v4().to_string(),
+            device: None,
+        })
     }
 
     /// Get the server URL
@@ -43,6 +137,85 @@ impl PimbleClient {
         &self.base_url
     }
 
+    /// Get the session this client's edits are recorded under for undo
+    ///
+    /// Anyone holding it can undo this client's edits, so it is never
+    /// shared with other clients.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Get the id other clients see this client's presence under
+    pub fn presence_id(&self) -> &str {
+        &self.presence_id
+    }
+
+    /// Introduce the client to the server and check they can talk
+    ///
+    /// The server's answer is kept, so only the first successful call
+    /// makes a request. Servers that predate the handshake, or whose
+    /// protocol range doesn't include this client's, are refused with
+    /// [`ClientError::Incompatible`].
+    pub async fn handshake(&self) -> Result<&ServerInfo> {
+        self.info
+            .get_or_try_init(|| async {
+                let request = HelloRequest {
+                    protocol_version: PROTOCOL_VERSION,
+                    client: format!("pimble-client {}", env!("CARGO_PKG_VERSION")),
+                };
+                let info = match self.client.hello(request).await.map_err(ClientError::from) {
+                    Ok(info) => info,
+                    Err(ClientError::Server(RpcError::MethodNotFound { .. })) => {
+                        return Err(ClientError::Incompatible(format!(
+                            "{} predates protocol negotiation",
+                            self.base_url
+                        )));
+                    }
+                    Err(e) => return Err(e),
+                };
+                if !info.supports(PROTOCOL_VERSION) {
+                    return Err(ClientError::Incompatible(format!(
+                        "{} ({}) speaks protocol {} to {}, this client speaks {}",
+                        self.base_url,
+                        info.server,
+                        info.min_protocol_version,
+                        info.protocol_version,
+                        PROTOCOL_VERSION
+                    )));
+                }
+                debug!("Connected to {} at {}", info.server, self.base_url);
+                Ok(info)
+            })
+            .await
+    }
+
+    /// What the server supports, once [`PimbleClient::handshake`] succeeded
+    ///
+    /// Always known for clients made with [`PimbleClient::connect`].
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.info.get()
+    }
+
+    /// The WebSocket connection to the server, opening it if needed
+    ///
+    /// It goes to the same address as HTTP requests, with the same
+    /// credentials.
+    async fn ws(&self) -> Result<&WsClient> {
+        self.ws
+            .get_or_try_init(|| async {
+                let mut url = self.base_url.clone();
+                let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+                url.set_scheme(scheme)
+                    .map_err(|_| ClientError::Connection(format!("No WebSocket address for {}", self.base_url)))?;
+                WsClientBuilder::default()
+                    .set_headers(self.headers.clone())
+                    .build(url)
+                    .await
+                    .map_err(|e| ClientError::Connection(e.to_string()))
+            })
+            .await
+    }
+
     // ========================================================================
     // Store Operations
     // ========================================================================
@@ -58,7 +231,7 @@ impl PimbleClient {
             .client
             .create_store(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok((response.store_id, response.root_node_id))
     }
@@ -73,7 +246,28 @@ impl PimbleClient {
             .client
             .open_store(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
+
+        Ok(response.store)
+    }
+
+    /// Ask the server to open a store served by another Pimble server
+    ///
+    /// With a `replica_path`, the server mirrors the store there and keeps
+    /// working on it while the other server is unreachable.
+    pub async fn open_remote_store(
+        &self,
+        url: Url,
+        auth: Option<AuthMethod>,
+        replica_path: Option<PathBuf>,
+    ) -> Result<Store> {
+        let request = OpenRemoteStoreRequest { url, auth, replica_path };
+
+        let response = self
+            .client
+            .open_remote_store(request)
+            .await
+            .map_err(ClientError::from)?;
 
         Ok(response.store)
     }
@@ -85,7 +279,7 @@ impl PimbleClient {
         self.client
             .close_store(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(())
     }
@@ -96,11 +290,40 @@ impl PimbleClient {
             .client
             .list_stores()
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.stores)
     }
 
+    /// Upgrade every node's content in a store to current formats
+    pub async fn migrate_store(&self, store_id: StoreId) -> Result<MigrationReport> {
+        let request = MigrateStoreRequest { store_id };
+
+        let response = self
+            .client
+            .migrate_store(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.report)
+    }
+
+    /// Rewrite a store's content files in full, dropping history where asked
+    ///
+    /// With `keep_history`, for callers holding copies of the content, no
+    /// history is dropped.
+    pub async fn compact_store(&self, store_id: StoreId, keep_history: bool) -> Result<CompactionReport> {
+        let request = CompactStoreRequest { store_id, keep_history };
+
+        let response = self
+            .client
+            .compact_store(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.report)
+    }
+
     // ========================================================================
     // Node Operations
     // ========================================================================
@@ -113,7 +336,7 @@ impl PimbleClient {
             .client
             .get_node(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.node)
     }
@@ -126,11 +349,24 @@ impl PimbleClient {
             .client
             .get_nodes(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.nodes)
     }
 
+    /// Resolve a link found in `store_id` against all open stores
+    pub async fn resolve_link(&self, store_id: StoreId, target: LinkTarget) -> Result<LinkResolution> {
+        let request = ResolveLinkRequest { store_id, target };
+
+        let response = self
+            .client
+            .resolve_link(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.resolution)
+    }
+
     /// Create a new node
     pub async fn create_node(
         &self,
@@ -144,13 +380,14 @@ impl PimbleClient {
             parent_id,
             node_type: node_type.into(),
             title: title.into(),
+            session_id: Some(self.session_id.clone()),
         };
 
         let response = self
             .client
             .create_node(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.node_id)
     }
@@ -166,16 +403,122 @@ impl PimbleClient {
             store_id,
             node_id,
             metadata,
+            session_id: Some(self.session_id.clone()),
         };
 
         self.client
             .update_node_metadata(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// Change a node's title
+    pub async fn set_title(&self, store_id: StoreId, node_id: NodeId, title: impl Into<String>) -> Result<NodeMetadata> {
+        let request = SetTitleRequest {
+            store_id,
+            node_id,
+            title: title.into(),
+            session_id: Some(self.session_id.clone()),
+        };
+
+        let response = self
+            .client
+            .set_title(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.metadata)
+    }
+
+    /// Add a tag to a node
+    pub async fn add_tag(&self, store_id: StoreId, node_id: NodeId, tag: impl Into<String>) -> Result<NodeMetadata> {
+        let request = TagRequest {
+            store_id,
+            node_id,
+            tag: tag.into(),
+            session_id: Some(self.session_id.clone()),
+        };
+
+        let response = self
+            .client
+            .add_tag(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.metadata)
+    }
+
+    /// Remove a tag from a node
+    pub async fn remove_tag(&self, store_id: StoreId, node_id: NodeId, tag: impl Into<String>) -> Result<NodeMetadata> {
+        let request = TagRequest {
+            store_id,
+            node_id,
+            tag: tag.into(),
+            session_id: Some(self.session_id.clone()),
+        };
+
+        let response = self
+            .client
+            .remove_tag(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.metadata)
+    }
+
+    /// Set one of a node's custom fields, or remove it with `None`
+    pub async fn set_custom_field(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        key: impl Into<String>,
+        value: Option<serde_json::Value>,
+    ) -> Result<NodeMetadata> {
+        let request = SetCustomFieldRequest {
+            store_id,
+            node_id,
+            key: key.into(),
+            value,
+            session_id: Some(self.session_id.clone()),
+        };
+
+        let response = self
+            .client
+            .set_custom_field(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.metadata)
+    }
+
+    /// Choose how much of a node's content history to keep
+    pub async fn set_history_mode(&self, store_id: StoreId, node_id: NodeId, mode: HistoryMode) -> Result<()> {
+        let request = SetHistoryModeRequest {
+            store_id,
+            node_id,
+            mode,
+        };
+
+        self.client
+            .set_history_mode(request)
+            .await
+            .map_err(ClientError::from)?;
 
         Ok(())
     }
 
+    /// Apply a single-field metadata edit with the matching method
+    pub async fn edit_node_metadata(&self, store_id: StoreId, node_id: NodeId, edit: &MetadataEdit) -> Result<NodeMetadata> {
+        match edit.clone() {
+            MetadataEdit::SetTitle { title } => self.set_title(store_id, node_id, title).await,
+            MetadataEdit::AddTag { tag } => self.add_tag(store_id, node_id, tag).await,
+            MetadataEdit::RemoveTag { tag } => self.remove_tag(store_id, node_id, tag).await,
+            MetadataEdit::SetCustomField { key, value } => self.set_custom_field(store_id, node_id, key, value).await,
+        }
+    }
+
     /// Update a node's content with raw document bytes
     pub async fn set_node_content_bytes(
         &self,
@@ -190,16 +533,164 @@ impl PimbleClient {
             store_id,
             node_id,
             content: encoded,
+            session_id: Some(self.session_id.clone()),
         };
 
         self.client
             .update_node_content(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// Merge encoded CRDT changes into a node's content
+    ///
+    /// Returns the content's heads after the merge.
+    pub async fn apply_node_changes(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        changes: Vec<Vec<u8>>,
+    ) -> Result<Vec<String>> {
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        let request = ApplyNodeChangesRequest {
+            store_id,
+            node_id,
+            changes: changes.iter().map(|c| engine.encode(c)).collect(),
+            session_id: Some(self.session_id.clone()),
+        };
+
+        let response = self
+            .client
+            .apply_node_changes(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.heads)
+    }
+
+    /// Get the changes to a node's content that aren't ancestors of `heads`
+    ///
+    /// Returns the encoded changes and the content's current heads.
+    pub async fn get_node_changes_since(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        heads: Vec<String>,
+    ) -> Result<(Vec<Vec<u8>>, Vec<String>)> {
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        let request = GetNodeChangesSinceRequest {
+            store_id,
+            node_id,
+            heads,
+        };
+
+        let response = self
+            .client
+            .get_node_changes_since(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        let changes = response
+            .changes
+            .iter()
+            .map(|c| engine.decode(c))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| ClientError::Rpc(format!("Invalid base64: {}", e)))?;
+
+        Ok((changes, response.heads))
+    }
+
+    /// Attribute edits to the documents this client tracks to `device`
+    ///
+    /// For a client sharing a process with a server, whose own edits are
+    /// attributed to the process's local device.
+    pub fn set_device(&mut self, device: DeviceId) {
+        self.device = Some(device);
+    }
+
+    /// Fetch a node's content document and start tracking it
+    pub async fn open_document(&self, store_id: StoreId, node_id: NodeId) -> Result<CrdtDocument> {
+        let node = self.get_node(store_id, node_id).await?;
+        let mut doc = CrdtDocument::load(&node.content)?;
+        self.track_document(store_id, node_id, &mut doc);
+        Ok(doc)
+    }
+
+    /// Record that the server has everything in `doc`
+    ///
+    /// For documents loaded from a node fetched some other way. Further
+    /// edits to `doc` are made as this client's device, if it has one.
+    pub fn track_document(&self, store_id: StoreId, node_id: NodeId, doc: &mut CrdtDocument) {
+        if let Some(device) = self.device {
+            if DeviceId::of_actor(&doc.actor().to_hex_string()) != Some(device) {
+                doc.set_actor(device.new_actor());
+            }
+        }
+        let heads = doc.get_heads().iter().map(|h| h.to_string()).collect();
+        self.set_known_heads(store_id, node_id, heads);
+    }
+
+    /// Stop tracking a document
+    pub fn close_document(&self, store_id: StoreId, node_id: NodeId) {
+        self.known_heads
+            .lock()
+            .expect("known heads lock poisoned")
+            .remove(&(store_id, node_id));
+    }
 
+    /// Send the server the changes made to `doc` since it was last in step
+    ///
+    /// The server merges them with any edits of its own; use
+    /// [`PimbleClient::pull_document`] to bring those in. An untracked
+    /// document sends its whole history.
+    pub async fn push_document(&self, store_id: StoreId, node_id: NodeId, doc: &mut CrdtDocument) -> Result<()> {
+        let known = doc.known_heads(&self.get_known_heads(store_id, node_id));
+        let changes = doc.encode_changes_since(&known);
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        self.apply_node_changes(store_id, node_id, changes).await?;
+        self.track_document(store_id, node_id, doc);
         Ok(())
     }
 
+    /// Merge the server's changes since `doc` was last in step into it
+    ///
+    /// Returns whether the document changed.
+    pub async fn pull_document(&self, store_id: StoreId, node_id: NodeId, doc: &mut CrdtDocument) -> Result<bool> {
+        let known = self.get_known_heads(store_id, node_id);
+        let (changes, heads) = self.get_node_changes_since(store_id, node_id, known).await?;
+
+        let before = doc.get_heads();
+        doc.apply_encoded_changes(&changes)?;
+        self.set_known_heads(store_id, node_id, heads);
+
+        Ok(doc.get_heads() != before)
+    }
+
+    fn get_known_heads(&self, store_id: StoreId, node_id: NodeId) -> Vec<String> {
+        self.known_heads
+            .lock()
+            .expect("known heads lock poisoned")
+            .get(&(store_id, node_id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set_known_heads(&self, store_id: StoreId, node_id: NodeId, heads: Vec<String>) {
+        self.known_heads
+            .lock()
+            .expect("known heads lock poisoned")
+            .insert((store_id, node_id), heads);
+    }
+
     /// Set a node's text content (replaces all content)
     pub async fn set_node_text(
         &self,
@@ -211,24 +702,29 @@ impl PimbleClient {
             store_id,
             node_id,
             text,
+            session_id: Some(self.session_id.clone()),
         };
 
         self.client
             .set_node_text(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(())
     }
 
     /// Delete a node
     pub async fn delete_node(&self, store_id: StoreId, node_id: NodeId) -> Result<()> {
-        let request = DeleteNodeRequest { store_id, node_id };
+        let request = DeleteNodeRequest {
+            store_id,
+            node_id,
+            session_id: Some(self.session_id.clone()),
+        };
 
         self.client
             .delete_node(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(())
     }
@@ -246,12 +742,66 @@ impl PimbleClient {
             node_id,
             new_parent_id,
             position,
+            session_id: Some(self.session_id.clone()),
         };
 
         self.client
             .move_node(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// Deep-copy a subtree under a new parent, possibly in another store
+    ///
+    /// Returns the ID of the copied root.
+    pub async fn copy_node(
+        &self,
+        source_store_id: StoreId,
+        node_id: NodeId,
+        target_store_id: StoreId,
+        new_parent_id: NodeId,
+        position: Option<usize>,
+    ) -> Result<NodeId> {
+        let request = CopyNodeRequest {
+            source_store_id,
+            node_id,
+            target_store_id,
+            new_parent_id,
+            position,
+        };
+
+        let response = self
+            .client
+            .copy_node(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.node_id)
+    }
+
+    /// Move a subtree from one store to another, keeping its node IDs
+    pub async fn move_node_across_stores(
+        &self,
+        source_store_id: StoreId,
+        node_id: NodeId,
+        target_store_id: StoreId,
+        new_parent_id: NodeId,
+        position: Option<usize>,
+    ) -> Result<()> {
+        let request = MoveNodeAcrossStoresRequest {
+            source_store_id,
+            node_id,
+            target_store_id,
+            new_parent_id,
+            position,
+        };
+
+        self.client
+            .move_node_across_stores(request)
+            .await
+            .map_err(ClientError::from)?;
 
         Ok(())
     }
@@ -264,7 +814,7 @@ impl PimbleClient {
             .client
             .get_children(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.children)
     }
@@ -283,7 +833,7 @@ impl PimbleClient {
             .client
             .load_workspace(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.workspace)
     }
@@ -298,7 +848,7 @@ impl PimbleClient {
         self.client
             .save_workspace(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(())
     }
@@ -318,11 +868,558 @@ impl PimbleClient {
             .client
             .create_workspace(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.workspace)
     }
 
+    // ========================================================================
+    // Export Operations
+    // ========================================================================
+
+    /// Export a subtree (or the whole store) to a folder of markdown files
+    pub async fn export_markdown(
+        &self,
+        store_id: StoreId,
+        node_id: Option<NodeId>,
+        output_dir: impl AsRef<Path>,
+        incremental: bool,
+    ) -> Result<ExportResponse> {
+        let request = ExportMarkdownRequest {
+            store_id,
+            node_id,
+            output_dir: output_dir.as_ref().to_path_buf(),
+            incremental,
+        };
+
+        self.client
+            .export_markdown(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    /// Publish a subtree (or the whole store) as a static HTML site
+    pub async fn export_html(
+        &self,
+        store_id: StoreId,
+        node_id: Option<NodeId>,
+        output_dir: impl AsRef<Path>,
+        site_title: Option<String>,
+        incremental: bool,
+    ) -> Result<ExportResponse> {
+        let request = ExportHtmlRequest {
+            store_id,
+            node_id,
+            output_dir: output_dir.as_ref().to_path_buf(),
+            site_title,
+            incremental,
+        };
+
+        self.client
+            .export_html(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    /// Export a subtree (or the whole store) as an OPML document
+    pub async fn export_opml(&self, store_id: StoreId, node_id: Option<NodeId>) -> Result<String> {
+        let request = ExportOpmlRequest { store_id, node_id };
+
+        let response = self
+            .client
+            .export_opml(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.opml)
+    }
+
+    /// Import an OPML document under a parent node (or the store root)
+    pub async fn import_opml(
+        &self,
+        store_id: StoreId,
+        parent_id: Option<NodeId>,
+        opml: impl Into<String>,
+    ) -> Result<Vec<NodeId>> {
+        let request = ImportOpmlRequest {
+            store_id,
+            parent_id,
+            opml: opml.into(),
+        };
+
+        let response = self
+            .client
+            .import_opml(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.node_ids)
+    }
+
+    // ========================================================================
+    // Replication Operations
+    // ========================================================================
+
+    /// Get a summary of a store's nodes and deletions for replication
+    pub async fn replication_manifest(&self, store_id: StoreId) -> Result<ReplicationManifestResponse> {
+        let request = ReplicationManifestRequest { store_id };
+
+        self.client
+            .replication_manifest(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    /// Send node records and deletions to the server's copy of a store
+    pub async fn apply_node_records(
+        &self,
+        store_id: StoreId,
+        nodes: Vec<Node>,
+        tombstones: Vec<Tombstone>,
+    ) -> Result<usize> {
+        let request = ApplyNodeRecordsRequest {
+            store_id,
+            nodes,
+            tombstones,
+        };
+
+        let response = self
+            .client
+            .apply_node_records(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.applied)
+    }
+
+    /// Send one content sync message and return the server's reply
+    pub async fn sync_node_content(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        peer_id: impl Into<String>,
+        message: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        let request = SyncNodeContentRequest {
+            store_id,
+            node_id,
+            peer_id: peer_id.into(),
+            message: message.map(|m| engine.encode(m)),
+        };
+
+        let response = self
+            .client
+            .sync_node_content(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        response
+            .message
+            .map(|m| engine.decode(m))
+            .transpose()
+            .map_err(|e| ClientError::Rpc(format!("Invalid base64: {}", e)))
+    }
+
+    /// Merge a tree structure into the server's copy of a store's tree
+    ///
+    /// Pass `None` to fetch the server's tree without changing it. Returns
+    /// the merged tree.
+    pub async fn merge_store_tree(&self, store_id: StoreId, tree: Option<&[u8]>) -> Result<Vec<u8>> {
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+
+        let request = MergeStoreTreeRequest {
+            store_id,
+            tree: tree.map(|t| engine.encode(t)),
+        };
+
+        let response = self
+            .client
+            .merge_store_tree(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        engine
+            .decode(response.tree)
+            .map_err(|e| ClientError::Rpc(format!("Invalid base64: {}", e)))
+    }
+
+    /// Ask the server to replicate a store with a peer once
+    pub async fn replicate_store(
+        &self,
+        store_id: StoreId,
+        peer_url: impl Into<String>,
+    ) -> Result<ReplicateStoreResponse> {
+        let request = ReplicateStoreRequest {
+            store_id,
+            peer_url: peer_url.into(),
+        };
+
+        self.client
+            .replicate_store(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    /// Ask the server to create a local replica of a store held by a peer
+    pub async fn clone_store(
+        &self,
+        peer_url: impl Into<String>,
+        store_id: StoreId,
+        path: impl AsRef<Path>,
+    ) -> Result<Store> {
+        let request = CloneStoreRequest {
+            peer_url: peer_url.into(),
+            store_id,
+            path: path.as_ref().to_path_buf(),
+        };
+
+        let response = self
+            .client
+            .clone_store(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.store)
+    }
+
+    /// Keep a store replicated with a peer in the background
+    pub async fn pair_store(
+        &self,
+        store_id: StoreId,
+        peer_url: impl Into<String>,
+        interval_secs: Option<u64>,
+    ) -> Result<()> {
+        let request = PairStoreRequest {
+            store_id,
+            peer_url: peer_url.into(),
+            interval_secs,
+        };
+
+        self.client
+            .pair_store(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// Stop background replication for a store
+    pub async fn unpair_store(&self, store_id: StoreId) -> Result<()> {
+        let request = UnpairStoreRequest { store_id };
+
+        self.client
+            .unpair_store(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    // ========================================================================
+    // Conflict Operations
+    // ========================================================================
+
+    /// List a store's unreconciled concurrent edits
+    pub async fn list_conflicts(&self, store_id: StoreId) -> Result<Vec<ConflictInfo>> {
+        let request = ListConflictsRequest { store_id };
+
+        let response = self
+            .client
+            .list_conflicts(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.conflicts)
+    }
+
+    /// Settle a conflicting key on a picked or merged value
+    ///
+    /// Returns the store's remaining conflicts.
+    pub async fn resolve_conflict(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        key: impl Into<String>,
+        value: serde_json::Value,
+    ) -> Result<Vec<ConflictInfo>> {
+        let request = ResolveConflictRequest {
+            store_id,
+            node_id,
+            key: key.into(),
+            value,
+        };
+
+        let response = self
+            .client
+            .resolve_conflict(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.conflicts)
+    }
+
+    // ========================================================================
+    // History Operations
+    // ========================================================================
+
+    /// List the changes to a node's content, oldest first
+    pub async fn get_node_history(&self, store_id: StoreId, node_id: NodeId) -> Result<Vec<HistoryEntry>> {
+        let request = GetNodeHistoryRequest { store_id, node_id };
+
+        let response = self
+            .client
+            .get_node_history(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.changes)
+    }
+
+    /// Get a node as it was at `heads`, along with its text at that version
+    pub async fn get_node_at_version(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        heads: Vec<String>,
+    ) -> Result<(Node, String)> {
+        let request = NodeVersionRequest { store_id, node_id, heads };
+
+        let response = self
+            .client
+            .get_node_at_version(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok((response.node, response.text))
+    }
+
+    /// Restore a node's content to its state at `heads`
+    ///
+    /// Returns the node after the restore.
+    pub async fn restore_node_version(&self, store_id: StoreId, node_id: NodeId, heads: Vec<String>) -> Result<Node> {
+        let request = NodeVersionRequest { store_id, node_id, heads };
+
+        let response = self
+            .client
+            .restore_node_version(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.node)
+    }
+
+    /// Diff a node's text between the versions at `from` and `to`
+    ///
+    /// `to` defaults to the current version.
+    pub async fn diff_node_versions(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        from: Vec<String>,
+        to: Option<Vec<String>>,
+    ) -> Result<NodeDiff> {
+        let request = DiffNodeVersionsRequest { store_id, node_id, from, to };
+
+        let response = self
+            .client
+            .diff_node_versions(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.diff)
+    }
+
+    // ========================================================================
+    // Identity Operations
+    // ========================================================================
+
+    /// Register a display name for a device writing to a store, or remove it
+    pub async fn set_identity(&self, store_id: StoreId, device_id: String, name: Option<String>) -> Result<()> {
+        let request = SetIdentityRequest {
+            store_id,
+            device_id,
+            name,
+        };
+
+        self.client
+            .set_identity(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// List the devices named in a store
+    pub async fn list_identities(&self, store_id: StoreId) -> Result<Vec<Identity>> {
+        let request = ListIdentitiesRequest { store_id };
+
+        let response = self
+            .client
+            .list_identities(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.identities)
+    }
+
+    // ========================================================================
+    // Change Subscriptions
+    // ========================================================================
+
+    /// Receive every change to a node from now on
+    ///
+    /// Opens a WebSocket connection to the server on first use.
+    pub async fn subscribe_node(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+    ) -> Result<Subscription<NodeChangedNotification>> {
+        let request = SubscribeNodeRequest { store_id, node_id };
+
+        self.ws()
+            .await?
+            .subscribe_node(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    /// Receive every change to the nodes of a store from now on
+    ///
+    /// If the client falls too far behind, the subscription ends with an
+    /// error; reload what's cached and subscribe again.
+    pub async fn subscribe_store(&self, store_id: StoreId) -> Result<Subscription<NodeChangedNotification>> {
+        let request = SubscribeStoreRequest { store_id };
+
+        self.ws()
+            .await?
+            .subscribe_store(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    // ========================================================================
+    // Presence Operations
+    // ========================================================================
+
+    /// Tell others this client is on a node, and where its cursor is
+    ///
+    /// `anchor` and `head` are anchors from `DocumentContent::anchor_at`:
+    /// the cursor and, for a selection, its other end. Presence expires
+    /// unless published again within the server's presence TTL.
+    pub async fn publish_presence(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        name: impl Into<String>,
+        anchor: Option<String>,
+        head: Option<String>,
+    ) -> Result<()> {
+        let request = PublishPresenceRequest {
+            store_id,
+            node_id,
+            client_id: self.presence_id.clone(),
+            name: name.into(),
+            anchor,
+            head,
+        };
+
+        self.client
+            .publish_presence(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// Withdraw this client's presence from a node
+    pub async fn leave_presence(&self, store_id: StoreId, node_id: NodeId) -> Result<()> {
+        let request = LeavePresenceRequest {
+            store_id,
+            node_id,
+            client_id: self.presence_id.clone(),
+        };
+
+        self.client
+            .leave_presence(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
+    /// List the clients present on a node, this one included
+    pub async fn get_presence(&self, store_id: StoreId, node_id: NodeId) -> Result<Vec<Presence>> {
+        let request = GetPresenceRequest { store_id, node_id };
+
+        let response = self
+            .client
+            .get_presence(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(response.present)
+    }
+
+    /// Receive everyone present on a node, now and whenever it changes
+    ///
+    /// Opens a WebSocket connection to the server on first use.
+    pub async fn subscribe_presence(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+    ) -> Result<Subscription<PresenceNotification>> {
+        let request = SubscribePresenceRequest { store_id, node_id };
+
+        self.ws()
+            .await?
+            .subscribe_presence(request)
+            .await
+            .map_err(ClientError::from)
+    }
+
+    // ========================================================================
+    // Undo Operations
+    // ========================================================================
+
+    /// Undo the latest edit made through this client
+    pub async fn undo(&self) -> Result<UndoResponse> {
+        let request = UndoRequest {
+            session_id: self.session_id.clone(),
+        };
+
+        self.client.undo(request).await.map_err(ClientError::from)
+    }
+
+    /// Redo the latest edit undone through this client
+    pub async fn redo(&self) -> Result<UndoResponse> {
+        let request = UndoRequest {
+            session_id: self.session_id.clone(),
+        };
+
+        self.client.redo(request).await.map_err(ClientError::from)
+    }
+
+    /// Tell the server to forget this client's undo history
+    ///
+    /// For when the client is done; edits made through it afterwards start
+    /// a new history.
+    pub async fn close_session(&self) -> Result<()> {
+        let request = CloseSessionRequest {
+            session_id: self.session_id.clone(),
+        };
+
+        self.client
+            .close_session(request)
+            .await
+            .map_err(ClientError::from)?;
+
+        Ok(())
+    }
+
     // ========================================================================
     // Search Operations
     // ========================================================================
@@ -346,7 +1443,7 @@ impl PimbleClient {
             .client
             .search(request)
             .await
-            .map_err(|e| ClientError::Rpc(e.to_string()))?;
+            .map_err(ClientError::from)?;
 
         Ok(response.results)
     }
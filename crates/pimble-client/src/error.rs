@@ -1,5 +1,7 @@
 //! Error types for pimble-client
 
+use pimble_core::{NodeId, StoreId};
+use pimble_rpc::RpcError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -7,17 +9,102 @@ pub enum ClientError {
     #[error("Connection error: {0}")]
     Connection(String),
 
+    /// A response the client couldn't make sense of
     #[error("RPC error: {0}")]
     Rpc(String),
 
+    #[error("Node not found: {0}")]
+    NodeNotFound(NodeId),
+
+    #[error("Store not found: {0}")]
+    StoreNotFound(StoreId),
+
+    #[error("Store not open: {0}")]
+    StoreNotOpen(StoreId),
+
+    #[error("Invalid params: {0}")]
+    InvalidParams(String),
+
+    #[error("Invalid operation: {0}")]
+    InvalidOperation(String),
+
+    /// Any other error the server returned
+    #[error(transparent)]
+    Server(RpcError),
+
     #[error("Not connected")]
     NotConnected,
 
+    /// The server speaks a protocol version this client can't
+    #[error("Incompatible server: {0}")]
+    Incompatible(String),
+
     #[error("Timeout")]
     Timeout,
 
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
+
+    #[error("CRDT error: {0}")]
+    Crdt(#[from] pimble_crdt::CrdtError),
+}
+
+impl ClientError {
+    /// Check whether the error means the server couldn't be reached
+    ///
+    /// Errors returned by the server itself (an unknown node, say) are not
+    /// connection errors.
+    pub fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Connection(_) | ClientError::NotConnected | ClientError::Timeout
+        )
+    }
+}
+
+impl From<jsonrpsee::core::ClientError> for ClientError {
+    fn from(e: jsonrpsee::core::ClientError) -> Self {
+        use jsonrpsee::core::ClientError as RpcClientError;
+        match e {
+            RpcClientError::Call(error) => RpcError::from_error_object(&error).into(),
+            RpcClientError::ParseError(_) => ClientError::Rpc(e.to_string()),
+            RpcClientError::RequestTimeout => ClientError::Timeout,
+            _ => ClientError::Connection(e.to_string()),
+        }
+    }
+}
+
+impl From<RpcError> for ClientError {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::NodeNotFound { node_id } => ClientError::NodeNotFound(node_id),
+            RpcError::StoreNotFound { store_id } => ClientError::StoreNotFound(store_id),
+            RpcError::StoreNotOpen { store_id } => ClientError::StoreNotOpen(store_id),
+            RpcError::InvalidParams { message } => ClientError::InvalidParams(message),
+            RpcError::InvalidOperation { message } => ClientError::InvalidOperation(message),
+            other => ClientError::Server(other),
+        }
+    }
+}
+
+/// The error to pass on when a server relays a failed call to a remote store
+impl From<ClientError> for RpcError {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::NodeNotFound(node_id) => RpcError::NodeNotFound { node_id },
+            ClientError::StoreNotFound(store_id) => RpcError::StoreNotFound { store_id },
+            ClientError::StoreNotOpen(store_id) => RpcError::StoreNotOpen { store_id },
+            ClientError::InvalidParams(message) => RpcError::InvalidParams { message },
+            ClientError::InvalidOperation(message) => RpcError::InvalidOperation { message },
+            ClientError::Server(e) => e,
+            e if e.is_connection_error() => RpcError::RemoteUnavailable {
+                message: e.to_string(),
+            },
+            e => RpcError::Internal {
+                message: e.to_string(),
+            },
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ClientError>;
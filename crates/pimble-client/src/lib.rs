@@ -2,6 +2,7 @@
 //!
 //! This crate provides:
 //! - JSON-RPC client for communicating with servers
+//! - Change and presence subscriptions over WebSocket
 //! - High-level API for store and node operations
 //! - Connection management
 
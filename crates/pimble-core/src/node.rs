@@ -8,6 +8,8 @@ use serde::{Deserialize, Serialize};
 use url::Url;
 use uuid::Uuid;
 
+use crate::StoreId;
+
 /// Unique identifier for a node
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct NodeId(pub Uuid);
@@ -80,6 +82,10 @@ pub struct Node {
 
     /// Links from this node to other nodes
     pub links: Vec<NodeLink>,
+
+    /// How much of the content's history is kept
+    #[serde(default, skip_serializing_if = "HistoryMode::is_full")]
+    pub history: HistoryMode,
 }
 
 impl Node {
@@ -100,6 +106,7 @@ impl Node {
             content: Vec::new(),
             children: Vec::new(),
             links: Vec::new(),
+            history: HistoryMode::Full,
         }
     }
 
@@ -154,6 +161,29 @@ impl Node {
     }
 }
 
+/// How much of a node's content history is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryMode {
+    /// Every change, so earlier versions can be browsed and restored
+    #[default]
+    Full,
+
+    /// Only the current state; history is dropped when the store is
+    /// compacted
+    ///
+    /// Dropping history starts the content over as a new document, so
+    /// replicas still holding the old history can't merge with it.
+    Truncate,
+}
+
+impl HistoryMode {
+    /// Whether every change is kept
+    pub fn is_full(&self) -> bool {
+        *self == Self::Full
+    }
+}
+
 /// Metadata associated with a node
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeMetadata {
@@ -173,6 +203,55 @@ pub struct NodeMetadata {
     pub custom: HashMap<String, serde_json::Value>,
 }
 
+/// Format of a node's content and the schema version it's written in
+///
+/// Recorded in the content itself, so readers don't have to guess what
+/// kind of bytes they're holding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ContentFormat {
+    /// Format identifier, such as "document"
+    pub id: String,
+
+    /// Schema version within the format
+    pub version: u32,
+}
+
+impl ContentFormat {
+    /// Create a content format
+    pub fn new(id: impl Into<String>, version: u32) -> Self {
+        Self { id: id.into(), version }
+    }
+}
+
+impl fmt::Display for ContentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} v{}", self.id, self.version)
+    }
+}
+
+/// A change to one field of a node's metadata
+///
+/// Edits touch a single field, so edits made concurrently on different
+/// devices merge instead of overwriting each other.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "edit", rename_all = "snake_case")]
+pub enum MetadataEdit {
+    /// Change the title
+    SetTitle { title: String },
+
+    /// Add a tag (tags are a set)
+    AddTag { tag: String },
+
+    /// Remove a tag
+    RemoveTag { tag: String },
+
+    /// Set a custom field, or remove it with `None`
+    SetCustomField {
+        key: String,
+        value: Option<serde_json::Value>,
+    },
+}
+
 /// A link from one node to another
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeLink {
@@ -217,6 +296,19 @@ impl NodeLink {
         }
     }
 
+    /// Create a reference link to a node in another store
+    pub fn cross_store(store_id: StoreId, target_id: NodeId) -> Self {
+        Self {
+            target: LinkTarget::CrossStore {
+                store_id,
+                node_id: target_id,
+                anchor: None,
+            },
+            link_type: "reference".to_string(),
+            source_anchor: None,
+        }
+    }
+
     /// Create an external link
     pub fn external(url: Url) -> Self {
         Self {
@@ -228,8 +320,12 @@ impl NodeLink {
 }
 
 /// Target of a link
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
+///
+/// Serialised with a `type` tag. Links without a `store_id` refer to a
+/// node in the same store as the link's source; links written before
+/// cross-store links existed deserialise that way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "LinkTargetRepr", into = "LinkTargetRepr")]
 pub enum LinkTarget {
     /// Link to another node
     Node(NodeId),
@@ -241,19 +337,227 @@ pub enum LinkTarget {
         anchor: String,
     },
 
+    /// Link to a node in another store, optionally deep
+    CrossStore {
+        store_id: StoreId,
+        node_id: NodeId,
+        anchor: Option<String>,
+    },
+
     /// Link to an external URL
     External(Url),
 }
 
+/// Wire format for [`LinkTarget`]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LinkTargetRepr {
+    Node {
+        node_id: NodeId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        store_id: Option<StoreId>,
+    },
+    Deep {
+        node_id: NodeId,
+        anchor: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        store_id: Option<StoreId>,
+    },
+    External {
+        url: Url,
+    },
+}
+
+impl From<LinkTargetRepr> for LinkTarget {
+    fn from(repr: LinkTargetRepr) -> Self {
+        match repr {
+            LinkTargetRepr::Node { node_id, store_id: None } => LinkTarget::Node(node_id),
+            LinkTargetRepr::Node { node_id, store_id: Some(store_id) } => LinkTarget::CrossStore {
+                store_id,
+                node_id,
+                anchor: None,
+            },
+            LinkTargetRepr::Deep { node_id, anchor, store_id: None } => {
+                LinkTarget::Deep { node_id, anchor }
+            }
+            LinkTargetRepr::Deep { node_id, anchor, store_id: Some(store_id) } => {
+                LinkTarget::CrossStore {
+                    store_id,
+                    node_id,
+                    anchor: Some(anchor),
+                }
+            }
+            LinkTargetRepr::External { url } => LinkTarget::External(url),
+        }
+    }
+}
+
+impl From<LinkTarget> for LinkTargetRepr {
+    fn from(target: LinkTarget) -> Self {
+        match target {
+            LinkTarget::Node(node_id) => LinkTargetRepr::Node { node_id, store_id: None },
+            LinkTarget::Deep { node_id, anchor } => LinkTargetRepr::Deep {
+                node_id,
+                anchor,
+                store_id: None,
+            },
+            LinkTarget::CrossStore { store_id, node_id, anchor: None } => LinkTargetRepr::Node {
+                node_id,
+                store_id: Some(store_id),
+            },
+            LinkTarget::CrossStore { store_id, node_id, anchor: Some(anchor) } => {
+                LinkTargetRepr::Deep {
+                    node_id,
+                    anchor,
+                    store_id: Some(store_id),
+                }
+            }
+            LinkTarget::External(url) => LinkTargetRepr::External { url },
+        }
+    }
+}
+
 impl LinkTarget {
+    /// URI scheme used for internal links embedded in node content
+    pub const URI_SCHEME: &'static str = "pimble";
+
     /// Get the target node ID if this is an internal link
     pub fn node_id(&self) -> Option<NodeId> {
         match self {
             LinkTarget::Node(id) => Some(*id),
             LinkTarget::Deep { node_id, .. } => Some(*node_id),
+            LinkTarget::CrossStore { node_id, .. } => Some(*node_id),
             LinkTarget::External(_) => None,
         }
     }
+
+    /// Get the target store ID if this link is store-qualified
+    pub fn store_id(&self) -> Option<StoreId> {
+        match self {
+            LinkTarget::CrossStore { store_id, .. } => Some(*store_id),
+            _ => None,
+        }
+    }
+
+    /// Get the anchor if this is a deep link
+    pub fn anchor(&self) -> Option<&str> {
+        match self {
+            LinkTarget::Deep { anchor, .. } => Some(anchor),
+            LinkTarget::CrossStore { anchor, .. } => anchor.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Qualify this target with a store, keeping node and anchor
+    ///
+    /// External links are returned unchanged.
+    pub fn in_store(&self, store_id: StoreId) -> Self {
+        match self.node_id() {
+            Some(node_id) => LinkTarget::CrossStore {
+                store_id,
+                node_id,
+                anchor: self.anchor().map(str::to_string),
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Drop the store qualification, keeping node and anchor
+    ///
+    /// Used when a cross-store link's target ends up in the link's own store.
+    pub fn local(&self) -> Self {
+        match self {
+            LinkTarget::CrossStore { node_id, anchor: None, .. } => LinkTarget::Node(*node_id),
+            LinkTarget::CrossStore { node_id, anchor: Some(anchor), .. } => LinkTarget::Deep {
+                node_id: *node_id,
+                anchor: anchor.clone(),
+            },
+            other => other.clone(),
+        }
+    }
+
+    /// Render this target as a URI suitable for embedding in content
+    ///
+    /// Internal links use `pimble://{node-id}`, or `pimble://{store-id}/{node-id}`
+    /// when store-qualified, with the deep-link anchor as the fragment;
+    /// external links render as their URL.
+    pub fn to_uri(&self) -> String {
+        match self {
+            LinkTarget::Node(id) => format!("{}://{}", Self::URI_SCHEME, id),
+            LinkTarget::Deep { node_id, anchor } => {
+                format!("{}://{}#{}", Self::URI_SCHEME, node_id, anchor)
+            }
+            LinkTarget::CrossStore { store_id, node_id, anchor } => {
+                let mut uri = format!("{}://{}/{}", Self::URI_SCHEME, store_id, node_id);
+                if let Some(anchor) = anchor {
+                    uri.push('#');
+                    uri.push_str(anchor);
+                }
+                uri
+            }
+            LinkTarget::External(url) => url.to_string(),
+        }
+    }
+
+    /// Parse a link URI as produced by [`LinkTarget::to_uri`]
+    ///
+    /// Returns `None` for relative paths and anything that isn't a valid
+    /// `pimble://` or absolute URL.
+    pub fn parse_uri(uri: &str) -> Option<Self> {
+        let prefix = format!("{}://", Self::URI_SCHEME);
+        if let Some(rest) = uri.strip_prefix(&prefix) {
+            let (path, anchor) = match rest.split_once('#') {
+                Some((path, anchor)) => (path, Some(anchor).filter(|a| !a.is_empty())),
+                None => (rest, None),
+            };
+            let path = path.trim_end_matches('/');
+            if let Some((store, node)) = path.split_once('/') {
+                return Some(LinkTarget::CrossStore {
+                    store_id: StoreId::parse(store).ok()?,
+                    node_id: NodeId::parse(node).ok()?,
+                    anchor: anchor.map(str::to_string),
+                });
+            }
+            let node_id = NodeId::parse(path).ok()?;
+            return Some(match anchor {
+                Some(anchor) => LinkTarget::Deep {
+                    node_id,
+                    anchor: anchor.to_string(),
+                },
+                None => LinkTarget::Node(node_id),
+            });
+        }
+        Url::parse(uri).ok().map(LinkTarget::External)
+    }
+}
+
+/// Outcome of resolving a link target against the open stores
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LinkResolution {
+    /// The target node was found
+    Resolved {
+        store_id: StoreId,
+        node: Box<Node>,
+        /// Anchor within the node, for deep links
+        anchor: Option<String>,
+    },
+
+    /// The target lives in a store that isn't currently open
+    Unavailable {
+        store_id: StoreId,
+        node_id: NodeId,
+    },
+
+    /// No open store contains the target node
+    Missing {
+        node_id: NodeId,
+    },
+
+    /// An external URL; nothing to resolve
+    External {
+        url: Url,
+    },
 }
 
 /// Well-known node types
@@ -299,3 +603,33 @@ mod serde_bytes_base64 {
             .map_err(|e| Error::custom(format!("base64 decode error: {}", e)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_target_serde() {
+        let node_id = NodeId::new();
+        let store_id = StoreId::new();
+        let targets = [
+            LinkTarget::Node(node_id),
+            LinkTarget::Deep { node_id, anchor: "heading:Intro".into() },
+            LinkTarget::CrossStore { store_id, node_id, anchor: None },
+            LinkTarget::CrossStore { store_id, node_id, anchor: Some("paragraph:2".into()) },
+            LinkTarget::External(Url::parse("https://example.com/").unwrap()),
+        ];
+        for target in targets {
+            let json = serde_json::to_string(&target).unwrap();
+            assert_eq!(serde_json::from_str::<LinkTarget>(&json).unwrap(), target);
+            assert_eq!(LinkTarget::parse_uri(&target.to_uri()), Some(target));
+        }
+
+        // Deep links stored before store qualification existed
+        let old = format!(r#"{{"type":"deep","node_id":"{}","anchor":"text:1-5"}}"#, node_id);
+        assert_eq!(
+            serde_json::from_str::<LinkTarget>(&old).unwrap(),
+            LinkTarget::Deep { node_id, anchor: "text:1-5".into() }
+        );
+    }
+}
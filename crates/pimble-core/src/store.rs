@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use url::Url;
 use uuid::Uuid;
 
-use crate::NodeId;
+use crate::{ContentFormat, NodeId};
 
 /// Unique identifier for a store
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -169,6 +169,22 @@ pub enum AuthMethod {
     },
 }
 
+impl AuthMethod {
+    /// The same method with its secrets blanked, for describing a store
+    /// to clients
+    pub fn redacted(&self) -> Self {
+        match self {
+            AuthMethod::None => AuthMethod::None,
+            AuthMethod::ApiKey { .. } => AuthMethod::ApiKey { key: String::new() },
+            AuthMethod::Bearer { .. } => AuthMethod::Bearer { token: String::new() },
+            AuthMethod::OAuth2 { client_id, .. } => AuthMethod::OAuth2 {
+                client_id: client_id.clone(),
+                refresh_token: String::new(),
+            },
+        }
+    }
+}
+
 /// Synchronization state of a store
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "state", rename_all = "snake_case")]
@@ -185,6 +201,14 @@ pub enum SyncState {
         last_sync: DateTime<Utc>,
     },
 
+    /// Remote unreachable; working from a local replica
+    Disconnected {
+        /// Local changes waiting to be sent
+        queued: usize,
+        /// When the last sync completed, if ever
+        last_sync: Option<DateTime<Utc>>,
+    },
+
     /// Has unresolved conflicts
     Conflict {
         /// Details about each conflict
@@ -202,6 +226,23 @@ impl SyncState {
     pub fn has_conflicts(&self) -> bool {
         matches!(self, SyncState::Conflict { .. })
     }
+
+    /// Number of local changes waiting to be sent
+    pub fn queued_changes(&self) -> usize {
+        match self {
+            SyncState::Disconnected { queued, .. } => *queued,
+            _ => 0,
+        }
+    }
+
+    /// When the last sync completed, if known
+    pub fn last_sync(&self) -> Option<DateTime<Utc>> {
+        match self {
+            SyncState::Synced { last_sync } => Some(*last_sync),
+            SyncState::Disconnected { last_sync, .. } => *last_sync,
+            _ => None,
+        }
+    }
 }
 
 /// Information about a sync conflict
@@ -213,8 +254,212 @@ pub struct ConflictInfo {
     /// Description of the conflict
     pub description: String,
 
-    /// When the conflict was detected
-    pub detected_at: DateTime<Utc>,
+    /// When the latest of the concurrent values was written, if known
+    #[serde(default)]
+    pub detected_at: Option<DateTime<Utc>>,
+
+    /// Key in the node's document holding the concurrent values
+    #[serde(default)]
+    pub key: String,
+
+    /// The concurrent values, for picking one or merging them
+    #[serde(default)]
+    pub values: Vec<serde_json::Value>,
+}
+
+/// Version summary of a node, exchanged when replicating a store
+///
+/// Peers compare summaries to decide which node records and which CRDT
+/// contents need to travel, without sending the nodes themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeVersion {
+    /// The node being summarised
+    pub node_id: NodeId,
+
+    /// When the node record was last modified
+    pub modified_at: DateTime<Utc>,
+
+    /// Heads of the node's CRDT content, as hex change hashes
+    pub heads: Vec<String>,
+}
+
+/// One change in the history of a node's CRDT content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Hash of the change, in hex; also names the version it produced
+    pub hash: String,
+
+    /// Automerge actor that made the change, in hex
+    pub actor: String,
+
+    /// When the change was made, if it was recorded
+    pub timestamp: Option<DateTime<Utc>>,
+
+    /// Message attached to the change, if any
+    pub message: Option<String>,
+
+    /// Display name registered for the device that made the change
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
+/// Whether a diff span adds or removes text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    Insert,
+    Delete,
+}
+
+/// A run of text inserted or deleted between two versions of a node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffSpan {
+    /// Whether the text was inserted or deleted
+    pub kind: DiffKind,
+
+    /// Character offset of the span: in the new text for inserts, in the
+    /// old text for deletes
+    pub offset: usize,
+
+    /// The inserted or deleted text
+    pub text: String,
+
+    /// Automerge actors whose changes made this edit, in hex
+    ///
+    /// Empty when the versions aren't on one line of history, so the edit
+    /// can't be traced to particular changes.
+    pub actors: Vec<String>,
+}
+
+/// The differences between two versions of a node's text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeDiff {
+    /// Inserted and deleted spans, in text order
+    pub spans: Vec<DiffSpan>,
+
+    /// Line-based unified diff of the two texts
+    pub unified: String,
+}
+
+/// A node whose content was upgraded to a newer format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeMigration {
+    /// The upgraded node
+    pub node_id: NodeId,
+
+    /// Format the content was in
+    pub from: ContentFormat,
+
+    /// Format the content is in now
+    pub to: ContentFormat,
+}
+
+/// Outcome of upgrading every node in a store to current content formats
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    /// Nodes whose content was upgraded
+    pub migrated: Vec<NodeMigration>,
+
+    /// Number of nodes already in a current format
+    pub current: usize,
+
+    /// Nodes whose content is in no format the store recognises, left as
+    /// they were
+    pub unrecognized: Vec<NodeId>,
+}
+
+/// Outcome of compacting the content files of a store
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionReport {
+    /// Number of content files rewritten
+    pub compacted: usize,
+
+    /// Nodes whose history was dropped, as their history mode asks
+    pub truncated: Vec<NodeId>,
+
+    /// Nodes whose history mode asks to drop it, but whose history was kept
+    /// because copies of the store elsewhere still build on it
+    #[serde(default)]
+    pub kept: Vec<NodeId>,
+
+    /// Total size of the content files before, in bytes
+    pub bytes_before: u64,
+
+    /// Total size of the content files after, in bytes
+    pub bytes_after: u64,
+}
+
+/// How a node changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeType {
+    Created,
+    Updated,
+    Deleted,
+    Moved,
+}
+
+/// A change to one node in a store, as announced to subscribers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeChange {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub change_type: ChangeType,
+
+    /// The node's parent, or for a deleted node the parent it had
+    #[serde(default)]
+    pub parent_id: Option<NodeId>,
+
+    /// The parent a moved node left; the same as `parent_id` for a reorder
+    #[serde(default)]
+    pub old_parent_id: Option<NodeId>,
+}
+
+/// The kind of edit an undo or redo reversed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EditKind {
+    /// A change to a node's content or metadata
+    Content,
+    /// Creating a node
+    Create,
+    /// Deleting a node
+    Delete,
+    /// Moving a node within its store
+    Move,
+}
+
+/// An edit reversed by an undo or redo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UndoneEdit {
+    /// Store holding the node
+    pub store_id: StoreId,
+
+    /// The node the edit was made to
+    pub node_id: NodeId,
+
+    /// What kind of edit was reversed
+    pub kind: EditKind,
+}
+
+/// A display name registered for a device writing to a store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    /// The device's id, in hex
+    pub device_id: String,
+
+    /// Name shown for the device's changes
+    pub name: String,
+}
+
+/// Record of a deleted node, kept so deletions replicate to peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    /// The deleted node
+    pub node_id: NodeId,
+
+    /// When the node was deleted
+    pub deleted_at: DateTime<Utc>,
 }
 
 /// Store manifest - metadata stored in manifest.json
@@ -0,0 +1,150 @@
+//! Device identities for Automerge actors
+//!
+//! Every change in an Automerge document is stamped with the actor that
+//! made it. A random actor per document says nothing about who that was,
+//! so documents created or loaded here use actors that start with the
+//! device's persistent id. History can then be traced back to a device.
+//!
+//! The actor still ends in a few random bytes. Two documents loaded from
+//! the same bytes must never share an actor: Automerge numbers each
+//! actor's changes in sequence, and two copies both making "change 5"
+//! can't be merged. The suffix keeps the client and server in one
+//! process, or two windows on one device, apart.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+
+pub use automerge::ActorId;
+
+/// Length of a device id in bytes
+const DEVICE_ID_LEN: usize = 16;
+
+/// Random bytes after the device id in each actor
+const ACTOR_SUFFIX_LEN: usize = 4;
+
+/// The device documents in this process are attributed to
+static LOCAL_DEVICE: RwLock<Option<DeviceId>> = RwLock::new(None);
+
+/// Persistent identity of one device, shown as 32 hex digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId([u8; DEVICE_ID_LEN]);
+
+impl DeviceId {
+    /// Generate a new random device id
+    pub fn random() -> Self {
+        let mut bytes = [0; DEVICE_ID_LEN];
+        bytes.copy_from_slice(&ActorId::random().to_bytes()[..DEVICE_ID_LEN]);
+        Self(bytes)
+    }
+
+    /// Parse a device id from its hex form
+    pub fn parse(hex: &str) -> Option<Self> {
+        let bytes = decode_hex(hex)?;
+        Some(Self(bytes.try_into().ok()?))
+    }
+
+    /// Read the device id stored at `path`, creating one if there is none
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(contents.trim())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid device id")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let device = Self::random();
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, device.to_string())?;
+                Ok(device)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The device that made changes as `actor`, given in hex
+    ///
+    /// Returns `None` for actors that don't carry a device id, such as
+    /// those of documents created before device ids existed.
+    pub fn of_actor(actor: &str) -> Option<Self> {
+        let bytes = decode_hex(actor)?;
+        if bytes.len() != DEVICE_ID_LEN + ACTOR_SUFFIX_LEN {
+            return None;
+        }
+        Self::parse(&actor[..DEVICE_ID_LEN * 2])
+    }
+
+    /// A fresh actor for one document on this device
+    pub fn new_actor(&self) -> ActorId {
+        let mut bytes = self.0.to_vec();
+        bytes.extend_from_slice(&ActorId::random().to_bytes()[..ACTOR_SUFFIX_LEN]);
+        ActorId::from(bytes)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Attribute documents created or loaded from now on to `device`
+pub fn set_local_device(device: DeviceId) {
+    *LOCAL_DEVICE.write().unwrap() = Some(device);
+}
+
+/// The device set with [`set_local_device`], if any
+pub fn local_device() -> Option<DeviceId> {
+    *LOCAL_DEVICE.read().unwrap()
+}
+
+/// Actor for a new document instance: the local device's, else random
+pub(crate) fn new_local_actor() -> ActorId {
+    match local_device() {
+        Some(device) => device.new_actor(),
+        None => ActorId::random(),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actor_carries_device() {
+        let device = DeviceId::random();
+        let first = device.new_actor();
+        let second = device.new_actor();
+
+        assert_ne!(first, second);
+        assert_eq!(DeviceId::of_actor(&first.to_hex_string()), Some(device));
+        assert_eq!(DeviceId::parse(&device.to_string()), Some(device));
+        assert_eq!(DeviceId::of_actor(&ActorId::random().to_hex_string()), None);
+    }
+
+    #[test]
+    fn test_load_or_create() {
+        let path = std::env::temp_dir()
+            .join(format!("pimble-device-{}", DeviceId::random()))
+            .join("device_id");
+
+        let created = DeviceId::load_or_create(&path).unwrap();
+        assert_eq!(DeviceId::load_or_create(&path).unwrap(), created);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! Incremental saves and compaction
+//!
+//! A saved Automerge document can be followed by chunks of changes made
+//! since, and loading the lot gives back the whole document. Appending
+//! those chunks is much cheaper than saving a long history in full, so
+//! saves append and a full save every so often compacts the file again.
+//!
+//! Where old history isn't wanted, a snapshot keeps only the current
+//! state. It is a new document as far as Automerge is concerned, so
+//! changes made to copies that still have the old history can't be merged
+//! into it.
+
+use automerge::iter::Span;
+use automerge::marks::{ExpandMark, Mark};
+use automerge::transaction::Transactable;
+use automerge::{hydrate, AutoCommit, ChangeHash, ObjId, ObjType, ReadDoc, Value, ROOT};
+
+use crate::{CrdtDocument, Result, TextMark};
+
+impl CrdtDocument {
+    /// Save the changes made since the document was last saved or loaded
+    ///
+    /// The bytes can be appended to the earlier save. Empty if nothing
+    /// changed.
+    pub fn save_incremental(&mut self) -> Vec<u8> {
+        self.commit();
+        self.inner_mut().save_incremental()
+    }
+
+    /// Save the changes the document has beyond `heads`
+    ///
+    /// Appended to a save made at `heads`, this loads as the current
+    /// document. Empty if there are no such changes.
+    pub fn save_after(&mut self, heads: &[ChangeHash]) -> Vec<u8> {
+        self.commit();
+        self.inner_mut().save_after(heads)
+    }
+
+    /// Copy of the current state without any of the history behind it
+    ///
+    /// Everything is written as a single change, including text marks and
+    /// block markers.
+    pub fn snapshot(&mut self) -> Result<CrdtDocument> {
+        self.commit();
+        let mut snapshot = CrdtDocument::new();
+        copy_object(self.inner(), &ROOT, snapshot.inner_mut(), &ROOT)?;
+        snapshot.commit_with_message("Snapshot of earlier history");
+        Ok(snapshot)
+    }
+}
+
+/// Write the contents of `from_obj` into the empty `to_obj`
+fn copy_object(from: &AutoCommit, from_obj: &ObjId, to: &mut AutoCommit, to_obj: &ObjId) -> Result<()> {
+    match from.object_type(from_obj)? {
+        ObjType::Map | ObjType::Table => {
+            for key in from.keys(from_obj) {
+                match from.get(from_obj, &key)? {
+                    Some((Value::Scalar(value), _)) => to.put(to_obj, &key, value.into_owned())?,
+                    Some((Value::Object(obj_type), id)) => {
+                        let child = to.put_object(to_obj, &key, obj_type)?;
+                        copy_object(from, &id, to, &child)?;
+                    }
+                    None => {}
+                }
+            }
+        }
+        ObjType::List => {
+            for index in 0..from.length(from_obj) {
+                match from.get(from_obj, index)? {
+                    Some((Value::Scalar(value), _)) => to.insert(to_obj, index, value.into_owned())?,
+                    Some((Value::Object(obj_type), id)) => {
+                        let child = to.insert_object(to_obj, index, obj_type)?;
+                        copy_object(from, &id, to, &child)?;
+                    }
+                    None => {}
+                }
+            }
+        }
+        ObjType::Text => {
+            let mut pos = 0;
+            for span in from.spans(from_obj)? {
+                match span {
+                    Span::Text(text, _) => {
+                        to.splice_text(to_obj, pos, 0, &text)?;
+                        pos += text.chars().count();
+                    }
+                    Span::Block(block) => {
+                        let marker = to.split_block(to_obj, pos)?;
+                        to.update_object(&marker, &hydrate::Value::Map(block))?;
+                        pos += 1;
+                    }
+                }
+            }
+
+            // Marks go on once all the text is in, as when it was written
+            for mark in from.marks(from_obj)? {
+                let expand = TextMark::from_mark(mark.name(), mark.value())
+                    .map_or(ExpandMark::None, |known| known.expand());
+                let data = Mark::new(mark.name().to_string(), mark.value().clone(), mark.start, mark.end);
+                to.mark(to_obj, data, expand)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DocumentContent;
+
+    #[test]
+    fn test_appended_saves_load() {
+        let mut content = DocumentContent::new();
+        content.set_text("one").unwrap();
+        let mut file = content.save();
+        let heads = content.document_mut().get_heads();
+
+        content.insert_text(3, " two").unwrap();
+        file.extend(content.document_mut().save_after(&heads));
+        content.insert_text(7, " three").unwrap();
+        file.extend(content.document_mut().save_incremental());
+        assert!(content.document_mut().save_incremental().is_empty());
+
+        let mut loaded = DocumentContent::load(&file).unwrap();
+        assert_eq!(loaded.get_text().unwrap(), "one two three");
+        assert_eq!(loaded.document_mut().get_heads(), content.document_mut().get_heads());
+    }
+
+    #[test]
+    fn test_snapshot_keeps_state_only() {
+        let mut content = DocumentContent::new();
+        content.set_markdown("# Plan\n\nSome **bold** and *soft* words").unwrap();
+        content.document_mut().set_string("meta:title", "Plan").unwrap();
+        content.document_mut().commit();
+        for i in 0..50 {
+            content.insert_text(6, "x").unwrap();
+            content.document_mut().commit_with_message(&format!("Edit {}", i));
+            content.delete_text(6, 1).unwrap();
+        }
+        content.insert_text(6, "New ").unwrap();
+
+        let mut snapshot = DocumentContent::from_document(content.document_mut().snapshot().unwrap());
+        assert_eq!(snapshot.to_markdown().unwrap(), content.to_markdown().unwrap());
+        assert_eq!(snapshot.document().get_string("meta:title").unwrap(), Some("Plan".to_string()));
+        assert_eq!(snapshot.document_mut().history().len(), 1);
+        assert!(snapshot.save().len() < content.save().len());
+    }
+}
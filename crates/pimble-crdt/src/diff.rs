@@ -0,0 +1,449 @@
+//! Text diffs between document versions
+//!
+//! Two versions of a document's text are compared character by character
+//! for a list of inserted and deleted spans, and line by line for a
+//! unified diff. When the newer version descends from the older one, the
+//! changes between them are replayed in order to find which actors made
+//! each span.
+
+use automerge::{Change, ChangeHash};
+use pimble_core::{DiffKind, DiffSpan};
+
+use crate::{DocumentContent, Result};
+
+/// Lines of context around each hunk of a unified diff
+pub const UNIFIED_CONTEXT: usize = 3;
+
+/// Edit distance past which the differing middle is replaced wholesale
+///
+/// Keeps the diff's memory bounded when two versions share little.
+const MAX_EDIT_DISTANCE: usize = 1024;
+
+/// The differences between two versions of a document's text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextDiff {
+    /// Text of the older version
+    pub old: String,
+    /// Text of the newer version
+    pub new: String,
+    /// Inserted and deleted spans, in text order
+    pub spans: Vec<DiffSpan>,
+}
+
+impl TextDiff {
+    /// Render the diff as a line-based unified diff
+    pub fn unified(&self, old_label: &str, new_label: &str) -> String {
+        unified_diff(&self.old, &self.new, old_label, new_label, UNIFIED_CONTEXT)
+    }
+}
+
+impl DocumentContent {
+    /// Diff the text between the versions at `from` and `to`
+    ///
+    /// Spans are found by comparing the two texts. If `to` descends from
+    /// `from`, each span also lists the actors whose changes inserted or
+    /// deleted its characters; otherwise the actors are left empty.
+    pub fn diff(&mut self, from: &[ChangeHash], to: &[ChangeHash]) -> Result<TextDiff> {
+        let old = self.text_at(from)?;
+        let new = self.text_at(to)?;
+        let old_chars: Vec<char> = old.chars().collect();
+        let new_chars: Vec<char> = new.chars().collect();
+        let mut spans = text_spans(&old_chars, &new_chars);
+
+        let doc = self.document_mut();
+        let mut later = doc.at(to)?;
+        if from.iter().all(|hash| later.inner_mut().get_change_by_hash(hash).is_some()) {
+            let replay = DocumentContent::from_document(doc.at(from)?);
+            let blame = Blame::replay(replay, &old_chars, later.get_changes_since(from))?;
+            for span in &mut spans {
+                span.actors = blame.actors_for(span);
+            }
+        }
+
+        Ok(TextDiff { old, new, spans })
+    }
+}
+
+/// Where a character of the replayed text came from
+#[derive(Debug, Clone, Copy)]
+enum Origin {
+    /// The character at this offset in the old text
+    Old(usize),
+    /// Inserted by the actor at this index
+    Inserted(usize),
+}
+
+/// Which actor inserted or deleted each character between two versions
+struct Blame {
+    /// Hex actor ids, indexed by [`Origin::Inserted`] and `deleted_by`
+    actors: Vec<String>,
+    /// Origin of each character of the new text
+    origins: Vec<Origin>,
+    /// Actor that deleted each character of the old text, if one did
+    deleted_by: Vec<Option<usize>>,
+}
+
+impl Blame {
+    /// Apply `changes` to `replay` one at a time, tracking each character
+    fn replay(mut replay: DocumentContent, old: &[char], changes: Vec<Change>) -> Result<Self> {
+        let mut blame = Self {
+            actors: Vec::new(),
+            origins: (0..old.len()).map(Origin::Old).collect(),
+            deleted_by: vec![None; old.len()],
+        };
+        let mut text = old.to_vec();
+
+        for change in changes {
+            let actor = change.actor_id().to_hex_string();
+            let actor = match blame.actors.iter().position(|a| *a == actor) {
+                Some(index) => index,
+                None => {
+                    blame.actors.push(actor);
+                    blame.actors.len() - 1
+                }
+            };
+
+            replay.document_mut().apply_change(change)?;
+            let next: Vec<char> = replay.get_text()?.chars().collect();
+            if next == text {
+                continue;
+            }
+
+            let mut origins = Vec::with_capacity(next.len());
+            let mut i = 0;
+            for edit in edit_script(&text, &next) {
+                match edit {
+                    Edit::Equal(n) => {
+                        origins.extend_from_slice(&blame.origins[i..i + n]);
+                        i += n;
+                    }
+                    Edit::Delete(n) => {
+                        for origin in &blame.origins[i..i + n] {
+                            if let Origin::Old(offset) = origin {
+                                blame.deleted_by[*offset] = Some(actor);
+                            }
+                        }
+                        i += n;
+                    }
+                    Edit::Insert(n) => origins.extend((0..n).map(|_| Origin::Inserted(actor))),
+                }
+            }
+            blame.origins = origins;
+            text = next;
+        }
+
+        Ok(blame)
+    }
+
+    /// Actors that made a span, in the order they first appear in it
+    fn actors_for(&self, span: &DiffSpan) -> Vec<String> {
+        let range = span.offset..span.offset + span.text.chars().count();
+        let indices: Vec<usize> = match span.kind {
+            DiffKind::Insert => self.origins[range]
+                .iter()
+                .filter_map(|origin| match origin {
+                    Origin::Inserted(actor) => Some(*actor),
+                    Origin::Old(_) => None,
+                })
+                .collect(),
+            DiffKind::Delete => self.deleted_by[range].iter().flatten().copied().collect(),
+        };
+
+        let mut actors: Vec<String> = Vec::new();
+        for index in indices {
+            if !actors.contains(&self.actors[index]) {
+                actors.push(self.actors[index].clone());
+            }
+        }
+        actors
+    }
+}
+
+/// Inserted and deleted spans turning `old` into `new`, without actors
+fn text_spans(old: &[char], new: &[char]) -> Vec<DiffSpan> {
+    let mut spans = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in edit_script(old, new) {
+        match edit {
+            Edit::Equal(n) => {
+                i += n;
+                j += n;
+            }
+            Edit::Delete(n) => {
+                spans.push(DiffSpan {
+                    kind: DiffKind::Delete,
+                    offset: i,
+                    text: old[i..i + n].iter().collect(),
+                    actors: Vec::new(),
+                });
+                i += n;
+            }
+            Edit::Insert(n) => {
+                spans.push(DiffSpan {
+                    kind: DiffKind::Insert,
+                    offset: j,
+                    text: new[j..j + n].iter().collect(),
+                    actors: Vec::new(),
+                });
+                j += n;
+            }
+        }
+    }
+    spans
+}
+
+/// Render a line-based unified diff of two texts
+///
+/// Returns an empty string if the texts are the same.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str, context: usize) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    // One entry per output line: its marker and the old and new line indices
+    let mut lines: Vec<(char, usize, usize)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in edit_script(&old_lines, &new_lines) {
+        match edit {
+            Edit::Equal(n) => {
+                lines.extend((0..n).map(|k| (' ', i + k, j + k)));
+                i += n;
+                j += n;
+            }
+            Edit::Delete(n) => {
+                lines.extend((0..n).map(|k| ('-', i + k, j)));
+                i += n;
+            }
+            Edit::Insert(n) => {
+                lines.extend((0..n).map(|k| ('+', i, j + k)));
+                j += n;
+            }
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|&k| lines[k].0 != ' ').collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    let mut start = 0;
+    while start < changed.len() {
+        // Changes closer than two contexts apart share a hunk
+        let mut end = start;
+        while end + 1 < changed.len() && changed[end + 1] - changed[end] <= 2 * context + 1 {
+            end += 1;
+        }
+        let first = changed[start].saturating_sub(context);
+        let last = (changed[end] + context + 1).min(lines.len());
+        let hunk = &lines[first..last];
+
+        let old_count = hunk.iter().filter(|line| line.0 != '+').count();
+        let new_count = hunk.iter().filter(|line| line.0 != '-').count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(hunk[0].1, old_count),
+            hunk_range(hunk[0].2, new_count)
+        ));
+        for &(marker, i, j) in hunk {
+            let line = if marker == '+' { new_lines[j] } else { old_lines[i] };
+            out.push(marker);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        start = end + 1;
+    }
+    out
+}
+
+/// Hunk header range for `count` lines starting after `start` lines
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// One run of an edit script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Shortest edit script turning `old` into `new`, as runs
+fn edit_script<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old.iter().rev().zip(new.iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    let mut script = Vec::new();
+    push_edit(&mut script, Edit::Equal(prefix));
+    match myers(old, new) {
+        Some(edits) => {
+            for edit in edits {
+                push_edit(&mut script, edit);
+            }
+        }
+        None => {
+            push_edit(&mut script, Edit::Delete(old.len()));
+            push_edit(&mut script, Edit::Insert(new.len()));
+        }
+    }
+    push_edit(&mut script, Edit::Equal(suffix));
+    script
+}
+
+/// Append an edit, extending the last run if it's the same kind
+fn push_edit(script: &mut Vec<Edit>, edit: Edit) {
+    match (script.last_mut(), edit) {
+        (_, Edit::Equal(0) | Edit::Delete(0) | Edit::Insert(0)) => {}
+        (Some(Edit::Equal(n)), Edit::Equal(m))
+        | (Some(Edit::Delete(n)), Edit::Delete(m))
+        | (Some(Edit::Insert(n)), Edit::Insert(m)) => *n += m,
+        _ => script.push(edit),
+    }
+}
+
+/// Myers' O(ND) diff as single-element edits
+///
+/// Returns `None` if the edit distance exceeds [`MAX_EDIT_DISTANCE`].
+fn myers<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<Edit>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let limit = (n + m).min(MAX_EDIT_DISTANCE as isize);
+    let offset = limit + 1;
+    let index = |k: isize| (k + offset) as usize;
+
+    // v[k] is the furthest x reached on diagonal k; trace[d] is v before round d
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    for d in 0..=limit {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, d, n, m, index));
+            }
+        }
+    }
+    None
+}
+
+/// Walk the Myers trace back from the end to recover the edits
+fn backtrack(trace: &[Vec<isize>], depth: isize, n: isize, m: isize, index: impl Fn(isize) -> usize) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..=depth).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal(1));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x { Edit::Insert(1) } else { Edit::Delete(1) });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actor(content: &mut DocumentContent) -> String {
+        content.document_mut().inner_mut().get_actor().to_hex_string()
+    }
+
+    #[test]
+    fn test_edit_script() {
+        let old: Vec<char> = "kitten".chars().collect();
+        let new: Vec<char> = "sitting".chars().collect();
+        let script = edit_script(&old, &new);
+        let deleted: usize = script.iter().map(|e| if let Edit::Delete(n) = e { *n } else { 0 }).sum();
+        let inserted: usize = script.iter().map(|e| if let Edit::Insert(n) = e { *n } else { 0 }).sum();
+        assert_eq!((deleted, inserted), (2, 3));
+
+        assert_eq!(edit_script(&old, &old), vec![Edit::Equal(6)]);
+        assert_eq!(edit_script::<char>(&[], &[]), vec![]);
+    }
+
+    #[test]
+    fn test_diff_attributes_spans_to_actors() {
+        let mut ours = DocumentContent::new();
+        ours.set_text("alpha beta").unwrap();
+        let base = ours.document_mut().get_heads();
+
+        let mut theirs = DocumentContent::from_document(ours.document_mut().fork());
+        theirs.insert_text(10, " gamma!").unwrap();
+        ours.delete_text(0, 6).unwrap();
+        ours.document_mut().merge(theirs.document_mut()).unwrap();
+        let head = ours.document_mut().get_heads();
+
+        let diff = ours.diff(&base, &head).unwrap();
+        assert_eq!(diff.old, "alpha beta");
+        assert_eq!(diff.new, "beta gamma!");
+        assert_eq!(
+            diff.spans,
+            vec![
+                DiffSpan { kind: DiffKind::Delete, offset: 0, text: "alpha ".into(), actors: vec![actor(&mut ours)] },
+                DiffSpan { kind: DiffKind::Insert, offset: 4, text: " gamma!".into(), actors: vec![actor(&mut theirs)] },
+            ]
+        );
+
+        // Going backwards there are no changes to trace the spans to
+        let diff = ours.diff(&head, &base).unwrap();
+        assert_eq!(diff.spans.len(), 2);
+        assert!(diff.spans.iter().all(|span| span.actors.is_empty()));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("a\nb\nc\n", "a\nB\nc\nd", "a/note", "b/note", 3);
+        assert_eq!(
+            diff,
+            "--- a/note\n+++ b/note\n@@ -1,3 +1,4 @@\n a\n-b\n+B\n c\n+d\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified_diff("same\n", "same\n", "a", "b", 3), "");
+
+        // Distant changes get separate hunks
+        let old: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let new: String = (1..=20)
+            .map(|n| match n {
+                2 => "two\n".to_string(),
+                19 => "nineteen\n".to_string(),
+                n => format!("{}\n", n),
+            })
+            .collect();
+        let diff = unified_diff(&old, &new, "a", "b", 1);
+        assert!(diff.contains("@@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n"));
+        assert!(diff.contains("@@ -18,3 +18,3 @@\n 18\n-19\n+nineteen\n 20\n"));
+    }
+}
@@ -1,9 +1,27 @@
 //! CRDT Document wrapper around Automerge
 
-use automerge::{transaction::Transactable, AutoCommit, Change, ChangeHash, ReadDoc};
+use automerge::transaction::{CommitOptions, Transactable};
+use automerge::{hydrate, ActorId, AutoCommit, Change, ChangeHash, ObjType, ReadDoc};
+use chrono::{DateTime, Utc};
 
+use crate::actor::new_local_actor;
 use crate::error::{CrdtError, Result};
 
+/// A root key holding concurrent values that haven't been reconciled
+///
+/// Automerge keeps every value written concurrently to a key and picks a
+/// deterministic winner for plain reads; this lists all of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyConflict {
+    /// The conflicting key
+    pub key: String,
+    /// Every concurrent value, the current winner first
+    pub values: Vec<serde_json::Value>,
+    /// When the latest of the values was written, if its change was
+    /// timestamped
+    pub written_at: Option<DateTime<Utc>>,
+}
+
 /// A CRDT document backed by Automerge
 ///
 /// This provides a high-level interface for working with Automerge documents,
@@ -15,10 +33,10 @@ pub struct CrdtDocument {
 
 impl CrdtDocument {
     /// Create a new empty document
+    ///
+    /// Edits are attributed to the local device, if one has been set.
     pub fn new() -> Self {
-        Self {
-            doc: AutoCommit::new(),
-        }
+        Self::from_inner(AutoCommit::new())
     }
 
     /// Load a document from bytes
@@ -26,22 +44,24 @@ impl CrdtDocument {
         if bytes.is_empty() {
             return Ok(Self::new());
         }
-        let doc = AutoCommit::load(bytes)?;
-        Ok(Self { doc })
+        Ok(Self::from_inner(AutoCommit::load(bytes)?))
     }
 
     /// Save the document to bytes
     pub fn save(&mut self) -> Vec<u8> {
+        self.commit();
         self.doc.save()
     }
 
     /// Get the current heads (for sync)
     pub fn get_heads(&mut self) -> Vec<ChangeHash> {
+        self.commit();
         self.doc.get_heads()
     }
 
     /// Get changes since the given heads
     pub fn get_changes_since(&mut self, heads: &[ChangeHash]) -> Vec<Change> {
+        self.commit();
         self.doc
             .get_changes(heads)
             .into_iter()
@@ -63,17 +83,44 @@ impl CrdtDocument {
 
     /// Merge another document into this one
     pub fn merge(&mut self, other: &mut CrdtDocument) -> Result<()> {
+        self.commit();
+        other.commit();
         self.doc.merge(&mut other.doc)?;
         Ok(())
     }
 
     /// Fork this document (create an independent copy)
     pub fn fork(&mut self) -> Self {
-        Self {
-            doc: self.doc.fork(),
+        self.commit();
+        Self::from_inner(self.doc.fork())
+    }
+
+    /// Commit pending edits as one change, stamped with the current time
+    ///
+    /// Edits are otherwise committed without a timestamp whenever the
+    /// document next needs its history, so this is called before saving,
+    /// syncing and reading heads. Does nothing if there are no edits.
+    pub fn commit(&mut self) {
+        if self.doc.pending_ops() > 0 {
+            self.doc
+                .commit_with(CommitOptions::default().with_time(Utc::now().timestamp_millis()));
         }
     }
 
+    /// The actor this document's edits are made as
+    pub fn actor(&self) -> &ActorId {
+        self.doc.get_actor()
+    }
+
+    /// Make further edits as `actor`
+    ///
+    /// Pending edits are committed first, under the previous actor. No
+    /// other document instance may use the same actor.
+    pub fn set_actor(&mut self, actor: ActorId) {
+        self.commit();
+        self.doc.set_actor(actor);
+    }
+
     /// Set a string value at the root level
     pub fn set_string(&mut self, key: &str, value: &str) -> Result<()> {
         self.doc.put(automerge::ROOT, key, value)?;
@@ -153,6 +200,89 @@ impl CrdtDocument {
         }
     }
 
+    /// Get every concurrent value of a key at the root level
+    ///
+    /// Returns one value normally and several after concurrent writes that
+    /// haven't been reconciled. Text objects are returned as their text,
+    /// maps and lists as JSON objects and arrays.
+    pub fn get_all(&self, key: &str) -> Result<Vec<serde_json::Value>> {
+        let mut values: Vec<serde_json::Value> = self
+            .doc
+            .get_all(automerge::ROOT, key)?
+            .into_iter()
+            .map(|(value, id)| self.value_json(value, &id))
+            .collect::<Result<_>>()?;
+        // Automerge orders the winner last
+        values.reverse();
+        Ok(values)
+    }
+
+    /// List root keys whose concurrent values differ
+    pub fn conflicts(&mut self) -> Result<Vec<KeyConflict>> {
+        self.commit();
+        let mut conflicts = Vec::new();
+        for key in self.doc.keys(automerge::ROOT).collect::<Vec<_>>() {
+            let values = self.get_all(&key)?;
+            if values.iter().any(|v| *v != values[0]) {
+                let written_at = self.written_at(&key)?;
+                conflicts.push(KeyConflict { key, values, written_at });
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// When the latest of a root key's concurrent values was written
+    fn written_at(&mut self, key: &str) -> Result<Option<DateTime<Utc>>> {
+        let ops: Vec<(u64, ActorId)> = self
+            .doc
+            .get_all(automerge::ROOT, key)?
+            .into_iter()
+            .filter_map(|(_, id)| match id {
+                automerge::ObjId::Id(counter, actor, _) => Some((counter, actor)),
+                automerge::ObjId::Root => None,
+            })
+            .collect();
+        let latest = self
+            .doc
+            .get_changes(&[])
+            .into_iter()
+            .filter(|change| {
+                let written = change.start_op().get()..=change.max_op();
+                ops.iter().any(|(counter, actor)| change.actor_id() == actor && written.contains(counter))
+            })
+            .map(|change| change.timestamp())
+            .max();
+        Ok(latest.filter(|millis| *millis != 0).and_then(DateTime::from_timestamp_millis))
+    }
+
+    /// Settle a key on one value, replacing all concurrent ones
+    ///
+    /// A string replaces a text object with a new one holding that text;
+    /// other JSON values are stored as scalars, or as maps and lists for
+    /// objects and arrays.
+    pub fn resolve(&mut self, key: &str, value: &serde_json::Value) -> Result<()> {
+        let is_text = matches!(self.doc.get(automerge::ROOT, key)?, Some((automerge::Value::Object(ObjType::Text), _)));
+        match value {
+            serde_json::Value::String(s) if is_text => {
+                let text_id = self.doc.put_object(automerge::ROOT, key, ObjType::Text)?;
+                self.doc.splice_text(&text_id, 0, 0, s)?;
+            }
+            other => self.put_json(&automerge::ROOT, key, other)?,
+        }
+        Ok(())
+    }
+
+    /// Make this document hold the same values as `other`, as new changes
+    ///
+    /// For bringing in a copy with its own history, such as one rebuilt by
+    /// an editor: only what differs is written, and text is updated with
+    /// minimal splices, so the result merges with concurrent edits. Root
+    /// keys for which `keep` returns true are left as they are.
+    pub fn update_from(&mut self, other: &CrdtDocument, keep: impl Fn(&str) -> bool) -> Result<()> {
+        let target = other.doc.hydrate(automerge::ROOT, None)?;
+        self.update_root(target, keep)
+    }
+
     /// Delete a key from the root level
     pub fn delete(&mut self, key: &str) -> Result<()> {
         self.doc.delete(automerge::ROOT, key)?;
@@ -175,6 +305,28 @@ impl CrdtDocument {
     }
 }
 
+impl CrdtDocument {
+    pub(crate) fn from_inner(doc: AutoCommit) -> Self {
+        Self {
+            doc: doc.with_actor(new_local_actor()),
+        }
+    }
+
+    /// Write `target` over the root, keeping keys for which `keep` is true
+    pub(crate) fn update_root(&mut self, mut target: hydrate::Value, keep: impl Fn(&str) -> bool) -> Result<()> {
+        let current = self.doc.hydrate(automerge::ROOT, None)?;
+        if let (hydrate::Value::Map(target), hydrate::Value::Map(current)) = (&mut target, &current) {
+            target.retain(|key, _| !keep(key));
+            for (key, value) in current.iter().filter(|(key, _)| keep(key)) {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+
+        self.doc.update_object(automerge::ROOT, &target)?;
+        Ok(())
+    }
+}
+
 impl Default for CrdtDocument {
     fn default() -> Self {
         Self::new()
@@ -223,4 +375,71 @@ mod tests {
         assert_eq!(doc1.get_string("key1").unwrap(), Some("value1".to_string()));
         assert_eq!(doc1.get_string("key2").unwrap(), Some("value2".to_string()));
     }
+
+    #[test]
+    fn test_conflicts_and_resolve() {
+        let mut doc1 = CrdtDocument::new();
+        doc1.set_string("title", "Base").unwrap();
+        doc1.set_int("count", 1).unwrap();
+        let mut doc2 = doc1.fork();
+
+        doc1.set_string("title", "Ours").unwrap();
+        doc2.set_string("title", "Theirs").unwrap();
+        doc1.set_int("count", 2).unwrap();
+        doc2.set_int("count", 2).unwrap();
+        doc1.merge(&mut doc2).unwrap();
+
+        // Equal concurrent values aren't a conflict
+        let conflicts = doc1.conflicts().unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key, "title");
+        let winner = doc1.get_string("title").unwrap().unwrap();
+        assert_eq!(conflicts[0].values[0], serde_json::Value::String(winner));
+        assert_eq!(conflicts[0].values.len(), 2);
+        assert!(conflicts[0].written_at.is_some());
+
+        doc1.resolve("title", &serde_json::json!("Ours and theirs")).unwrap();
+        assert!(doc1.conflicts().unwrap().is_empty());
+        assert_eq!(doc1.get_all("title").unwrap(), vec![serde_json::json!("Ours and theirs")]);
+    }
+
+    #[test]
+    fn test_update_from_unrelated_copy() {
+        let mut content = crate::DocumentContent::new();
+        content.set_text("one two").unwrap();
+        content.document_mut().set_string("meta:title", "Notes").unwrap();
+        let mut concurrent = crate::DocumentContent::from_document(content.document_mut().fork());
+        concurrent.insert_text(7, " three").unwrap();
+
+        // An editor rebuilds the document from scratch, with its own history
+        let mut edited = crate::DocumentContent::new();
+        edited.set_text("zero one two").unwrap();
+
+        let doc = content.document_mut();
+        doc.update_from(edited.document(), |key| key.starts_with("meta:")).unwrap();
+        doc.merge(concurrent.document_mut()).unwrap();
+        assert_eq!(content.get_text().unwrap(), "zero one two three");
+        assert_eq!(content.document().get_string("meta:title").unwrap(), Some("Notes".to_string()));
+    }
+
+    #[test]
+    fn test_commit_stamps_time() {
+        let mut doc = CrdtDocument::new();
+        doc.set_string("title", "Notes").unwrap();
+        let heads = doc.get_heads();
+        doc.commit();
+        assert_eq!(doc.get_heads(), heads);
+
+        let history = doc.history();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].timestamp.is_some());
+        assert_eq!(history[0].actor, doc.actor().to_hex_string());
+
+        let mut copy = doc.fork();
+        assert_ne!(copy.actor(), doc.actor());
+        copy.set_string("title", "Copy").unwrap();
+        doc.set_string("title", "Original").unwrap();
+        doc.merge(&mut copy).unwrap();
+        assert_eq!(doc.history().len(), 3);
+    }
 }
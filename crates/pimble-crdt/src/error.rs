@@ -21,6 +21,33 @@ pub enum CrdtError {
 
     #[error("Serialization error: {0}")]
     Serialization(String),
+
+    #[error("Sync error: {0}")]
+    Sync(String),
+
+    #[error("Invalid path {0}")]
+    InvalidPath(String),
+
+    #[error("Invalid move: {0}")]
+    InvalidMove(String),
+
+    #[error("Unknown change: {0}")]
+    UnknownChange(String),
+
+    #[error("No migration from content format {0}")]
+    NoMigration(String),
+
+    #[error("Invalid anchor: {0}")]
+    InvalidAnchor(String),
+}
+
+impl From<automerge::error::UpdateObjectError> for CrdtError {
+    fn from(err: automerge::error::UpdateObjectError) -> Self {
+        match err {
+            automerge::error::UpdateObjectError::Automerge(e) => CrdtError::Automerge(e),
+            other => CrdtError::Serialization(other.to_string()),
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, CrdtError>;
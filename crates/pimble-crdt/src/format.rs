@@ -0,0 +1,308 @@
+//! Content format envelope and migrations between format versions
+//!
+//! Every node's content document records the format it holds and that
+//! format's schema version in two metadata keys, so the record survives
+//! restores and whole-document updates just as the title does. Content
+//! written before the envelope existed is recognised by its shape, and a
+//! [`MigrationRegistry`] upgrades it a version at a time to the current
+//! version of its format.
+
+use std::collections::HashMap;
+
+use automerge::{ObjType, ReadDoc, Value, ROOT};
+use pimble_core::ContentFormat;
+
+use crate::{CrdtDocument, CrdtError, DocumentContent, MetadataContent, Result};
+
+/// Content of document nodes: text with marks and block structure
+pub const DOCUMENT_FORMAT: &str = "document";
+
+/// Content of folder nodes, which hold only metadata
+pub const FOLDER_FORMAT: &str = "folder";
+
+/// Documents written by the app's rich text editor
+pub const EDITOR_FORMAT: &str = "editor";
+
+/// Plain UTF-8 bytes, from before content was kept in Automerge
+pub const TEXT_FORMAT: &str = "text";
+
+/// Root key of the editor's list of blocks
+const EDITOR_BLOCKS_KEY: &str = "blocks";
+
+const FORMAT_KEY: &str = "meta:format";
+const FORMAT_VERSION_KEY: &str = "meta:format_version";
+
+impl CrdtDocument {
+    /// Get the content format recorded in the document, if any
+    pub fn content_format(&self) -> Result<Option<ContentFormat>> {
+        let Some(id) = self.get_string(FORMAT_KEY)? else {
+            return Ok(None);
+        };
+        let version = self.get_int(FORMAT_VERSION_KEY)?.unwrap_or(1);
+        Ok(Some(ContentFormat::new(id, u32::try_from(version).unwrap_or(0))))
+    }
+
+    /// Record the document's content format
+    pub fn set_content_format(&mut self, format: &ContentFormat) -> Result<()> {
+        if self.content_format()?.as_ref() == Some(format) {
+            return Ok(());
+        }
+        self.set_string(FORMAT_KEY, &format.id)?;
+        self.set_int(FORMAT_VERSION_KEY, format.version as i64)
+    }
+}
+
+/// Work out the format of a document that predates the envelope
+///
+/// Returns `None` for documents holding keys no known format uses.
+fn detect_format(doc: &CrdtDocument, node_type: &str) -> Option<ContentFormat> {
+    let keys: Vec<String> = doc
+        .inner()
+        .keys(ROOT)
+        .filter(|key| !MetadataContent::is_metadata_key(key))
+        .collect();
+    match keys.as_slice() {
+        [] if node_type == "folder" => Some(ContentFormat::new(FOLDER_FORMAT, 1)),
+        [] if node_type == "document" => Some(ContentFormat::new(DOCUMENT_FORMAT, 1)),
+        [key] if key == DocumentContent::TEXT_KEY => Some(ContentFormat::new(DOCUMENT_FORMAT, 1)),
+        [key] if key == EDITOR_BLOCKS_KEY && holds_list(doc, key) => {
+            Some(ContentFormat::new(EDITOR_FORMAT, 1))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a root key holds a list object
+fn holds_list(doc: &CrdtDocument, key: &str) -> bool {
+    matches!(
+        doc.inner().get(ROOT, key),
+        Ok(Some((Value::Object(ObjType::List), _)))
+    )
+}
+
+/// Identify the format of a node's content
+///
+/// Uses the recorded format when there is one, otherwise the format the
+/// content's shape suggests. Bytes that aren't an Automerge document are
+/// plain text, version 0.
+pub fn identify_format(content: &[u8], node_type: &str) -> Option<ContentFormat> {
+    match CrdtDocument::load(content) {
+        Ok(doc) => doc
+            .content_format()
+            .ok()
+            .flatten()
+            .or_else(|| detect_format(&doc, node_type)),
+        Err(_) => Some(ContentFormat::new(TEXT_FORMAT, 0)),
+    }
+}
+
+/// A step upgrading content from one version of its format to the next
+type MigrationStep = Box<dyn Fn(&mut CrdtDocument) -> Result<()> + Send + Sync>;
+
+/// Content upgraded by [`MigrationRegistry::migrate`]
+#[derive(Debug)]
+pub struct MigratedContent {
+    /// Format the content was in
+    pub from: ContentFormat,
+
+    /// Format the content is in now
+    pub to: ContentFormat,
+
+    /// The upgraded content, with its format recorded
+    pub content: Vec<u8>,
+}
+
+/// Upgrades node content to the current version of its format
+///
+/// A format's current version is one past its latest registered step, or
+/// the version it was declared with if that's later. The default registry
+/// knows the formats pimble writes.
+pub struct MigrationRegistry {
+    current: HashMap<String, u32>,
+    steps: HashMap<(String, u32), MigrationStep>,
+}
+
+impl MigrationRegistry {
+    /// Create a registry that knows no formats
+    pub fn empty() -> Self {
+        Self {
+            current: HashMap::new(),
+            steps: HashMap::new(),
+        }
+    }
+
+    /// Declare a format and its current version
+    pub fn declare(&mut self, format: &str, version: u32) {
+        let current = self.current.entry(format.to_string()).or_insert(version);
+        *current = (*current).max(version);
+    }
+
+    /// Register a step upgrading content of a format from `from_version`
+    /// to the next version
+    pub fn register(
+        &mut self,
+        format: &str,
+        from_version: u32,
+        step: impl Fn(&mut CrdtDocument) -> Result<()> + Send + Sync + 'static,
+    ) {
+        self.declare(format, from_version + 1);
+        self.steps.insert((format.to_string(), from_version), Box::new(step));
+    }
+
+    /// Get the current version of a format, if the registry knows it
+    pub fn current_version(&self, format: &str) -> Option<u32> {
+        self.current.get(format).copied()
+    }
+
+    /// Bring content up to the current version of its format
+    ///
+    /// The result has its format recorded, so content that was current but
+    /// didn't say so comes back too. Bytes that aren't an Automerge
+    /// document are taken as plain text and become document content.
+    /// Returns `None` when there's nothing to do: the content is current
+    /// and says so, is in a format the registry doesn't know, or is in a
+    /// newer version than it knows.
+    pub fn migrate(&self, content: &[u8], node_type: &str) -> Result<Option<MigratedContent>> {
+        let (mut doc, from, recorded) = match CrdtDocument::load(content) {
+            Ok(doc) => {
+                let recorded = doc.content_format()?;
+                let is_recorded = recorded.is_some();
+                match recorded.or_else(|| detect_format(&doc, node_type)) {
+                    Some(format) => (doc, format, is_recorded),
+                    None => return Ok(None),
+                }
+            }
+            Err(_) => {
+                let mut text = DocumentContent::new();
+                text.set_text(&String::from_utf8_lossy(content))?;
+                let doc = std::mem::take(text.document_mut());
+                (doc, ContentFormat::new(TEXT_FORMAT, 0), false)
+            }
+        };
+
+        let mut format = match from.id.as_str() {
+            TEXT_FORMAT => ContentFormat::new(DOCUMENT_FORMAT, 1),
+            _ => from.clone(),
+        };
+        let Some(current) = self.current_version(&format.id) else {
+            return Ok(None);
+        };
+        if format.version > current || (recorded && format.version == current) {
+            return Ok(None);
+        }
+
+        while format.version < current {
+            let step = self
+                .steps
+                .get(&(format.id.clone(), format.version))
+                .ok_or_else(|| CrdtError::NoMigration(format.to_string()))?;
+            step(&mut doc)?;
+            format.version += 1;
+        }
+        doc.set_content_format(&format)?;
+
+        Ok(Some(MigratedContent {
+            from,
+            to: format,
+            content: doc.save(),
+        }))
+    }
+}
+
+impl Default for MigrationRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.declare(DOCUMENT_FORMAT, 1);
+        registry.declare(FOLDER_FORMAT, 1);
+        registry.declare(EDITOR_FORMAT, 1);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge::transaction::Transactable;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_records_detected_formats() {
+        let registry = MigrationRegistry::default();
+
+        let mut legacy = DocumentContent::new();
+        legacy.set_text("Old note").unwrap();
+        let migrated = registry.migrate(&legacy.save(), "document").unwrap().unwrap();
+        assert_eq!(migrated.from, ContentFormat::new(DOCUMENT_FORMAT, 1));
+        assert_eq!(migrated.to, ContentFormat::new(DOCUMENT_FORMAT, 1));
+        let content = DocumentContent::load(&migrated.content).unwrap();
+        assert_eq!(content.get_text().unwrap(), "Old note");
+        assert_eq!(content.document().content_format().unwrap(), Some(migrated.to));
+
+        // Recorded and current: nothing to do
+        assert!(registry.migrate(&migrated.content, "document").unwrap().is_none());
+
+        let folder = registry.migrate(&[], "folder").unwrap().unwrap();
+        assert_eq!(folder.to, ContentFormat::new(FOLDER_FORMAT, 1));
+
+        // Unknown shapes are left alone
+        let mut unknown = CrdtDocument::new();
+        unknown.set_string("blocks", "?").unwrap();
+        assert!(registry.migrate(&unknown.save(), "document").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_identify_editor_content() {
+        let mut doc = CrdtDocument::new();
+        doc.inner_mut()
+            .put_object(ROOT, EDITOR_BLOCKS_KEY, ObjType::List)
+            .unwrap();
+        assert_eq!(
+            identify_format(&doc.save(), "document"),
+            Some(ContentFormat::new(EDITOR_FORMAT, 1))
+        );
+
+        let migrated = MigrationRegistry::default()
+            .migrate(&doc.save(), "document")
+            .unwrap()
+            .unwrap();
+        assert_eq!(migrated.from, ContentFormat::new(EDITOR_FORMAT, 1));
+        assert_eq!(migrated.to, ContentFormat::new(EDITOR_FORMAT, 1));
+    }
+
+    #[test]
+    fn test_migrate_plain_text() {
+        let registry = MigrationRegistry::default();
+        let migrated = registry.migrate(b"# Notes\n\nPlain bytes", "document").unwrap().unwrap();
+        assert_eq!(migrated.from, ContentFormat::new(TEXT_FORMAT, 0));
+        assert_eq!(migrated.to, ContentFormat::new(DOCUMENT_FORMAT, 1));
+        let content = DocumentContent::load(&migrated.content).unwrap();
+        assert_eq!(content.get_text().unwrap(), "# Notes\n\nPlain bytes");
+    }
+
+    #[test]
+    fn test_migrate_runs_registered_steps_in_order() {
+        let mut registry = MigrationRegistry::default();
+        registry.register(DOCUMENT_FORMAT, 2, |doc| doc.set_string("step", "2 to 3"));
+        registry.register(DOCUMENT_FORMAT, 1, |doc| doc.set_string("step", "1 to 2"));
+        assert_eq!(registry.current_version(DOCUMENT_FORMAT), Some(3));
+
+        let mut doc = CrdtDocument::new();
+        doc.set_content_format(&ContentFormat::new(DOCUMENT_FORMAT, 1)).unwrap();
+        let migrated = registry.migrate(&doc.save(), "document").unwrap().unwrap();
+        assert_eq!(migrated.to.version, 3);
+        let doc = CrdtDocument::load(&migrated.content).unwrap();
+        assert_eq!(doc.get_string("step").unwrap(), Some("2 to 3".to_string()));
+
+        // Content from a newer version is left for a newer build
+        let mut newer = CrdtDocument::new();
+        newer.set_content_format(&ContentFormat::new(DOCUMENT_FORMAT, 4)).unwrap();
+        assert!(registry.migrate(&newer.save(), "document").unwrap().is_none());
+
+        // A gap in the steps is an error rather than a silent skip
+        let mut gappy = MigrationRegistry::empty();
+        gappy.register(FOLDER_FORMAT, 2, |_| Ok(()));
+        let mut folder = CrdtDocument::new();
+        folder.set_content_format(&ContentFormat::new(FOLDER_FORMAT, 1)).unwrap();
+        assert!(matches!(gappy.migrate(&folder.save(), "folder"), Err(CrdtError::NoMigration(_))));
+    }
+}
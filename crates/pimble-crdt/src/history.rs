@@ -0,0 +1,172 @@
+//! Document history and time travel
+//!
+//! Automerge keeps every change a document has seen, so any earlier state
+//! can be read back by naming the heads it had at the time. Versions are
+//! exchanged as hex change hashes, the same form used for heads elsewhere.
+//!
+//! Restoring a version doesn't rewind history: it writes the old values as
+//! a new change on top, which merges with concurrent edits like any other.
+
+use automerge::transaction::{CommitOptions, Transactable};
+use automerge::{ChangeHash, ReadDoc, ROOT};
+use chrono::{DateTime, Utc};
+use pimble_core::HistoryEntry;
+
+use crate::{CrdtDocument, CrdtError, DocumentContent, MetadataContent, Result};
+
+impl CrdtDocument {
+    /// List every change in the document, oldest first
+    ///
+    /// Changes are in causal order: each comes after the changes it
+    /// depends on.
+    pub fn history(&mut self) -> Vec<HistoryEntry> {
+        self.commit();
+        self.inner_mut()
+            .get_changes(&[])
+            .into_iter()
+            .map(|change| HistoryEntry {
+                hash: change.hash().to_string(),
+                actor: change.actor_id().to_hex_string(),
+                timestamp: match change.timestamp() {
+                    0 => None,
+                    millis => DateTime::from_timestamp_millis(millis),
+                },
+                message: change.message().cloned(),
+                author: None,
+            })
+            .collect()
+    }
+
+    /// Parse hex heads, checking the document knows each change
+    pub fn parse_heads(&mut self, heads: &[String]) -> Result<Vec<ChangeHash>> {
+        heads
+            .iter()
+            .map(|head| {
+                let hash: ChangeHash = head.parse().map_err(|_| CrdtError::UnknownChange(head.clone()))?;
+                match self.inner_mut().get_change_by_hash(&hash) {
+                    Some(_) => Ok(hash),
+                    None => Err(CrdtError::UnknownChange(head.clone())),
+                }
+            })
+            .collect()
+    }
+
+    /// Parse hex heads, dropping any the document doesn't know
+    ///
+    /// For heads reported by a peer that may have changes this document
+    /// hasn't seen yet.
+    pub fn known_heads(&mut self, heads: &[String]) -> Vec<ChangeHash> {
+        heads
+            .iter()
+            .filter_map(|head| head.parse::<ChangeHash>().ok())
+            .filter(|hash| self.inner_mut().get_change_by_hash(hash).is_some())
+            .collect()
+    }
+
+    /// Copy of the document as it was at `heads`
+    pub fn at(&mut self, heads: &[ChangeHash]) -> Result<CrdtDocument> {
+        self.commit();
+        Ok(CrdtDocument::from_inner(self.inner_mut().fork_at(heads)?))
+    }
+
+    /// Bring the document back to its state at `heads`, as a new change
+    ///
+    /// Root keys for which `keep` returns true are left at their current
+    /// values. Text is restored with minimal splices rather than replaced.
+    pub fn restore(&mut self, heads: &[ChangeHash], keep: impl Fn(&str) -> bool) -> Result<()> {
+        let target = self.inner_mut().hydrate(ROOT, Some(heads))?;
+        self.update_root(target, keep)
+    }
+
+    /// Commit pending operations with a message and the current time
+    ///
+    /// Does nothing if there are no pending operations.
+    pub fn commit_with_message(&mut self, message: &str) {
+        if self.inner_mut().pending_ops() == 0 {
+            return;
+        }
+        self.inner_mut().commit_with(
+            CommitOptions::default()
+                .with_message(message.to_string())
+                .with_time(Utc::now().timestamp_millis()),
+        );
+    }
+}
+
+impl DocumentContent {
+    /// Get the text content as it was at `heads`
+    pub fn text_at(&self, heads: &[ChangeHash]) -> Result<String> {
+        self.read_text(Some(heads))
+    }
+
+    /// Restore the text to its state at `heads`, as a new change
+    ///
+    /// Metadata is left as it is now; only the content goes back.
+    pub fn restore(&mut self, heads: &[ChangeHash]) -> Result<()> {
+        let version = heads
+            .iter()
+            .map(|h| short_hash(&h.to_string()).to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let doc = self.document_mut();
+        doc.restore(heads, MetadataContent::is_metadata_key)?;
+        doc.commit_with_message(&format!("Restore version {}", version));
+        Ok(())
+    }
+}
+
+/// First characters of a change hash, enough to tell versions apart
+pub fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(8)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_at_and_restore() {
+        let mut content = DocumentContent::new();
+        content.set_text("Hello").unwrap();
+        let first = content.document_mut().get_heads();
+        content.insert_text(5, " world").unwrap();
+        content.document_mut().set_string("meta:title", "Greeting").unwrap();
+        content.delete_text(0, 5).unwrap();
+
+        assert_eq!(content.text_at(&first).unwrap(), "Hello");
+        assert_eq!(content.get_text().unwrap(), " world");
+
+        let history = content.document_mut().history();
+        assert!(history.len() >= 2);
+        assert_eq!(history[0].hash, first[0].to_string());
+        let heads = content.document_mut().parse_heads(&[history[0].hash.clone()]).unwrap();
+        assert_eq!(heads, first);
+        assert!(content.document_mut().parse_heads(&["00".repeat(32)]).is_err());
+        let mixed = [history[0].hash.clone(), "00".repeat(32), "nonsense".into()];
+        assert_eq!(content.document_mut().known_heads(&mixed), first);
+
+        content.restore(&heads).unwrap();
+        assert_eq!(content.get_text().unwrap(), "Hello");
+        // Metadata isn't rolled back with the content
+        assert_eq!(content.document().get_string("meta:title").unwrap(), Some("Greeting".into()));
+
+        let last = content.document_mut().history().pop().unwrap();
+        assert!(last.message.unwrap().starts_with("Restore version"));
+        assert!(last.timestamp.is_some());
+    }
+
+    #[test]
+    fn test_restore_merges_with_concurrent_edits() {
+        let mut ours = DocumentContent::new();
+        ours.set_text("one two").unwrap();
+        let base = ours.document_mut().get_heads();
+        ours.insert_text(7, " three").unwrap();
+
+        let mut theirs = DocumentContent::from_document(ours.document_mut().fork());
+        theirs.insert_text(0, "zero ").unwrap();
+        ours.restore(&base).unwrap();
+
+        ours.document_mut().merge(theirs.document_mut()).unwrap();
+        assert_eq!(ours.get_text().unwrap(), "zero one two");
+    }
+}
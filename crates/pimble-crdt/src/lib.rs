@@ -3,12 +3,44 @@
 //! This crate provides:
 //! - CRDT document management using Automerge
 //! - Change tracking and merging
+//! - Incremental saves and history snapshots
+//! - Persistent device ids attributing changes to devices
+//! - Nested paths, list operations and JSON/serde conversion
+//! - Document history and restoring earlier versions
+//! - Text diffs between document versions
 //! - Node content serialization
+//! - Content format records and migrations between format versions
+//! - Rich text with marks, block structure and markdown conversion
+//! - Node metadata with field-level merges
+//! - Incremental peer sync via the Automerge sync protocol
+//! - Store tree structure with conflict-free moves
+//! - Undo and redo of local edits
 
+pub mod actor;
+pub mod compact;
+pub mod diff;
 pub mod document;
 pub mod error;
+pub mod format;
+pub mod history;
+pub mod metadata;
 pub mod node_content;
+pub mod path;
+pub mod rich_text;
+pub mod sync;
+pub mod tree;
+pub mod undo;
 
+pub use actor::*;
+pub use diff::*;
 pub use document::*;
 pub use error::*;
+pub use format::*;
+pub use history::*;
+pub use metadata::*;
 pub use node_content::*;
+pub use path::*;
+pub use rich_text::*;
+pub use sync::*;
+pub use tree::*;
+pub use undo::*;
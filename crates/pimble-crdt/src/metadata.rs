@@ -0,0 +1,286 @@
+//! Node metadata kept in the node's CRDT document
+//!
+//! Title, tags and custom fields live alongside the node's content as
+//! individual root keys, so each field merges on its own: a tag added on
+//! one device and a title changed on another both survive. Tags are a set
+//! (one key per tag) and custom fields a map (one key per field, holding
+//! the value as JSON). The node's JSON `NodeMetadata` is a view derived
+//! from these keys with [`MetadataContent::apply_to`].
+//!
+//! Keys are flat rather than nested objects so that two devices adding the
+//! first tag concurrently don't each create a competing container.
+
+use std::collections::HashMap;
+
+use automerge::ReadDoc;
+use pimble_core::{MetadataEdit, NodeMetadata};
+
+use crate::{CrdtDocument, CrdtError, Result};
+
+/// Key prefixes for metadata fields
+const META_PREFIX: &str = "meta:";
+const TAG_PREFIX: &str = "meta:tag:";
+
+/// Key of a node's title
+pub const TITLE_KEY: &str = "meta:title";
+
+/// Prefix of custom field keys, whose values are stored as JSON strings
+pub const FIELD_PREFIX: &str = "meta:field:";
+
+/// Metadata fields of a node's CRDT document
+pub struct MetadataContent {
+    doc: CrdtDocument,
+}
+
+impl MetadataContent {
+    /// Load metadata from a node's content bytes
+    pub fn load(bytes: &[u8]) -> Result<Self> {
+        Ok(Self {
+            doc: CrdtDocument::load(bytes)?,
+        })
+    }
+
+    /// Wrap an existing CRDT document
+    pub fn from_document(doc: CrdtDocument) -> Self {
+        Self { doc }
+    }
+
+    /// Unwrap the CRDT document
+    pub fn into_document(self) -> CrdtDocument {
+        self.doc
+    }
+
+    /// Save the whole document to bytes
+    pub fn save(&mut self) -> Vec<u8> {
+        self.doc.save()
+    }
+
+    /// Get the underlying CRDT document
+    pub fn document(&self) -> &CrdtDocument {
+        &self.doc
+    }
+
+    /// Check whether the document holds metadata yet
+    ///
+    /// Nodes created before metadata moved into the document only have it
+    /// in their JSON record.
+    pub fn has_metadata(&self) -> bool {
+        self.doc.contains_key(TITLE_KEY)
+    }
+
+    /// Check whether a root key holds metadata rather than content
+    pub fn is_metadata_key(key: &str) -> bool {
+        key.starts_with(META_PREFIX)
+    }
+
+    /// Get the title
+    pub fn title(&self) -> Result<String> {
+        Ok(self.doc.get_string(TITLE_KEY)?.unwrap_or_default())
+    }
+
+    /// Get the tags, sorted
+    pub fn tags(&self) -> Vec<String> {
+        self.keys_with_prefix(TAG_PREFIX)
+            .map(|key| key[TAG_PREFIX.len()..].to_string())
+            .collect()
+    }
+
+    /// Get the custom fields
+    pub fn custom_fields(&self) -> Result<HashMap<String, serde_json::Value>> {
+        let mut fields = HashMap::new();
+        for key in self.keys_with_prefix(FIELD_PREFIX) {
+            let json = self.doc.get_string(&key)?.unwrap_or_default();
+            let value = serde_json::from_str(&json).map_err(|e| CrdtError::Serialization(e.to_string()))?;
+            fields.insert(key[FIELD_PREFIX.len()..].to_string(), value);
+        }
+        Ok(fields)
+    }
+
+    /// Apply a single-field edit
+    ///
+    /// The edit is committed as its own change, with a message saying what
+    /// it did. Returns whether anything changed.
+    pub fn apply(&mut self, edit: &MetadataEdit) -> Result<bool> {
+        let changed = self.apply_uncommitted(edit)?;
+        if changed {
+            self.doc.commit_with_message(&edit_message(edit));
+        }
+        Ok(changed)
+    }
+
+    fn apply_uncommitted(&mut self, edit: &MetadataEdit) -> Result<bool> {
+        match edit {
+            MetadataEdit::SetTitle { title } => {
+                if self.has_metadata() && self.title()? == *title {
+                    return Ok(false);
+                }
+                self.doc.set_string(TITLE_KEY, title)?;
+            }
+            MetadataEdit::AddTag { tag } => {
+                let key = format!("{}{}", TAG_PREFIX, tag);
+                if self.doc.contains_key(&key) {
+                    return Ok(false);
+                }
+                self.doc.set_bool(&key, true)?;
+            }
+            MetadataEdit::RemoveTag { tag } => {
+                let key = format!("{}{}", TAG_PREFIX, tag);
+                if !self.doc.contains_key(&key) {
+                    return Ok(false);
+                }
+                self.doc.delete(&key)?;
+            }
+            MetadataEdit::SetCustomField { key, value } => {
+                let key = format!("{}{}", FIELD_PREFIX, key);
+                match value {
+                    Some(value) => {
+                        let json = value.to_string();
+                        if self.doc.get_string(&key)?.as_deref() == Some(json.as_str()) {
+                            return Ok(false);
+                        }
+                        self.doc.set_string(&key, &json)?;
+                    }
+                    None => {
+                        if !self.doc.contains_key(&key) {
+                            return Ok(false);
+                        }
+                        self.doc.delete(&key)?;
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Bring the document in line with a whole metadata struct
+    ///
+    /// Only fields that differ are written, so this merges like the
+    /// equivalent single-field edits would. Returns whether anything
+    /// changed.
+    pub fn write(&mut self, metadata: &NodeMetadata) -> Result<bool> {
+        let mut edits = vec![MetadataEdit::SetTitle {
+            title: metadata.title.clone(),
+        }];
+
+        let current_tags = self.tags();
+        edits.extend(
+            current_tags
+                .iter()
+                .filter(|tag| !metadata.tags.contains(tag))
+                .map(|tag| MetadataEdit::RemoveTag { tag: tag.clone() }),
+        );
+        edits.extend(metadata.tags.iter().map(|tag| MetadataEdit::AddTag { tag: tag.clone() }));
+
+        let current_fields = self.custom_fields()?;
+        edits.extend(
+            current_fields
+                .keys()
+                .filter(|key| !metadata.custom.contains_key(*key))
+                .map(|key| MetadataEdit::SetCustomField {
+                    key: key.clone(),
+                    value: None,
+                }),
+        );
+        edits.extend(metadata.custom.iter().map(|(key, value)| MetadataEdit::SetCustomField {
+            key: key.clone(),
+            value: Some(value.clone()),
+        }));
+
+        let mut changed = false;
+        for edit in &edits {
+            changed |= self.apply_uncommitted(edit)?;
+        }
+        if changed {
+            self.doc.commit_with_message("Update metadata");
+        }
+        Ok(changed)
+    }
+
+    /// Copy the document's fields into a metadata view
+    ///
+    /// Timestamps belong to the node record and are left alone. Does
+    /// nothing if the document holds no metadata yet.
+    pub fn apply_to(&self, metadata: &mut NodeMetadata) -> Result<()> {
+        if !self.has_metadata() {
+            return Ok(());
+        }
+        metadata.title = self.title()?;
+        metadata.tags = self.tags();
+        metadata.custom = self.custom_fields()?;
+        Ok(())
+    }
+
+    fn keys_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.doc
+            .inner()
+            .keys(automerge::ROOT)
+            .filter(move |key| key.starts_with(prefix))
+    }
+}
+
+/// Change message describing a metadata edit
+fn edit_message(edit: &MetadataEdit) -> String {
+    match edit {
+        MetadataEdit::SetTitle { .. } => "Set title".to_string(),
+        MetadataEdit::AddTag { tag } => format!("Add tag {}", tag),
+        MetadataEdit::RemoveTag { tag } => format!("Remove tag {}", tag),
+        MetadataEdit::SetCustomField { key, value: Some(_) } => format!("Set field {}", key),
+        MetadataEdit::SetCustomField { key, value: None } => format!("Remove field {}", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pimble_core::Node;
+
+    fn edit_title(title: &str) -> MetadataEdit {
+        MetadataEdit::SetTitle { title: title.into() }
+    }
+
+    fn add_tag(tag: &str) -> MetadataEdit {
+        MetadataEdit::AddTag { tag: tag.into() }
+    }
+
+    #[test]
+    fn test_write_and_derive_view() {
+        let mut node = Node::document("Plan");
+        node.metadata.tags = vec!["work".into(), "draft".into()];
+        node.metadata.custom.insert("priority".into(), serde_json::json!(2));
+
+        let mut meta = MetadataContent::load(&[]).unwrap();
+        assert!(!meta.has_metadata());
+        assert!(meta.write(&node.metadata).unwrap());
+        assert!(!meta.write(&node.metadata).unwrap());
+
+        let mut view = Node::document("").metadata;
+        MetadataContent::load(&meta.save()).unwrap().apply_to(&mut view).unwrap();
+        assert_eq!(view.title, "Plan");
+        assert_eq!(view.tags, vec!["draft".to_string(), "work".to_string()]);
+        assert_eq!(view.custom["priority"], serde_json::json!(2));
+    }
+
+    #[test]
+    fn test_concurrent_field_edits_merge() {
+        let mut base = MetadataContent::load(&[]).unwrap();
+        base.write(&Node::document("Plan").metadata).unwrap();
+        let mut other = MetadataContent::from_document(base.doc.fork());
+
+        base.apply(&add_tag("work")).unwrap();
+        base.apply(&MetadataEdit::SetCustomField {
+            key: "status".into(),
+            value: Some(serde_json::json!("open")),
+        })
+        .unwrap();
+        other.apply(&edit_title("Roadmap")).unwrap();
+        other.apply(&add_tag("ideas")).unwrap();
+
+        base.doc.merge(&mut other.doc).unwrap();
+        assert_eq!(base.title().unwrap(), "Roadmap");
+        assert_eq!(base.tags(), vec!["ideas".to_string(), "work".to_string()]);
+        assert_eq!(base.custom_fields().unwrap()["status"], serde_json::json!("open"));
+
+        base.apply(&MetadataEdit::RemoveTag { tag: "work".into() }).unwrap();
+        assert_eq!(base.tags(), vec!["ideas".to_string()]);
+    }
+}
@@ -1,8 +1,8 @@
 //! Node content management using CRDT documents
 
-use automerge::{transaction::Transactable, ObjType, ReadDoc};
+use automerge::{transaction::Transactable, ChangeHash, Cursor, CursorPosition, ObjId, ObjType, ReadDoc};
 
-use crate::{CrdtDocument, CrdtError, Result};
+use crate::{CrdtDocument, CrdtError, Result, BLOCK_MARKER};
 
 /// Content for a document node (markdown/rich text)
 pub struct DocumentContent {
@@ -11,7 +11,7 @@ pub struct DocumentContent {
 
 impl DocumentContent {
     /// Key for the text content
-    const TEXT_KEY: &'static str = "text";
+    pub(crate) const TEXT_KEY: &'static str = "text";
 
     /// Create new empty document content
     pub fn new() -> Self {
@@ -27,19 +27,40 @@ impl DocumentContent {
         })
     }
 
+    /// Wrap an existing CRDT document
+    pub fn from_document(doc: CrdtDocument) -> Self {
+        Self { doc }
+    }
+
     /// Save document content to bytes
     pub fn save(&mut self) -> Vec<u8> {
         self.doc.save()
     }
 
     /// Get the text content
+    ///
+    /// Block markers read as newlines, so offsets match those taken by
+    /// [`DocumentContent::insert_text`] and [`DocumentContent::delete_text`].
     pub fn get_text(&self) -> Result<String> {
+        self.read_text(None)
+    }
+
+    /// Read the text, now or as it was at `heads`
+    pub(crate) fn read_text(&self, heads: Option<&[ChangeHash]>) -> Result<String> {
         // Check if we have a text object
         let inner = self.doc.inner();
-        match inner.get(automerge::ROOT, Self::TEXT_KEY)? {
+        let value = match heads {
+            Some(heads) => inner.get_at(automerge::ROOT, Self::TEXT_KEY, heads)?,
+            None => inner.get(automerge::ROOT, Self::TEXT_KEY)?,
+        };
+        match value {
             Some((value, id)) => match value {
                 automerge::Value::Object(ObjType::Text) => {
-                    Ok(inner.text(&id)?.to_string())
+                    let text = match heads {
+                        Some(heads) => inner.text_at(&id, heads)?,
+                        None => inner.text(&id)?,
+                    };
+                    Ok(text.replace(BLOCK_MARKER, "\n"))
                 }
                 automerge::Value::Scalar(s) => match s.as_ref() {
                     automerge::ScalarValue::Str(s) => Ok(s.to_string()),
@@ -75,20 +96,8 @@ impl DocumentContent {
 
     /// Insert text at a position
     pub fn insert_text(&mut self, pos: usize, text: &str) -> Result<()> {
-        let inner = self.doc.inner_mut();
-
-        // Get or create the text object
-        let text_id = match inner.get(automerge::ROOT, Self::TEXT_KEY)? {
-            Some((automerge::Value::Object(ObjType::Text), id)) => id,
-            Some(_) => {
-                // Not a text object, recreate it
-                inner.delete(automerge::ROOT, Self::TEXT_KEY)?;
-                inner.put_object(automerge::ROOT, Self::TEXT_KEY, ObjType::Text)?
-            }
-            None => inner.put_object(automerge::ROOT, Self::TEXT_KEY, ObjType::Text)?,
-        };
-
-        inner.splice_text(&text_id, pos, 0, text)?;
+        let text_id = self.text_id()?;
+        self.doc.inner_mut().splice_text(&text_id, pos, 0, text)?;
         Ok(())
     }
 
@@ -105,6 +114,35 @@ impl DocumentContent {
         }
     }
 
+    /// A stable anchor for the position before character `pos`
+    ///
+    /// Unlike an offset, an anchor keeps pointing at the same place in the
+    /// text while others edit before it. Positions at or past the end
+    /// anchor to the end of the text.
+    pub fn anchor_at(&self, pos: usize) -> Result<String> {
+        let Some(text_id) = self.existing_text_id()? else {
+            return Ok(Cursor::Start.to_string());
+        };
+        let inner = self.doc.inner();
+        let position = if pos >= inner.length(&text_id) {
+            CursorPosition::End
+        } else {
+            CursorPosition::Index(pos)
+        };
+        Ok(inner.get_cursor(&text_id, position, None)?.to_string())
+    }
+
+    /// The current offset of an anchor from [`DocumentContent::anchor_at`]
+    ///
+    /// If the anchored character has been deleted, this is where it was.
+    pub fn anchor_position(&self, anchor: &str) -> Result<usize> {
+        let cursor = Cursor::try_from(anchor).map_err(|_| CrdtError::InvalidAnchor(anchor.to_string()))?;
+        match self.existing_text_id()? {
+            Some(text_id) => Ok(self.doc.inner().get_cursor_position(&text_id, &cursor, None)?),
+            None => Ok(0),
+        }
+    }
+
     /// Get the underlying CRDT document
     pub fn document(&self) -> &CrdtDocument {
         &self.doc
@@ -114,6 +152,28 @@ impl DocumentContent {
     pub fn document_mut(&mut self) -> &mut CrdtDocument {
         &mut self.doc
     }
+
+    /// The text object, if there is one
+    fn existing_text_id(&self) -> Result<Option<ObjId>> {
+        Ok(match self.doc.inner().get(automerge::ROOT, Self::TEXT_KEY)? {
+            Some((automerge::Value::Object(ObjType::Text), id)) => Some(id),
+            _ => None,
+        })
+    }
+
+    /// Get or create the text object
+    pub(crate) fn text_id(&mut self) -> Result<ObjId> {
+        let inner = self.doc.inner_mut();
+        Ok(match inner.get(automerge::ROOT, Self::TEXT_KEY)? {
+            Some((automerge::Value::Object(ObjType::Text), id)) => id,
+            Some(_) => {
+                // Not a text object, recreate it
+                inner.delete(automerge::ROOT, Self::TEXT_KEY)?;
+                inner.put_object(automerge::ROOT, Self::TEXT_KEY, ObjType::Text)?
+            }
+            None => inner.put_object(automerge::ROOT, Self::TEXT_KEY, ObjType::Text)?,
+        })
+    }
 }
 
 impl Default for DocumentContent {
@@ -186,6 +246,22 @@ mod tests {
         assert_eq!(content.get_text().unwrap(), "HelloWorld!");
     }
 
+    #[test]
+    fn test_anchor_follows_edits() {
+        let mut content = DocumentContent::new();
+        content.set_text("Hello World").unwrap();
+        let anchor = content.anchor_at(6).unwrap();
+        let end = content.anchor_at(11).unwrap();
+
+        content.insert_text(0, ">> ").unwrap();
+        assert_eq!(content.anchor_position(&anchor).unwrap(), 9);
+        assert_eq!(content.anchor_position(&end).unwrap(), 14);
+
+        content.delete_text(0, 9).unwrap();
+        assert_eq!(content.anchor_position(&anchor).unwrap(), 0);
+        assert!(content.anchor_position("not an anchor").is_err());
+    }
+
     #[test]
     fn test_document_save_load() {
         let mut content = DocumentContent::new();
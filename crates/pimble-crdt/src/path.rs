@@ -0,0 +1,459 @@
+//! Nested paths into documents, and conversion to and from JSON
+//!
+//! A path is a list of segments, map keys and list indexes, leading from
+//! the root of a document to a value: `path!["tasks", 3, "done"]`. Values
+//! are read and written through serde, so structured content can be
+//! stored as nested Automerge maps and lists rather than flattened into
+//! root keys, and any serde type can be kept in a document.
+//!
+//! JSON objects become maps, arrays become lists and the rest become
+//! scalars, so JSON round-trips losslessly. Text objects read as strings.
+
+use std::fmt;
+
+use automerge::{transaction::Transactable, ObjId, ObjType, Prop, ReadDoc, ScalarValue, ROOT};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{CrdtDocument, CrdtError, Result};
+
+/// One step of a path: a map key or a list index
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+impl From<&str> for PathSegment {
+    fn from(key: &str) -> Self {
+        Self::Key(key.to_string())
+    }
+}
+
+impl From<String> for PathSegment {
+    fn from(key: String) -> Self {
+        Self::Key(key)
+    }
+}
+
+impl From<usize> for PathSegment {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key) => write!(f, "{}", key),
+            Self::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+/// Build a path from keys and indexes: `path!["tasks", 3, "done"]`
+#[macro_export]
+macro_rules! path {
+    ($($segment:expr),* $(,)?) => {
+        [$($crate::PathSegment::from($segment)),*]
+    };
+}
+
+/// Where a value is written within its parent
+enum Slot {
+    /// Over the value at a key or index
+    Put(Prop),
+    /// As a new list element at an index
+    Insert(usize),
+}
+
+fn path_string(path: &[PathSegment]) -> String {
+    path.iter().map(ToString::to_string).collect::<Vec<_>>().join("/")
+}
+
+fn invalid_path(path: &[PathSegment], reason: &str) -> CrdtError {
+    CrdtError::InvalidPath(format!("{}: {}", path_string(path), reason))
+}
+
+fn json_value<T: Serialize + ?Sized>(value: &T) -> Result<serde_json::Value> {
+    serde_json::to_value(value).map_err(|e| CrdtError::Serialization(e.to_string()))
+}
+
+/// The kind of object a segment indexes into
+fn container_for(segment: &PathSegment) -> ObjType {
+    match segment {
+        PathSegment::Key(_) => ObjType::Map,
+        PathSegment::Index(_) => ObjType::List,
+    }
+}
+
+impl CrdtDocument {
+    /// Read the value at a path, if there is one
+    ///
+    /// Use `serde_json::Value` for `T` to read whatever is there. The empty
+    /// path reads the whole document.
+    pub fn get_at<T: DeserializeOwned>(&self, path: &[PathSegment]) -> Result<Option<T>> {
+        let Some(value) = self.json_at(path)? else {
+            return Ok(None);
+        };
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(|e| CrdtError::Serialization(e.to_string()))
+    }
+
+    /// Write a value at a path, creating missing maps and lists on the way
+    ///
+    /// Maps and lists are written as new objects replacing what was there,
+    /// so to merge with concurrent edits inside one, set its fields. A list
+    /// index may be one past the end, to append. The empty path replaces
+    /// the whole document, which takes a map.
+    pub fn set_at<T: Serialize + ?Sized>(&mut self, path: &[PathSegment], value: &T) -> Result<()> {
+        let value = json_value(value)?;
+        let Some((last, parent_path)) = path.split_last() else {
+            return self.replace_root(&value);
+        };
+        let parent = self.container_mut(parent_path, container_for(last))?;
+        let slot = self.slot(&parent, last, path, false)?;
+        self.write_json(&parent, slot, &value)
+    }
+
+    /// Insert a value into the list at a path, before `index`
+    ///
+    /// The list is created if it's missing.
+    pub fn insert_at<T: Serialize + ?Sized>(&mut self, path: &[PathSegment], index: usize, value: &T) -> Result<()> {
+        let value = json_value(value)?;
+        let list = self.container_mut(path, ObjType::List)?;
+        let slot = self.slot(&list, &PathSegment::Index(index), path, true)?;
+        self.write_json(&list, slot, &value)
+    }
+
+    /// Append a value to the list at a path, returning its index
+    pub fn push_at<T: Serialize + ?Sized>(&mut self, path: &[PathSegment], value: &T) -> Result<usize> {
+        let index = self.len_at(path)?.unwrap_or(0);
+        self.insert_at(path, index, value)?;
+        Ok(index)
+    }
+
+    /// Remove the map entry or list element at a path
+    pub fn delete_at(&mut self, path: &[PathSegment]) -> Result<()> {
+        let Some((last, parent_path)) = path.split_last() else {
+            return Err(invalid_path(path, "can't delete the root"));
+        };
+        let Some(parent) = self.object_at(parent_path)? else {
+            return Err(CrdtError::KeyNotFound(path_string(path)));
+        };
+        match self.slot(&parent, last, path, false)? {
+            Slot::Put(prop) if self.inner().get(&parent, prop.clone())?.is_some() => {
+                self.inner_mut().delete(&parent, prop)?;
+                Ok(())
+            }
+            _ => Err(CrdtError::KeyNotFound(path_string(path))),
+        }
+    }
+
+    /// Number of elements in the list, characters in the text or entries in
+    /// the map at a path, if there is one
+    pub fn len_at(&self, path: &[PathSegment]) -> Result<Option<usize>> {
+        let Some(obj) = self.object_at(path)? else {
+            return Ok(None);
+        };
+        Ok(Some(self.inner().length(&obj)))
+    }
+
+    /// Convert the whole document to a JSON object
+    pub fn to_json(&self) -> Result<serde_json::Value> {
+        self.object_json(&ROOT)
+    }
+
+    /// Create a document holding a JSON object
+    pub fn from_json(value: &serde_json::Value) -> Result<Self> {
+        let mut doc = Self::new();
+        doc.replace_root(value)?;
+        Ok(doc)
+    }
+
+    /// Read any value as JSON: scalars as themselves, text as a string and
+    /// maps and lists recursively
+    pub(crate) fn value_json(&self, value: automerge::Value<'_>, id: &ObjId) -> Result<serde_json::Value> {
+        Ok(match value {
+            automerge::Value::Object(_) => self.object_json(id)?,
+            automerge::Value::Scalar(s) => match s.as_ref() {
+                ScalarValue::Str(s) => serde_json::Value::String(s.to_string()),
+                ScalarValue::Int(i) => serde_json::Value::from(*i),
+                ScalarValue::Uint(u) => serde_json::Value::from(*u),
+                ScalarValue::F64(f) => serde_json::Value::from(*f),
+                ScalarValue::Counter(c) => serde_json::Value::from(i64::from(c)),
+                ScalarValue::Timestamp(t) => serde_json::Value::from(*t),
+                ScalarValue::Boolean(b) => serde_json::Value::Bool(*b),
+                ScalarValue::Null => serde_json::Value::Null,
+                other => serde_json::Value::String(other.to_string()),
+            },
+        })
+    }
+
+    /// Write a JSON value into a parent object
+    pub(crate) fn put_json(&mut self, obj: &ObjId, key: &str, value: &serde_json::Value) -> Result<()> {
+        self.write_json(obj, Slot::Put(key.into()), value)
+    }
+
+    fn object_json(&self, obj: &ObjId) -> Result<serde_json::Value> {
+        let doc = self.inner();
+        Ok(match doc.object_type(obj)? {
+            ObjType::Map | ObjType::Table => {
+                let mut map = serde_json::Map::new();
+                for key in doc.keys(obj) {
+                    if let Some((value, id)) = doc.get(obj, key.as_str())? {
+                        let value = self.value_json(value, &id)?;
+                        map.insert(key, value);
+                    }
+                }
+                serde_json::Value::Object(map)
+            }
+            ObjType::List => {
+                let mut items = Vec::new();
+                for index in 0..doc.length(obj) {
+                    if let Some((value, id)) = doc.get(obj, index)? {
+                        items.push(self.value_json(value, &id)?);
+                    }
+                }
+                serde_json::Value::Array(items)
+            }
+            ObjType::Text => serde_json::Value::String(doc.text(obj)?),
+        })
+    }
+
+    fn json_at(&self, path: &[PathSegment]) -> Result<Option<serde_json::Value>> {
+        let Some((last, parent_path)) = path.split_last() else {
+            return self.to_json().map(Some);
+        };
+        let Some(parent) = self.object_at(parent_path)? else {
+            return Ok(None);
+        };
+        match self.child(&parent, last, path)? {
+            Some((value, id)) => self.value_json(value, &id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the map, list or text at a path
+    fn object_at(&self, path: &[PathSegment]) -> Result<Option<ObjId>> {
+        let mut obj = ROOT;
+        for (depth, segment) in path.iter().enumerate() {
+            match self.child(&obj, segment, &path[..=depth])? {
+                Some((automerge::Value::Object(_), id)) => obj = id,
+                Some((automerge::Value::Scalar(_), _)) => {
+                    return Err(invalid_path(&path[..=depth], "not a map or list"))
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(obj))
+    }
+
+    /// Find the map or list at a path, creating it and any missing parents
+    ///
+    /// Parents are created as maps or lists according to the segment that
+    /// follows them; the object at the end of the path is created as `last`.
+    fn container_mut(&mut self, path: &[PathSegment], last: ObjType) -> Result<ObjId> {
+        let mut obj = ROOT;
+        for (depth, segment) in path.iter().enumerate() {
+            let here = &path[..=depth];
+            obj = match self.child(&obj, segment, here)? {
+                Some((automerge::Value::Object(ObjType::Text), _)) => {
+                    return Err(invalid_path(here, "text can't hold values"))
+                }
+                Some((automerge::Value::Object(_), id)) => id,
+                Some((automerge::Value::Scalar(_), _)) => return Err(invalid_path(here, "not a map or list")),
+                None => {
+                    let obj_type = path.get(depth + 1).map_or(last, container_for);
+                    match self.slot(&obj, segment, here, false)? {
+                        Slot::Put(prop) => self.inner_mut().put_object(&obj, prop, obj_type)?,
+                        Slot::Insert(index) => self.inner_mut().insert_object(&obj, index, obj_type)?,
+                    }
+                }
+            };
+        }
+        Ok(obj)
+    }
+
+    /// Get the value under a segment of a map or list
+    fn child(
+        &self,
+        obj: &ObjId,
+        segment: &PathSegment,
+        path: &[PathSegment],
+    ) -> Result<Option<(automerge::Value<'_>, ObjId)>> {
+        let doc = self.inner();
+        match (doc.object_type(obj)?, segment) {
+            (ObjType::Map | ObjType::Table, PathSegment::Key(key)) => Ok(doc.get(obj, key.as_str())?),
+            (ObjType::List, PathSegment::Index(index)) if *index < doc.length(obj) => Ok(doc.get(obj, *index)?),
+            (ObjType::List, PathSegment::Index(_)) => Ok(None),
+            (ObjType::Map | ObjType::Table, PathSegment::Index(_)) => Err(invalid_path(path, "index into a map")),
+            (ObjType::List, PathSegment::Key(_)) => Err(invalid_path(path, "key into a list")),
+            (ObjType::Text, _) => Err(invalid_path(path, "path into text")),
+        }
+    }
+
+    /// Work out where a value under a segment of a map or list goes
+    ///
+    /// List indexes may be one past the end; with `insert` the value always
+    /// goes in as a new element.
+    fn slot(&self, obj: &ObjId, segment: &PathSegment, path: &[PathSegment], insert: bool) -> Result<Slot> {
+        match (self.inner().object_type(obj)?, segment) {
+            (ObjType::Map | ObjType::Table, PathSegment::Key(key)) => Ok(Slot::Put(key.as_str().into())),
+            (ObjType::List, PathSegment::Index(index)) => {
+                let len = self.inner().length(obj);
+                match *index {
+                    index if index < len && !insert => Ok(Slot::Put(index.into())),
+                    index if index <= len => Ok(Slot::Insert(index)),
+                    _ => Err(invalid_path(path, &format!("index out of bounds for a list of {}", len))),
+                }
+            }
+            (ObjType::Map | ObjType::Table, PathSegment::Index(_)) => Err(invalid_path(path, "index into a map")),
+            (ObjType::List, PathSegment::Key(_)) => Err(invalid_path(path, "key into a list")),
+            (ObjType::Text, _) => Err(invalid_path(path, "path into text")),
+        }
+    }
+
+    fn write_json(&mut self, obj: &ObjId, slot: Slot, value: &serde_json::Value) -> Result<()> {
+        let scalar = match value {
+            serde_json::Value::Object(map) => {
+                let map_id = self.create_object(obj, slot, ObjType::Map)?;
+                for (key, value) in map {
+                    self.put_json(&map_id, key, value)?;
+                }
+                return Ok(());
+            }
+            serde_json::Value::Array(items) => {
+                let list_id = self.create_object(obj, slot, ObjType::List)?;
+                for (index, value) in items.iter().enumerate() {
+                    self.write_json(&list_id, Slot::Insert(index), value)?;
+                }
+                return Ok(());
+            }
+            serde_json::Value::Null => ScalarValue::Null,
+            serde_json::Value::Bool(b) => ScalarValue::Boolean(*b),
+            serde_json::Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+                (Some(i), _) => ScalarValue::Int(i),
+                (None, Some(u)) => ScalarValue::Uint(u),
+                (None, None) => ScalarValue::F64(n.as_f64().unwrap_or_default()),
+            },
+            serde_json::Value::String(s) => ScalarValue::from(s.as_str()),
+        };
+        match slot {
+            Slot::Put(prop) => self.inner_mut().put(obj, prop, scalar)?,
+            Slot::Insert(index) => self.inner_mut().insert(obj, index, scalar)?,
+        }
+        Ok(())
+    }
+
+    fn create_object(&mut self, obj: &ObjId, slot: Slot, obj_type: ObjType) -> Result<ObjId> {
+        Ok(match slot {
+            Slot::Put(prop) => self.inner_mut().put_object(obj, prop, obj_type)?,
+            Slot::Insert(index) => self.inner_mut().insert_object(obj, index, obj_type)?,
+        })
+    }
+
+    /// Make the root hold exactly the entries of a JSON object
+    fn replace_root(&mut self, value: &serde_json::Value) -> Result<()> {
+        let serde_json::Value::Object(map) = value else {
+            return Err(CrdtError::TypeMismatch {
+                expected: "object".to_string(),
+                actual: value.to_string(),
+            });
+        };
+        for key in self.inner().keys(ROOT).collect::<Vec<_>>() {
+            if !map.contains_key(&key) {
+                self.inner_mut().delete(ROOT, key.as_str())?;
+            }
+        }
+        for (key, value) in map {
+            self.put_json(&ROOT, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Task {
+        title: String,
+        done: bool,
+        estimate: Option<f64>,
+    }
+
+    #[test]
+    fn test_nested_paths() {
+        let mut doc = CrdtDocument::new();
+        doc.set_at(&path!["project", "name"], "Launch").unwrap();
+        doc.push_at(&path!["tasks"], &json!({"title": "Plan", "done": false})).unwrap();
+        doc.push_at(&path!["tasks"], &json!({"title": "Ship", "done": false})).unwrap();
+        doc.set_at(&path!["tasks", 1, "done"], &true).unwrap();
+
+        assert_eq!(doc.get_at::<String>(&path!["project", "name"]).unwrap(), Some("Launch".to_string()));
+        assert_eq!(doc.get_at::<bool>(&path!["tasks", 1, "done"]).unwrap(), Some(true));
+        assert_eq!(doc.get_at::<bool>(&path!["tasks", 5, "done"]).unwrap(), None);
+        assert_eq!(doc.len_at(&path!["tasks"]).unwrap(), Some(2));
+
+        doc.insert_at(&path!["tasks"], 0, &json!({"title": "Draft", "done": true})).unwrap();
+        doc.delete_at(&path!["tasks", 2]).unwrap();
+        let titles: Vec<String> = (0..2usize)
+            .map(|i| doc.get_at(&path!["tasks", i, "title"]).unwrap().unwrap())
+            .collect();
+        assert_eq!(titles, vec!["Draft", "Plan"]);
+
+        // Paths must match the shape of the document
+        assert!(matches!(doc.set_at(&path!["tasks", "first"], &1), Err(CrdtError::InvalidPath(_))));
+        assert!(matches!(doc.set_at(&path!["tasks", 7], &1), Err(CrdtError::InvalidPath(_))));
+        assert!(matches!(doc.get_at::<bool>(&path!["project", "name", "x"]), Err(CrdtError::InvalidPath(_))));
+        assert!(matches!(doc.delete_at(&path!["project", "owner"]), Err(CrdtError::KeyNotFound(_))));
+    }
+
+    #[test]
+    fn test_serde_values() {
+        let mut doc = CrdtDocument::new();
+        let task = Task {
+            title: "Review".to_string(),
+            done: false,
+            estimate: Some(1.5),
+        };
+        doc.set_at(&path!["task"], &task).unwrap();
+        assert_eq!(doc.get_at::<Task>(&path!["task"]).unwrap(), Some(task));
+
+        // Fields of a stored value merge with concurrent edits
+        let mut theirs = doc.fork();
+        theirs.set_at(&path!["task", "done"], &true).unwrap();
+        doc.set_at(&path!["task", "title"], "Review PR").unwrap();
+        doc.merge(&mut theirs).unwrap();
+        let merged: Task = doc.get_at(&path!["task"]).unwrap().unwrap();
+        assert_eq!(merged.title, "Review PR");
+        assert!(merged.done);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = json!({
+            "title": "Notes",
+            "count": -3,
+            "big": u64::MAX,
+            "ratio": 0.25,
+            "flags": [true, null, {"nested": ["a", 1]}],
+            "empty": {}
+        });
+        let mut doc = CrdtDocument::from_json(&value).unwrap();
+        assert_eq!(doc.to_json().unwrap(), value);
+
+        let loaded = CrdtDocument::load(&doc.save()).unwrap();
+        assert_eq!(loaded.to_json().unwrap(), value);
+        assert_eq!(loaded.get_at::<serde_json::Value>(&[]).unwrap(), Some(value));
+
+        let mut replaced = loaded;
+        replaced.set_at(&[], &json!({"title": "Other"})).unwrap();
+        assert_eq!(replaced.to_json().unwrap(), json!({"title": "Other"}));
+        assert!(CrdtDocument::from_json(&json!([1, 2])).is_err());
+    }
+}
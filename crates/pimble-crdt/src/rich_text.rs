@@ -0,0 +1,738 @@
+//! Rich text in document content: inline marks and block structure
+//!
+//! Formatting lives in the text object itself, the way Automerge models rich
+//! text: bold, italic, code and link spans are marks, and every block starts
+//! with a block marker naming its kind. Both merge with concurrent edits just
+//! like the characters around them. Markdown is the interchange format, so
+//! export sees the structure the editor produces.
+
+use std::sync::Arc;
+
+use automerge::iter::Span;
+use automerge::marks::{ExpandMark, Mark, MarkSet};
+use automerge::transaction::Transactable;
+use automerge::{hydrate, AutoCommit, ObjId, ObjType, ReadDoc, ScalarValue, ROOT};
+use serde::{Deserialize, Serialize};
+
+use crate::{DocumentContent, Result};
+
+/// Character Automerge puts in plain text in place of a block marker
+pub const BLOCK_MARKER: char = '\u{fffc}';
+
+/// The kind of a block, stored in the marker that starts it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockKind {
+    Paragraph,
+    Heading { level: u8 },
+    BulletItem,
+    OrderedItem,
+    Quote,
+    CodeBlock { language: Option<String> },
+}
+
+impl BlockKind {
+    /// Read the kind of a block marker; unknown kinds read as paragraphs
+    fn from_marker(marker: &hydrate::Map) -> Self {
+        let Some(hydrate::Value::Scalar(ScalarValue::Str(kind))) = marker.get("type") else {
+            return Self::Paragraph;
+        };
+        match kind.as_str() {
+            "heading" => {
+                let level = match marker_attr(marker, "level") {
+                    Some(hydrate::Value::Scalar(ScalarValue::Int(level))) => *level,
+                    Some(hydrate::Value::Scalar(ScalarValue::Uint(level))) => *level as i64,
+                    _ => 1,
+                };
+                Self::Heading {
+                    level: level.clamp(1, 6) as u8,
+                }
+            }
+            "unordered-list-item" => Self::BulletItem,
+            "ordered-list-item" => Self::OrderedItem,
+            "blockquote" => Self::Quote,
+            "code-block" => Self::CodeBlock {
+                language: match marker_attr(marker, "language") {
+                    Some(hydrate::Value::Scalar(ScalarValue::Str(language))) => Some(language.to_string()),
+                    _ => None,
+                },
+            },
+            _ => Self::Paragraph,
+        }
+    }
+
+    /// Write this kind into a block marker
+    fn write_marker(&self, inner: &mut AutoCommit, marker: &ObjId) -> Result<()> {
+        let kind = match self {
+            Self::Paragraph => "paragraph",
+            Self::Heading { .. } => "heading",
+            Self::BulletItem => "unordered-list-item",
+            Self::OrderedItem => "ordered-list-item",
+            Self::Quote => "blockquote",
+            Self::CodeBlock { .. } => "code-block",
+        };
+        inner.put(marker, "type", kind)?;
+        inner.put_object(marker, "parents", ObjType::List)?;
+        let attrs = inner.put_object(marker, "attrs", ObjType::Map)?;
+        match self {
+            Self::Heading { level } => inner.put(&attrs, "level", *level as i64)?,
+            Self::CodeBlock { language: Some(language) } => inner.put(&attrs, "language", language.as_str())?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn marker_attr<'a>(marker: &'a hydrate::Map, key: &str) -> Option<&'a hydrate::Value> {
+    match marker.get("attrs") {
+        Some(hydrate::Value::Map(attrs)) => attrs.get(key),
+        _ => None,
+    }
+}
+
+/// Inline formatting applied to a span of text
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextMark {
+    Bold,
+    Italic,
+    Code,
+    Link { url: String },
+}
+
+impl TextMark {
+    /// Name of the Automerge mark
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Bold => "strong",
+            Self::Italic => "em",
+            Self::Code => "code",
+            Self::Link { .. } => "link",
+        }
+    }
+
+    fn value(&self) -> ScalarValue {
+        match self {
+            Self::Link { url } => ScalarValue::from(url.as_str()),
+            _ => ScalarValue::Boolean(true),
+        }
+    }
+
+    /// Whether text typed at the edges of the span takes on the mark
+    pub(crate) fn expand(&self) -> ExpandMark {
+        match self {
+            Self::Bold | Self::Italic => ExpandMark::After,
+            Self::Code | Self::Link { .. } => ExpandMark::None,
+        }
+    }
+
+    pub(crate) fn from_mark(name: &str, value: &ScalarValue) -> Option<Self> {
+        match (name, value) {
+            (_, ScalarValue::Null) => None,
+            ("strong", _) => Some(Self::Bold),
+            ("em", _) => Some(Self::Italic),
+            ("code", _) => Some(Self::Code),
+            ("link", ScalarValue::Str(url)) => Some(Self::Link { url: url.to_string() }),
+            _ => None,
+        }
+    }
+
+    /// Nesting order in markdown, outermost first
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Link { .. } => 0,
+            Self::Bold => 1,
+            Self::Italic => 2,
+            Self::Code => 3,
+        }
+    }
+
+    fn markdown_delimiters(&self) -> (&'static str, String) {
+        match self {
+            Self::Bold => ("**", "**".to_string()),
+            Self::Italic => ("_", "_".to_string()),
+            Self::Code => ("`", "`".to_string()),
+            Self::Link { url } => ("[", format!("]({})", url)),
+        }
+    }
+}
+
+/// A run of text with the same marks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RichSpan {
+    pub text: String,
+    /// Marks on the text, outermost first
+    pub marks: Vec<TextMark>,
+}
+
+/// A block of content with its marked spans
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RichBlock {
+    pub kind: BlockKind,
+    pub spans: Vec<RichSpan>,
+}
+
+impl RichBlock {
+    /// The block's text without formatting
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+}
+
+impl DocumentContent {
+    /// Create document content from markdown
+    pub fn from_markdown(markdown: &str) -> Result<Self> {
+        let mut content = Self::new();
+        content.set_markdown(markdown)?;
+        Ok(content)
+    }
+
+    /// Replace all content with the blocks and marks of some markdown
+    pub fn set_markdown(&mut self, markdown: &str) -> Result<()> {
+        self.set_blocks(&parse_markdown(markdown))
+    }
+
+    /// Replace all content with the given blocks
+    pub fn set_blocks(&mut self, blocks: &[RichBlock]) -> Result<()> {
+        let inner = self.document_mut().inner_mut();
+        let text = inner.put_object(ROOT, Self::TEXT_KEY, ObjType::Text)?;
+
+        // Marks go on once all the text is in, so text inserted at the end of
+        // a mark doesn't take it on
+        let mut marks = Vec::new();
+        let mut pos = 0;
+        for block in blocks {
+            let marker = inner.split_block(&text, pos)?;
+            block.kind.write_marker(inner, &marker)?;
+            pos += 1;
+            for span in &block.spans {
+                let len = span.text.chars().count();
+                inner.splice_text(&text, pos, 0, &span.text)?;
+                marks.extend(span.marks.iter().map(|mark| (pos, pos + len, mark)));
+                pos += len;
+            }
+        }
+        for (start, end, mark) in marks {
+            let mark_data = Mark::new(mark.name().to_string(), mark.value(), start, end);
+            inner.mark(&text, mark_data, mark.expand())?;
+        }
+        Ok(())
+    }
+
+    /// Start a new block at a position, splitting the block there
+    pub fn insert_block(&mut self, pos: usize, kind: &BlockKind) -> Result<()> {
+        let text = self.text_id()?;
+        let inner = self.document_mut().inner_mut();
+        let marker = inner.split_block(&text, pos)?;
+        kind.write_marker(inner, &marker)
+    }
+
+    /// Change the kind of the block holding a position
+    ///
+    /// Text before the first block marker gets a marker of its own.
+    pub fn set_block_kind(&mut self, pos: usize, kind: &BlockKind) -> Result<()> {
+        let text = self.text_id()?;
+        let inner = self.document_mut().inner_mut();
+
+        let mut marker_pos = None;
+        let mut offset = 0;
+        for span in inner.spans(&text)? {
+            if offset > pos {
+                break;
+            }
+            match span {
+                Span::Block(_) => {
+                    marker_pos = Some(offset);
+                    offset += 1;
+                }
+                Span::Text(text, _) => offset += text.chars().count(),
+            }
+        }
+
+        let marker = match marker_pos {
+            Some(p) => inner.get(&text, p)?.map(|(_, marker)| marker),
+            None => None,
+        };
+        let marker = match marker {
+            Some(marker) => marker,
+            None => inner.split_block(&text, 0)?,
+        };
+        kind.write_marker(inner, &marker)
+    }
+
+    /// Apply a mark to the text between two positions
+    pub fn mark(&mut self, start: usize, end: usize, mark: &TextMark) -> Result<()> {
+        let text = self.text_id()?;
+        let mark_data = Mark::new(mark.name().to_string(), mark.value(), start, end);
+        self.document_mut().inner_mut().mark(&text, mark_data, mark.expand())?;
+        Ok(())
+    }
+
+    /// Remove a mark from the text between two positions
+    pub fn unmark(&mut self, start: usize, end: usize, mark: &TextMark) -> Result<()> {
+        let text = self.text_id()?;
+        self.document_mut()
+            .inner_mut()
+            .unmark(&text, mark.name(), start, end, mark.expand())?;
+        Ok(())
+    }
+
+    /// Rewrite the URLs of link marks
+    ///
+    /// `rewrite` returns the new URL for links that should change. Returns
+    /// whether anything changed.
+    pub fn rewrite_link_marks(&mut self, mut rewrite: impl FnMut(&str) -> Option<String>) -> Result<bool> {
+        let inner = self.document().inner();
+        let Some((automerge::Value::Object(ObjType::Text), text)) = inner.get(ROOT, Self::TEXT_KEY)? else {
+            return Ok(false);
+        };
+
+        let mut rewritten = Vec::new();
+        let mut pos = 0;
+        for span in inner.spans(&text)? {
+            match span {
+                Span::Block(_) => pos += 1,
+                Span::Text(text, marks) => {
+                    let len = text.chars().count();
+                    for mark in text_marks(marks.as_ref()) {
+                        if let TextMark::Link { url } = mark {
+                            if let Some(url) = rewrite(&url) {
+                                rewritten.push((pos, pos + len, TextMark::Link { url }));
+                            }
+                        }
+                    }
+                    pos += len;
+                }
+            }
+        }
+        for (start, end, mark) in &rewritten {
+            self.mark(*start, *end, mark)?;
+        }
+        Ok(!rewritten.is_empty())
+    }
+
+    /// Get the content as blocks of marked spans
+    ///
+    /// Text before the first block marker, which is all the text of content
+    /// written before blocks existed, reads as a paragraph.
+    pub fn blocks(&self) -> Result<Vec<RichBlock>> {
+        let inner = self.document().inner();
+        let Some((automerge::Value::Object(ObjType::Text), text)) = inner.get(ROOT, Self::TEXT_KEY)? else {
+            let text = self.get_text()?;
+            if text.is_empty() {
+                return Ok(Vec::new());
+            }
+            return Ok(vec![RichBlock {
+                kind: BlockKind::Paragraph,
+                spans: vec![RichSpan { text, marks: Vec::new() }],
+            }]);
+        };
+
+        let mut blocks: Vec<RichBlock> = Vec::new();
+        for span in inner.spans(&text)? {
+            match span {
+                Span::Block(marker) => blocks.push(RichBlock {
+                    kind: BlockKind::from_marker(&marker),
+                    spans: Vec::new(),
+                }),
+                Span::Text(text, marks) => {
+                    if blocks.is_empty() {
+                        blocks.push(RichBlock {
+                            kind: BlockKind::Paragraph,
+                            spans: Vec::new(),
+                        });
+                    }
+                    if let Some(block) = blocks.last_mut() {
+                        push_span(&mut block.spans, &text, text_marks(marks.as_ref()));
+                    }
+                }
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Render the content as markdown
+    ///
+    /// Content without block markers is taken to be markdown already and
+    /// comes back as it is.
+    pub fn to_markdown(&self) -> Result<String> {
+        Ok(render_markdown(&self.blocks()?))
+    }
+}
+
+fn text_marks(marks: Option<&Arc<MarkSet>>) -> Vec<TextMark> {
+    let mut marks: Vec<TextMark> = marks
+        .into_iter()
+        .flat_map(|set| set.iter())
+        .filter_map(|(name, value)| TextMark::from_mark(name, value))
+        .collect();
+    marks.sort_by_key(TextMark::rank);
+    marks
+}
+
+/// Append text to a list of spans, extending the last one if the marks match
+fn push_span(spans: &mut Vec<RichSpan>, text: &str, marks: Vec<TextMark>) {
+    if text.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(last) if last.marks == marks => last.text.push_str(text),
+        _ => spans.push(RichSpan {
+            text: text.to_string(),
+            marks,
+        }),
+    }
+}
+
+/// Render blocks as markdown
+pub fn render_markdown(blocks: &[RichBlock]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&BlockKind> = None;
+    let mut number = 0;
+    for block in blocks {
+        let continues_list = previous == Some(&block.kind)
+            && matches!(block.kind, BlockKind::BulletItem | BlockKind::OrderedItem);
+        if previous.is_some() {
+            out.push_str(if continues_list { "\n" } else { "\n\n" });
+        }
+        number = if continues_list { number + 1 } else { 1 };
+
+        match &block.kind {
+            BlockKind::Paragraph => out.push_str(&render_inline(&block.spans)),
+            BlockKind::Heading { level } => {
+                out.push_str(&"#".repeat(*level as usize));
+                out.push(' ');
+                out.push_str(&render_inline(&block.spans));
+            }
+            BlockKind::BulletItem => {
+                out.push_str("- ");
+                out.push_str(&render_inline(&block.spans));
+            }
+            BlockKind::OrderedItem => {
+                out.push_str(&format!("{}. ", number));
+                out.push_str(&render_inline(&block.spans));
+            }
+            BlockKind::Quote => {
+                let quoted: Vec<String> = render_inline(&block.spans)
+                    .split('\n')
+                    .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+                    .collect();
+                out.push_str(&quoted.join("\n"));
+            }
+            BlockKind::CodeBlock { language } => {
+                out.push_str("```");
+                out.push_str(language.as_deref().unwrap_or(""));
+                out.push('\n');
+                out.push_str(&block.text());
+                out.push_str("\n```");
+            }
+        }
+        previous = Some(&block.kind);
+    }
+    out
+}
+
+fn render_inline(spans: &[RichSpan]) -> String {
+    let mut out = String::new();
+    let mut open: Vec<&TextMark> = Vec::new();
+    for span in spans {
+        // Close back to the marks this span shares, then open the rest
+        let keep = open.iter().take_while(|&&mark| span.marks.contains(mark)).count();
+        while open.len() > keep {
+            if let Some(mark) = open.pop() {
+                out.push_str(&mark.markdown_delimiters().1);
+            }
+        }
+        for mark in &span.marks {
+            if !open.contains(&mark) {
+                out.push_str(mark.markdown_delimiters().0);
+                open.push(mark);
+            }
+        }
+        out.push_str(&span.text);
+    }
+    while let Some(mark) = open.pop() {
+        out.push_str(&mark.markdown_delimiters().1);
+    }
+    out
+}
+
+/// Parse markdown into blocks
+///
+/// Covers what the editor produces: ATX headings, bullet and numbered list
+/// items, block quotes, fenced code blocks and paragraphs, with bold, italic,
+/// code and link spans. Anything else is kept as paragraph text.
+pub fn parse_markdown(markdown: &str) -> Vec<RichBlock> {
+    let mut blocks = Vec::new();
+    // The block being built, for blocks that continue over several lines
+    let mut current: Option<(BlockKind, String)> = None;
+    let finish = |blocks: &mut Vec<RichBlock>, current: &mut Option<(BlockKind, String)>| {
+        if let Some((kind, text)) = current.take() {
+            let mut spans = Vec::new();
+            parse_inline(&text, &[], &mut spans);
+            blocks.push(RichBlock { kind, spans });
+        }
+    };
+
+    let mut lines = markdown.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+
+        if let Some(info) = trimmed.strip_prefix("```") {
+            finish(&mut blocks, &mut current);
+            let code: Vec<&str> = lines.by_ref().take_while(|l| !l.trim().starts_with("```")).collect();
+            let mut spans = Vec::new();
+            push_span(&mut spans, &code.join("\n"), Vec::new());
+            blocks.push(RichBlock {
+                kind: BlockKind::CodeBlock {
+                    language: Some(info.trim().to_string()).filter(|l| !l.is_empty()),
+                },
+                spans,
+            });
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            finish(&mut blocks, &mut current);
+            continue;
+        }
+
+        match (block_start(trimmed), &mut current) {
+            (Some((BlockKind::Quote, text)), Some((BlockKind::Quote, quoted))) => {
+                quoted.push('\n');
+                quoted.push_str(text);
+            }
+            (Some((kind, text)), _) => {
+                finish(&mut blocks, &mut current);
+                let is_heading = matches!(kind, BlockKind::Heading { .. });
+                current = Some((kind, text.to_string()));
+                if is_heading {
+                    finish(&mut blocks, &mut current);
+                }
+            }
+            // Lines without a marker continue paragraphs and list items
+            (None, Some((BlockKind::Paragraph | BlockKind::BulletItem | BlockKind::OrderedItem, text))) => {
+                text.push('\n');
+                text.push_str(trimmed);
+            }
+            (None, _) => {
+                finish(&mut blocks, &mut current);
+                current = Some((BlockKind::Paragraph, trimmed.to_string()));
+            }
+        }
+    }
+    finish(&mut blocks, &mut current);
+    blocks
+}
+
+/// Recognise a line that starts a block, returning its kind and text
+fn block_start(line: &str) -> Option<(BlockKind, &str)> {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&hashes) {
+        let rest = &line[hashes..];
+        if rest.is_empty() || rest.starts_with(' ') {
+            return Some((BlockKind::Heading { level: hashes as u8 }, rest.trim()));
+        }
+    }
+
+    if let Some(rest) = line.strip_prefix('>') {
+        return Some((BlockKind::Quote, rest.strip_prefix(' ').unwrap_or(rest)));
+    }
+
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(bullet) {
+            return Some((BlockKind::BulletItem, rest.trim_start()));
+        }
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        let rest = &line[digits..];
+        if let Some(rest) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return Some((BlockKind::OrderedItem, rest.trim_start()));
+        }
+    }
+
+    None
+}
+
+/// Parse inline formatting, appending spans that carry `marks` as well
+fn parse_inline(text: &str, marks: &[TextMark], spans: &mut Vec<RichSpan>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut literal = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\\' && chars.get(i + 1).is_some_and(|next| next.is_ascii_punctuation()) {
+            literal.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+
+        let Some((inner, next, mark)) = inline_span(&chars, i) else {
+            literal.push(c);
+            i += 1;
+            continue;
+        };
+
+        push_span(spans, &literal, marks.to_vec());
+        literal.clear();
+
+        let mut nested = marks.to_vec();
+        if !nested.contains(&mark) {
+            nested.push(mark.clone());
+            nested.sort_by_key(TextMark::rank);
+        }
+        if mark == TextMark::Code {
+            push_span(spans, &inner, nested);
+        } else {
+            parse_inline(&inner, &nested, spans);
+        }
+        i = next;
+    }
+    push_span(spans, &literal, marks.to_vec());
+}
+
+/// Find a marked span opening at `start`
+///
+/// Returns the span's inner text, the position after it and its mark.
+fn inline_span(chars: &[char], start: usize) -> Option<(String, usize, TextMark)> {
+    let find = |from: usize, close: &dyn Fn(usize) -> bool| (from..chars.len()).find(|&j| close(j));
+    let is = |j: usize, c: char| chars.get(j) == Some(&c);
+    let alnum = |j: Option<usize>| j.and_then(|j| chars.get(j)).is_some_and(|c| c.is_alphanumeric());
+    let text = |from: usize, to: usize| chars[from..to].iter().collect::<String>();
+
+    let (inner_start, inner_end, next, mark) = match chars[start] {
+        '`' => {
+            let end = find(start + 1, &|j| is(j, '`'))?;
+            (start + 1, end, end + 1, TextMark::Code)
+        }
+        '*' if is(start + 1, '*') => {
+            // The closing run's last two stars, so "**a _b_**" and "**a*b***" close at the end
+            let end = find(start + 2, &|j| is(j, '*') && is(j + 1, '*') && !is(j + 2, '*'))?;
+            (start + 2, end, end + 2, TextMark::Bold)
+        }
+        '*' => {
+            let end = find(start + 1, &|j| is(j, '*') && !is(j + 1, '*') && !is(j - 1, '*'))?;
+            (start + 1, end, end + 1, TextMark::Italic)
+        }
+        // Underscores inside words, as in snake_case, aren't emphasis
+        '_' if !alnum(start.checked_sub(1)) => {
+            let end = find(start + 1, &|j| is(j, '_') && !alnum(Some(j + 1)))?;
+            (start + 1, end, end + 1, TextMark::Italic)
+        }
+        '[' => {
+            let close = find(start + 1, &|j| is(j, ']'))?;
+            if !is(close + 1, '(') {
+                return None;
+            }
+            let end = find(close + 2, &|j| is(j, ')'))?;
+            let url = text(close + 2, end);
+            (start + 1, close, end + 1, TextMark::Link { url })
+        }
+        _ => return None,
+    };
+
+    if inner_end <= inner_start {
+        return None;
+    }
+    Some((text(inner_start, inner_end), next, mark))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKDOWN: &str = "# Plans\n\
+        \n\
+        Some **bold** and _italic_ text, with `code` and a [link](https://example.com).\n\
+        \n\
+        - first\n\
+        - second\n\
+        \n\
+        1. one\n\
+        2. two\n\
+        \n\
+        > quoted\n\
+        > twice\n\
+        \n\
+        ```rust\n\
+        fn main() {}\n\
+        ```";
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let content = DocumentContent::from_markdown(MARKDOWN).unwrap();
+        let blocks = content.blocks().unwrap();
+        let kinds: Vec<&BlockKind> = blocks.iter().map(|b| &b.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &BlockKind::Heading { level: 1 },
+                &BlockKind::Paragraph,
+                &BlockKind::BulletItem,
+                &BlockKind::BulletItem,
+                &BlockKind::OrderedItem,
+                &BlockKind::OrderedItem,
+                &BlockKind::Quote,
+                &BlockKind::CodeBlock { language: Some("rust".to_string()) },
+            ]
+        );
+        assert_eq!(
+            blocks[1].spans[1],
+            RichSpan {
+                text: "bold".to_string(),
+                marks: vec![TextMark::Bold],
+            }
+        );
+        assert_eq!(blocks[6].text(), "quoted\ntwice");
+        assert_eq!(content.to_markdown().unwrap(), MARKDOWN);
+
+        // Plain text keeps the words, with markers read as newlines
+        let text = content.get_text().unwrap();
+        assert!(text.starts_with("\nPlans\nSome bold and italic text"));
+    }
+
+    #[test]
+    fn test_nested_and_overlapping_marks() {
+        let mut content = DocumentContent::new();
+        content.insert_block(0, &BlockKind::Paragraph).unwrap();
+        content.insert_text(1, "one two three").unwrap();
+        content.mark(1, 8, &TextMark::Bold).unwrap();
+        content.mark(5, 14, &TextMark::Italic).unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "**one _two_**_ three_");
+
+        content.unmark(5, 8, &TextMark::Italic).unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "**one two**_ three_");
+
+        let reparsed = DocumentContent::from_markdown("**a _b_** c_d_e and *f*").unwrap();
+        let spans = &reparsed.blocks().unwrap()[0].spans;
+        assert_eq!(spans[1].marks, vec![TextMark::Bold, TextMark::Italic]);
+        assert_eq!(spans[2].text, " c_d_e and ");
+        assert_eq!(spans[3].marks, vec![TextMark::Italic]);
+    }
+
+    #[test]
+    fn test_structure_merges_with_concurrent_edits() {
+        let mut ours = DocumentContent::from_markdown("Title\n\nBody text").unwrap();
+        let mut theirs = DocumentContent::from_document(ours.document_mut().fork());
+
+        ours.set_block_kind(2, &BlockKind::Heading { level: 1 }).unwrap();
+        theirs.mark(12, 16, &TextMark::Bold).unwrap();
+        theirs.insert_text(16, " here").unwrap();
+
+        ours.document_mut().merge(theirs.document_mut()).unwrap();
+        assert_eq!(ours.to_markdown().unwrap(), "# Title\n\nBody **text here**");
+    }
+
+    #[test]
+    fn test_flat_text_renders_as_is() {
+        let mut content = DocumentContent::new();
+        content.set_text("# Old note\n\nWritten *before* blocks").unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "# Old note\n\nWritten *before* blocks");
+        assert_eq!(content.blocks().unwrap().len(), 1);
+
+        content.set_block_kind(3, &BlockKind::Quote).unwrap();
+        assert_eq!(content.blocks().unwrap()[0].kind, BlockKind::Quote);
+    }
+}
@@ -0,0 +1,513 @@
+//! Store tree structure as a CRDT
+//!
+//! The shape of a store's tree (each node's parent and its place among its
+//! siblings) is kept in one Automerge document per store as a log of move
+//! operations. Every insert, move, reorder and removal appends a record
+//! stamped with a Lamport counter and the author's actor ID; records are
+//! never edited, so merging two copies of the log simply takes the union.
+//!
+//! The tree is the result of replaying the log in (counter, actor) order.
+//! A move that would make a node its own ancestor at that point in the
+//! replay is skipped, so concurrent moves can never produce a cycle, and
+//! since every peer replays the same records in the same order, every peer
+//! ends up with the same tree. Sibling order comes from fractional position
+//! keys, with ties broken by node ID.
+
+use std::collections::HashMap;
+
+use automerge::{transaction::Transactable, ReadDoc};
+use pimble_core::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::document::CrdtDocument;
+use crate::error::{CrdtError, Result};
+
+/// One entry in the move log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MoveRecord {
+    node: NodeId,
+    /// New parent; None for a top-level node such as the store root
+    parent: Option<NodeId>,
+    /// Fractional key ordering the node among its siblings
+    position: String,
+    /// Lamport counter, higher than every record the author had seen
+    counter: u64,
+    /// Automerge actor of the author, breaking counter ties
+    actor: String,
+    /// The node was removed from the tree
+    #[serde(default)]
+    removed: bool,
+}
+
+impl MoveRecord {
+    /// Key of the record in the document's root map
+    ///
+    /// Counters are zero-padded so keys sort in replay order.
+    fn key(&self) -> String {
+        format!("{:020}:{}", self.counter, self.actor)
+    }
+}
+
+/// Where a node currently sits
+#[derive(Debug, Clone)]
+struct Placement {
+    parent: Option<NodeId>,
+    position: String,
+}
+
+/// The tree obtained by replaying the move log
+#[derive(Debug, Default)]
+struct TreeState {
+    placements: HashMap<NodeId, Placement>,
+    children: HashMap<NodeId, Vec<NodeId>>,
+    max_counter: u64,
+}
+
+impl TreeState {
+    /// Replay a record on top of the current state
+    fn apply(&mut self, record: &MoveRecord) {
+        self.max_counter = self.max_counter.max(record.counter);
+        if record.removed {
+            self.placements.remove(&record.node);
+            return;
+        }
+        if let Some(parent) = record.parent {
+            if self.is_ancestor(record.node, parent) {
+                // Would create a cycle; an earlier move wins
+                return;
+            }
+        }
+        self.placements.insert(
+            record.node,
+            Placement {
+                parent: record.parent,
+                position: record.position.clone(),
+            },
+        );
+    }
+
+    /// Check whether `ancestor` is `node` or one of its ancestors
+    fn is_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut cursor = Some(node);
+        while let Some(id) = cursor {
+            if id == ancestor {
+                return true;
+            }
+            cursor = self.placements.get(&id).and_then(|p| p.parent);
+        }
+        false
+    }
+
+    /// Rebuild the ordered child lists
+    fn index(&mut self) {
+        let mut children: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for (&node, placement) in &self.placements {
+            if let Some(parent) = placement.parent {
+                children.entry(parent).or_default().push(node);
+            }
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| {
+                let (pa, pb) = (&self.placements[a].position, &self.placements[b].position);
+                pa.cmp(pb).then_with(|| a.0.cmp(&b.0))
+            });
+        }
+        self.children = children;
+    }
+}
+
+/// A store's tree structure, backed by an Automerge document
+#[derive(Debug)]
+pub struct TreeDocument {
+    doc: CrdtDocument,
+    state: TreeState,
+}
+
+impl TreeDocument {
+    /// Create an empty tree
+    pub fn new() -> Self {
+        Self {
+            doc: CrdtDocument::new(),
+            state: TreeState::default(),
+        }
+    }
+
+    /// Load a tree from bytes
+    pub fn load(bytes: &[u8]) -> Result<Self> {
+        let mut tree = Self {
+            doc: CrdtDocument::load(bytes)?,
+            state: TreeState::default(),
+        };
+        tree.resolve()?;
+        Ok(tree)
+    }
+
+    /// Save the tree to bytes
+    pub fn save(&mut self) -> Vec<u8> {
+        self.doc.save()
+    }
+
+    /// Fork the tree (create an independent copy)
+    pub fn fork(&mut self) -> Self {
+        Self {
+            doc: self.doc.fork(),
+            state: TreeState {
+                placements: self.state.placements.clone(),
+                children: self.state.children.clone(),
+                max_counter: self.state.max_counter,
+            },
+        }
+    }
+
+    /// Merge another copy of the tree into this one
+    pub fn merge(&mut self, other: &mut TreeDocument) -> Result<()> {
+        self.doc.merge(&mut other.doc)?;
+        self.resolve()
+    }
+
+    /// Get the underlying CRDT document
+    pub fn document(&self) -> &CrdtDocument {
+        &self.doc
+    }
+
+    /// Check whether a node is in the tree
+    pub fn contains(&self, node_id: NodeId) -> bool {
+        self.state.placements.contains_key(&node_id)
+    }
+
+    /// Get a node's parent
+    pub fn parent(&self, node_id: NodeId) -> Option<NodeId> {
+        self.state.placements.get(&node_id).and_then(|p| p.parent)
+    }
+
+    /// Get a node's children, in order
+    pub fn children(&self, node_id: NodeId) -> Vec<NodeId> {
+        self.state.children.get(&node_id).cloned().unwrap_or_default()
+    }
+
+    /// Get every node in the tree
+    pub fn nodes(&self) -> Vec<NodeId> {
+        self.state.placements.keys().copied().collect()
+    }
+
+    /// Add a node under `parent`, at `index` among its siblings or last
+    ///
+    /// Adding a node that is already in the tree moves it.
+    pub fn insert(&mut self, node_id: NodeId, parent: Option<NodeId>, index: Option<usize>) -> Result<()> {
+        let position = match parent {
+            Some(parent) => self.position_at(node_id, parent, index),
+            None => String::new(),
+        };
+        self.record(node_id, parent, position, false)
+    }
+
+    /// Move a node under a new parent, at `index` among its new siblings
+    ///
+    /// Without an index the node goes last, or stays where it is if it is
+    /// already a child of `new_parent`.
+    pub fn move_node(&mut self, node_id: NodeId, new_parent: NodeId, index: Option<usize>) -> Result<()> {
+        if !self.contains(node_id) {
+            return Err(CrdtError::InvalidMove(format!("{} is not in the tree", node_id)));
+        }
+        if !self.contains(new_parent) {
+            return Err(CrdtError::InvalidMove(format!("{} is not in the tree", new_parent)));
+        }
+        if self.state.is_ancestor(node_id, new_parent) {
+            return Err(CrdtError::InvalidMove(format!(
+                "Cannot move {} into itself or one of its descendants",
+                node_id
+            )));
+        }
+        if index.is_none() && self.parent(node_id) == Some(new_parent) {
+            return Ok(());
+        }
+        let position = self.position_at(node_id, new_parent, index);
+        self.record(node_id, Some(new_parent), position, false)
+    }
+
+    /// Remove a node from the tree
+    ///
+    /// Its descendants are not removed: they stay in the tree under it,
+    /// unreachable from the root, so putting the node back brings them along.
+    pub fn remove(&mut self, node_id: NodeId) -> Result<()> {
+        if !self.contains(node_id) {
+            return Ok(());
+        }
+        self.record(node_id, None, String::new(), true)
+    }
+
+    // Private helpers
+
+    /// Position key for placing a node at `index` among a parent's children
+    ///
+    /// Concurrent inserts can leave siblings with equal keys, which are
+    /// ordered by node ID. Between two of those the node shares their key
+    /// if its ID falls between theirs, and otherwise goes after them all.
+    fn position_at(&self, node_id: NodeId, parent: NodeId, index: Option<usize>) -> String {
+        let siblings: Vec<(NodeId, &str)> = self
+            .children(parent)
+            .into_iter()
+            .filter(|id| *id != node_id)
+            .map(|id| (id, self.state.placements[&id].position.as_str()))
+            .collect();
+        let index = index.unwrap_or(siblings.len()).min(siblings.len());
+        let Some((before_id, before)) = index.checked_sub(1).map(|i| siblings[i]) else {
+            return key_between("", siblings.first().map(|(_, key)| *key));
+        };
+        if let Some((after_id, after)) = siblings.get(index) {
+            if *after == before && before_id.0 < node_id.0 && node_id.0 < after_id.0 {
+                return before.to_string();
+            }
+        }
+        let after = siblings[index..].iter().map(|(_, key)| *key).find(|after| *after > before);
+        key_between(before, after)
+    }
+
+    fn record(&mut self, node: NodeId, parent: Option<NodeId>, position: String, removed: bool) -> Result<()> {
+        let record = MoveRecord {
+            node,
+            parent,
+            position,
+            counter: self.state.max_counter + 1,
+            actor: self.doc.inner().get_actor().to_hex_string(),
+            removed,
+        };
+        let json = serde_json::to_string(&record).map_err(|e| CrdtError::Serialization(e.to_string()))?;
+        self.doc.inner_mut().put(automerge::ROOT, record.key(), json)?;
+
+        // The new record sorts after every known one, so it replays last
+        self.state.apply(&record);
+        self.state.index();
+        Ok(())
+    }
+
+    /// Replay the whole move log
+    fn resolve(&mut self) -> Result<()> {
+        let mut records = Vec::new();
+        for key in self.doc.inner().keys(automerge::ROOT) {
+            let json = self
+                .doc
+                .get_string(&key)?
+                .ok_or_else(|| CrdtError::KeyNotFound(key.clone()))?;
+            let record: MoveRecord =
+                serde_json::from_str(&json).map_err(|e| CrdtError::Serialization(e.to_string()))?;
+            records.push(record);
+        }
+        records.sort_by(|a, b| a.counter.cmp(&b.counter).then_with(|| a.actor.cmp(&b.actor)));
+
+        let mut state = TreeState::default();
+        for record in &records {
+            state.apply(record);
+        }
+        state.index();
+        self.state = state;
+        Ok(())
+    }
+}
+
+impl Default for TreeDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Digits of position keys; 'a' is zero and never ends a key
+const DIGITS: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+/// Generate a position key sorting strictly between `before` and `after`
+///
+/// An empty `before` is the lowest key and a missing `after` the highest.
+fn key_between(before: &str, after: Option<&str>) -> String {
+    let digits = |s: &str| -> Vec<usize> {
+        s.bytes()
+            .map(|b| DIGITS.iter().position(|d| *d == b).unwrap_or(0))
+            .collect()
+    };
+    let a = digits(before);
+    let b = after.map(digits);
+    midpoint(&a, b.as_deref())
+        .into_iter()
+        .map(|d| DIGITS[d] as char)
+        .collect()
+}
+
+fn midpoint(a: &[usize], b: Option<&[usize]>) -> Vec<usize> {
+    if let Some(b) = b {
+        // Keep the common prefix, then split the rest
+        let n = (0..b.len())
+            .take_while(|&i| a.get(i).copied().unwrap_or(0) == b[i])
+            .count();
+        if n > 0 {
+            let mut key = b[..n].to_vec();
+            key.extend(midpoint(a.get(n..).unwrap_or(&[]), Some(&b[n..])));
+            return key;
+        }
+    }
+
+    // Keys written by other replicas may not sort after `a` as they should;
+    // such a bound can't be kept, so it is dropped
+    let digit_a = a.first().copied().unwrap_or(0);
+    let b = b.filter(|b| b.first().is_some_and(|digit_b| *digit_b > digit_a));
+    let digit_b = b.map_or(DIGITS.len(), |b| b[0]);
+    if digit_b - digit_a > 1 {
+        vec![(digit_a + digit_b).div_ceil(2)]
+    } else if let Some(b) = b.filter(|b| b.len() > 1) {
+        vec![b[0]]
+    } else {
+        let mut key = vec![digit_a];
+        key.extend(midpoint(a.get(1..).unwrap_or(&[]), None));
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_with(nodes: &[(NodeId, Option<NodeId>)]) -> TreeDocument {
+        let mut tree = TreeDocument::new();
+        for (node, parent) in nodes {
+            tree.insert(*node, *parent, None).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_key_between_orders_keys() {
+        let first = key_between("", None);
+        let second = key_between(&first, None);
+        let middle = key_between(&first, Some(&second));
+        let front = key_between("", Some(&first));
+        assert!(front < first && first < middle && middle < second);
+
+        // Repeated inserts at the front keep finding room
+        let mut key = first;
+        for _ in 0..100 {
+            let next = key_between("", Some(&key));
+            assert!(next < key && !next.is_empty() && !next.ends_with('a'));
+            key = next;
+        }
+
+        // A bound that doesn't sort after `before` is ignored, not trusted
+        assert!(key_between("n", Some("c")).as_str() > "n");
+        assert!(key_between("n", Some("n")).as_str() > "n");
+        assert!(key_between("nd", Some("n")).as_str() > "nd");
+    }
+
+    #[test]
+    fn test_insert_between_equal_keys() {
+        let mut ids: Vec<NodeId> = (0..5).map(|_| NodeId::new()).collect();
+        ids.sort_by_key(|id| id.0);
+        let (root, x, y) = (NodeId::new(), NodeId::new(), NodeId::new());
+        let mut left = tree_with(&[(root, None), (x, Some(root))]);
+        let mut right = left.fork();
+
+        // Concurrent appends get the same key, and are ordered by ID
+        left.insert(ids[1], Some(root), None).unwrap();
+        right.insert(ids[3], Some(root), None).unwrap();
+        left.merge(&mut right).unwrap();
+        left.insert(y, Some(root), None).unwrap();
+        assert_eq!(left.children(root), vec![x, ids[1], ids[3], y]);
+
+        // Between them, a node shares the key if its ID fits, and otherwise
+        // goes after them but before later siblings
+        left.insert(ids[2], Some(root), Some(2)).unwrap();
+        assert_eq!(left.children(root), vec![x, ids[1], ids[2], ids[3], y]);
+        left.insert(ids[0], Some(root), Some(2)).unwrap();
+        assert_eq!(left.children(root), vec![x, ids[1], ids[2], ids[3], ids[0], y]);
+    }
+
+    #[test]
+    fn test_insert_and_reorder() {
+        let (root, a, b, c) = (NodeId::new(), NodeId::new(), NodeId::new(), NodeId::new());
+        let mut tree = tree_with(&[(root, None), (a, Some(root)), (b, Some(root)), (c, Some(root))]);
+        assert_eq!(tree.children(root), vec![a, b, c]);
+
+        tree.move_node(c, root, Some(0)).unwrap();
+        assert_eq!(tree.children(root), vec![c, a, b]);
+        tree.move_node(c, root, Some(1)).unwrap();
+        assert_eq!(tree.children(root), vec![a, c, b]);
+
+        tree.move_node(b, a, None).unwrap();
+        assert_eq!(tree.parent(b), Some(a));
+        assert!(tree.move_node(a, b, None).is_err());
+
+        let reloaded = TreeDocument::load(&tree.save()).unwrap();
+        assert_eq!(reloaded.children(root), vec![a, c]);
+        assert_eq!(reloaded.children(a), vec![b]);
+    }
+
+    #[test]
+    fn test_concurrent_moves_never_form_a_cycle() {
+        let (root, a, b) = (NodeId::new(), NodeId::new(), NodeId::new());
+        let mut left = tree_with(&[(root, None), (a, Some(root)), (b, Some(root))]);
+        let mut right = left.fork();
+
+        // Each side moves one node under the other
+        left.move_node(a, b, None).unwrap();
+        right.move_node(b, a, None).unwrap();
+
+        let mut merged_left = left.fork();
+        merged_left.merge(&mut right).unwrap();
+        right.merge(&mut left).unwrap();
+
+        for tree in [&merged_left, &right] {
+            // Exactly one of the moves took effect, the same on both sides
+            let under_a = tree.parent(b) == Some(a);
+            let under_b = tree.parent(a) == Some(b);
+            assert!(under_a ^ under_b);
+        }
+        assert_eq!(merged_left.parent(a), right.parent(a));
+        assert_eq!(merged_left.parent(b), right.parent(b));
+        assert_eq!(merged_left.children(root), right.children(root));
+    }
+
+    #[test]
+    fn test_concurrent_reorders_converge_without_duplicates() {
+        let (root, a, b, c) = (NodeId::new(), NodeId::new(), NodeId::new(), NodeId::new());
+        let mut left = tree_with(&[(root, None), (a, Some(root)), (b, Some(root)), (c, Some(root))]);
+        let mut right = left.fork();
+
+        left.move_node(c, root, Some(0)).unwrap();
+        right.move_node(c, root, Some(1)).unwrap();
+        right.move_node(a, root, None).unwrap();
+
+        let mut merged = left.fork();
+        merged.merge(&mut right).unwrap();
+        right.merge(&mut left).unwrap();
+
+        let children = merged.children(root);
+        assert_eq!(children, right.children(root));
+        assert_eq!(children.len(), 3);
+        assert!([a, b, c].iter().all(|n| children.contains(n)));
+    }
+
+    #[test]
+    fn test_remove_and_reinsert() {
+        let (root, a) = (NodeId::new(), NodeId::new());
+        let mut tree = tree_with(&[(root, None), (a, Some(root))]);
+
+        tree.remove(a).unwrap();
+        assert!(!tree.contains(a));
+        assert!(tree.children(root).is_empty());
+
+        tree.insert(a, Some(root), None).unwrap();
+        assert_eq!(tree.children(root), vec![a]);
+    }
+
+    #[test]
+    fn test_remove_keeps_descendants() {
+        let (root, a, b) = (NodeId::new(), NodeId::new(), NodeId::new());
+        let mut tree = tree_with(&[(root, None), (a, Some(root)), (b, Some(a))]);
+
+        tree.remove(a).unwrap();
+        assert!(tree.children(root).is_empty());
+        assert!(tree.contains(b));
+        assert_eq!(tree.parent(b), Some(a));
+        assert_eq!(tree.children(a), vec![b]);
+
+        tree.insert(a, Some(root), None).unwrap();
+        assert_eq!(tree.children(root), vec![a]);
+        assert_eq!(tree.children(a), vec![b]);
+    }
+}
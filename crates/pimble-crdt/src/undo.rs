@@ -0,0 +1,628 @@
+//! Undo and redo of local edits
+//!
+//! An [`UndoStep`] is recorded from the heads a document had before a
+//! local edit. Reverting it writes the reverse of that edit as new changes,
+//! so an undo merges and replicates like any other edit, and only what the
+//! edit itself changed is put back:
+//!
+//! - a map value is restored only while it still holds what the edit
+//!   wrote, so a value someone has changed since is left alone
+//! - text the edit inserted is deleted, and text it deleted is inserted
+//!   again where it was, found through cursors, so text typed since by
+//!   anyone stays where it is
+//! - marks the edit added are removed, and marks it removed are restored
+//!
+//! Edits merged in from collaborators are never recorded, so they are
+//! never undone. An [`UndoManager`] keeps the undo and redo stacks for a
+//! session.
+
+use std::collections::{HashMap, HashSet};
+
+use automerge::marks::{ExpandMark, Mark};
+use automerge::transaction::Transactable;
+use automerge::{hydrate, AutoCommit, ChangeHash, Cursor, ObjId, ObjType, ReadDoc, ScalarValue, Value};
+
+use crate::{CrdtDocument, Result};
+
+/// How many edits an [`UndoManager`] keeps by default
+pub const DEFAULT_UNDO_LIMIT: usize = 100;
+
+/// A map entry an edit changed
+#[derive(Debug, Clone)]
+struct ValueChange {
+    obj: ObjId,
+    key: String,
+    before: Option<hydrate::Value>,
+    after: Option<hydrate::Value>,
+}
+
+/// A run of text an edit deleted, with the marks it had, or a single
+/// block marker with the block's attributes
+#[derive(Debug, Clone)]
+struct DeletedRun {
+    cursors: Vec<Cursor>,
+    text: String,
+    marks: Vec<(String, ScalarValue)>,
+    block: Option<hydrate::Value>,
+}
+
+/// A mark an edit added or removed, anchored at its first and last characters
+#[derive(Debug, Clone, PartialEq)]
+struct MarkSpan {
+    name: String,
+    value: ScalarValue,
+    first: Cursor,
+    last: Cursor,
+}
+
+/// Changes an edit made inside a text object
+#[derive(Debug, Clone)]
+struct TextChange {
+    obj: ObjId,
+    inserted: Vec<Cursor>,
+    deleted: Vec<DeletedRun>,
+    added_marks: Vec<MarkSpan>,
+    removed_marks: Vec<MarkSpan>,
+}
+
+/// Characters put back by an undo, from the deleted elements' cursors to
+/// the cursors of the elements that replaced them
+type Restored = HashMap<Vec<u8>, Cursor>;
+
+/// The reverse of one local edit to a document
+#[derive(Debug, Clone)]
+pub struct UndoStep {
+    values: Vec<ValueChange>,
+    texts: Vec<TextChange>,
+    restored: Restored,
+}
+
+impl UndoStep {
+    /// Record the edit that took a document from `before` to its current heads
+    ///
+    /// Returns `None` if the edit changed nothing, or if it replaced the
+    /// document with one that doesn't descend from `before`.
+    pub fn record(doc: &mut CrdtDocument, before: &[ChangeHash]) -> Result<Option<Self>> {
+        if before.iter().any(|hash| doc.inner_mut().get_change_by_hash(hash).is_none()) {
+            return Ok(None);
+        }
+        let after = doc.get_heads();
+        let mut step = Self {
+            values: Vec::new(),
+            texts: Vec::new(),
+            restored: Restored::new(),
+        };
+        if after != before {
+            step.compare_map(doc.inner(), &automerge::ROOT, before, &after)?;
+        }
+        Ok((!step.values.is_empty() || !step.texts.is_empty()).then_some(step))
+    }
+
+    /// Reverse the edit, returning the step that reverses the reversal
+    ///
+    /// Returns `None` if there was nothing left to reverse, because
+    /// everything the edit changed has been changed again since. Other
+    /// steps for the same document should [`follow`](Self::follow) the
+    /// returned step.
+    pub fn revert(&self, doc: &mut CrdtDocument) -> Result<Option<UndoStep>> {
+        let before = doc.get_heads();
+        let mut restored = Restored::new();
+        for change in &self.values {
+            change.revert(doc.inner_mut())?;
+        }
+        for change in &self.texts {
+            change.revert(doc.inner_mut(), &mut restored)?;
+        }
+        doc.commit_with_message("Revert edit");
+        Ok(Self::record(doc, &before)?.map(|step| Self { restored, ..step }))
+    }
+
+    /// Follow text that a reverted step put back
+    ///
+    /// Reinserted text is new text as far as the document is concerned,
+    /// so this points the step at it instead of at the text it replaced.
+    pub fn follow(&mut self, reverted: &UndoStep) {
+        if reverted.restored.is_empty() {
+            return;
+        }
+        let follow = |cursor: &mut Cursor| {
+            if let Some(replacement) = reverted.restored.get(&cursor.to_bytes()) {
+                *cursor = replacement.clone();
+            }
+        };
+        for text in &mut self.texts {
+            text.inserted.iter_mut().for_each(follow);
+            for run in &mut text.deleted {
+                run.cursors.iter_mut().for_each(follow);
+            }
+            for mark in text.added_marks.iter_mut().chain(&mut text.removed_marks) {
+                follow(&mut mark.first);
+                follow(&mut mark.last);
+            }
+        }
+    }
+
+    fn compare_map(&mut self, doc: &AutoCommit, obj: &ObjId, before: &[ChangeHash], after: &[ChangeHash]) -> Result<()> {
+        let mut keys: Vec<String> = doc.keys_at(obj, before).collect();
+        for key in doc.keys_at(obj, after) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+
+        for key in keys {
+            let old = doc.get_at(obj, key.as_str(), before)?;
+            let new = doc.get_at(obj, key.as_str(), after)?;
+            match (&old, &new) {
+                (Some((Value::Object(ObjType::Map | ObjType::Table), a)), Some((Value::Object(_), b))) if a == b => {
+                    self.compare_map(doc, a, before, after)?;
+                }
+                (Some((Value::Object(ObjType::Text), a)), Some((Value::Object(_), b))) if a == b => {
+                    if let Some(text) = TextChange::record(doc, a, before, after)? {
+                        self.texts.push(text);
+                    }
+                }
+                _ => {
+                    let old = hydrate_value(doc, old, Some(before))?;
+                    let new = hydrate_value(doc, new, Some(after))?;
+                    if old != new {
+                        self.values.push(ValueChange {
+                            obj: obj.clone(),
+                            key,
+                            before: old,
+                            after: new,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ValueChange {
+    fn revert(&self, doc: &mut AutoCommit) -> Result<()> {
+        // Concurrent values mean someone else wrote the key too
+        let mut current = doc.get_all(&self.obj, self.key.as_str())?;
+        if current.len() > 1 || hydrate_value(doc, current.pop(), None)? != self.after {
+            return Ok(());
+        }
+
+        let key = self.key.as_str();
+        match &self.before {
+            None => doc.delete(&self.obj, key)?,
+            Some(hydrate::Value::Scalar(value)) => doc.put(&self.obj, key, value.clone())?,
+            Some(value) => {
+                let obj_type = match value {
+                    hydrate::Value::List(_) => ObjType::List,
+                    hydrate::Value::Text(_) => ObjType::Text,
+                    _ => ObjType::Map,
+                };
+                let id = doc.put_object(&self.obj, key, obj_type)?;
+                doc.update_object(&id, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TextChange {
+    fn record(doc: &AutoCommit, obj: &ObjId, before: &[ChangeHash], after: &[ChangeHash]) -> Result<Option<Self>> {
+        let old = element_cursors(doc, obj, before)?;
+        let new = element_cursors(doc, obj, after)?;
+        let old_ids: HashSet<Vec<u8>> = old.iter().map(Cursor::to_bytes).collect();
+        let new_ids: HashSet<Vec<u8>> = new.iter().map(Cursor::to_bytes).collect();
+
+        let inserted = new
+            .into_iter()
+            .filter(|cursor| !old_ids.contains(&cursor.to_bytes()))
+            .collect();
+
+        // Group deleted characters into runs of the same marks; each block
+        // marker is a run of its own, so it comes back as a block
+        let chars: Vec<char> = doc.text_at(obj, before)?.chars().collect();
+        let spans: Vec<_> = doc.marks_at(obj, before)?.into_iter().collect();
+        let mut deleted: Vec<DeletedRun> = Vec::new();
+        let mut previous = None;
+        for (index, cursor) in old.iter().enumerate() {
+            if new_ids.contains(&cursor.to_bytes()) {
+                continue;
+            }
+            if let Some((Value::Object(_), marker)) = doc.get_at(obj, index, before)? {
+                deleted.push(DeletedRun {
+                    cursors: vec![cursor.clone()],
+                    text: String::new(),
+                    marks: Vec::new(),
+                    block: Some(doc.hydrate(&marker, Some(before))?),
+                });
+                previous = None;
+                continue;
+            }
+            let ch = chars.get(index).copied().unwrap_or('\n');
+            let marks: Vec<(String, ScalarValue)> = spans
+                .iter()
+                .filter(|mark| mark.start <= index && index < mark.end)
+                .map(|mark| (mark.name().to_string(), mark.value().clone()))
+                .collect();
+            match deleted.last_mut() {
+                Some(run) if run.block.is_none() && previous == index.checked_sub(1) && run.marks == marks => {
+                    run.cursors.push(cursor.clone());
+                    run.text.push(ch);
+                }
+                _ => deleted.push(DeletedRun {
+                    cursors: vec![cursor.clone()],
+                    text: ch.to_string(),
+                    marks,
+                    block: None,
+                }),
+            }
+            previous = Some(index);
+        }
+
+        let old_marks = mark_spans(doc, obj, before)?;
+        let new_marks = mark_spans(doc, obj, after)?;
+        let added_marks: Vec<MarkSpan> = new_marks.iter().filter(|m| !old_marks.contains(m)).cloned().collect();
+        let removed_marks: Vec<MarkSpan> = old_marks.into_iter().filter(|m| !new_marks.contains(m)).collect();
+
+        let change = Self {
+            obj: obj.clone(),
+            inserted,
+            deleted,
+            added_marks,
+            removed_marks,
+        };
+        let changed = !change.inserted.is_empty()
+            || !change.deleted.is_empty()
+            || !change.added_marks.is_empty()
+            || !change.removed_marks.is_empty();
+        Ok(changed.then_some(change))
+    }
+
+    fn revert(&self, doc: &mut AutoCommit, restored: &mut Restored) -> Result<()> {
+        let obj = &self.obj;
+
+        // Delete what the edit inserted, wherever it still is
+        let mut positions = Vec::new();
+        for cursor in &self.inserted {
+            if let Some(pos) = visible_position(doc, obj, cursor)? {
+                positions.push(pos);
+            }
+        }
+        positions.sort_unstable();
+        for pos in positions.into_iter().rev() {
+            doc.delete(obj, pos)?;
+        }
+
+        // Put back what it deleted, where it was and with the marks it had
+        // rather than those of its new neighbours
+        for run in &self.deleted {
+            let start = doc.get_cursor_position(obj, &run.cursors[0], None)?;
+            if let Some(block) = &run.block {
+                let marker = doc.split_block(obj, start)?;
+                doc.update_object(&marker, block)?;
+                restored.insert(run.cursors[0].to_bytes(), doc.get_cursor(obj, start, None)?);
+                continue;
+            }
+            doc.splice_text(obj, start, 0, &run.text)?;
+            let end = start + run.cursors.len();
+            for (index, cursor) in run.cursors.iter().enumerate() {
+                restored.insert(cursor.to_bytes(), doc.get_cursor(obj, start + index, None)?);
+            }
+            let inherited: Vec<String> = doc
+                .marks(obj)?
+                .into_iter()
+                .filter(|mark| mark.start < end && start < mark.end)
+                .map(|mark| mark.name().to_string())
+                .collect();
+            for name in inherited {
+                doc.unmark(obj, &name, start, end, ExpandMark::None)?;
+            }
+            for (name, value) in &run.marks {
+                doc.mark(obj, Mark::new(name.clone(), value.clone(), start, end), ExpandMark::None)?;
+            }
+        }
+
+        for mark in &self.added_marks {
+            if let Some((start, end)) = mark_range(doc, obj, mark)? {
+                doc.unmark(obj, &mark.name, start, end, ExpandMark::None)?;
+            }
+        }
+        for mark in &self.removed_marks {
+            if let Some((start, end)) = mark_range(doc, obj, mark)? {
+                let data = Mark::new(mark.name.clone(), mark.value.clone(), start, end);
+                doc.mark(obj, data, ExpandMark::None)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hydrate a value read from a document, at `heads` or now
+fn hydrate_value(
+    doc: &AutoCommit,
+    value: Option<(Value<'_>, ObjId)>,
+    heads: Option<&[ChangeHash]>,
+) -> Result<Option<hydrate::Value>> {
+    Ok(match value {
+        Some((Value::Object(_), id)) => Some(doc.hydrate(&id, heads)?),
+        Some((Value::Scalar(value), _)) => Some(hydrate::Value::Scalar(value.into_owned())),
+        None => None,
+    })
+}
+
+/// Cursors naming each element of a sequence at `heads`
+fn element_cursors(doc: &AutoCommit, obj: &ObjId, heads: &[ChangeHash]) -> Result<Vec<Cursor>> {
+    (0..doc.length_at(obj, heads))
+        .map(|index| Ok(doc.get_cursor(obj, index, Some(heads))?))
+        .collect()
+}
+
+fn mark_spans(doc: &AutoCommit, obj: &ObjId, heads: &[ChangeHash]) -> Result<Vec<MarkSpan>> {
+    let mut spans = Vec::new();
+    for mark in doc.marks_at(obj, heads)? {
+        if mark.end > mark.start {
+            spans.push(MarkSpan {
+                name: mark.name().to_string(),
+                value: mark.value().clone(),
+                first: doc.get_cursor(obj, mark.start, Some(heads))?,
+                last: doc.get_cursor(obj, mark.end - 1, Some(heads))?,
+            });
+        }
+    }
+    Ok(spans)
+}
+
+/// Where an element is now, if it hasn't been deleted
+fn visible_position(doc: &AutoCommit, obj: &ObjId, cursor: &Cursor) -> Result<Option<usize>> {
+    let pos = doc.get_cursor_position(obj, cursor, None)?;
+    if pos < doc.length(obj) && doc.get_cursor(obj, pos, None)? == *cursor {
+        Ok(Some(pos))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Where a marked span is now, if any of it is left
+fn mark_range(doc: &AutoCommit, obj: &ObjId, mark: &MarkSpan) -> Result<Option<(usize, usize)>> {
+    let start = doc.get_cursor_position(obj, &mark.first, None)?;
+    let end = match visible_position(doc, obj, &mark.last)? {
+        Some(pos) => pos + 1,
+        None => doc.get_cursor_position(obj, &mark.last, None)?,
+    };
+    Ok((end > start).then_some((start, end)))
+}
+
+/// Undo and redo stacks for a session
+///
+/// Entries are usually [`UndoStep`]s for a single document, but a store
+/// can keep its own entries, such as tree operations, on the same stacks.
+/// Recording a new edit forgets the edits that could be redone.
+#[derive(Debug)]
+pub struct UndoManager<T = UndoStep> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+    limit: usize,
+}
+
+impl<T> UndoManager<T> {
+    /// Create a manager keeping up to [`DEFAULT_UNDO_LIMIT`] edits
+    pub fn new() -> Self {
+        Self::with_limit(DEFAULT_UNDO_LIMIT)
+    }
+
+    /// Create a manager keeping up to `limit` edits
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Record a new edit
+    pub fn record(&mut self, entry: T) {
+        self.redo.clear();
+        self.push_undo(entry);
+    }
+
+    /// Take the latest edit to undo
+    pub fn pop_undo(&mut self) -> Option<T> {
+        self.undo.pop()
+    }
+
+    /// Take the latest undone edit to redo
+    pub fn pop_redo(&mut self) -> Option<T> {
+        self.redo.pop()
+    }
+
+    /// Put back an edit that can be undone, keeping what can be redone
+    pub fn push_undo(&mut self, entry: T) {
+        self.undo.push(entry);
+        if self.undo.len() > self.limit {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Put back an undone edit that can be redone
+    pub fn push_redo(&mut self, entry: T) {
+        self.redo.push(entry);
+    }
+
+    /// Visit every edit on both stacks, to update them after another
+    /// edit is undone or redone
+    pub fn for_each_mut(&mut self, f: impl FnMut(&mut T)) {
+        self.undo.iter_mut().chain(&mut self.redo).for_each(f);
+    }
+
+    /// Check whether there's an edit to undo
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    /// Check whether there's an edit to redo
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+impl<T> Default for UndoManager<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UndoManager<UndoStep> {
+    /// Record the local edit that took a document from `before` to its
+    /// current heads, returning whether it changed anything
+    pub fn record_edit(&mut self, doc: &mut CrdtDocument, before: &[ChangeHash]) -> Result<bool> {
+        match UndoStep::record(doc, before)? {
+            Some(step) => {
+                self.record(step);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Undo the latest edit that can still be undone
+    ///
+    /// Edits whose changes have all been overwritten since are skipped.
+    /// Returns whether an edit was undone.
+    pub fn undo(&mut self, doc: &mut CrdtDocument) -> Result<bool> {
+        while let Some(step) = self.pop_undo() {
+            if let Some(redo) = step.revert(doc)? {
+                self.for_each_mut(|other| other.follow(&redo));
+                self.push_redo(redo);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Redo the latest undone edit that can still be redone
+    pub fn redo(&mut self, doc: &mut CrdtDocument) -> Result<bool> {
+        while let Some(step) = self.pop_redo() {
+            if let Some(undo) = step.revert(doc)? {
+                self.for_each_mut(|other| other.follow(&undo));
+                self.push_undo(undo);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DocumentContent, TextMark};
+
+    #[test]
+    fn test_undo_redo_local_edits() {
+        let mut content = DocumentContent::new();
+        content.set_text("Hello world").unwrap();
+        content.document_mut().set_string("meta:title", "Notes").unwrap();
+        let mut undo = UndoManager::new();
+
+        let before = content.document_mut().get_heads();
+        content.insert_text(5, ",").unwrap();
+        content.delete_text(7, 5).unwrap();
+        content.insert_text(7, "there").unwrap();
+        assert!(undo.record_edit(content.document_mut(), &before).unwrap());
+
+        let before = content.document_mut().get_heads();
+        content.document_mut().set_string("meta:title", "Greeting").unwrap();
+        undo.record_edit(content.document_mut(), &before).unwrap();
+        assert_eq!(content.get_text().unwrap(), "Hello, there");
+
+        assert!(undo.undo(content.document_mut()).unwrap());
+        assert_eq!(content.document().get_string("meta:title").unwrap().as_deref(), Some("Notes"));
+        assert!(undo.undo(content.document_mut()).unwrap());
+        assert_eq!(content.get_text().unwrap(), "Hello world");
+        assert!(!undo.undo(content.document_mut()).unwrap());
+
+        assert!(undo.redo(content.document_mut()).unwrap());
+        assert_eq!(content.get_text().unwrap(), "Hello, there");
+        assert!(undo.redo(content.document_mut()).unwrap());
+        assert_eq!(content.document().get_string("meta:title").unwrap().as_deref(), Some("Greeting"));
+        assert!(!undo.can_redo());
+
+        // A new edit forgets what could be redone
+        undo.undo(content.document_mut()).unwrap();
+        let before = content.document_mut().get_heads();
+        content.document_mut().set_int("meta:priority", 1).unwrap();
+        undo.record_edit(content.document_mut(), &before).unwrap();
+        assert!(!undo.can_redo());
+    }
+
+    #[test]
+    fn test_undo_keeps_collaborator_edits() {
+        let mut ours = DocumentContent::new();
+        ours.set_text("one two").unwrap();
+        ours.document_mut().set_string("meta:title", "Base").unwrap();
+        let mut theirs = DocumentContent::from_document(ours.document_mut().fork());
+        let mut undo = UndoManager::new();
+
+        let before = ours.document_mut().get_heads();
+        ours.insert_text(3, " and a half").unwrap();
+        ours.document_mut().set_string("meta:title", "Ours").unwrap();
+        ours.document_mut().set_string("meta:status", "draft").unwrap();
+        undo.record_edit(ours.document_mut(), &before).unwrap();
+
+        // A collaborator types and renames concurrently
+        theirs.insert_text(7, " three").unwrap();
+        theirs.insert_text(0, "zero ").unwrap();
+        theirs.document_mut().set_string("meta:title", "Theirs").unwrap();
+        ours.document_mut().merge(theirs.document_mut()).unwrap();
+        let title = ours.document().get_string("meta:title").unwrap();
+
+        // Only our own changes are reversed, around theirs
+        assert!(undo.undo(ours.document_mut()).unwrap());
+        assert_eq!(ours.get_text().unwrap(), "zero one two three");
+        assert_eq!(ours.document().get_string("meta:title").unwrap(), title);
+        assert!(!ours.document().contains_key("meta:status"));
+
+        undo.redo(ours.document_mut()).unwrap();
+        assert_eq!(ours.get_text().unwrap(), "zero one and a half two three");
+    }
+
+    #[test]
+    fn test_undo_marks_and_deletions() {
+        let mut content = DocumentContent::from_markdown("Ship **it** today").unwrap();
+        let mut undo = UndoManager::new();
+
+        let before = content.document_mut().get_heads();
+        content.mark(1, 5, &TextMark::Italic).unwrap();
+        content.unmark(6, 8, &TextMark::Bold).unwrap();
+        undo.record_edit(content.document_mut(), &before).unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "_Ship_ it today");
+
+        let before = content.document_mut().get_heads();
+        content.delete_text(5, 3).unwrap();
+        undo.record_edit(content.document_mut(), &before).unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "_Ship_ today");
+
+        undo.undo(content.document_mut()).unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "_Ship_ it today");
+        undo.undo(content.document_mut()).unwrap();
+        assert_eq!(content.to_markdown().unwrap(), "Ship **it** today");
+    }
+
+    #[test]
+    fn test_undo_restores_deleted_blocks() {
+        let mut content = DocumentContent::from_markdown("# Title\n\nBody text").unwrap();
+        let blocks = content.blocks().unwrap();
+        let mut undo = UndoManager::new();
+
+        // Delete the end of the heading and the paragraph's marker
+        let before = content.document_mut().get_heads();
+        content.delete_text(4, 5).unwrap();
+        undo.record_edit(content.document_mut(), &before).unwrap();
+        assert_eq!(content.blocks().unwrap().len(), 1);
+
+        undo.undo(content.document_mut()).unwrap();
+        assert_eq!(content.blocks().unwrap(), blocks);
+        undo.redo(content.document_mut()).unwrap();
+        assert_eq!(content.blocks().unwrap().len(), 1);
+    }
+}
@@ -49,6 +49,13 @@ impl PluginHost {
         self.plugins.values().map(|p| p.info()).collect()
     }
 
+    /// The node types plugins are registered for, in order
+    pub fn node_types(&self) -> Vec<String> {
+        let mut node_types: Vec<String> = self.plugins.keys().cloned().collect();
+        node_types.sort();
+        node_types
+    }
+
     /// Check if a node type is supported
     pub fn supports(&self, node_type: &str) -> bool {
         self.plugins.contains_key(node_type)
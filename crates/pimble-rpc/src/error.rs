@@ -1,39 +1,222 @@
 //! Error types for pimble-rpc
+//!
+//! Every error a server returns carries a stable code and, as its `data`,
+//! the error itself serialized with a `kind` tag and the ids or paths it
+//! concerns. Clients decode that back with [`RpcError::from_error_object`]
+//! instead of parsing messages.
+//!
+//! | Code   | Kind                  | Data              |
+//! |--------|-----------------------|-------------------|
+//! | -32700 | `parse`               | `message`         |
+//! | -32601 | `method_not_found`    | `message`         |
+//! | -32602 | `invalid_params`      | `message`         |
+//! | -32603 | `internal`            | `message`         |
+//! | -32001 | `store_not_found`     | `store_id`        |
+//! | -32002 | `node_not_found`      | `node_id`         |
+//! | -32003 | `store_not_open`      | `store_id`        |
+//! | -32004 | `store_exists`        | `path`            |
+//! | -32005 | `invalid_path`        | `path`            |
+//! | -32006 | `invalid_operation`   | `message`         |
+//! | -32007 | `invalid_format`      | `message`         |
+//! | -32008 | `io`                  | `message`         |
+//! | -32009 | `crdt`                | `message`         |
+//! | -32010 | `unknown_version`     | `change`          |
+//! | -32011 | `invalid_node_type`   | `node_type`       |
+//! | -32012 | `invalid_link_target` | `target`          |
+//! | -32013 | `remote_unavailable`  | `message`         |
+//! | -32014 | `missing_changes`     | `changes`         |
+//!
+//! Codes are never reused. Errors with a code this version doesn't know
+//! decode as [`RpcError::Other`].
 
+use jsonrpsee::types::{ErrorObject, ErrorObjectOwned};
+use pimble_core::{CoreError, NodeId, StoreId};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum RpcError {
-    #[error("Method not found: {0}")]
-    MethodNotFound(String),
+    #[error("Parse error: {message}")]
+    Parse { message: String },
 
-    #[error("Invalid params: {0}")]
-    InvalidParams(String),
+    #[error("Method not found: {message}")]
+    MethodNotFound { message: String },
 
-    #[error("Internal error: {0}")]
-    Internal(String),
+    #[error("Invalid params: {message}")]
+    InvalidParams { message: String },
 
-    #[error("Store error: {0}")]
-    Store(String),
+    #[error("Internal error: {message}")]
+    Internal { message: String },
 
-    #[error("Node error: {0}")]
-    Node(String),
+    #[error("Store not found: {store_id}")]
+    StoreNotFound { store_id: StoreId },
 
-    #[error("Serialization error: {0}")]
-    Serialization(#[from] serde_json::Error),
+    #[error("Node not found: {node_id}")]
+    NodeNotFound { node_id: NodeId },
+
+    #[error("Store not open: {store_id}")]
+    StoreNotOpen { store_id: StoreId },
+
+    #[error("Store already exists at path: {path}")]
+    StoreExists { path: String },
+
+    #[error("Invalid store path: {path}")]
+    InvalidPath { path: String },
+
+    #[error("Invalid operation: {message}")]
+    InvalidOperation { message: String },
+
+    #[error("Invalid format: {message}")]
+    InvalidFormat { message: String },
+
+    #[error("IO error: {message}")]
+    Io { message: String },
+
+    #[error("CRDT error: {message}")]
+    Crdt { message: String },
+
+    #[error("Unknown version: {change}")]
+    UnknownVersion { change: String },
+
+    #[error("Invalid node type: {node_type}")]
+    InvalidNodeType { node_type: String },
+
+    #[error("Invalid link target: {target}")]
+    InvalidLinkTarget { target: String },
+
+    #[error("Remote store unavailable: {message}")]
+    RemoteUnavailable { message: String },
+
+    /// Changes were sent that build on changes the server doesn't have
+    #[error("Missing changes: {}", changes.join(", "))]
+    MissingChanges { changes: Vec<String> },
+
+    /// An error whose code isn't in this version's catalogue
+    #[error("Server error {code}: {message}")]
+    Other { code: i32, message: String },
 }
 
 impl RpcError {
+    /// The stable JSON-RPC error code for this error
     pub fn code(&self) -> i32 {
         match self {
-            RpcError::MethodNotFound(_) => -32601,
-            RpcError::InvalidParams(_) => -32602,
-            RpcError::Internal(_) => -32603,
-            RpcError::Store(_) => -32001,
-            RpcError::Node(_) => -32002,
-            RpcError::Serialization(_) => -32700,
+            RpcError::Parse { .. } => -32700,
+            RpcError::MethodNotFound { .. } => -32601,
+            RpcError::InvalidParams { .. } => -32602,
+            RpcError::Internal { .. } => -32603,
+            RpcError::StoreNotFound { .. } => -32001,
+            RpcError::NodeNotFound { .. } => -32002,
+            RpcError::StoreNotOpen { .. } => -32003,
+            RpcError::StoreExists { .. } => -32004,
+            RpcError::InvalidPath { .. } => -32005,
+            RpcError::InvalidOperation { .. } => -32006,
+            RpcError::InvalidFormat { .. } => -32007,
+            RpcError::Io { .. } => -32008,
+            RpcError::Crdt { .. } => -32009,
+            RpcError::UnknownVersion { .. } => -32010,
+            RpcError::InvalidNodeType { .. } => -32011,
+            RpcError::InvalidLinkTarget { .. } => -32012,
+            RpcError::RemoteUnavailable { .. } => -32013,
+            RpcError::MissingChanges { .. } => -32014,
+            RpcError::Other { code, .. } => *code,
+        }
+    }
+
+    /// Shorthand for [`RpcError::InvalidParams`]
+    pub fn invalid_params(message: impl std::fmt::Display) -> Self {
+        RpcError::InvalidParams {
+            message: message.to_string(),
+        }
+    }
+
+    /// Recover the error a server sent
+    ///
+    /// Errors without a payload this version understands, such as those
+    /// jsonrpsee raises itself for malformed requests, are rebuilt from
+    /// their code and message.
+    pub fn from_error_object(error: &ErrorObject<'_>) -> Self {
+        if let Some(error) = error
+            .data()
+            .and_then(|data| serde_json::from_str::<RpcError>(data.get()).ok())
+        {
+            return error;
+        }
+        let message = error.message().to_string();
+        match error.code() {
+            -32700 => RpcError::Parse { message },
+            -32601 => RpcError::MethodNotFound { message },
+            -32602 => RpcError::InvalidParams { message },
+            -32603 => RpcError::Internal { message },
+            code => RpcError::Other { code, message },
+        }
+    }
+}
+
+impl From<RpcError> for ErrorObjectOwned {
+    fn from(e: RpcError) -> Self {
+        ErrorObjectOwned::owned(e.code(), e.to_string(), Some(e))
+    }
+}
+
+impl From<CoreError> for RpcError {
+    fn from(e: CoreError) -> Self {
+        match e {
+            CoreError::NodeNotFound(node_id) => RpcError::NodeNotFound { node_id },
+            CoreError::StoreNotFound(store_id) => RpcError::StoreNotFound { store_id },
+            CoreError::InvalidNodeType(node_type) => RpcError::InvalidNodeType { node_type },
+            CoreError::InvalidLinkTarget(target) => RpcError::InvalidLinkTarget { target },
+            CoreError::Serialization(e) => RpcError::InvalidFormat {
+                message: e.to_string(),
+            },
+            CoreError::InvalidUuid(e) => RpcError::invalid_params(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(e: serde_json::Error) -> Self {
+        RpcError::Parse {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for RpcError {
+    fn from(e: std::io::Error) -> Self {
+        RpcError::Io {
+            message: e.to_string(),
         }
     }
 }
 
 pub type Result<T> = std::result::Result<T, RpcError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_round_trip() {
+        let node_id = NodeId::new();
+        let sent = RpcError::NodeNotFound { node_id };
+        let object = ErrorObjectOwned::from(sent.clone());
+
+        assert_eq!(object.code(), -32002);
+        assert_eq!(object.message(), format!("Node not found: {}", node_id));
+        let data: serde_json::Value = serde_json::from_str(object.data().unwrap().get()).unwrap();
+        assert_eq!(data["kind"], "node_not_found");
+        assert_eq!(data["node_id"], node_id.to_string());
+        assert_eq!(RpcError::from_error_object(&object), sent);
+
+        // Errors without a payload keep their code
+        let bare = ErrorObjectOwned::owned(-32050, "Something new", None::<()>);
+        assert_eq!(
+            RpcError::from_error_object(&bare),
+            RpcError::Other {
+                code: -32050,
+                message: "Something new".into()
+            }
+        );
+    }
+}
@@ -1,5 +1,6 @@
 //! RPC method definitions using jsonrpsee
 
+use jsonrpsee::core::SubscriptionResult;
 use jsonrpsee::proc_macros::rpc;
 use jsonrpsee::types::ErrorObjectOwned;
 
@@ -10,6 +11,14 @@ use crate::types::*;
 /// This defines all available RPC methods for the Pimble server.
 #[rpc(server, client, namespace = "pimble")]
 pub trait PimbleApi {
+    // ========================================================================
+    // Handshake
+    // ========================================================================
+
+    /// Introduce the client and learn what the server supports
+    #[method(name = "hello")]
+    async fn hello(&self, request: HelloRequest) -> Result<ServerInfo, ErrorObjectOwned>;
+
     // ========================================================================
     // Store Operations
     // ========================================================================
@@ -22,6 +31,10 @@ pub trait PimbleApi {
     #[method(name = "openStore")]
     async fn open_store(&self, request: OpenStoreRequest) -> Result<OpenStoreResponse, ErrorObjectOwned>;
 
+    /// Open a store served by another Pimble server
+    #[method(name = "openRemoteStore")]
+    async fn open_remote_store(&self, request: OpenRemoteStoreRequest) -> Result<OpenStoreResponse, ErrorObjectOwned>;
+
     /// Close a store
     #[method(name = "closeStore")]
     async fn close_store(&self, request: CloseStoreRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
@@ -30,6 +43,14 @@ pub trait PimbleApi {
     #[method(name = "listStores")]
     async fn list_stores(&self) -> Result<ListStoresResponse, ErrorObjectOwned>;
 
+    /// Upgrade every node's content in a store to current formats
+    #[method(name = "migrateStore")]
+    async fn migrate_store(&self, request: MigrateStoreRequest) -> Result<MigrateStoreResponse, ErrorObjectOwned>;
+
+    /// Rewrite a store's content files in full, dropping history where asked
+    #[method(name = "compactStore")]
+    async fn compact_store(&self, request: CompactStoreRequest) -> Result<CompactStoreResponse, ErrorObjectOwned>;
+
     // ========================================================================
     // Node Operations
     // ========================================================================
@@ -42,6 +63,10 @@ pub trait PimbleApi {
     #[method(name = "getNodes")]
     async fn get_nodes(&self, request: GetNodesRequest) -> Result<GetNodesResponse, ErrorObjectOwned>;
 
+    /// Resolve a link target across all open stores
+    #[method(name = "resolveLink")]
+    async fn resolve_link(&self, request: ResolveLinkRequest) -> Result<ResolveLinkResponse, ErrorObjectOwned>;
+
     /// Create a new node
     #[method(name = "createNode")]
     async fn create_node(&self, request: CreateNodeRequest) -> Result<CreateNodeResponse, ErrorObjectOwned>;
@@ -50,10 +75,38 @@ pub trait PimbleApi {
     #[method(name = "updateNodeMetadata")]
     async fn update_node_metadata(&self, request: UpdateNodeMetadataRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
 
-    /// Update a node's content
+    /// Change a node's title, merging with concurrent edits to other fields
+    #[method(name = "setTitle")]
+    async fn set_title(&self, request: SetTitleRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned>;
+
+    /// Add a tag to a node
+    #[method(name = "addTag")]
+    async fn add_tag(&self, request: TagRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned>;
+
+    /// Remove a tag from a node
+    #[method(name = "removeTag")]
+    async fn remove_tag(&self, request: TagRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned>;
+
+    /// Set or remove one of a node's custom fields
+    #[method(name = "setCustomField")]
+    async fn set_custom_field(&self, request: SetCustomFieldRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned>;
+
+    /// Choose how much of a node's content history to keep
+    #[method(name = "setHistoryMode")]
+    async fn set_history_mode(&self, request: SetHistoryModeRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
+    /// Replace a node's content with a whole document
     #[method(name = "updateNodeContent")]
     async fn update_node_content(&self, request: UpdateNodeContentRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
 
+    /// Merge CRDT changes into a node's content
+    #[method(name = "applyNodeChanges")]
+    async fn apply_node_changes(&self, request: ApplyNodeChangesRequest) -> Result<ApplyNodeChangesResponse, ErrorObjectOwned>;
+
+    /// Get the changes to a node's content beyond the caller's heads
+    #[method(name = "getNodeChangesSince")]
+    async fn get_node_changes_since(&self, request: GetNodeChangesSinceRequest) -> Result<GetNodeChangesSinceResponse, ErrorObjectOwned>;
+
     /// Set a node's text content (replaces all content)
     #[method(name = "setNodeText")]
     async fn set_node_text(&self, request: SetNodeTextRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
@@ -66,6 +119,14 @@ pub trait PimbleApi {
     #[method(name = "moveNode")]
     async fn move_node(&self, request: MoveNodeRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
 
+    /// Deep-copy a subtree with fresh IDs, possibly into another store
+    #[method(name = "copyNode")]
+    async fn copy_node(&self, request: CopyNodeRequest) -> Result<CopyNodeResponse, ErrorObjectOwned>;
+
+    /// Move a subtree from one store to another
+    #[method(name = "moveNodeAcrossStores")]
+    async fn move_node_across_stores(&self, request: MoveNodeAcrossStoresRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
     /// Get children of a node
     #[method(name = "getChildren")]
     async fn get_children(&self, request: GetChildrenRequest) -> Result<GetChildrenResponse, ErrorObjectOwned>;
@@ -86,6 +147,159 @@ pub trait PimbleApi {
     #[method(name = "createWorkspace")]
     async fn create_workspace(&self, request: CreateWorkspaceRequest) -> Result<LoadWorkspaceResponse, ErrorObjectOwned>;
 
+    // ========================================================================
+    // Export Operations
+    // ========================================================================
+
+    /// Export a subtree to a folder of markdown files
+    #[method(name = "exportMarkdown")]
+    async fn export_markdown(&self, request: ExportMarkdownRequest) -> Result<ExportResponse, ErrorObjectOwned>;
+
+    /// Publish a subtree as a static HTML site
+    #[method(name = "exportHtml")]
+    async fn export_html(&self, request: ExportHtmlRequest) -> Result<ExportResponse, ErrorObjectOwned>;
+
+    /// Export a subtree as an OPML outline
+    #[method(name = "exportOpml")]
+    async fn export_opml(&self, request: ExportOpmlRequest) -> Result<ExportOpmlResponse, ErrorObjectOwned>;
+
+    /// Import an OPML outline into a store
+    #[method(name = "importOpml")]
+    async fn import_opml(&self, request: ImportOpmlRequest) -> Result<ImportOpmlResponse, ErrorObjectOwned>;
+
+    // ========================================================================
+    // Replication Operations
+    // ========================================================================
+
+    /// Summarise a store's nodes and deletions for a replicating peer
+    #[method(name = "replicationManifest")]
+    async fn replication_manifest(&self, request: ReplicationManifestRequest) -> Result<ReplicationManifestResponse, ErrorObjectOwned>;
+
+    /// Apply node records and deletions sent by a replicating peer
+    #[method(name = "applyNodeRecords")]
+    async fn apply_node_records(&self, request: ApplyNodeRecordsRequest) -> Result<ApplyNodeRecordsResponse, ErrorObjectOwned>;
+
+    /// Exchange one Automerge sync message for a node's content
+    #[method(name = "syncNodeContent")]
+    async fn sync_node_content(&self, request: SyncNodeContentRequest) -> Result<SyncNodeContentResponse, ErrorObjectOwned>;
+
+    /// Merge a peer's tree structure into a store's and return the result
+    #[method(name = "mergeStoreTree")]
+    async fn merge_store_tree(&self, request: MergeStoreTreeRequest) -> Result<MergeStoreTreeResponse, ErrorObjectOwned>;
+
+    /// Replicate a store with another server once
+    #[method(name = "replicateStore")]
+    async fn replicate_store(&self, request: ReplicateStoreRequest) -> Result<ReplicateStoreResponse, ErrorObjectOwned>;
+
+    /// Create a local replica of a store held by another server
+    #[method(name = "cloneStore")]
+    async fn clone_store(&self, request: CloneStoreRequest) -> Result<OpenStoreResponse, ErrorObjectOwned>;
+
+    /// Keep a store replicated with another server in the background
+    #[method(name = "pairStore")]
+    async fn pair_store(&self, request: PairStoreRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
+    /// Stop background replication for a store
+    #[method(name = "unpairStore")]
+    async fn unpair_store(&self, request: UnpairStoreRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
+    // ========================================================================
+    // Conflict Operations
+    // ========================================================================
+
+    /// List a store's unreconciled concurrent edits
+    #[method(name = "listConflicts")]
+    async fn list_conflicts(&self, request: ListConflictsRequest) -> Result<ListConflictsResponse, ErrorObjectOwned>;
+
+    /// Settle a conflict on a picked or merged value; returns what remains
+    #[method(name = "resolveConflict")]
+    async fn resolve_conflict(&self, request: ResolveConflictRequest) -> Result<ListConflictsResponse, ErrorObjectOwned>;
+
+    // ========================================================================
+    // History Operations
+    // ========================================================================
+
+    /// List the changes to a node's content, oldest first
+    #[method(name = "getNodeHistory")]
+    async fn get_node_history(&self, request: GetNodeHistoryRequest) -> Result<GetNodeHistoryResponse, ErrorObjectOwned>;
+
+    /// Get a node as it was at an earlier version
+    #[method(name = "getNodeAtVersion")]
+    async fn get_node_at_version(&self, request: NodeVersionRequest) -> Result<GetNodeAtVersionResponse, ErrorObjectOwned>;
+
+    /// Restore a node's content to an earlier version, as a new change
+    #[method(name = "restoreNodeVersion")]
+    async fn restore_node_version(&self, request: NodeVersionRequest) -> Result<GetNodeResponse, ErrorObjectOwned>;
+
+    /// Diff a node's text between two versions
+    #[method(name = "diffNodeVersions")]
+    async fn diff_node_versions(&self, request: DiffNodeVersionsRequest) -> Result<DiffNodeVersionsResponse, ErrorObjectOwned>;
+
+    // ========================================================================
+    // Identity Operations
+    // ========================================================================
+
+    /// Register or remove the display name of a device writing to a store
+    #[method(name = "setIdentity")]
+    async fn set_identity(&self, request: SetIdentityRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
+    /// List the devices named in a store
+    #[method(name = "listIdentities")]
+    async fn list_identities(&self, request: ListIdentitiesRequest) -> Result<ListIdentitiesResponse, ErrorObjectOwned>;
+
+    // ========================================================================
+    // Change Subscriptions
+    // ========================================================================
+
+    /// Receive every change to one node of a local store
+    #[subscription(name = "subscribeNode", unsubscribe = "unsubscribeNode", item = NodeChangedNotification)]
+    async fn subscribe_node(&self, request: SubscribeNodeRequest) -> SubscriptionResult;
+
+    /// Receive every change to the nodes of a local store
+    ///
+    /// The subscription ends with an error if the subscriber falls too far
+    /// behind to be sent every change; it should reload and subscribe again.
+    #[subscription(name = "subscribeStore", unsubscribe = "unsubscribeStore", item = NodeChangedNotification)]
+    async fn subscribe_store(&self, request: SubscribeStoreRequest) -> SubscriptionResult;
+
+    // ========================================================================
+    // Presence Operations
+    // ========================================================================
+
+    /// Announce or refresh a client's presence and cursor on a node
+    ///
+    /// Presence is kept in memory only and expires unless refreshed.
+    #[method(name = "publishPresence")]
+    async fn publish_presence(&self, request: PublishPresenceRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
+    /// Withdraw a client's presence from a node
+    #[method(name = "leavePresence")]
+    async fn leave_presence(&self, request: LeavePresenceRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
+    /// List the clients present on a node
+    #[method(name = "getPresence")]
+    async fn get_presence(&self, request: GetPresenceRequest) -> Result<PresenceResponse, ErrorObjectOwned>;
+
+    /// Receive the clients present on a node whenever they change
+    #[subscription(name = "subscribePresence", unsubscribe = "unsubscribePresence", item = PresenceNotification)]
+    async fn subscribe_presence(&self, request: SubscribePresenceRequest) -> SubscriptionResult;
+
+    // ========================================================================
+    // Undo Operations
+    // ========================================================================
+
+    /// Undo the session's latest edit to a local store
+    #[method(name = "undo")]
+    async fn undo(&self, request: UndoRequest) -> Result<UndoResponse, ErrorObjectOwned>;
+
+    /// Redo the session's latest undone edit
+    #[method(name = "redo")]
+    async fn redo(&self, request: UndoRequest) -> Result<UndoResponse, ErrorObjectOwned>;
+
+    /// Forget the session's undo history, once its client is done
+    #[method(name = "closeSession")]
+    async fn close_session(&self, request: CloseSessionRequest) -> Result<EmptyResponse, ErrorObjectOwned>;
+
     // ========================================================================
     // Search Operations
     // ========================================================================
@@ -95,7 +309,7 @@ pub trait PimbleApi {
     async fn search(&self, request: SearchRequest) -> Result<SearchResponse, ErrorObjectOwned>;
 }
 
-/// Helper function to convert any error to ErrorObjectOwned
-pub fn to_rpc_error(e: impl std::fmt::Display) -> ErrorObjectOwned {
-    ErrorObjectOwned::owned(-32000, e.to_string(), None::<()>)
+/// Convert an error to the JSON-RPC error sent to clients, with its code and data
+pub fn to_rpc_error(e: impl Into<crate::RpcError>) -> ErrorObjectOwned {
+    e.into().into()
 }
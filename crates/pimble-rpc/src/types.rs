@@ -2,8 +2,81 @@
 
 use std::path::PathBuf;
 
-use pimble_core::{Node, NodeId, NodeMetadata, Store, StoreId, Workspace};
+use chrono::{DateTime, Utc};
+use pimble_core::{
+    AuthMethod, CompactionReport, ConflictInfo, HistoryEntry, HistoryMode, Identity, LinkResolution, LinkTarget, MigrationReport, Node, NodeChange, NodeDiff, NodeId,
+    NodeMetadata, NodeVersion, Store, StoreId, Tombstone, UndoneEdit, Workspace,
+};
 use serde::{Deserialize, Serialize};
+use url::Url;
+
+// ============================================================================
+// Handshake
+// ============================================================================
+
+/// Protocol version spoken by this build
+///
+/// Bumped whenever a change to methods or types would break peers built
+/// before it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version this build can still talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Greeting a client sends before anything else
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloRequest {
+    pub protocol_version: u32,
+    /// Client build, e.g. "pimble-client 0.1.0"
+    pub client: String,
+}
+
+/// What a server speaks and supports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    /// Server build, e.g. "pimble-server 0.1.0"
+    pub server: String,
+    /// Node types the server has plugins for
+    pub node_types: Vec<String>,
+    pub features: Features,
+    pub limits: Limits,
+}
+
+impl ServerInfo {
+    /// Check whether a client speaking `version` can talk to this server
+    pub fn supports(&self, version: u32) -> bool {
+        (self.min_protocol_version..=self.protocol_version).contains(&version)
+    }
+}
+
+/// Optional parts of the API a server has enabled
+///
+/// Features a server doesn't mention are off.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Features {
+    /// Full-text search
+    pub search: bool,
+    /// Search by meaning rather than words
+    pub semantic_search: bool,
+    /// Store replication with peer servers
+    pub sync: bool,
+    /// Change and presence subscriptions over WebSocket
+    pub subscriptions: bool,
+}
+
+/// Limits a server applies to each connection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Largest request accepted, in bytes
+    pub max_request_size: u32,
+    /// Largest response sent, in bytes
+    pub max_response_size: u32,
+    pub max_connections: u32,
+    pub max_subscriptions_per_connection: u32,
+}
 
 // ============================================================================
 // Store Operations
@@ -35,6 +108,19 @@ pub struct OpenStoreResponse {
     pub store: Store,
 }
 
+/// Request to open a store served by another Pimble server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenRemoteStoreRequest {
+    /// Server URL, optionally naming the store with `?store=<id>`
+    pub url: Url,
+    /// Credentials for the remote server (none if omitted)
+    #[serde(default)]
+    pub auth: Option<AuthMethod>,
+    /// Keep an offline replica of the store at this path on the server
+    #[serde(default)]
+    pub replica_path: Option<PathBuf>,
+}
+
 /// Request to close a store
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CloseStoreRequest {
@@ -51,6 +137,34 @@ pub struct ListStoresResponse {
     pub stores: Vec<Store>,
 }
 
+/// Request to upgrade every node's content in a store to current formats
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateStoreRequest {
+    pub store_id: StoreId,
+}
+
+/// Response with what upgrading a store's content did
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateStoreResponse {
+    pub report: MigrationReport,
+}
+
+/// Request to rewrite a store's content files in full
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactStoreRequest {
+    pub store_id: StoreId,
+    /// Keep history that nodes' history modes ask to drop, because the
+    /// caller holds copies of the content that build on it
+    #[serde(default)]
+    pub keep_history: bool,
+}
+
+/// Response with what compacting a store did
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactStoreResponse {
+    pub report: CompactionReport,
+}
+
 // ============================================================================
 // Node Operations
 // ============================================================================
@@ -81,6 +195,20 @@ pub struct GetNodesResponse {
     pub nodes: Vec<Node>,
 }
 
+/// Request to resolve a link target across the open stores
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveLinkRequest {
+    /// Store containing the node the link is in
+    pub store_id: StoreId,
+    pub target: LinkTarget,
+}
+
+/// Response describing where a link points
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveLinkResponse {
+    pub resolution: LinkResolution,
+}
+
 /// Request to create a new node
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateNodeRequest {
@@ -88,6 +216,9 @@ pub struct CreateNodeRequest {
     pub parent_id: Option<NodeId>,
     pub node_type: String,
     pub title: String,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 /// Response after creating a node
@@ -102,6 +233,59 @@ pub struct UpdateNodeMetadataRequest {
     pub store_id: StoreId,
     pub node_id: NodeId,
     pub metadata: NodeMetadata,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Request to change a node's title
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetTitleRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub title: String,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Request to add a tag to, or remove one from, a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub tag: String,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Request to set or remove one of a node's custom fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetCustomFieldRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub key: String,
+    /// New value; null or omitted removes the field
+    #[serde(default)]
+    pub value: Option<serde_json::Value>,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Request to choose how much of a node's history to keep
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetHistoryModeRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub mode: HistoryMode,
+}
+
+/// Response with a node's metadata after an edit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeMetadataResponse {
+    pub metadata: NodeMetadata,
 }
 
 /// Request to update a node's content
@@ -111,6 +295,47 @@ pub struct UpdateNodeContentRequest {
     pub node_id: NodeId,
     /// Base64-encoded document bytes (full replacement)
     pub content: String,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Request to merge CRDT changes into a node's content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyNodeChangesRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    /// Base64-encoded Automerge changes, oldest first
+    pub changes: Vec<String>,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Response with a node's content heads after merging changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyNodeChangesResponse {
+    /// Heads of the merged content, as hex change hashes
+    pub heads: Vec<String>,
+}
+
+/// Request for the changes to a node's content beyond some heads
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetNodeChangesSinceRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    /// Heads the caller already has; empty for the whole history
+    #[serde(default)]
+    pub heads: Vec<String>,
+}
+
+/// Response with the changes a caller is missing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetNodeChangesSinceResponse {
+    /// Base64-encoded Automerge changes, oldest first
+    pub changes: Vec<String>,
+    /// Heads of the node's content, as hex change hashes
+    pub heads: Vec<String>,
 }
 
 /// Request to set a node's text content (replaces all content)
@@ -120,6 +345,9 @@ pub struct SetNodeTextRequest {
     pub node_id: NodeId,
     /// The new text content
     pub text: String,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 /// Request to delete a node
@@ -127,6 +355,9 @@ pub struct SetNodeTextRequest {
 pub struct DeleteNodeRequest {
     pub store_id: StoreId,
     pub node_id: NodeId,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 /// Request to move a node to a new parent
@@ -137,6 +368,37 @@ pub struct MoveNodeRequest {
     pub new_parent_id: NodeId,
     /// Position within the new parent's children (None = append)
     pub position: Option<usize>,
+    /// Client session making the edit, so it can undo it
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Request to deep-copy a subtree, possibly into another store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyNodeRequest {
+    pub source_store_id: StoreId,
+    pub node_id: NodeId,
+    pub target_store_id: StoreId,
+    pub new_parent_id: NodeId,
+    /// Position within the new parent's children (None = append)
+    pub position: Option<usize>,
+}
+
+/// Response containing the root of a copied subtree
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyNodeResponse {
+    pub node_id: NodeId,
+}
+
+/// Request to move a subtree from one store to another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveNodeAcrossStoresRequest {
+    pub source_store_id: StoreId,
+    pub node_id: NodeId,
+    pub target_store_id: StoreId,
+    pub new_parent_id: NodeId,
+    /// Position within the new parent's children (None = append)
+    pub position: Option<usize>,
 }
 
 /// Request to get children of a node
@@ -212,6 +474,397 @@ pub struct SearchResponse {
     pub total: usize,
 }
 
+// ============================================================================
+// Export Operations
+// ============================================================================
+
+/// Request to export a subtree to a folder of markdown files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportMarkdownRequest {
+    pub store_id: StoreId,
+    /// Root of the subtree to export (None = whole store)
+    pub node_id: Option<NodeId>,
+    /// Directory to write into
+    pub output_dir: PathBuf,
+    /// Only rewrite changed files and prune stale ones
+    #[serde(default)]
+    pub incremental: bool,
+}
+
+/// Request to publish a subtree as a static HTML site
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportHtmlRequest {
+    pub store_id: StoreId,
+    /// Root of the subtree to export (None = whole store)
+    pub node_id: Option<NodeId>,
+    /// Directory to write into
+    pub output_dir: PathBuf,
+    /// Site title (defaults to the root node's title)
+    #[serde(default)]
+    pub site_title: Option<String>,
+    /// Only rewrite changed files and prune stale ones
+    #[serde(default)]
+    pub incremental: bool,
+}
+
+/// Summary of an export run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportResponse {
+    /// Files written, relative to the output directory
+    pub written: Vec<PathBuf>,
+    /// Files left untouched because they were unchanged
+    pub unchanged: usize,
+    /// Stale files removed from a previous export
+    pub removed: Vec<PathBuf>,
+    /// Asset files copied
+    pub assets_copied: usize,
+}
+
+/// Request to export a subtree as OPML
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportOpmlRequest {
+    pub store_id: StoreId,
+    /// Root of the subtree to export (None = whole store)
+    pub node_id: Option<NodeId>,
+}
+
+/// Response containing an OPML document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportOpmlResponse {
+    pub opml: String,
+}
+
+/// Request to import an OPML document into a store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportOpmlRequest {
+    pub store_id: StoreId,
+    /// Node to import under (None = store root)
+    pub parent_id: Option<NodeId>,
+    /// The OPML document
+    pub opml: String,
+}
+
+/// Response listing the top-level nodes created by an import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportOpmlResponse {
+    pub node_ids: Vec<NodeId>,
+}
+
+// ============================================================================
+// Conflict Operations
+// ============================================================================
+
+/// Request to list a store's unreconciled concurrent edits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListConflictsRequest {
+    pub store_id: StoreId,
+}
+
+/// Settle a conflicting key of a node on a single value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveConflictRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    /// Key from the conflict being resolved
+    pub key: String,
+    /// One of the conflicting values, or a merge of them
+    pub value: serde_json::Value,
+}
+
+/// Response with a store's conflicts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListConflictsResponse {
+    pub conflicts: Vec<ConflictInfo>,
+}
+
+// ============================================================================
+// History Operations
+// ============================================================================
+
+/// Request to list the changes to a node's content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetNodeHistoryRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+}
+
+/// Response with a node's changes, oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetNodeHistoryResponse {
+    pub changes: Vec<HistoryEntry>,
+}
+
+/// Request naming an earlier version of a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeVersionRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    /// Heads of the version, as hex change hashes
+    pub heads: Vec<String>,
+}
+
+/// Response with a node as it was at a version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetNodeAtVersionResponse {
+    /// The node, with its content and metadata at that version
+    pub node: Node,
+    /// The node's text at that version
+    pub text: String,
+}
+
+/// Request to diff a node's text between two versions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffNodeVersionsRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    /// Heads of the older version; empty for the start of history
+    pub from: Vec<String>,
+    /// Heads of the newer version; the current version if omitted
+    #[serde(default)]
+    pub to: Option<Vec<String>>,
+}
+
+/// Response with the differences between two versions of a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffNodeVersionsResponse {
+    pub diff: NodeDiff,
+}
+
+// ============================================================================
+// Identity Operations
+// ============================================================================
+
+/// Request to name a device writing to a store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetIdentityRequest {
+    pub store_id: StoreId,
+    /// The device's id, in hex
+    pub device_id: String,
+    /// Name to show for the device's changes; None removes it
+    pub name: Option<String>,
+}
+
+/// Request to list the devices named in a store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListIdentitiesRequest {
+    pub store_id: StoreId,
+}
+
+/// Response with a store's named devices
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListIdentitiesResponse {
+    pub identities: Vec<Identity>,
+}
+
+// ============================================================================
+// Undo Operations
+// ============================================================================
+
+/// Request to undo or redo a client session's latest edit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoRequest {
+    pub session_id: String,
+}
+
+/// Response after an undo or redo
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoResponse {
+    /// The edit reversed; None when there was nothing to reverse
+    pub edit: Option<UndoneEdit>,
+    /// Whether the session has an edit left to undo
+    pub can_undo: bool,
+    /// Whether the session has an edit left to redo
+    pub can_redo: bool,
+}
+
+/// Request to forget a client session's undo history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloseSessionRequest {
+    pub session_id: String,
+}
+
+// ============================================================================
+// Replication Operations
+// ============================================================================
+
+/// Request a summary of a store's nodes for replication
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationManifestRequest {
+    pub store_id: StoreId,
+}
+
+/// Summary of a store's nodes and deletions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationManifestResponse {
+    pub store: Store,
+    pub nodes: Vec<NodeVersion>,
+    pub tombstones: Vec<Tombstone>,
+}
+
+/// Apply node records and deletions sent by a peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyNodeRecordsRequest {
+    pub store_id: StoreId,
+    pub nodes: Vec<Node>,
+    #[serde(default)]
+    pub tombstones: Vec<Tombstone>,
+}
+
+/// Response with the number of nodes added, updated or deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyNodeRecordsResponse {
+    pub applied: usize,
+}
+
+/// Exchange one Automerge sync message for a node's content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncNodeContentRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    /// Identifies the calling peer, so its sync state can be kept
+    pub peer_id: String,
+    /// Base64-encoded sync message; None to start a session
+    pub message: Option<String>,
+}
+
+/// Reply to a content sync message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncNodeContentResponse {
+    /// Base64-encoded sync message; None when there is nothing to send
+    pub message: Option<String>,
+}
+
+/// Merge a peer's copy of a store's tree structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeStoreTreeRequest {
+    pub store_id: StoreId,
+    /// Base64-encoded tree document; None to just fetch the server's
+    #[serde(default)]
+    pub tree: Option<String>,
+}
+
+/// The merged tree structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeStoreTreeResponse {
+    /// Base64-encoded tree document
+    pub tree: String,
+}
+
+/// Replicate a store with the same store on another server, once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicateStoreRequest {
+    pub store_id: StoreId,
+    /// URL of the peer server
+    pub peer_url: String,
+}
+
+/// What a replication pass exchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplicateStoreResponse {
+    /// Node records fetched from the peer
+    pub pulled: usize,
+    /// Node records sent to the peer
+    pub pushed: usize,
+    /// Nodes whose content was merged with the peer's
+    pub content_synced: usize,
+    /// Nodes deleted locally because the peer deleted them
+    pub deleted: usize,
+}
+
+/// Create a local replica of a store on another server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneStoreRequest {
+    /// URL of the server holding the store
+    pub peer_url: String,
+    pub store_id: StoreId,
+    /// Where to create the replica
+    pub path: PathBuf,
+}
+
+/// Keep a store replicated with another server in the background
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairStoreRequest {
+    pub store_id: StoreId,
+    /// URL of the peer server
+    pub peer_url: String,
+    /// Seconds between replication passes (default 30)
+    pub interval_secs: Option<u64>,
+}
+
+/// Stop background replication for a store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnpairStoreRequest {
+    pub store_id: StoreId,
+}
+
+// ============================================================================
+// Presence Operations
+// ============================================================================
+
+/// One client's presence on an open node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    /// Identifies the client; a client's session id serves
+    pub client_id: String,
+    /// Name shown to other clients
+    pub name: String,
+    /// Cursor position, as an anchor from `DocumentContent::anchor_at`
+    pub anchor: Option<String>,
+    /// Other end of the selection, if any
+    pub head: Option<String>,
+    /// When the client last published
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Announce or refresh a client's presence on a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishPresenceRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub client_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub anchor: Option<String>,
+    #[serde(default)]
+    pub head: Option<String>,
+}
+
+/// Withdraw a client's presence from a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeavePresenceRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub client_id: String,
+}
+
+/// Get the clients present on a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetPresenceRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+}
+
+/// Clients present on a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceResponse {
+    pub present: Vec<Presence>,
+}
+
+/// Subscribe to presence on a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribePresenceRequest {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+}
+
+/// Notification that presence on a node changed, listing everyone present
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceNotification {
+    pub store_id: StoreId,
+    pub node_id: NodeId,
+    pub present: Vec<Presence>,
+}
+
 // ============================================================================
 // Subscription Types (for WebSocket)
 // ============================================================================
@@ -230,22 +883,10 @@ pub struct SubscribeStoreRequest {
 }
 
 /// Notification of node change
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NodeChangedNotification {
-    pub store_id: StoreId,
-    pub node_id: NodeId,
-    pub change_type: ChangeType,
-}
+pub type NodeChangedNotification = NodeChange;
 
 /// Type of change
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum ChangeType {
-    Created,
-    Updated,
-    Deleted,
-    Moved,
-}
+pub use pimble_core::ChangeType;
 
 // ============================================================================
 // Common Response Types
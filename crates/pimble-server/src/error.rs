@@ -1,5 +1,7 @@
 //! Error types for pimble-server
 
+use pimble_rpc::RpcError;
+use pimble_store::StoreError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,6 +15,12 @@ pub enum ServerError {
     #[error("RPC error: {0}")]
     Rpc(#[from] pimble_rpc::RpcError),
 
+    #[error("Peer error: {0}")]
+    Peer(#[from] pimble_client::ClientError),
+
+    #[error("CRDT error: {0}")]
+    Crdt(#[from] pimble_crdt::CrdtError),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -20,4 +28,18 @@ pub enum ServerError {
     Json(#[from] serde_json::Error),
 }
 
+impl From<ServerError> for RpcError {
+    fn from(e: ServerError) -> Self {
+        match e {
+            ServerError::Server(message) => RpcError::Internal { message },
+            ServerError::Store(e) => e.into(),
+            ServerError::Rpc(e) => e,
+            ServerError::Peer(e) => e.into(),
+            ServerError::Crdt(e) => StoreError::Crdt(e).into(),
+            ServerError::Io(e) => e.into(),
+            ServerError::Json(e) => StoreError::Serialization(e).into(),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, ServerError>;
@@ -1,36 +1,241 @@
 //! RPC method handlers
 
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::time::Duration;
 
-use jsonrpsee::core::async_trait;
+use jsonrpsee::core::{async_trait, SubscriptionResult};
+use jsonrpsee::PendingSubscriptionSink;
+use jsonrpsee::SubscriptionMessage;
 use jsonrpsee::types::ErrorObjectOwned;
-use pimble_core::{Node, Workspace};
-use pimble_crdt::DocumentContent;
+use pimble_core::{AuthMethod, MetadataEdit, Node, NodeChange, NodeId, StoreId, UndoneEdit, Workspace};
+use pimble_crdt::{DeviceId, DocumentContent};
 use pimble_rpc::{
-    to_rpc_error, CloseStoreRequest, CreateNodeRequest, CreateNodeResponse, CreateStoreRequest,
-    CreateStoreResponse, CreateWorkspaceRequest, DeleteNodeRequest, EmptyResponse,
-    GetChildrenRequest, GetChildrenResponse, GetNodeRequest, GetNodeResponse, GetNodesRequest,
-    GetNodesResponse, ListStoresResponse, LoadWorkspaceRequest, LoadWorkspaceResponse,
-    MoveNodeRequest, OpenStoreRequest, OpenStoreResponse, PimbleApiServer, SaveWorkspaceRequest,
-    SearchRequest, SearchResponse, SetNodeTextRequest, UpdateNodeContentRequest, UpdateNodeMetadataRequest,
+    to_rpc_error, ApplyNodeChangesRequest, ApplyNodeChangesResponse, ApplyNodeRecordsRequest,
+    ApplyNodeRecordsResponse, CloneStoreRequest, CloseSessionRequest, CloseStoreRequest, CompactStoreRequest,
+    CompactStoreResponse, CopyNodeRequest,
+    CopyNodeResponse, CreateNodeRequest, CreateNodeResponse, CreateStoreRequest,
+    CreateStoreResponse, CreateWorkspaceRequest, DeleteNodeRequest, DiffNodeVersionsRequest,
+    DiffNodeVersionsResponse, EmptyResponse, GetPresenceRequest, LeavePresenceRequest, ExportHtmlRequest, ExportMarkdownRequest,
+    ExportOpmlRequest, ExportOpmlResponse, ExportResponse, GetChildrenRequest,
+    GetChildrenResponse, GetNodeAtVersionResponse, GetNodeChangesSinceRequest,
+    GetNodeChangesSinceResponse, GetNodeHistoryRequest, GetNodeHistoryResponse, GetNodeRequest,
+    GetNodeResponse, GetNodesRequest, GetNodesResponse, HelloRequest, ImportOpmlRequest, ImportOpmlResponse,
+    ListConflictsRequest, ListConflictsResponse, ListIdentitiesRequest, ListIdentitiesResponse,
+    ListStoresResponse, LoadWorkspaceRequest,
+    LoadWorkspaceResponse, MergeStoreTreeRequest, MergeStoreTreeResponse, MigrateStoreRequest,
+    MigrateStoreResponse, MoveNodeAcrossStoresRequest, MoveNodeRequest, NodeMetadataResponse,
+    NodeVersionRequest,
+    OpenRemoteStoreRequest, OpenStoreRequest, OpenStoreResponse, PairStoreRequest,
+    PimbleApiServer, Presence, PresenceNotification, PresenceResponse, PublishPresenceRequest,
+    ReplicateStoreRequest, ReplicateStoreResponse, ReplicationManifestRequest,
+    ReplicationManifestResponse, ResolveConflictRequest, ResolveLinkRequest,
+    ResolveLinkResponse, RpcError, SaveWorkspaceRequest, SearchRequest, SearchResponse,
+    ServerInfo, SetCustomFieldRequest, SetHistoryModeRequest, SetIdentityRequest, SetNodeTextRequest, SetTitleRequest,
+    SubscribeNodeRequest, SubscribePresenceRequest, SubscribeStoreRequest, SyncNodeContentRequest,
+    SyncNodeContentResponse, TagRequest, UndoRequest, UndoResponse, UnpairStoreRequest,
+    UpdateNodeContentRequest, UpdateNodeMetadataRequest,
 };
-use pimble_store::StoreManager;
-use tokio::sync::RwLock;
+use pimble_store::{ExportReport, HtmlExportOptions, MarkdownExportOptions, RemoteStore, StoreError, StoreManager};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{RwLock, RwLockWriteGuard};
 use tracing::{debug, info};
 
+use crate::presence::PresenceHub;
+use crate::replication::{ReplicationReport, Replicator};
+
 /// RPC handler implementation
 pub struct RpcHandler {
     store_manager: Arc<RwLock<StoreManager>>,
+    replicator: Arc<Replicator>,
+    presence: Arc<PresenceHub>,
+    /// Answer to every client's hello
+    info: ServerInfo,
 }
 
 impl RpcHandler {
-    pub fn new(store_manager: Arc<RwLock<StoreManager>>) -> Self {
-        Self { store_manager }
+    pub fn new(
+        store_manager: Arc<RwLock<StoreManager>>,
+        replicator: Arc<Replicator>,
+        presence: Arc<PresenceHub>,
+        info: ServerInfo,
+    ) -> Self {
+        Self {
+            store_manager,
+            replicator,
+            presence,
+            info,
+        }
+    }
+
+    /// Lock the store manager for a request on `store_id`
+    ///
+    /// Requests on a remote store get a view holding just that store
+    /// instead, so its network I/O happens without the server-wide lock.
+    async fn manager(&self, store_id: StoreId) -> ManagerGuard<'_> {
+        if let Some(view) = self.store_manager.read().await.remote_view(store_id) {
+            return ManagerGuard::Remote(view);
+        }
+        ManagerGuard::Shared(self.store_manager.write().await)
+    }
+
+    /// Make an edit to a node, recording it for the session making it to undo
+    ///
+    /// Returns the locked manager, for saving the edit.
+    async fn edit_node<T>(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        session_id: Option<&str>,
+        edit: impl AsyncFnOnce(&mut StoreManager) -> Result<T, StoreError>,
+    ) -> Result<(ManagerGuard<'_>, T), ErrorObjectOwned> {
+        let mut manager = self.manager(store_id).await;
+        let checkpoint = manager
+            .undo_checkpoint(session_id, store_id, node_id)
+            .await
+            .map_err(to_rpc_error)?;
+        let value = edit(&mut manager).await.map_err(to_rpc_error)?;
+        manager.record_undo(checkpoint).await.map_err(to_rpc_error)?;
+        Ok((manager, value))
+    }
+
+    /// Apply a single-field metadata edit and save it
+    async fn edit_metadata(
+        &self,
+        store_id: StoreId,
+        node_id: NodeId,
+        edit: MetadataEdit,
+        session_id: Option<String>,
+    ) -> Result<NodeMetadataResponse, ErrorObjectOwned> {
+        debug!("Editing metadata for node {} in store {}: {:?}", node_id, store_id, edit);
+
+        let (mut manager, metadata) = self
+            .edit_node(store_id, node_id, session_id.as_deref(), async |manager| {
+                manager.edit_node_metadata(store_id, node_id, &edit).await
+            })
+            .await?;
+
+        manager
+            .flush(store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(NodeMetadataResponse { metadata })
+    }
+
+    /// Send a subscriber the changes to a local store that `keep` selects
+    async fn forward_changes(
+        &self,
+        pending: PendingSubscriptionSink,
+        store_id: StoreId,
+        keep: impl Fn(&NodeChange) -> bool,
+    ) -> SubscriptionResult {
+        let mut changes = {
+            let manager = self.store_manager.read().await;
+            let refusal = if !manager.is_open(store_id) {
+                Some(StoreError::NotOpen(store_id))
+            } else if manager.is_remote(store_id) {
+                Some(StoreError::InvalidOperation(
+                    "Subscribe to a remote store on the server holding it".into(),
+                ))
+            } else {
+                None
+            };
+            if let Some(e) = refusal {
+                pending.reject(to_rpc_error(e)).await;
+                return Ok(());
+            }
+            manager.subscribe_changes()
+        };
+        let sink = pending.accept().await?;
+
+        loop {
+            let change = tokio::select! {
+                _ = sink.closed() => break,
+                change = changes.recv() => match change {
+                    Ok(change) if keep(&change) => change,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => {
+                        return Err(format!("Missed {} changes; reload and subscribe again", missed).into());
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            sink.send(SubscriptionMessage::from_json(&change)?).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The store manager, locked for one request
+enum ManagerGuard<'a> {
+    Shared(RwLockWriteGuard<'a, StoreManager>),
+    Remote(StoreManager),
+}
+
+impl Deref for ManagerGuard<'_> {
+    type Target = StoreManager;
+
+    fn deref(&self) -> &StoreManager {
+        match self {
+            ManagerGuard::Shared(manager) => manager,
+            ManagerGuard::Remote(manager) => manager,
+        }
+    }
+}
+
+impl DerefMut for ManagerGuard<'_> {
+    fn deref_mut(&mut self) -> &mut StoreManager {
+        match self {
+            ManagerGuard::Shared(manager) => manager,
+            ManagerGuard::Remote(manager) => manager,
+        }
+    }
+}
+
+/// Save the store an undo or redo changed and report the session's state
+async fn undo_response(
+    manager: &mut StoreManager,
+    session_id: &str,
+    edit: Option<UndoneEdit>,
+) -> Result<UndoResponse, ErrorObjectOwned> {
+    if let Some(edit) = &edit {
+        manager.flush(edit.store_id).await.map_err(to_rpc_error)?;
+    }
+    let (can_undo, can_redo) = manager.undo_state(session_id);
+    Ok(UndoResponse {
+        edit,
+        can_undo,
+        can_redo,
+    })
+}
+
+fn replicate_response(report: ReplicationReport) -> ReplicateStoreResponse {
+    ReplicateStoreResponse {
+        pulled: report.pulled,
+        pushed: report.pushed,
+        content_synced: report.content_synced,
+        deleted: report.deleted,
+    }
+}
+
+fn export_response(report: ExportReport) -> ExportResponse {
+    ExportResponse {
+        written: report.written,
+        unchanged: report.unchanged,
+        removed: report.removed,
+        assets_copied: report.assets_copied,
     }
 }
 
 #[async_trait]
 impl PimbleApiServer for RpcHandler {
+    async fn hello(&self, request: HelloRequest) -> Result<ServerInfo, ErrorObjectOwned> {
+        debug!("Hello from {} speaking protocol {}", request.client, request.protocol_version);
+        Ok(self.info.clone())
+    }
+
     async fn create_store(
         &self,
         request: CreateStoreRequest,
@@ -72,6 +277,30 @@ impl PimbleApiServer for RpcHandler {
         Ok(OpenStoreResponse { store })
     }
 
+    async fn open_remote_store(
+        &self,
+        request: OpenRemoteStoreRequest,
+    ) -> Result<OpenStoreResponse, ErrorObjectOwned> {
+        info!("Opening remote store at {}", request.url);
+
+        // Connect before locking the manager, in case the remote is slow or
+        // is this server
+        let auth = request.auth.unwrap_or(AuthMethod::None);
+        let remote = match request.replica_path {
+            Some(path) => RemoteStore::connect_with_replica(request.url, auth, path).await,
+            None => RemoteStore::connect(request.url, auth).await,
+        }
+        .map_err(to_rpc_error)?;
+
+        let mut manager = self.store_manager.write().await;
+        let store_id = manager.add_remote_store(remote);
+        let store = manager
+            .get_store_info(store_id)
+            .map_err(to_rpc_error)?;
+
+        Ok(OpenStoreResponse { store })
+    }
+
     async fn close_store(
         &self,
         request: CloseStoreRequest,
@@ -103,13 +332,43 @@ impl PimbleApiServer for RpcHandler {
         Ok(ListStoresResponse { stores })
     }
 
+    async fn migrate_store(
+        &self,
+        request: MigrateStoreRequest,
+    ) -> Result<MigrateStoreResponse, ErrorObjectOwned> {
+        info!("Migrating content formats in store {}", request.store_id);
+
+        let mut manager = self.manager(request.store_id).await;
+        let report = manager
+            .migrate_store(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(MigrateStoreResponse { report })
+    }
+
+    async fn compact_store(
+        &self,
+        request: CompactStoreRequest,
+    ) -> Result<CompactStoreResponse, ErrorObjectOwned> {
+        info!("Compacting content files in store {}", request.store_id);
+
+        let mut manager = self.manager(request.store_id).await;
+        let report = manager
+            .compact_store(request.store_id, request.keep_history)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(CompactStoreResponse { report })
+    }
+
     async fn get_node(
         &self,
         request: GetNodeRequest,
     ) -> Result<GetNodeResponse, ErrorObjectOwned> {
         debug!("Getting node {} from store {}", request.node_id, request.store_id);
 
-        let mut manager = self.store_manager.write().await;
+        let mut manager = self.manager(request.store_id).await;
         let node = manager
             .get_node(request.store_id, request.node_id)
             .await
@@ -128,7 +387,7 @@ impl PimbleApiServer for RpcHandler {
             request.store_id
         );
 
-        let mut manager = self.store_manager.write().await;
+        let mut manager = self.manager(request.store_id).await;
         let mut nodes = Vec::new();
 
         for node_id in request.node_ids {
@@ -143,6 +402,25 @@ impl PimbleApiServer for RpcHandler {
         Ok(GetNodesResponse { nodes })
     }
 
+    async fn resolve_link(
+        &self,
+        request: ResolveLinkRequest,
+    ) -> Result<ResolveLinkResponse, ErrorObjectOwned> {
+        debug!(
+            "Resolving link {} from store {}",
+            request.target.to_uri(),
+            request.store_id
+        );
+
+        let mut manager = self.store_manager.write().await;
+        let resolution = manager
+            .resolve_link(request.store_id, &request.target)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(ResolveLinkResponse { resolution })
+    }
+
     async fn create_node(
         &self,
         request: CreateNodeRequest,
@@ -155,11 +433,11 @@ impl PimbleApiServer for RpcHandler {
         let mut node = Node::new(&request.node_type);
         node.metadata.title = request.title;
 
-        let mut manager = self.store_manager.write().await;
-        let node_id = manager
-            .create_node(request.store_id, node, request.parent_id)
-            .await
-            .map_err(to_rpc_error)?;
+        let (_, node_id) = self
+            .edit_node(request.store_id, node.id, request.session_id.as_deref(), async |manager| {
+                manager.create_node(request.store_id, node, request.parent_id).await
+            })
+            .await?;
 
         Ok(CreateNodeResponse { node_id })
     }
@@ -173,12 +451,57 @@ impl PimbleApiServer for RpcHandler {
             request.node_id, request.store_id
         );
 
-        let mut manager = self.store_manager.write().await;
+        let (mut manager, ()) = self
+            .edit_node(request.store_id, request.node_id, request.session_id.as_deref(), async |manager| {
+                manager.update_node_metadata(request.store_id, request.node_id, request.metadata).await
+            })
+            .await?;
+
         manager
-            .update_node_metadata(request.store_id, request.node_id, request.metadata)
+            .flush(request.store_id)
             .await
             .map_err(to_rpc_error)?;
 
+        Ok(EmptyResponse {})
+    }
+
+    async fn set_title(&self, request: SetTitleRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned> {
+        let edit = MetadataEdit::SetTitle { title: request.title };
+        self.edit_metadata(request.store_id, request.node_id, edit, request.session_id).await
+    }
+
+    async fn add_tag(&self, request: TagRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned> {
+        let edit = MetadataEdit::AddTag { tag: request.tag };
+        self.edit_metadata(request.store_id, request.node_id, edit, request.session_id).await
+    }
+
+    async fn remove_tag(&self, request: TagRequest) -> Result<NodeMetadataResponse, ErrorObjectOwned> {
+        let edit = MetadataEdit::RemoveTag { tag: request.tag };
+        self.edit_metadata(request.store_id, request.node_id, edit, request.session_id).await
+    }
+
+    async fn set_custom_field(
+        &self,
+        request: SetCustomFieldRequest,
+    ) -> Result<NodeMetadataResponse, ErrorObjectOwned> {
+        let edit = MetadataEdit::SetCustomField {
+            key: request.key,
+            value: request.value,
+        };
+        self.edit_metadata(request.store_id, request.node_id, edit, request.session_id).await
+    }
+
+    async fn set_history_mode(&self, request: SetHistoryModeRequest) -> Result<EmptyResponse, ErrorObjectOwned> {
+        info!(
+            "Setting history mode of node {} in store {} to {:?}",
+            request.node_id, request.store_id, request.mode
+        );
+
+        let mut manager = self.manager(request.store_id).await;
+        manager
+            .set_history_mode(request.store_id, request.node_id, request.mode)
+            .await
+            .map_err(to_rpc_error)?;
         manager
             .flush(request.store_id)
             .await
@@ -199,13 +522,13 @@ impl PimbleApiServer for RpcHandler {
         use base64::Engine;
         let content = base64::engine::general_purpose::STANDARD
             .decode(&request.content)
-            .map_err(|e| to_rpc_error(format!("Invalid base64: {}", e)))?;
+            .map_err(|e| to_rpc_error(RpcError::invalid_params(format!("Invalid base64: {}", e))))?;
 
-        let mut manager = self.store_manager.write().await;
-        manager
-            .update_node_content(request.store_id, request.node_id, content)
-            .await
-            .map_err(to_rpc_error)?;
+        let (mut manager, ()) = self
+            .edit_node(request.store_id, request.node_id, request.session_id.as_deref(), async |manager| {
+                manager.update_node_content(request.store_id, request.node_id, content).await
+            })
+            .await?;
 
         manager
             .flush(request.store_id)
@@ -215,6 +538,59 @@ impl PimbleApiServer for RpcHandler {
         Ok(EmptyResponse {})
     }
 
+    async fn apply_node_changes(
+        &self,
+        request: ApplyNodeChangesRequest,
+    ) -> Result<ApplyNodeChangesResponse, ErrorObjectOwned> {
+        debug!(
+            "Applying {} changes to node {} in store {}",
+            request.changes.len(),
+            request.node_id,
+            request.store_id
+        );
+
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+        let changes = request
+            .changes
+            .iter()
+            .map(|c| engine.decode(c))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| to_rpc_error(RpcError::invalid_params(format!("Invalid base64: {}", e))))?;
+
+        let (mut manager, heads) = self
+            .edit_node(request.store_id, request.node_id, request.session_id.as_deref(), async |manager| {
+                manager.apply_node_changes(request.store_id, request.node_id, &changes).await
+            })
+            .await?;
+        manager.flush(request.store_id).await.map_err(to_rpc_error)?;
+
+        Ok(ApplyNodeChangesResponse { heads })
+    }
+
+    async fn get_node_changes_since(
+        &self,
+        request: GetNodeChangesSinceRequest,
+    ) -> Result<GetNodeChangesSinceResponse, ErrorObjectOwned> {
+        debug!(
+            "Getting changes to node {} in store {} since {:?}",
+            request.node_id, request.store_id, request.heads
+        );
+
+        let mut manager = self.manager(request.store_id).await;
+        let (changes, heads) = manager
+            .get_node_changes_since(request.store_id, request.node_id, &request.heads)
+            .await
+            .map_err(to_rpc_error)?;
+
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+        Ok(GetNodeChangesSinceResponse {
+            changes: changes.iter().map(|c| engine.encode(c)).collect(),
+            heads,
+        })
+    }
+
     async fn set_node_text(
         &self,
         request: SetNodeTextRequest,
@@ -224,17 +600,19 @@ impl PimbleApiServer for RpcHandler {
             request.node_id, request.store_id
         );
 
-        let mut manager = self.store_manager.write().await;
-
         // Create new document content with the text
         let mut doc_content = DocumentContent::new();
-        doc_content.set_text(&request.text).map_err(to_rpc_error)?;
+        doc_content
+            .set_text(&request.text)
+            .map_err(|e| to_rpc_error(StoreError::from(e)))?;
+        doc_content.document_mut().commit_with_message("Set text");
 
         // Save the document to the node
-        manager
-            .save_node_document(request.store_id, request.node_id, doc_content.document_mut())
-            .await
-            .map_err(to_rpc_error)?;
+        let (mut manager, ()) = self
+            .edit_node(request.store_id, request.node_id, request.session_id.as_deref(), async |manager| {
+                manager.save_node_document(request.store_id, request.node_id, doc_content.document_mut()).await
+            })
+            .await?;
 
         // Flush changes to disk
         manager
@@ -254,11 +632,11 @@ impl PimbleApiServer for RpcHandler {
             request.node_id, request.store_id
         );
 
-        let mut manager = self.store_manager.write().await;
-        manager
-            .delete_node(request.store_id, request.node_id)
-            .await
-            .map_err(to_rpc_error)?;
+        let (_, ()) = self
+            .edit_node(request.store_id, request.node_id, request.session_id.as_deref(), async |manager| {
+                manager.delete_node(request.store_id, request.node_id).await
+            })
+            .await?;
 
         Ok(EmptyResponse {})
     }
@@ -272,14 +650,77 @@ impl PimbleApiServer for RpcHandler {
             request.node_id, request.new_parent_id, request.store_id
         );
 
+        let (mut manager, ()) = self
+            .edit_node(request.store_id, request.node_id, request.session_id.as_deref(), async |manager| {
+                manager.move_node(request.store_id, request.node_id, request.new_parent_id, request.position).await
+            })
+            .await?;
+
+        manager
+            .flush(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn copy_node(
+        &self,
+        request: CopyNodeRequest,
+    ) -> Result<CopyNodeResponse, ErrorObjectOwned> {
+        info!(
+            "Copying node {} from store {} to parent {} in store {}",
+            request.node_id, request.source_store_id, request.new_parent_id, request.target_store_id
+        );
+
+        let mut manager = self.store_manager.write().await;
+        let node_id = manager
+            .copy_node(
+                request.source_store_id,
+                request.node_id,
+                request.target_store_id,
+                request.new_parent_id,
+                request.position,
+            )
+            .await
+            .map_err(to_rpc_error)?;
+
+        manager
+            .flush(request.target_store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(CopyNodeResponse { node_id })
+    }
+
+    async fn move_node_across_stores(
+        &self,
+        request: MoveNodeAcrossStoresRequest,
+    ) -> Result<EmptyResponse, ErrorObjectOwned> {
+        info!(
+            "Moving node {} from store {} to parent {} in store {}",
+            request.node_id, request.source_store_id, request.new_parent_id, request.target_store_id
+        );
+
         let mut manager = self.store_manager.write().await;
         manager
-            .move_node(request.store_id, request.node_id, request.new_parent_id, request.position)
+            .move_node_across_stores(
+                request.source_store_id,
+                request.node_id,
+                request.target_store_id,
+                request.new_parent_id,
+                request.position,
+            )
             .await
             .map_err(to_rpc_error)?;
 
+        // Write the target first so a failure can't lose the subtree
         manager
-            .flush(request.store_id)
+            .flush(request.target_store_id)
+            .await
+            .map_err(to_rpc_error)?;
+        manager
+            .flush(request.source_store_id)
             .await
             .map_err(to_rpc_error)?;
 
@@ -295,7 +736,7 @@ impl PimbleApiServer for RpcHandler {
             request.node_id, request.store_id
         );
 
-        let mut manager = self.store_manager.write().await;
+        let mut manager = self.manager(request.store_id).await;
         let children = manager
             .get_children(request.store_id, request.node_id)
             .await
@@ -354,6 +795,500 @@ impl PimbleApiServer for RpcHandler {
         Ok(LoadWorkspaceResponse { workspace })
     }
 
+    async fn export_markdown(
+        &self,
+        request: ExportMarkdownRequest,
+    ) -> Result<ExportResponse, ErrorObjectOwned> {
+        info!(
+            "Exporting store {} to markdown at {:?}",
+            request.store_id, request.output_dir
+        );
+
+        let options = MarkdownExportOptions {
+            incremental: request.incremental,
+        };
+
+        let mut manager = self.store_manager.write().await;
+        let report = manager
+            .export_markdown(request.store_id, request.node_id, &request.output_dir, options)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(export_response(report))
+    }
+
+    async fn export_html(
+        &self,
+        request: ExportHtmlRequest,
+    ) -> Result<ExportResponse, ErrorObjectOwned> {
+        info!(
+            "Publishing store {} as HTML to {:?}",
+            request.store_id, request.output_dir
+        );
+
+        let options = HtmlExportOptions {
+            incremental: request.incremental,
+            site_title: request.site_title,
+        };
+
+        let mut manager = self.store_manager.write().await;
+        let report = manager
+            .export_html(request.store_id, request.node_id, &request.output_dir, options)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(export_response(report))
+    }
+
+    async fn export_opml(
+        &self,
+        request: ExportOpmlRequest,
+    ) -> Result<ExportOpmlResponse, ErrorObjectOwned> {
+        info!("Exporting store {} to OPML", request.store_id);
+
+        let mut manager = self.store_manager.write().await;
+        let opml = manager
+            .export_opml(request.store_id, request.node_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(ExportOpmlResponse { opml })
+    }
+
+    async fn import_opml(
+        &self,
+        request: ImportOpmlRequest,
+    ) -> Result<ImportOpmlResponse, ErrorObjectOwned> {
+        info!("Importing OPML into store {}", request.store_id);
+
+        let mut manager = self.store_manager.write().await;
+        let node_ids = manager
+            .import_opml(request.store_id, request.parent_id, &request.opml)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(ImportOpmlResponse { node_ids })
+    }
+
+    async fn replication_manifest(
+        &self,
+        request: ReplicationManifestRequest,
+    ) -> Result<ReplicationManifestResponse, ErrorObjectOwned> {
+        debug!("Summarising store {} for replication", request.store_id);
+
+        let mut manager = self.store_manager.write().await;
+        let (nodes, tombstones) = manager
+            .replication_manifest(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+        let store = manager.get_store_info(request.store_id).map_err(to_rpc_error)?;
+
+        Ok(ReplicationManifestResponse {
+            store,
+            nodes,
+            tombstones,
+        })
+    }
+
+    async fn apply_node_records(
+        &self,
+        request: ApplyNodeRecordsRequest,
+    ) -> Result<ApplyNodeRecordsResponse, ErrorObjectOwned> {
+        debug!(
+            "Applying {} node records and {} tombstones to store {}",
+            request.nodes.len(),
+            request.tombstones.len(),
+            request.store_id
+        );
+
+        let mut manager = self.store_manager.write().await;
+        let applied = manager
+            .apply_node_records(request.store_id, request.nodes, &request.tombstones)
+            .await
+            .map_err(to_rpc_error)?;
+        manager.flush(request.store_id).await.map_err(to_rpc_error)?;
+
+        Ok(ApplyNodeRecordsResponse { applied })
+    }
+
+    async fn sync_node_content(
+        &self,
+        request: SyncNodeContentRequest,
+    ) -> Result<SyncNodeContentResponse, ErrorObjectOwned> {
+        debug!(
+            "Content sync for node {} in store {} from {}",
+            request.node_id, request.store_id, request.peer_id
+        );
+
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+        let message = request
+            .message
+            .map(|m| engine.decode(m))
+            .transpose()
+            .map_err(|e| to_rpc_error(RpcError::invalid_params(format!("Invalid base64: {}", e))))?;
+
+        let mut manager = self.store_manager.write().await;
+        let reply = manager
+            .sync_node_content(request.store_id, &request.peer_id, request.node_id, message.as_deref())
+            .await
+            .map_err(to_rpc_error)?;
+        manager.flush(request.store_id).await.map_err(to_rpc_error)?;
+
+        Ok(SyncNodeContentResponse {
+            message: reply.map(|m| engine.encode(m)),
+        })
+    }
+
+    async fn merge_store_tree(
+        &self,
+        request: MergeStoreTreeRequest,
+    ) -> Result<MergeStoreTreeResponse, ErrorObjectOwned> {
+        debug!("Merging tree of store {}", request.store_id);
+
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+        let tree = request
+            .tree
+            .map(|t| engine.decode(t))
+            .transpose()
+            .map_err(|e| to_rpc_error(RpcError::invalid_params(format!("Invalid base64: {}", e))))?;
+
+        let mut manager = self.store_manager.write().await;
+        let merged = match tree {
+            Some(tree) => {
+                let merged = manager
+                    .merge_store_tree(request.store_id, &tree)
+                    .await
+                    .map_err(to_rpc_error)?;
+                manager.flush(request.store_id).await.map_err(to_rpc_error)?;
+                merged
+            }
+            None => manager.save_store_tree(request.store_id).map_err(to_rpc_error)?,
+        };
+
+        Ok(MergeStoreTreeResponse {
+            tree: engine.encode(merged),
+        })
+    }
+
+    async fn replicate_store(
+        &self,
+        request: ReplicateStoreRequest,
+    ) -> Result<ReplicateStoreResponse, ErrorObjectOwned> {
+        info!("Replicating store {} with {}", request.store_id, request.peer_url);
+
+        let report = self
+            .replicator
+            .replicate(request.store_id, &request.peer_url)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(replicate_response(report))
+    }
+
+    async fn clone_store(
+        &self,
+        request: CloneStoreRequest,
+    ) -> Result<OpenStoreResponse, ErrorObjectOwned> {
+        info!(
+            "Cloning store {} from {} to {:?}",
+            request.store_id, request.peer_url, request.path
+        );
+
+        let store = self
+            .replicator
+            .clone_store(&request.peer_url, request.store_id, &request.path)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(OpenStoreResponse { store })
+    }
+
+    async fn pair_store(
+        &self,
+        request: PairStoreRequest,
+    ) -> Result<EmptyResponse, ErrorObjectOwned> {
+        info!("Pairing store {} with {}", request.store_id, request.peer_url);
+
+        if !self.store_manager.read().await.is_open(request.store_id) {
+            return Err(to_rpc_error(StoreError::NotOpen(request.store_id)));
+        }
+        let interval = Duration::from_secs(request.interval_secs.unwrap_or(30).max(1));
+        self.replicator
+            .pair(request.store_id, request.peer_url, interval)
+            .await;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn unpair_store(
+        &self,
+        request: UnpairStoreRequest,
+    ) -> Result<EmptyResponse, ErrorObjectOwned> {
+        info!("Unpairing store {}", request.store_id);
+
+        self.replicator.unpair(request.store_id).await;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn list_conflicts(
+        &self,
+        request: ListConflictsRequest,
+    ) -> Result<ListConflictsResponse, ErrorObjectOwned> {
+        debug!("Listing conflicts in store {}", request.store_id);
+
+        let mut manager = self.manager(request.store_id).await;
+        let conflicts = manager
+            .list_conflicts(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(ListConflictsResponse { conflicts })
+    }
+
+    async fn resolve_conflict(
+        &self,
+        request: ResolveConflictRequest,
+    ) -> Result<ListConflictsResponse, ErrorObjectOwned> {
+        info!(
+            "Resolving conflict on '{}' of node {} in store {}",
+            request.key, request.node_id, request.store_id
+        );
+
+        let mut manager = self.manager(request.store_id).await;
+        let conflicts = manager
+            .resolve_conflict(request.store_id, request.node_id, &request.key, &request.value)
+            .await
+            .map_err(to_rpc_error)?;
+
+        manager
+            .flush(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(ListConflictsResponse { conflicts })
+    }
+
+    async fn get_node_history(
+        &self,
+        request: GetNodeHistoryRequest,
+    ) -> Result<GetNodeHistoryResponse, ErrorObjectOwned> {
+        debug!("Getting history of node {} in store {}", request.node_id, request.store_id);
+
+        let mut manager = self.manager(request.store_id).await;
+        let changes = manager
+            .get_node_history(request.store_id, request.node_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(GetNodeHistoryResponse { changes })
+    }
+
+    async fn get_node_at_version(
+        &self,
+        request: NodeVersionRequest,
+    ) -> Result<GetNodeAtVersionResponse, ErrorObjectOwned> {
+        debug!(
+            "Getting node {} in store {} at {:?}",
+            request.node_id, request.store_id, request.heads
+        );
+
+        let mut manager = self.manager(request.store_id).await;
+        let (node, text) = manager
+            .get_node_at_version(request.store_id, request.node_id, &request.heads)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(GetNodeAtVersionResponse { node, text })
+    }
+
+    async fn restore_node_version(
+        &self,
+        request: NodeVersionRequest,
+    ) -> Result<GetNodeResponse, ErrorObjectOwned> {
+        info!(
+            "Restoring node {} in store {} to {:?}",
+            request.node_id, request.store_id, request.heads
+        );
+
+        let mut manager = self.manager(request.store_id).await;
+        manager
+            .restore_node_version(request.store_id, request.node_id, &request.heads)
+            .await
+            .map_err(to_rpc_error)?;
+
+        manager
+            .flush(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        let node = manager
+            .get_node(request.store_id, request.node_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(GetNodeResponse { node })
+    }
+
+    async fn diff_node_versions(
+        &self,
+        request: DiffNodeVersionsRequest,
+    ) -> Result<DiffNodeVersionsResponse, ErrorObjectOwned> {
+        debug!(
+            "Diffing node {} in store {} from {:?} to {:?}",
+            request.node_id, request.store_id, request.from, request.to
+        );
+
+        let mut manager = self.manager(request.store_id).await;
+        let diff = manager
+            .diff_node_versions(request.store_id, request.node_id, &request.from, request.to.as_deref())
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(DiffNodeVersionsResponse { diff })
+    }
+
+    async fn set_identity(&self, request: SetIdentityRequest) -> Result<EmptyResponse, ErrorObjectOwned> {
+        info!(
+            "Setting the name of device {} in store {} to {:?}",
+            request.device_id, request.store_id, request.name
+        );
+
+        let device = DeviceId::parse(&request.device_id)
+            .ok_or_else(|| {
+                to_rpc_error(RpcError::invalid_params(format!(
+                    "Invalid device id: {}",
+                    request.device_id
+                )))
+            })?;
+
+        let mut manager = self.manager(request.store_id).await;
+        manager
+            .set_identity(request.store_id, device, request.name)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn list_identities(
+        &self,
+        request: ListIdentitiesRequest,
+    ) -> Result<ListIdentitiesResponse, ErrorObjectOwned> {
+        debug!("Listing identities in store {}", request.store_id);
+
+        let mut manager = self.manager(request.store_id).await;
+        let identities = manager
+            .list_identities(request.store_id)
+            .await
+            .map_err(to_rpc_error)?;
+
+        Ok(ListIdentitiesResponse { identities })
+    }
+
+    async fn subscribe_node(&self, pending: PendingSubscriptionSink, request: SubscribeNodeRequest) -> SubscriptionResult {
+        debug!("Subscribing to changes of node {} in store {}", request.node_id, request.store_id);
+
+        let (store_id, node_id) = (request.store_id, request.node_id);
+        self.forward_changes(pending, store_id, |change| change.node_id == node_id && change.store_id == store_id)
+            .await
+    }
+
+    async fn subscribe_store(&self, pending: PendingSubscriptionSink, request: SubscribeStoreRequest) -> SubscriptionResult {
+        debug!("Subscribing to changes in store {}", request.store_id);
+
+        let store_id = request.store_id;
+        self.forward_changes(pending, store_id, |change| change.store_id == store_id).await
+    }
+
+    async fn publish_presence(&self, request: PublishPresenceRequest) -> Result<EmptyResponse, ErrorObjectOwned> {
+        debug!("Presence of {} on node {} in store {}", request.client_id, request.node_id, request.store_id);
+
+        let presence = Presence {
+            client_id: request.client_id,
+            name: request.name,
+            anchor: request.anchor,
+            head: request.head,
+            last_seen: chrono::Utc::now(),
+        };
+        self.presence.publish(request.store_id, request.node_id, presence);
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn leave_presence(&self, request: LeavePresenceRequest) -> Result<EmptyResponse, ErrorObjectOwned> {
+        debug!("{} left node {} in store {}", request.client_id, request.node_id, request.store_id);
+
+        self.presence.leave(request.store_id, request.node_id, &request.client_id);
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn get_presence(&self, request: GetPresenceRequest) -> Result<PresenceResponse, ErrorObjectOwned> {
+        Ok(PresenceResponse {
+            present: self.presence.list(request.store_id, request.node_id),
+        })
+    }
+
+    async fn subscribe_presence(
+        &self,
+        pending: PendingSubscriptionSink,
+        request: SubscribePresenceRequest,
+    ) -> SubscriptionResult {
+        debug!("Subscribing to presence on node {} in store {}", request.node_id, request.store_id);
+
+        let (store_id, node_id) = (request.store_id, request.node_id);
+        let mut updates = self.presence.subscribe();
+        let sink = pending.accept().await?;
+
+        // Start with everyone already there, and resend it all after missing updates
+        let current = || PresenceNotification {
+            store_id,
+            node_id,
+            present: self.presence.list(store_id, node_id),
+        };
+        sink.send(SubscriptionMessage::from_json(&current())?).await?;
+        loop {
+            let notification = tokio::select! {
+                _ = sink.closed() => break,
+                update = updates.recv() => match update {
+                    Ok(update) if update.store_id == store_id && update.node_id == node_id => update,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => current(),
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            sink.send(SubscriptionMessage::from_json(&notification)?).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn undo(&self, request: UndoRequest) -> Result<UndoResponse, ErrorObjectOwned> {
+        info!("Undoing the latest edit of session {}", request.session_id);
+
+        let mut manager = self.store_manager.write().await;
+        let edit = manager.undo(&request.session_id).await.map_err(to_rpc_error)?;
+        undo_response(&mut manager, &request.session_id, edit).await
+    }
+
+    async fn redo(&self, request: UndoRequest) -> Result<UndoResponse, ErrorObjectOwned> {
+        info!("Redoing the latest undone edit of session {}", request.session_id);
+
+        let mut manager = self.store_manager.write().await;
+        let edit = manager.redo(&request.session_id).await.map_err(to_rpc_error)?;
+        undo_response(&mut manager, &request.session_id, edit).await
+    }
+
+    async fn close_session(&self, request: CloseSessionRequest) -> Result<EmptyResponse, ErrorObjectOwned> {
+        debug!("Closing session {}", request.session_id);
+
+        self.store_manager.write().await.close_session(&request.session_id);
+        Ok(EmptyResponse {})
+    }
+
     async fn search(
         &self,
         request: SearchRequest,
@@ -4,11 +4,17 @@
 //! - JSON-RPC server over HTTP and WebSocket
 //! - Store management
 //! - Search coordination
+//! - Presence of clients on open nodes
+//! - Store replication with peer servers
 
 pub mod error;
 pub mod handler;
+pub mod presence;
+pub mod replication;
 pub mod server;
 
 pub use error::*;
 pub use handler::*;
+pub use presence::*;
+pub use replication::*;
 pub use server::*;
@@ -0,0 +1,168 @@
+//! Ephemeral presence of clients on open nodes
+//!
+//! Clients viewing or editing a node publish who they are and where their
+//! cursor is. Nothing here touches a store: presence lives in memory, is
+//! broadcast to subscribers as it changes, and lapses when a client stops
+//! refreshing it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use pimble_core::{NodeId, StoreId};
+use pimble_rpc::{Presence, PresenceNotification};
+use tokio::sync::broadcast;
+
+/// How long a presence lasts without being refreshed
+pub const PRESENCE_TTL: Duration = Duration::from_secs(30);
+
+/// Notifications kept for subscribers that fall behind
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Clients present on each node, keyed by client id
+type PresenceMap = HashMap<(StoreId, NodeId), HashMap<String, Presence>>;
+
+/// Presence of every client on every node, shared by all connections
+pub struct PresenceHub {
+    present: Mutex<PresenceMap>,
+    sender: broadcast::Sender<PresenceNotification>,
+    ttl: Duration,
+}
+
+impl PresenceHub {
+    /// Create a hub whose entries expire after [`PRESENCE_TTL`]
+    pub fn new() -> Self {
+        Self::with_ttl(PRESENCE_TTL)
+    }
+
+    /// Create a hub whose entries expire after `ttl`
+    pub fn with_ttl(ttl: Duration) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            present: Mutex::new(HashMap::new()),
+            sender,
+            ttl,
+        }
+    }
+
+    /// How long entries last without being refreshed
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Record a client's presence on a node, replacing what it published before
+    pub fn publish(&self, store_id: StoreId, node_id: NodeId, mut presence: Presence) {
+        presence.last_seen = Utc::now();
+        let mut present = self.present.lock().unwrap();
+        let clients = present.entry((store_id, node_id)).or_default();
+        clients.insert(presence.client_id.clone(), presence);
+        self.notify(store_id, node_id, clients);
+    }
+
+    /// Remove a client's presence from a node
+    pub fn leave(&self, store_id: StoreId, node_id: NodeId, client_id: &str) {
+        let mut present = self.present.lock().unwrap();
+        let Some(clients) = present.get_mut(&(store_id, node_id)) else {
+            return;
+        };
+        if clients.remove(client_id).is_some() {
+            self.notify(store_id, node_id, clients);
+        }
+        if clients.is_empty() {
+            present.remove(&(store_id, node_id));
+        }
+    }
+
+    /// The clients present on a node, ordered by client id
+    pub fn list(&self, store_id: StoreId, node_id: NodeId) -> Vec<Presence> {
+        let present = self.present.lock().unwrap();
+        present.get(&(store_id, node_id)).map(sorted).unwrap_or_default()
+    }
+
+    /// Drop every presence not refreshed within the TTL
+    ///
+    /// Subscribers to each node that lost someone are notified.
+    pub fn expire(&self) {
+        let ttl = chrono::Duration::from_std(self.ttl).unwrap_or(chrono::Duration::MAX);
+        let cutoff = Utc::now() - ttl;
+        let mut present = self.present.lock().unwrap();
+        for (&(store_id, node_id), clients) in present.iter_mut() {
+            let before = clients.len();
+            clients.retain(|_, presence| presence.last_seen > cutoff);
+            if clients.len() != before {
+                self.notify(store_id, node_id, clients);
+            }
+        }
+        present.retain(|_, clients| !clients.is_empty());
+    }
+
+    /// Receive a notification every time presence on any node changes
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceNotification> {
+        self.sender.subscribe()
+    }
+
+    fn notify(&self, store_id: StoreId, node_id: NodeId, clients: &HashMap<String, Presence>) {
+        // Nobody listening is fine
+        let _ = self.sender.send(PresenceNotification {
+            store_id,
+            node_id,
+            present: sorted(clients),
+        });
+    }
+}
+
+impl Default for PresenceHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sorted(clients: &HashMap<String, Presence>) -> Vec<Presence> {
+    let mut present: Vec<Presence> = clients.values().cloned().collect();
+    present.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    present
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presence(client_id: &str) -> Presence {
+        Presence {
+            client_id: client_id.into(),
+            name: client_id.to_uppercase(),
+            anchor: None,
+            head: None,
+            last_seen: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_presence_expires() {
+        let hub = PresenceHub::with_ttl(Duration::from_millis(50));
+        let (store_id, node_id) = (StoreId::new(), NodeId::new());
+        let mut updates = hub.subscribe();
+
+        hub.publish(store_id, node_id, presence("a"));
+        hub.publish(store_id, node_id, presence("b"));
+        assert_eq!(updates.recv().await.unwrap().present.len(), 1);
+        assert_eq!(updates.recv().await.unwrap().present.len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        hub.publish(store_id, node_id, presence("b"));
+        updates.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        hub.expire();
+
+        // Only the client that refreshed is left
+        let update = updates.recv().await.unwrap();
+        assert_eq!(update.present.len(), 1);
+        assert_eq!(update.present[0].client_id, "b");
+        assert_eq!(hub.list(store_id, node_id), update.present);
+
+        hub.leave(store_id, node_id, "b");
+        assert!(updates.recv().await.unwrap().present.is_empty());
+        assert!(hub.list(store_id, node_id).is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+//! Replication of stores with other Pimble servers
+//!
+//! A store is replicated by pairing it with the same store (same store ID)
+//! on a peer server. Each pass compares both sides' node summaries, moves
+//! newer node records and tombstones in each direction, merges the two
+//! copies of the store's tree structure, then runs the Automerge sync
+//! protocol for every node whose content differs. The peer
+//! does no work of its own beyond answering RPCs, so a pass started on
+//! either side brings both up to date.
+//!
+//! Progress is reflected in the store's [`SyncState`].
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pimble_client::PimbleClient;
+use pimble_core::{Node, NodeId, Store, StoreId, SyncState};
+use pimble_crdt::CrdtDocument;
+use pimble_store::{ReplicationPlan, StoreError, StoreManager};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::Result;
+
+/// Upper bound on sync messages exchanged for one node in one pass
+const MAX_SYNC_ROUNDS: usize = 32;
+
+/// What a replication pass exchanged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicationReport {
+    /// Node records fetched from the peer
+    pub pulled: usize,
+    /// Node records sent to the peer
+    pub pushed: usize,
+    /// Nodes whose content was merged with the peer's
+    pub content_synced: usize,
+    /// Nodes deleted locally because the peer deleted them
+    pub deleted: usize,
+}
+
+/// Replicates stores with peer servers, once or in the background
+pub struct Replicator {
+    store_manager: Arc<RwLock<StoreManager>>,
+
+    /// How this server identifies itself to peers
+    peer_id: String,
+
+    /// Background replication tasks, by store
+    pairings: Mutex<HashMap<StoreId, JoinHandle<()>>>,
+}
+
+impl Replicator {
+    /// Create a replicator that identifies itself to peers as `peer_id`
+    pub fn new(store_manager: Arc<RwLock<StoreManager>>, peer_id: impl Into<String>) -> Self {
+        Self {
+            store_manager,
+            peer_id: peer_id.into(),
+            pairings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The ID this server presents to peers
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Replicate a store with the same store on a peer, once
+    ///
+    /// The store is marked Syncing while the pass runs, then Synced, or
+    /// Conflict if merged content holds concurrent edits to the same
+    /// field, or Offline if the peer couldn't be reached or the pass failed.
+    pub async fn replicate(&self, store_id: StoreId, peer_url: &str) -> Result<ReplicationReport> {
+        self.set_state(store_id, SyncState::Syncing).await;
+
+        let result = async {
+            let report = self.run(store_id, peer_url).await?;
+            let conflicts = self.store_manager.write().await.list_conflicts(store_id).await?;
+            Ok((report, conflicts))
+        };
+        match result.await {
+            Ok((report, conflicts)) => {
+                self.set_state(store_id, StoreManager::state_after_sync(conflicts)).await;
+                info!("Replicated store {} with {}: {:?}", store_id, peer_url, report);
+                Ok(report)
+            }
+            Err(e) => {
+                self.set_state(store_id, SyncState::Offline).await;
+                warn!("Replicating store {} with {} failed: {}", store_id, peer_url, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Create a local replica of a store held by a peer and fill it in
+    pub async fn clone_store(&self, peer_url: &str, store_id: StoreId, path: impl AsRef<Path>) -> Result<Store> {
+        let client = PimbleClient::connect(peer_url).await?;
+        let remote = client.replication_manifest(store_id).await?;
+
+        self.store_manager
+            .write()
+            .await
+            .create_replica(path, store_id, remote.store.name, remote.store.root_node_id)
+            .await?;
+        self.replicate(store_id, peer_url).await?;
+
+        let manager = self.store_manager.read().await;
+        Ok(manager.get_store_info(store_id)?)
+    }
+
+    /// Replicate a store with a peer every `interval` until unpaired
+    ///
+    /// Replaces any existing pairing for the store. Failed passes are
+    /// logged and retried on the next tick.
+    pub async fn pair(self: &Arc<Self>, store_id: StoreId, peer_url: impl Into<String>, interval: Duration) {
+        let peer_url = peer_url.into();
+        let replicator = Arc::clone(self);
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                // Failures are logged by replicate() and retried next tick
+                let _ = replicator.replicate(store_id, &peer_url).await;
+            }
+        });
+
+        if let Some(previous) = self.pairings.lock().await.insert(store_id, task) {
+            previous.abort();
+        }
+    }
+
+    /// Stop background replication for a store
+    ///
+    /// Returns whether the store was paired.
+    pub async fn unpair(&self, store_id: StoreId) -> bool {
+        match self.pairings.lock().await.remove(&store_id) {
+            Some(task) => {
+                task.abort();
+                self.set_state(store_id, SyncState::Offline).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop all background replication
+    pub async fn unpair_all(&self) {
+        for (_, task) in self.pairings.lock().await.drain() {
+            task.abort();
+        }
+    }
+
+    // Private helpers
+
+    async fn run(&self, store_id: StoreId, peer_url: &str) -> Result<ReplicationReport> {
+        let client = PimbleClient::connect(peer_url).await?;
+        let remote = client.replication_manifest(store_id).await?;
+        let (local, local_tombstones) = self
+            .store_manager
+            .write()
+            .await
+            .replication_manifest(store_id)
+            .await?;
+
+        let plan = ReplicationPlan::new(&local, &local_tombstones, &remote.nodes, &remote.tombstones);
+        debug!(
+            "Replication plan for store {}: pull {}, push {}, content {}",
+            store_id,
+            plan.pull.len(),
+            plan.push.len(),
+            plan.content.len()
+        );
+        let mut report = ReplicationReport::default();
+
+        // Take the peer's deletions and newer records
+        let pulled = if plan.pull.is_empty() {
+            Vec::new()
+        } else {
+            client.get_nodes(store_id, plan.pull.clone()).await?
+        };
+        report.pulled = pulled.len();
+        {
+            let mut manager = self.store_manager.write().await;
+            report.deleted = manager
+                .apply_node_records(store_id, Vec::new(), &remote.tombstones)
+                .await?;
+            manager.apply_node_records(store_id, pulled, &[]).await?;
+            manager.flush(store_id).await?;
+        }
+
+        // Send ours
+        let pushed = {
+            let mut manager = self.store_manager.write().await;
+            let mut nodes: Vec<Node> = Vec::with_capacity(plan.push.len());
+            for &node_id in &plan.push {
+                match manager.get_node(store_id, node_id).await {
+                    Ok(node) => nodes.push(node),
+                    Err(StoreError::NodeNotFound(_)) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            nodes
+        };
+        report.pushed = pushed.len();
+        if !pushed.is_empty() || !local_tombstones.is_empty() {
+            client.apply_node_records(store_id, pushed, local_tombstones).await?;
+        }
+
+        // Merge tree structure, so concurrent moves resolve the same way here and there
+        let ours = self.store_manager.write().await.save_store_tree(store_id)?;
+        let merged = client.merge_store_tree(store_id, Some(&ours)).await?;
+        self.store_manager.write().await.merge_store_tree(store_id, &merged).await?;
+
+        // Merge content edited on both sides
+        for &node_id in &plan.content {
+            if self.sync_content(&client, store_id, node_id, peer_url).await? {
+                report.content_synced += 1;
+            }
+        }
+        self.store_manager.write().await.flush(store_id).await?;
+
+        Ok(report)
+    }
+
+    /// Run the sync protocol for one node's content
+    ///
+    /// Returns false if the node no longer exists locally.
+    async fn sync_content(
+        &self,
+        client: &PimbleClient,
+        store_id: StoreId,
+        node_id: NodeId,
+        peer_url: &str,
+    ) -> Result<bool> {
+        let (mut doc, mut state) = {
+            let mut manager = self.store_manager.write().await;
+            let content = match manager.get_node(store_id, node_id).await {
+                Ok(node) => node.content,
+                Err(StoreError::NodeNotFound(_)) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+            let doc = if content.is_empty() {
+                CrdtDocument::new()
+            } else {
+                CrdtDocument::load(&content)?
+            };
+            let state = manager.load_peer_sync_state(store_id, peer_url, node_id).await?;
+            (doc, state)
+        };
+
+        // The store isn't locked while messages are in flight
+        let mut message = doc.generate_sync_message(&mut state);
+        for _ in 0..MAX_SYNC_ROUNDS {
+            let Some(outgoing) = message else {
+                break;
+            };
+            let reply = client
+                .sync_node_content(store_id, node_id, self.peer_id.as_str(), Some(&outgoing))
+                .await?;
+            if let Some(reply) = reply {
+                doc.receive_sync_message(&mut state, &reply)?;
+            }
+            message = doc.generate_sync_message(&mut state);
+        }
+
+        let mut manager = self.store_manager.write().await;
+        manager.merge_synced_content(store_id, node_id, &mut doc).await?;
+        manager.save_peer_sync_state(store_id, peer_url, node_id, &state).await?;
+        Ok(true)
+    }
+
+    async fn set_state(&self, store_id: StoreId, state: SyncState) {
+        self.store_manager.write().await.set_sync_state(store_id, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{PimbleServer, ServerConfig};
+    use pimble_crdt::DocumentContent;
+    use tempfile::tempdir;
+
+    async fn start_server() -> PimbleServer {
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        server
+    }
+
+    async fn text_of(manager: &Arc<RwLock<StoreManager>>, store_id: StoreId, node_id: NodeId) -> String {
+        let node = manager.write().await.get_node(store_id, node_id).await.unwrap();
+        DocumentContent::load(&node.content).unwrap().get_text().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replicate_between_two_servers() {
+        let dir = tempdir().unwrap();
+        let (mut a, mut b) = (start_server().await, start_server().await);
+        let (manager_a, manager_b) = (a.store_manager(), b.store_manager());
+        let url_a = format!("http://{}", a.addr());
+        let url_b = format!("http://{}", b.addr());
+
+        // A store on A with a document in it
+        let (store_id, root, doc_id) = {
+            let mut manager = manager_a.write().await;
+            let store_id = manager.create_local_store(dir.path().join("a.pimble"), "Shared").await.unwrap();
+            let root = manager.root_node_id(store_id).unwrap();
+            let mut doc = Node::document("Plan");
+            let mut content = DocumentContent::new();
+            content.set_text("Hello").unwrap();
+            doc.content = content.save();
+            let doc_id = manager.create_node(store_id, doc, Some(root)).await.unwrap();
+            (store_id, root, doc_id)
+        };
+
+        // B clones it from A
+        let client_b = PimbleClient::connect(&url_b).await.unwrap();
+        let cloned = client_b.clone_store(&url_a, store_id, dir.path().join("b.pimble")).await.unwrap();
+        assert_eq!(cloned.root_node_id, root);
+        assert!(cloned.sync_state.is_synced());
+        assert_eq!(text_of(&manager_b, store_id, doc_id).await, "Hello");
+
+        // Concurrent edits: text on both sides, a new node on B, a deletion on A
+        let extra = Node::folder("Only on B");
+        let extra_id = extra.id;
+        {
+            let mut manager = manager_b.write().await;
+            manager.create_node(store_id, extra, Some(root)).await.unwrap();
+            let mut content = DocumentContent::from_document(manager.get_node_document(store_id, doc_id).await.unwrap());
+            content.insert_text(5, " world").unwrap();
+            manager.update_node_content(store_id, doc_id, content.save()).await.unwrap();
+        }
+        let doomed = {
+            let mut manager = manager_a.write().await;
+            let mut content = DocumentContent::from_document(manager.get_node_document(store_id, doc_id).await.unwrap());
+            content.insert_text(0, ">> ").unwrap();
+            manager.update_node_content(store_id, doc_id, content.save()).await.unwrap();
+            manager.create_node(store_id, Node::document("Doomed"), Some(root)).await.unwrap()
+        };
+        client_b.replicate_store(store_id, &url_a).await.unwrap();
+        manager_a.write().await.delete_node(store_id, doomed).await.unwrap();
+
+        // A replicates with B: both edits merge and the deletion carries over
+        let client_a = PimbleClient::connect(&url_a).await.unwrap();
+        let report = client_a.replicate_store(store_id, &url_b).await.unwrap();
+        assert_eq!(report.deleted, 0);
+        assert_eq!(text_of(&manager_a, store_id, doc_id).await, ">> Hello world");
+        assert_eq!(text_of(&manager_b, store_id, doc_id).await, ">> Hello world");
+
+        for manager in [&manager_a, &manager_b] {
+            let mut manager = manager.write().await;
+            let root_node = manager.get_node(store_id, root).await.unwrap();
+            assert_eq!(root_node.children.len(), 2);
+            assert!(root_node.children.contains(&doc_id));
+            assert!(root_node.children.contains(&extra_id));
+            assert!(manager.get_node(store_id, doomed).await.is_err());
+            assert!(manager.get_store_info(store_id).unwrap().sync_state.is_synced());
+        }
+
+        // Nothing left to exchange
+        let report = client_a.replicate_store(store_id, &url_b).await.unwrap();
+        assert_eq!((report.pulled, report.pushed, report.content_synced), (0, 0, 0));
+
+        a.stop().await.unwrap();
+        b.stop().await.unwrap();
+    }
+}
@@ -1,36 +1,63 @@
 //! Server startup and management
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use jsonrpsee::server::{Server, ServerHandle};
-use pimble_rpc::PimbleApiServer;
+use pimble_crdt::{set_local_device, DeviceId};
+use pimble_plugins::create_default_host;
+use pimble_rpc::{Features, Limits, PimbleApiServer, ServerInfo, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 use pimble_store::StoreManager;
 use tokio::sync::RwLock;
 use tracing::info;
 
 use crate::handler::RpcHandler;
+use crate::presence::PresenceHub;
+use crate::replication::Replicator;
 use crate::Result;
 
+/// Limits applied to every connection, and reported to clients
+const LIMITS: Limits = Limits {
+    max_request_size: 10 * 1024 * 1024,
+    max_response_size: 10 * 1024 * 1024,
+    max_connections: 100,
+    max_subscriptions_per_connection: 1024,
+};
+
 /// Configuration for the Pimble server
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     /// Address to bind to
     pub addr: SocketAddr,
+
+    /// Directory for the server's own state, such as its id
+    ///
+    /// Edits the server makes are attributed to that id, as the process's
+    /// local device. Without a state directory the server takes a new id
+    /// every time it starts, so peers can't resume syncing with it where
+    /// they left off, and its edits keep random actors.
+    pub state_dir: Option<PathBuf>,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             addr: "127.0.0.1:9876".parse().unwrap(),
+            state_dir: None,
         }
     }
 }
 
+/// File in the state directory holding the server's id
+const SERVER_ID_FILE: &str = "server_id";
+
 /// The Pimble server
 pub struct PimbleServer {
     config: ServerConfig,
     store_manager: Arc<RwLock<StoreManager>>,
+    replicator: Option<Arc<Replicator>>,
+    presence: Arc<PresenceHub>,
     handle: Option<ServerHandle>,
 }
 
@@ -45,6 +72,8 @@ impl PimbleServer {
         Self {
             config,
             store_manager: Arc::new(RwLock::new(StoreManager::new())),
+            replicator: None,
+            presence: Arc::new(PresenceHub::new()),
             handle: None,
         }
     }
@@ -57,15 +86,54 @@ impl PimbleServer {
     /// Start the server
     pub async fn start(&mut self) -> Result<()> {
         let server = Server::builder()
+            .max_request_body_size(LIMITS.max_request_size)
+            .max_response_body_size(LIMITS.max_response_size)
+            .max_connections(LIMITS.max_connections)
+            .max_subscriptions_per_connection(LIMITS.max_subscriptions_per_connection)
             .build(&self.config.addr)
             .await
             .map_err(|e| crate::ServerError::Server(e.to_string()))?;
 
-        let handler = RpcHandler::new(Arc::clone(&self.store_manager));
+        // Record the bound address, in case port 0 was requested
+        self.config.addr = server.local_addr()?;
+
+        // Peers keep sync state per server, so the id must be unique and
+        // stay the same across restarts
+        let id = match &self.config.state_dir {
+            Some(dir) => {
+                let id = DeviceId::load_or_create(&dir.join(SERVER_ID_FILE))?;
+                set_local_device(id);
+                id
+            }
+            None => DeviceId::random(),
+        };
+        let replicator = Arc::new(Replicator::new(Arc::clone(&self.store_manager), format!("pimble-{}", id)));
+        self.replicator = Some(Arc::clone(&replicator));
+
+        let handler = RpcHandler::new(
+            Arc::clone(&self.store_manager),
+            replicator,
+            Arc::clone(&self.presence),
+            server_info(),
+        );
         let methods = handler.into_rpc();
 
         info!("Starting Pimble server on {}", self.config.addr);
         let handle = server.start(methods);
+
+        // Drop the presence of clients that went away without leaving
+        let presence = Arc::clone(&self.presence);
+        let stopped = handle.clone().stopped();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(presence.ttl() / 3);
+            tokio::pin!(stopped);
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    _ = ticks.tick() => presence.expire(),
+                }
+            }
+        });
         self.handle = Some(handle);
 
         Ok(())
@@ -73,6 +141,9 @@ impl PimbleServer {
 
     /// Stop the server
     pub async fn stop(&mut self) -> Result<()> {
+        if let Some(replicator) = self.replicator.take() {
+            replicator.unpair_all().await;
+        }
         if let Some(handle) = self.handle.take() {
             handle.stop().map_err(|e| crate::ServerError::Server(e.to_string()))?;
             info!("Pimble server stopped");
@@ -87,7 +158,17 @@ impl PimbleServer {
         }
     }
 
-    /// Get the server address
+    /// Get the replicator, once the server has started
+    pub fn replicator(&self) -> Option<Arc<Replicator>> {
+        self.replicator.clone()
+    }
+
+    /// Get the presence of clients on open nodes
+    pub fn presence(&self) -> Arc<PresenceHub> {
+        Arc::clone(&self.presence)
+    }
+
+    /// Get the server address (the bound address once started)
     pub fn addr(&self) -> SocketAddr {
         self.config.addr
     }
@@ -99,6 +180,24 @@ impl Default for PimbleServer {
     }
 }
 
+/// What this build of the server speaks and supports
+pub fn server_info() -> ServerInfo {
+    ServerInfo {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        server: format!("pimble-server {}", env!("CARGO_PKG_VERSION")),
+        node_types: create_default_host().node_types(),
+        features: Features {
+            // Search requests are accepted but not answered yet
+            search: false,
+            semantic_search: false,
+            sync: true,
+            subscriptions: true,
+        },
+        limits: LIMITS,
+    }
+}
+
 /// Start a server and run it until shutdown
 pub async fn run_server(config: ServerConfig) -> Result<()> {
     let mut server = PimbleServer::with_config(config);
@@ -119,3 +218,412 @@ pub async fn run_server(config: ServerConfig) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pimble_client::{ClientError, PimbleClient, Subscription};
+    use pimble_core::{AuthMethod, ChangeType, LinkResolution, LinkTarget, Node, NodeId, StoreId, StoreLocation, SyncState};
+    use pimble_rpc::{NodeChangedNotification, RpcError};
+    use pimble_store::StoreError;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_open_remote_store() {
+        let dir = tempdir().unwrap();
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let store_id = server
+            .store_manager()
+            .write()
+            .await
+            .create_local_store(dir.path().join("served.pimble"), "Served")
+            .await
+            .unwrap();
+
+        // Another manager opens the store through the server
+        let mut manager = StoreManager::new();
+        let url = format!("http://{}/?store={}", server.addr(), store_id).parse().unwrap();
+        let bearer = AuthMethod::Bearer { token: "secret".into() };
+        assert_eq!(manager.open_remote_store(url, bearer).await.unwrap(), store_id);
+        let info = manager.get_store_info(store_id).unwrap();
+        assert!(info.is_remote());
+        assert!(info.sync_state.is_synced());
+        assert!(matches!(info.location, StoreLocation::Remote { auth: AuthMethod::Bearer { token }, .. } if token.is_empty()));
+
+        let root = manager.root_node_id(store_id).unwrap();
+        let node = manager.create_node(store_id, Node::document("Remote note"), Some(root)).await.unwrap();
+        let children = manager.get_children(store_id, root).await.unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id, node);
+
+        // The node really lives on the server
+        let served = server.store_manager().write().await.get_node(store_id, node).await.unwrap();
+        assert_eq!(served.metadata.title, "Remote note");
+
+        // With the server gone, reads come from the cache and writes fail
+        server.stop().await.unwrap();
+        server.wait().await;
+        assert_eq!(manager.get_node(store_id, node).await.unwrap().metadata.title, "Remote note");
+        assert!(matches!(manager.get_store_info(store_id).unwrap().sync_state, SyncState::Offline));
+        assert!(manager.delete_node(store_id, node).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_link_into_remote_store() {
+        let dir = tempdir().unwrap();
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let served = server
+            .store_manager()
+            .write()
+            .await
+            .create_local_store(dir.path().join("served.pimble"), "Served")
+            .await
+            .unwrap();
+
+        let mut manager = StoreManager::new();
+        let local = manager.create_local_store(dir.path().join("local.pimble"), "Local").await.unwrap();
+        let url = format!("http://{}/?store={}", server.addr(), served).parse().unwrap();
+        manager.open_remote_store(url, AuthMethod::None).await.unwrap();
+        let root = manager.root_node_id(served).unwrap();
+        let node = manager.create_node(served, Node::document("Remote note"), Some(root)).await.unwrap();
+
+        // Qualified and plain links from the local store both find it
+        for link in [LinkTarget::Node(node).in_store(served), LinkTarget::Node(node)] {
+            match manager.resolve_link(local, &link).await.unwrap() {
+                LinkResolution::Resolved { store_id, node: resolved, .. } => {
+                    assert_eq!(store_id, served);
+                    assert_eq!(resolved.id, node);
+                }
+                other => panic!("unexpected resolution: {:?}", other),
+            }
+        }
+        let missing = NodeId::new();
+        assert!(matches!(
+            manager.resolve_link(local, &LinkTarget::Node(missing).in_store(served)).await.unwrap(),
+            LinkResolution::Missing { node_id } if node_id == missing
+        ));
+
+        // Subtrees can't be copied into a remote store
+        let local_root = manager.root_node_id(local).unwrap();
+        let note = manager.create_node(local, Node::document("Local note"), Some(local_root)).await.unwrap();
+        assert!(matches!(
+            manager.copy_node(local, note, served, root, None).await,
+            Err(StoreError::InvalidOperation(_))
+        ));
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_remote_store_on_itself() {
+        let dir = tempdir().unwrap();
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let url = format!("http://{}", server.addr());
+        let client = PimbleClient::connect(&url).await.unwrap();
+        let (store_id, _) = client.create_store(dir.path().join("own.pimble"), "Own").await.unwrap();
+
+        // Connecting asks this server about its stores, so it must not be
+        // holding the store manager meanwhile
+        let opened = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            client.open_remote_store(url.parse().unwrap(), None, None),
+        )
+        .await
+        .expect("opening a store served by this server deadlocked")
+        .unwrap();
+        assert_eq!(opened.id, store_id);
+        server.stop().await.unwrap();
+    }
+
+    async fn next_change(changes: &mut Subscription<NodeChangedNotification>) -> (NodeId, ChangeType) {
+        let change = changes.next().await.unwrap().unwrap();
+        (change.node_id, change.change_type)
+    }
+
+    #[tokio::test]
+    async fn test_store_subscription() {
+        let dir = tempdir().unwrap();
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let url = format!("http://{}", server.addr());
+
+        let client = PimbleClient::connect(&url).await.unwrap();
+        let (store_id, root) = client.create_store(dir.path().join("watched.pimble"), "Watched").await.unwrap();
+        let mut changes = client.subscribe_store(store_id).await.unwrap();
+
+        let folder = client.create_node(store_id, Some(root), "folder", "Folder").await.unwrap();
+        let note = client.create_node(store_id, Some(root), "document", "Note").await.unwrap();
+        assert_eq!(next_change(&mut changes).await, (folder, ChangeType::Created));
+        assert_eq!(next_change(&mut changes).await, (note, ChangeType::Created));
+
+        client.set_title(store_id, note, "Renamed").await.unwrap();
+        assert_eq!(next_change(&mut changes).await, (note, ChangeType::Updated));
+        client.move_node(store_id, note, folder, None).await.unwrap();
+        assert_eq!(next_change(&mut changes).await, (note, ChangeType::Moved));
+        client.delete_node(store_id, note).await.unwrap();
+        assert_eq!(next_change(&mut changes).await, (note, ChangeType::Deleted));
+
+        // Stores that aren't open can't be watched
+        assert!(client.subscribe_store(StoreId::new()).await.is_err());
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_id_persists() {
+        let dir = tempdir().unwrap();
+        let config = ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            state_dir: Some(dir.path().to_path_buf()),
+        };
+        let peer_id = |server: &PimbleServer| server.replicator().unwrap().peer_id().to_string();
+
+        let mut first = PimbleServer::with_config(config.clone());
+        first.start().await.unwrap();
+        let id = peer_id(&first);
+        first.stop().await.unwrap();
+
+        // Its edits are attributed to the same id
+        let device = pimble_crdt::local_device().unwrap();
+        assert_eq!(format!("pimble-{}", device), id);
+
+        let mut again = PimbleServer::with_config(config);
+        again.start().await.unwrap();
+        assert_eq!(peer_id(&again), id);
+        again.stop().await.unwrap();
+
+        // Servers without a state directory are still told apart
+        let mut other = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        other.start().await.unwrap();
+        assert_ne!(peer_id(&other), id);
+        other.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let url = format!("http://{}", server.addr());
+
+        let client = PimbleClient::connect(&url).await.unwrap();
+        let info = client.server_info().unwrap();
+        assert_eq!(info, &server_info());
+        assert!(info.node_types.iter().any(|t| t == "document"));
+        assert!(info.features.subscriptions);
+        assert!(info.supports(PROTOCOL_VERSION) && !info.supports(PROTOCOL_VERSION + 1));
+
+        // Connecting now reaches the server, so a stopped one is noticed
+        server.stop().await.unwrap();
+        server.wait().await;
+        let err = PimbleClient::connect(&url).await.err().unwrap();
+        assert!(err.is_connection_error());
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_typed() {
+        let dir = tempdir().unwrap();
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let client = PimbleClient::connect(&format!("http://{}", server.addr())).await.unwrap();
+        let (store_id, _) = client.create_store(dir.path().join("errors.pimble"), "Errors").await.unwrap();
+
+        let missing = NodeId::new();
+        let err = client.get_node(store_id, missing).await.unwrap_err();
+        assert!(matches!(err, ClientError::NodeNotFound(id) if id == missing));
+
+        let closed = StoreId::new();
+        let err = client.get_node(closed, missing).await.unwrap_err();
+        assert!(matches!(err, ClientError::StoreNotOpen(id) if id == closed));
+        let err = client.subscribe_store(closed).await.unwrap_err();
+        assert!(matches!(err, ClientError::StoreNotOpen(id) if id == closed));
+
+        let err = client.create_store(dir.path().join("errors.pimble"), "Again").await.unwrap_err();
+        assert!(matches!(err, ClientError::Server(RpcError::StoreExists { .. })));
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_presence_broadcast() {
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let url = format!("http://{}", server.addr());
+        let (store_id, node_id) = (StoreId::new(), NodeId::new());
+
+        let watcher = PimbleClient::connect(&url).await.unwrap();
+        let editor = PimbleClient::connect(&url).await.unwrap();
+        let mut updates = watcher.subscribe_presence(store_id, node_id).await.unwrap();
+        assert!(updates.next().await.unwrap().unwrap().present.is_empty());
+
+        editor
+            .publish_presence(store_id, node_id, "Editor", Some("3@abcd".into()), None)
+            .await
+            .unwrap();
+        let update = updates.next().await.unwrap().unwrap();
+        assert_eq!(update.present.len(), 1);
+        assert_eq!(update.present[0].client_id, editor.presence_id());
+        assert_ne!(update.present[0].client_id, editor.session_id());
+        assert_eq!(update.present[0].anchor.as_deref(), Some("3@abcd"));
+        assert_eq!(watcher.get_presence(store_id, node_id).await.unwrap(), update.present);
+
+        editor.leave_presence(store_id, node_id).await.unwrap();
+        assert!(updates.next().await.unwrap().unwrap().present.is_empty());
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remote_replica_works_offline() {
+        let dir = tempdir().unwrap();
+        let served_path = dir.path().join("served.pimble");
+        let replica_path = dir.path().join("replica.pimble");
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let addr = server.addr();
+        let store_id = server
+            .store_manager()
+            .write()
+            .await
+            .create_local_store(&served_path, "Served")
+            .await
+            .unwrap();
+        let root = server.store_manager().read().await.root_node_id(store_id).unwrap();
+        let shared = server
+            .store_manager()
+            .write()
+            .await
+            .create_node(store_id, Node::document("Shared"), Some(root))
+            .await
+            .unwrap();
+
+        // The replica is filled on open
+        let url = format!("http://{}/?store={}", addr, store_id);
+        let mut manager = StoreManager::new();
+        manager
+            .open_remote_store_with_replica(url.parse().unwrap(), AuthMethod::None, &replica_path)
+            .await
+            .unwrap();
+        assert_eq!(manager.get_children(store_id, root).await.unwrap().len(), 1);
+
+        // Edits made while the server is gone are kept and queued
+        server.store_manager().write().await.flush_all().await.unwrap();
+        server.stop().await.unwrap();
+        server.wait().await;
+        let offline = manager.create_node(store_id, Node::document("Offline"), Some(root)).await.unwrap();
+        let mut doc = manager.get_node_document(store_id, shared).await.unwrap();
+        doc.set_string("offline", "local edit").unwrap();
+        manager.save_node_document(store_id, shared, &mut doc).await.unwrap();
+        assert_eq!(manager.get_children(store_id, root).await.unwrap().len(), 2);
+        let state = manager.get_store_info(store_id).unwrap().sync_state;
+        assert!(matches!(state, SyncState::Disconnected { queued: 2, last_sync: Some(_) }));
+
+        // The queue survives a restart, and the replica opens offline
+        drop(manager);
+        let mut manager = StoreManager::new();
+        manager
+            .open_remote_store_with_replica(url.parse().unwrap(), AuthMethod::None, &replica_path)
+            .await
+            .unwrap();
+        assert_eq!(manager.get_store_info(store_id).unwrap().sync_state.queued_changes(), 2);
+
+        // Once the server is back, the queue is replayed and merged
+        let mut server = PimbleServer::with_config(ServerConfig { addr, ..Default::default() });
+        server.start().await.unwrap();
+        server.store_manager().write().await.open_local_store(&served_path).await.unwrap();
+        {
+            let served = server.store_manager();
+            let mut served = served.write().await;
+            let mut doc = served.get_node_document(store_id, shared).await.unwrap();
+            doc.set_string("server", "remote edit").unwrap();
+            served.save_node_document(store_id, shared, &mut doc).await.unwrap();
+        }
+        manager.refresh_remote_stores().await;
+        assert!(manager.get_store_info(store_id).unwrap().sync_state.is_synced());
+
+        let served = server.store_manager();
+        let mut served = served.write().await;
+        assert_eq!(served.get_node(store_id, offline).await.unwrap().metadata.title, "Offline");
+        let doc = served.get_node_document(store_id, shared).await.unwrap();
+        assert_eq!(doc.get_string("offline").unwrap().as_deref(), Some("local edit"));
+        assert_eq!(doc.get_string("server").unwrap().as_deref(), Some("remote edit"));
+        drop(served);
+        let doc = manager.get_node_document(store_id, shared).await.unwrap();
+        assert_eq!(doc.get_string("server").unwrap().as_deref(), Some("remote edit"));
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replica_reports_dropped_changes() {
+        let dir = tempdir().unwrap();
+        let served_path = dir.path().join("served.pimble");
+        let mut server = PimbleServer::with_config(ServerConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        });
+        server.start().await.unwrap();
+        let addr = server.addr();
+        let served = server.store_manager();
+        let store_id = served.write().await.create_local_store(&served_path, "Served").await.unwrap();
+        let root = served.read().await.root_node_id(store_id).unwrap();
+        let doomed = served
+            .write()
+            .await
+            .create_node(store_id, Node::document("Doomed"), Some(root))
+            .await
+            .unwrap();
+
+        let url = format!("http://{}/?store={}", addr, store_id);
+        let mut manager = StoreManager::new();
+        manager
+            .open_remote_store_with_replica(url.parse().unwrap(), AuthMethod::None, dir.path().join("replica.pimble"))
+            .await
+            .unwrap();
+
+        // Edit a node offline that the server deletes meanwhile
+        served.write().await.delete_node(store_id, doomed).await.unwrap();
+        served.write().await.flush_all().await.unwrap();
+        server.stop().await.unwrap();
+        server.wait().await;
+        let mut metadata = manager.get_node(store_id, doomed).await.unwrap().metadata;
+        metadata.title = "Edited offline".into();
+        manager.update_node_metadata(store_id, doomed, metadata).await.unwrap();
+
+        // The next write succeeds; the stale edit is reported as dropped
+        let mut server = PimbleServer::with_config(ServerConfig { addr, ..Default::default() });
+        server.start().await.unwrap();
+        server.store_manager().write().await.open_local_store(&served_path).await.unwrap();
+        let note = manager.create_node(store_id, Node::document("Note"), Some(root)).await.unwrap();
+        let dropped = manager.take_dropped_changes(store_id).await;
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].op.node_id(), doomed);
+        assert!(manager.take_dropped_changes(store_id).await.is_empty());
+        assert!(server.store_manager().write().await.get_node(store_id, note).await.is_ok());
+        server.stop().await.unwrap();
+    }
+}
\ No newline at end of file
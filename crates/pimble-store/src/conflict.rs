@@ -0,0 +1,137 @@
+//! Finding and resolving concurrent edits in node content
+//!
+//! When two replicas write the same key of a node's document concurrently,
+//! Automerge keeps both values and reads return a deterministic winner.
+//! Nothing is lost, but the user never sees the other version. This module
+//! reports such keys as [`ConflictInfo`] entries and settles them on a
+//! value the user picks or merges.
+//!
+//! Custom metadata fields are stored as JSON strings; their conflicting
+//! values are decoded here so callers see and send plain JSON values.
+
+use pimble_core::{ConflictInfo, Node, NodeId};
+use pimble_crdt::{CrdtDocument, FIELD_PREFIX, TITLE_KEY};
+
+use crate::error::{Result, StoreError};
+use crate::local::LocalStore;
+
+/// List the conflicting keys in one node's content
+pub fn node_conflicts(node: &Node) -> Result<Vec<ConflictInfo>> {
+    if node.content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut doc = CrdtDocument::load(&node.content)?;
+
+    doc.conflicts()?
+        .into_iter()
+        .map(|conflict| {
+            let values = if conflict.key.starts_with(FIELD_PREFIX) {
+                conflict.values.iter().map(decode_field).collect()
+            } else {
+                conflict.values
+            };
+            Ok(ConflictInfo {
+                node_id: node.id,
+                description: describe(&node.metadata.title, &conflict.key),
+                detected_at: conflict.written_at,
+                key: conflict.key,
+                values,
+            })
+        })
+        .collect()
+}
+
+/// List the conflicts in every node of a store
+pub async fn list_conflicts(store: &mut LocalStore) -> Result<Vec<ConflictInfo>> {
+    store.flush().await?;
+    let mut conflicts = Vec::new();
+    for node_id in store.list_node_ids().await? {
+        let node = store.get_node(node_id).await?;
+        conflicts.extend(node_conflicts(node)?);
+    }
+    Ok(conflicts)
+}
+
+/// Settle a conflicting key on `value`
+///
+/// The value may be one of the conflicting versions or a merge of them.
+/// The node's metadata view is refreshed, since the key may be a
+/// metadata field.
+pub async fn resolve_conflict(
+    store: &mut LocalStore,
+    node_id: NodeId,
+    key: &str,
+    value: &serde_json::Value,
+) -> Result<()> {
+    let mut doc = store.get_node_document(node_id).await?;
+    if doc.get_all(key)?.is_empty() {
+        return Err(StoreError::InvalidOperation(format!("Node {} has no key '{}'", node_id, key)));
+    }
+
+    if key.starts_with(FIELD_PREFIX) {
+        doc.resolve(key, &serde_json::Value::String(value.to_string()))?;
+    } else {
+        doc.resolve(key, value)?;
+    }
+    doc.commit_with_message(&format!("Resolve conflict on {}", key));
+    store.save_node_document(node_id, &mut doc).await
+}
+
+fn decode_field(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(json) => serde_json::from_str(json).unwrap_or_else(|_| value.clone()),
+        other => other.clone(),
+    }
+}
+
+fn describe(title: &str, key: &str) -> String {
+    let what = match key {
+        "text" => "Text".to_string(),
+        TITLE_KEY => "Title".to_string(),
+        _ => match key.strip_prefix(FIELD_PREFIX) {
+            Some(field) => format!("Field '{}'", field),
+            None => format!("'{}'", key),
+        },
+    };
+    format!("{} of '{}' was changed on two devices at once", what, title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pimble_crdt::DocumentContent;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_conflicting_text_is_listed_and_resolved() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("a.pimble"), "A").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Plan"), Some(root)).await.unwrap();
+        assert!(list_conflicts(&mut store).await.unwrap().is_empty());
+
+        // Both sides replace the whole text
+        let mut ours = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        let mut theirs = DocumentContent::from_document(ours.document_mut().fork());
+        ours.set_text("Ours").unwrap();
+        theirs.set_text("Theirs").unwrap();
+        ours.document_mut().merge(theirs.document_mut()).unwrap();
+        store.merge_node_content(node_id, ours.save()).await.unwrap();
+
+        let conflicts = list_conflicts(&mut store).await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].node_id, node_id);
+        assert_eq!(conflicts[0].key, "text");
+        assert!(conflicts[0].values.contains(&serde_json::json!("Ours")));
+        assert!(conflicts[0].values.contains(&serde_json::json!("Theirs")));
+        assert!(conflicts[0].detected_at.is_some());
+        assert_eq!(list_conflicts(&mut store).await.unwrap()[0].detected_at, conflicts[0].detected_at);
+
+        resolve_conflict(&mut store, node_id, "text", &serde_json::json!("Ours, then theirs"))
+            .await
+            .unwrap();
+        assert!(list_conflicts(&mut store).await.unwrap().is_empty());
+        let content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        assert_eq!(content.get_text().unwrap(), "Ours, then theirs");
+    }
+}
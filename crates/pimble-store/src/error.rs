@@ -1,6 +1,8 @@
 //! Error types for pimble-store
 
 use pimble_core::{NodeId, StoreId};
+use pimble_crdt::CrdtError;
+use pimble_rpc::RpcError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -23,6 +25,9 @@ pub enum StoreError {
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
 
+    #[error("Invalid format: {0}")]
+    InvalidFormat(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -32,8 +37,51 @@ pub enum StoreError {
     #[error("CRDT error: {0}")]
     Crdt(#[from] pimble_crdt::CrdtError),
 
+    #[error("Remote store error: {0}")]
+    Remote(#[from] pimble_client::ClientError),
+
     #[error("Core error: {0}")]
     Core(#[from] pimble_core::CoreError),
+
+    #[error("Changes depend on changes that are missing: {}", .0.join(", "))]
+    MissingChanges(Vec<String>),
+}
+
+impl From<StoreError> for RpcError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::StoreNotFound(store_id) => RpcError::StoreNotFound { store_id },
+            StoreError::NodeNotFound(node_id) => RpcError::NodeNotFound { node_id },
+            StoreError::StoreExists(path) => RpcError::StoreExists { path },
+            StoreError::InvalidPath(path) => RpcError::InvalidPath { path },
+            StoreError::NotOpen(store_id) => RpcError::StoreNotOpen { store_id },
+            StoreError::InvalidOperation(message) => RpcError::InvalidOperation { message },
+            StoreError::InvalidFormat(message) => RpcError::InvalidFormat { message },
+            StoreError::Io(e) => e.into(),
+            StoreError::Serialization(e) => RpcError::InvalidFormat {
+                message: e.to_string(),
+            },
+            StoreError::Crdt(e) => crdt_rpc_error(e),
+            StoreError::Remote(e) => e.into(),
+            StoreError::Core(e) => e.into(),
+            StoreError::MissingChanges(changes) => RpcError::MissingChanges { changes },
+        }
+    }
+}
+
+/// Map a content error to the code clients see
+///
+/// Bad paths and anchors come from the request; unknown changes are
+/// versions the document doesn't have. The rest are CRDT failures.
+fn crdt_rpc_error(e: CrdtError) -> RpcError {
+    match e {
+        CrdtError::UnknownChange(change) => RpcError::UnknownVersion { change },
+        CrdtError::InvalidPath(_) | CrdtError::InvalidAnchor(_) => RpcError::invalid_params(e),
+        CrdtError::InvalidMove(message) => RpcError::InvalidOperation { message },
+        e => RpcError::Crdt {
+            message: e.to_string(),
+        },
+    }
 }
 
 pub type Result<T> = std::result::Result<T, StoreError>;
@@ -0,0 +1,555 @@
+//! Static HTML site export
+//!
+//! Renders a subtree as a self-contained, read-only site using the same
+//! layout as the markdown export, with `.html` pages:
+//!
+//! ```text
+//! out/
+//! ├── .pimble-export.json     # What the last export wrote
+//! ├── style.css               # Shared stylesheet
+//! ├── index.html              # The exported root
+//! ├── projects/
+//! │   ├── index.html
+//! │   └── roadmap.html
+//! ├── _tags/                  # One page per tag plus an index of tags
+//! │   ├── index.html
+//! │   └── work.html
+//! └── assets/                 # Assets referenced from content
+//! ```
+//!
+//! Every page carries a navigation sidebar built from the node tree and a
+//! backlinks section. Headings get slug ids so deep links resolve to
+//! fragments.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use pimble_core::{LinkTarget, Node, NodeId};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use tracing::info;
+
+use super::{
+    anchor_fragment, asset_path, display_title, exported_asset, find_links, node_text, plan_paths,
+    relative_path, slugify, ExportReport, ExportTree, ExportWriter,
+};
+use crate::error::Result;
+use crate::local::LocalStore;
+
+/// Directory holding tag index pages
+const TAGS_DIR: &str = "_tags";
+
+/// Stylesheet written alongside the pages
+const STYLESHEET: &str = "style.css";
+
+const STYLE: &str = "\
+body { margin: 0; display: flex; font-family: system-ui, sans-serif; line-height: 1.5; color: #222; }
+nav { width: 16rem; flex-shrink: 0; padding: 1rem; border-right: 1px solid #ddd; background: #fafafa; min-height: 100vh; box-sizing: border-box; }
+nav ul { list-style: none; padding-left: 1rem; margin: 0; }
+nav > ul { padding-left: 0; }
+nav a { color: inherit; text-decoration: none; }
+nav a.current { font-weight: bold; }
+main { flex: 1; max-width: 48rem; padding: 1rem 2rem; }
+.tags a { display: inline-block; margin-right: 0.5rem; padding: 0 0.4rem; border-radius: 0.25rem; background: #eef; font-size: 0.85em; text-decoration: none; }
+.backlinks { margin-top: 3rem; border-top: 1px solid #ddd; font-size: 0.9em; }
+pre { background: #f4f4f4; padding: 0.75rem; overflow-x: auto; }
+img { max-width: 100%; }
+";
+
+/// Options for publishing a subtree as static HTML
+#[derive(Debug, Clone, Default)]
+pub struct HtmlExportOptions {
+    /// Only rewrite files whose content changed since the last export, and
+    /// remove files for nodes that are no longer part of the subtree
+    pub incremental: bool,
+
+    /// Site title shown in the sidebar (defaults to the root node's title)
+    pub site_title: Option<String>,
+}
+
+/// Exports a store subtree to a static HTML site
+pub struct HtmlExporter {
+    options: HtmlExportOptions,
+}
+
+impl HtmlExporter {
+    /// Create a new exporter
+    pub fn new(options: HtmlExportOptions) -> Self {
+        Self { options }
+    }
+
+    /// Export the subtree rooted at `root` into `out_dir`
+    pub async fn export(
+        &self,
+        store: &mut LocalStore,
+        root: NodeId,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<ExportReport> {
+        let out_dir = out_dir.as_ref();
+        let tree = ExportTree::load(store, root).await?;
+        let site = Site::new(&tree, self.options.site_title.clone());
+
+        let mut writer = ExportWriter::open(out_dir, self.options.incremental).await?;
+        let assets_dir = store.assets_dir();
+
+        writer.write(Path::new(STYLESHEET), STYLE.as_bytes()).await?;
+
+        for node_id in tree.depth_first() {
+            let Some(node) = tree.get(&node_id) else {
+                continue;
+            };
+            let mut assets = Vec::new();
+            let page = site.render_node(node, &mut assets);
+            writer.write(&site.paths[&node_id], page.as_bytes()).await?;
+            writer.copy_assets(&assets_dir, &assets).await?;
+        }
+
+        if !site.tags.is_empty() {
+            let index = Path::new(TAGS_DIR).join("index.html");
+            writer.write(&index, site.render_tag_index(&index).as_bytes()).await?;
+            for tag in site.tags.keys() {
+                let path = site.tag_path(tag);
+                writer.write(&path, site.render_tag_page(tag, &path).as_bytes()).await?;
+            }
+        }
+
+        let report = writer.finish().await?;
+        info!(
+            "Published {} nodes from store {} as HTML to {:?} ({} written, {} unchanged, {} removed)",
+            tree.len(),
+            store.id,
+            out_dir,
+            report.written.len(),
+            report.unchanged,
+            report.removed.len()
+        );
+        Ok(report)
+    }
+}
+
+impl Default for HtmlExporter {
+    fn default() -> Self {
+        Self::new(HtmlExportOptions::default())
+    }
+}
+
+/// Everything needed to render pages for one export run
+struct Site<'a> {
+    tree: &'a ExportTree,
+    title: String,
+    paths: HashMap<NodeId, PathBuf>,
+    texts: HashMap<NodeId, String>,
+    /// Nodes linking to each node, from metadata links and content links
+    backlinks: HashMap<NodeId, Vec<NodeId>>,
+    /// Nodes carrying each tag, in tree order
+    tags: BTreeMap<String, Vec<NodeId>>,
+    /// File name of each tag's page, unique even for tags that slug alike
+    tag_slugs: HashMap<String, String>,
+}
+
+impl<'a> Site<'a> {
+    fn new(tree: &'a ExportTree, title: Option<String>) -> Self {
+        let paths = plan_paths(tree, "html");
+        let order = tree.depth_first();
+        let texts: HashMap<NodeId, String> = order
+            .iter()
+            .filter_map(|id| tree.get(id).map(|n| (*id, node_text(n))))
+            .collect();
+
+        let mut backlinks: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        let mut tags: BTreeMap<String, Vec<NodeId>> = BTreeMap::new();
+        for id in &order {
+            let Some(node) = tree.get(id) else {
+                continue;
+            };
+            let mut targets: HashSet<NodeId> =
+                node.links.iter().filter_map(|l| l.target.node_id()).collect();
+            targets.extend(
+                find_links(&texts[id])
+                    .iter()
+                    .filter_map(|l| l.target()?.node_id()),
+            );
+            for target in targets {
+                if target != *id && tree.contains(&target) {
+                    backlinks.entry(target).or_default().push(*id);
+                }
+            }
+            for tag in &node.metadata.tags {
+                tags.entry(tag.clone()).or_default().push(*id);
+            }
+        }
+        // HashSet iteration order isn't stable; keep pages deterministic
+        let position: HashMap<NodeId, usize> =
+            order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        for sources in backlinks.values_mut() {
+            sources.sort_by_key(|id| position[id]);
+        }
+
+        let title = title
+            .or_else(|| tree.get(&tree.root).map(display_title))
+            .unwrap_or_else(|| "Untitled".to_string());
+
+        // "Work" and "work" are different tags; number clashing slugs
+        let mut used = HashSet::new();
+        let mut tag_slugs = HashMap::new();
+        for tag in tags.keys() {
+            // "index" is reserved for the list of all tags
+            let stem = match slugify(tag).as_str() {
+                "" => "tag".to_string(),
+                "index" => "tag-index".to_string(),
+                slug => slug.to_string(),
+            };
+            let mut slug = stem.clone();
+            let mut n = 2;
+            while !used.insert(slug.clone()) {
+                slug = format!("{stem}-{n}");
+                n += 1;
+            }
+            tag_slugs.insert(tag.clone(), slug);
+        }
+
+        Self { tree, title, paths, texts, backlinks, tags, tag_slugs }
+    }
+
+    fn tag_path(&self, tag: &str) -> PathBuf {
+        let slug = self.tag_slugs.get(tag).map(String::as_str).unwrap_or("tag");
+        Path::new(TAGS_DIR).join(format!("{slug}.html"))
+    }
+
+    /// Render a node's page; asset paths it references are pushed onto `assets`
+    fn render_node(&self, node: &Node, assets: &mut Vec<PathBuf>) -> String {
+        let path = &self.paths[&node.id];
+        let mut body = String::new();
+
+        body.push_str(&format!("<h1>{}</h1>\n", escape_html(&display_title(node))));
+        if !node.metadata.tags.is_empty() {
+            body.push_str("<p class=\"tags\">");
+            for tag in &node.metadata.tags {
+                body.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_html(&relative_path(path, &self.tag_path(tag))),
+                    escape_html(tag)
+                ));
+            }
+            body.push_str("</p>\n");
+        }
+
+        let text = self.texts.get(&node.id).map(String::as_str).unwrap_or("");
+        body.push_str(&self.render_markdown(text, path, assets));
+
+        if let Some(sources) = self.backlinks.get(&node.id) {
+            body.push_str("<section class=\"backlinks\">\n<h2>Backlinks</h2>\n<ul>\n");
+            for source in sources {
+                body.push_str(&format!("<li>{}</li>\n", self.node_link(source, path)));
+            }
+            body.push_str("</ul>\n</section>\n");
+        }
+
+        self.page(&display_title(node), path, &body)
+    }
+
+    fn render_tag_index(&self, path: &Path) -> String {
+        let mut body = String::from("<h1>Tags</h1>\n<ul>\n");
+        for (tag, nodes) in &self.tags {
+            body.push_str(&format!(
+                "<li><a href=\"{}\">{}</a> ({})</li>\n",
+                escape_html(&relative_path(path, &self.tag_path(tag))),
+                escape_html(tag),
+                nodes.len()
+            ));
+        }
+        body.push_str("</ul>\n");
+        self.page("Tags", path, &body)
+    }
+
+    fn render_tag_page(&self, tag: &str, path: &Path) -> String {
+        let mut body = format!("<h1>Tagged “{}”</h1>\n<ul>\n", escape_html(tag));
+        for node_id in self.tags.get(tag).into_iter().flatten() {
+            body.push_str(&format!("<li>{}</li>\n", self.node_link(node_id, path)));
+        }
+        body.push_str("</ul>\n");
+        body.push_str(&format!(
+            "<p><a href=\"{}\">All tags</a></p>\n",
+            escape_html(&relative_path(path, &Path::new(TAGS_DIR).join("index.html")))
+        ));
+        self.page(&format!("Tagged “{}”", tag), path, &body)
+    }
+
+    /// Wrap page content in the document shell with the navigation sidebar
+    fn page(&self, title: &str, path: &Path, body: &str) -> String {
+        let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!(
+            "<title>{} — {}</title>\n",
+            escape_html(title),
+            escape_html(&self.title)
+        ));
+        out.push_str(&format!(
+            "<link rel=\"stylesheet\" href=\"{}\">\n</head>\n<body>\n",
+            relative_path(path, Path::new(STYLESHEET))
+        ));
+
+        out.push_str("<nav>\n");
+        out.push_str(&format!(
+            "<p><strong><a href=\"{}\">{}</a></strong></p>\n",
+            escape_html(&relative_path(path, &self.paths[&self.tree.root])),
+            escape_html(&self.title)
+        ));
+        if let Some(root) = self.tree.get(&self.tree.root) {
+            if !root.children.is_empty() {
+                self.render_nav(&root.children, path, &mut out);
+            }
+        }
+        if !self.tags.is_empty() {
+            out.push_str(&format!(
+                "<p><a href=\"{}\">Tags</a></p>\n",
+                escape_html(&relative_path(path, &Path::new(TAGS_DIR).join("index.html")))
+            ));
+        }
+        out.push_str("</nav>\n");
+
+        out.push_str("<main>\n");
+        out.push_str(body);
+        out.push_str("</main>\n</body>\n</html>\n");
+        out
+    }
+
+    fn render_nav(&self, children: &[NodeId], current: &Path, out: &mut String) {
+        out.push_str("<ul>\n");
+        for child_id in children {
+            let Some(child) = self.tree.get(child_id) else {
+                continue;
+            };
+            let target = &self.paths[child_id];
+            let class = if target == current { " class=\"current\"" } else { "" };
+            out.push_str(&format!(
+                "<li><a href=\"{}\"{}>{}</a>",
+                escape_html(&relative_path(current, target)),
+                class,
+                escape_html(&display_title(child))
+            ));
+            if !child.children.is_empty() {
+                out.push('\n');
+                self.render_nav(&child.children, current, out);
+            }
+            out.push_str("</li>\n");
+        }
+        out.push_str("</ul>\n");
+    }
+
+    fn node_link(&self, node_id: &NodeId, from: &Path) -> String {
+        let title = self.tree.get(node_id).map(display_title).unwrap_or_default();
+        format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(&relative_path(from, &self.paths[node_id])),
+            escape_html(&title)
+        )
+    }
+
+    /// Relative URL for an internal link target, with its anchor as a fragment
+    fn link_url(&self, target: &LinkTarget, from: &Path) -> Option<String> {
+        let target_id = target.node_id()?;
+        let mut url = relative_path(from, self.paths.get(&target_id)?);
+        if let Some(fragment) = target
+            .anchor()
+            .and_then(|a| anchor_fragment(self.texts.get(&target_id)?, a))
+        {
+            url.push('#');
+            url.push_str(&fragment);
+        }
+        Some(url)
+    }
+
+    /// Render markdown content to HTML
+    ///
+    /// Internal links become relative page URLs, asset references are
+    /// collected, and headings get slug ids matching `anchor_fragment`.
+    /// The site is published as is, so raw HTML is shown as text and links
+    /// that are neither http(s) nor relative are dropped.
+    fn render_markdown(&self, text: &str, path: &Path, assets: &mut Vec<PathBuf>) -> String {
+        let mut rewrite = |url: CowStr<'static>| -> CowStr<'static> {
+            if let Some(target) = LinkTarget::parse_uri(&url).filter(|t| t.node_id().is_some()) {
+                return match self.link_url(&target, path) {
+                    Some(rel) => rel.into(),
+                    None => CowStr::Borrowed(""),
+                };
+            }
+            if let Some(asset) = asset_path(&url) {
+                let rel = relative_path(path, &exported_asset(&asset));
+                assets.push(asset);
+                return rel.into();
+            }
+            if is_safe_url(&url) {
+                url
+            } else {
+                CowStr::Borrowed("")
+            }
+        };
+
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_FOOTNOTES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS;
+        let mut events: Vec<Event> = Parser::new_ext(text, options)
+            .map(|event| match event {
+                Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+                Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                    let dest_url = rewrite(dest_url.into_static());
+                    Event::Start(Tag::Link { link_type, dest_url, title, id })
+                }
+                Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                    let dest_url = rewrite(dest_url.into_static());
+                    Event::Start(Tag::Image { link_type, dest_url, title, id })
+                }
+                event => event,
+            })
+            .collect();
+
+        // Give headings slug ids from their text
+        for i in 0..events.len() {
+            let Event::Start(Tag::Heading { id: None, .. }) = &events[i] else {
+                continue;
+            };
+            let mut heading = String::new();
+            for event in &events[i + 1..] {
+                match event {
+                    Event::End(TagEnd::Heading(_)) => break,
+                    Event::Text(t) | Event::Code(t) => heading.push_str(t),
+                    _ => {}
+                }
+            }
+            let slug = slugify(&heading);
+            if let Event::Start(Tag::Heading { id, .. }) = &mut events[i] {
+                if !slug.is_empty() {
+                    *id = Some(slug.into());
+                }
+            }
+        }
+
+        let mut out = String::new();
+        html::push_html(&mut out, events.into_iter());
+        out
+    }
+}
+
+/// Whether a link URL is safe to publish: http(s), or relative to the site
+fn is_safe_url(url: &str) -> bool {
+    // Browsers skip whitespace and control characters when reading a scheme
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(i) if url[i..].starts_with(':') => {
+            let scheme = url[..i].to_ascii_lowercase();
+            scheme == "http" || scheme == "https"
+        }
+        _ => true,
+    }
+}
+
+/// Escape text for HTML content and attribute values
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pimble_crdt::DocumentContent;
+    use tempfile::tempdir;
+
+    fn content(text: &str) -> Vec<u8> {
+        let mut doc = DocumentContent::new();
+        doc.set_text(text).unwrap();
+        doc.save()
+    }
+
+    #[tokio::test]
+    async fn test_export_html_site() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = store.root_node_id();
+
+        let folder = store.create_node(Node::folder("Projects"), Some(root)).await.unwrap();
+        let mut roadmap = Node::document("Roadmap");
+        roadmap.metadata.tags = vec!["work".to_string()];
+        let target = store.create_node(roadmap, Some(folder)).await.unwrap();
+        store
+            .update_node_content(target, content("## Q3 Plans\n\nShip it & celebrate.\n"))
+            .await
+            .unwrap();
+
+        let note = store.create_node(Node::document("Notes"), Some(root)).await.unwrap();
+        let link = LinkTarget::Deep { node_id: target, anchor: "heading:Q3-Plans".into() };
+        store
+            .update_node_content(note, content(&format!("See [plan]({}).", link.to_uri())))
+            .await
+            .unwrap();
+
+        let out = dir.path().join("site");
+        let report = HtmlExporter::default().export(&mut store, root, &out).await.unwrap();
+        // style.css, 4 pages, tag index and one tag page
+        assert_eq!(report.written.len(), 7);
+
+        let notes = std::fs::read_to_string(out.join("notes.html")).unwrap();
+        assert!(notes.contains("<a href=\"projects/roadmap.html#q3-plans\">plan</a>"));
+        assert!(notes.contains("<link rel=\"stylesheet\" href=\"style.css\">"));
+        assert!(notes.contains("<a href=\"notes.html\" class=\"current\">Notes</a>"));
+
+        let page = std::fs::read_to_string(out.join("projects/roadmap.html")).unwrap();
+        assert!(page.contains("<h2 id=\"q3-plans\">Q3 Plans</h2>"));
+        assert!(page.contains("Ship it &amp; celebrate."));
+        assert!(page.contains("<h2>Backlinks</h2>"));
+        assert!(page.contains("<a href=\"../notes.html\">Notes</a>"));
+        assert!(page.contains("<a href=\"../_tags/work.html\">work</a>"));
+
+        let tag_page = std::fs::read_to_string(out.join("_tags/work.html")).unwrap();
+        assert!(tag_page.contains("<a href=\"../projects/roadmap.html\">Roadmap</a>"));
+    }
+
+    #[tokio::test]
+    async fn test_export_html_is_inert() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = store.root_node_id();
+
+        let mut upper = Node::document("Upper");
+        upper.metadata.tags = vec!["Work".to_string()];
+        let upper = store.create_node(upper, Some(root)).await.unwrap();
+        store
+            .update_node_content(
+                upper,
+                content("<script>alert(1)</script>\n\n[x](javascript:alert(1)) [y](https://example.com) [z](other.html)\n"),
+            )
+            .await
+            .unwrap();
+        let mut lower = Node::document("Lower");
+        lower.metadata.tags = vec!["work".to_string()];
+        store.create_node(lower, Some(root)).await.unwrap();
+
+        let out = dir.path().join("site");
+        HtmlExporter::default().export(&mut store, root, &out).await.unwrap();
+
+        let page = std::fs::read_to_string(out.join("upper.html")).unwrap();
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("&lt;script&gt;"));
+        assert!(!page.contains("javascript:"));
+        assert!(page.contains("<a href=\"https://example.com\">y</a>"));
+        assert!(page.contains("<a href=\"other.html\">z</a>"));
+
+        // Tags differing only in case get a page each
+        let first = std::fs::read_to_string(out.join("_tags/work.html")).unwrap();
+        let second = std::fs::read_to_string(out.join("_tags/work-2.html")).unwrap();
+        assert!(first.contains("<h1>Tagged “Work”</h1>"));
+        assert!(second.contains("<h1>Tagged “work”</h1>"));
+    }
+}
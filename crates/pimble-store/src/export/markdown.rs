@@ -0,0 +1,295 @@
+//! Markdown folder export
+//!
+//! Writes a subtree as a directory of `.md` files mirroring the node tree:
+//!
+//! ```text
+//! out/
+//! ├── .pimble-export.json     # What the last export wrote
+//! ├── index.md                # The exported root's own content
+//! ├── meeting-notes.md        # A leaf document
+//! ├── projects/               # A folder (or any node with children)
+//! │   ├── index.md
+//! │   └── roadmap.md
+//! └── assets/                 # Assets referenced from content
+//! ```
+//!
+//! Each file starts with YAML front-matter holding the node's metadata.
+//! `pimble://` links between exported nodes become relative paths, and
+//! deep-link anchors become heading fragments.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use pimble_core::{LinkTarget, Node, NodeId};
+use tracing::info;
+
+use super::{
+    anchor_fragment, asset_path, exported_asset, node_text, plan_paths, relative_path,
+    rewrite_links, ExportReport, ExportTree, ExportWriter,
+};
+use crate::error::Result;
+use crate::local::LocalStore;
+
+/// Options for exporting a subtree to markdown files
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownExportOptions {
+    /// Only rewrite files whose content changed since the last export, and
+    /// remove files for nodes that are no longer part of the subtree
+    pub incremental: bool,
+}
+
+/// Exports a store subtree to a folder of markdown files
+pub struct MarkdownExporter {
+    options: MarkdownExportOptions,
+}
+
+impl MarkdownExporter {
+    /// Create a new exporter
+    pub fn new(options: MarkdownExportOptions) -> Self {
+        Self { options }
+    }
+
+    /// Export the subtree rooted at `root` into `out_dir`
+    pub async fn export(
+        &self,
+        store: &mut LocalStore,
+        root: NodeId,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<ExportReport> {
+        let out_dir = out_dir.as_ref();
+        let tree = ExportTree::load(store, root).await?;
+        let paths = plan_paths(&tree, "md");
+        let texts: HashMap<NodeId, String> = tree
+            .depth_first()
+            .into_iter()
+            .filter_map(|id| tree.get(&id).map(|n| (id, node_text(n))))
+            .collect();
+
+        let mut writer = ExportWriter::open(out_dir, self.options.incremental).await?;
+        let assets_dir = store.assets_dir();
+
+        for node_id in tree.depth_first() {
+            let Some(node) = tree.get(&node_id) else {
+                continue;
+            };
+            let path = &paths[&node_id];
+            let mut assets = Vec::new();
+            let rendered = render_node(node, path, &texts, &paths, &mut assets);
+            writer.write(path, rendered.as_bytes()).await?;
+            writer.copy_assets(&assets_dir, &assets).await?;
+        }
+
+        let report = writer.finish().await?;
+        info!(
+            "Exported {} nodes from store {} to {:?} ({} written, {} unchanged, {} removed)",
+            tree.len(),
+            store.id,
+            out_dir,
+            report.written.len(),
+            report.unchanged,
+            report.removed.len()
+        );
+        Ok(report)
+    }
+}
+
+impl Default for MarkdownExporter {
+    fn default() -> Self {
+        Self::new(MarkdownExportOptions::default())
+    }
+}
+
+/// Render a single node as front-matter plus markdown body
+///
+/// Asset paths referenced by the body are pushed onto `assets`.
+fn render_node(
+    node: &Node,
+    path: &Path,
+    texts: &HashMap<NodeId, String>,
+    paths: &HashMap<NodeId, PathBuf>,
+    assets: &mut Vec<PathBuf>,
+) -> String {
+    let link_path = |target: &LinkTarget| -> Option<String> {
+        let target_id = target.node_id()?;
+        let target_path = paths.get(&target_id)?;
+        let mut rel = relative_path(path, target_path);
+        if let Some(fragment) = target
+            .anchor()
+            .and_then(|a| anchor_fragment(texts.get(&target_id)?, a))
+        {
+            rel.push('#');
+            rel.push_str(&fragment);
+        }
+        Some(rel)
+    };
+
+    let mut out = front_matter(node, &link_path);
+
+    let body = texts.get(&node.id).map(String::as_str).unwrap_or("");
+    let body = rewrite_links(body, |link| {
+        if let Some(target) = link.target() {
+            return link_path(&target);
+        }
+        let asset = asset_path(&link.url)?;
+        let rel = relative_path(path, &exported_asset(&asset));
+        assets.push(asset);
+        Some(rel)
+    });
+
+    if !body.is_empty() {
+        out.push('\n');
+        out.push_str(&body);
+        if !body.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Render a node's metadata as a YAML front-matter block
+///
+/// Strings are emitted as JSON-quoted scalars and custom values as JSON
+/// flow nodes, both of which are valid YAML.
+fn front_matter(node: &Node, link_path: &dyn Fn(&LinkTarget) -> Option<String>) -> String {
+    let meta = &node.metadata;
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_else(|_| "\"\"".to_string());
+
+    let mut out = String::from("---\n");
+    out.push_str(&format!("id: {}\n", quote(&node.id.to_string())));
+    out.push_str(&format!("title: {}\n", quote(&meta.title)));
+    out.push_str(&format!("type: {}\n", quote(&node.node_type)));
+    out.push_str(&format!("created: {}\n", meta.created_at.to_rfc3339()));
+    out.push_str(&format!("modified: {}\n", meta.modified_at.to_rfc3339()));
+
+    if !meta.tags.is_empty() {
+        out.push_str("tags:\n");
+        for tag in &meta.tags {
+            out.push_str(&format!("  - {}\n", quote(tag)));
+        }
+    }
+
+    if !meta.custom.is_empty() {
+        out.push_str("custom:\n");
+        let mut keys: Vec<&String> = meta.custom.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = serde_json::to_string(&meta.custom[key]).unwrap_or_else(|_| "null".into());
+            out.push_str(&format!("  {}: {}\n", quote(key), value));
+        }
+    }
+
+    if !node.links.is_empty() {
+        out.push_str("links:\n");
+        for link in &node.links {
+            let target = link_path(&link.target).unwrap_or_else(|| link.target.to_uri());
+            out.push_str(&format!("  - target: {}\n", quote(&target)));
+            out.push_str(&format!("    type: {}\n", quote(&link.link_type)));
+        }
+    }
+
+    out.push_str("---\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pimble_core::NodeLink;
+    use pimble_crdt::DocumentContent;
+    use tempfile::tempdir;
+
+    fn content(text: &str) -> Vec<u8> {
+        let mut doc = DocumentContent::new();
+        doc.set_text(text).unwrap();
+        doc.save()
+    }
+
+    fn rich_content(markdown: &str) -> Vec<u8> {
+        DocumentContent::from_markdown(markdown).unwrap().save()
+    }
+
+    #[tokio::test]
+    async fn test_export_markdown_tree() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = store.root_node_id();
+
+        let folder = store.create_node(Node::folder("Projects"), Some(root)).await.unwrap();
+        let target = store.create_node(Node::document("Roadmap"), Some(folder)).await.unwrap();
+        store
+            .update_node_content(target, rich_content("# Roadmap\n\n## Q3\n\nShip **it**.\n"))
+            .await
+            .unwrap();
+
+        let mut note = Node::document("Meeting Notes");
+        note.metadata.tags = vec!["work".to_string()];
+        note.add_link(NodeLink::deep(target, "heading:Q3"));
+        let note = store.create_node(note, Some(root)).await.unwrap();
+        let link = LinkTarget::Deep { node_id: target, anchor: "heading:Q3".into() };
+        store
+            .update_node_content(note, content(&format!("See [plan]({}).", link.to_uri())))
+            .await
+            .unwrap();
+
+        let out = dir.path().join("out");
+        let report = MarkdownExporter::default().export(&mut store, root, &out).await.unwrap();
+        assert_eq!(report.written.len(), 4);
+
+        let note_md = std::fs::read_to_string(out.join("meeting-notes.md")).unwrap();
+        assert!(note_md.starts_with("---\n"));
+        assert!(note_md.contains("title: \"Meeting Notes\""));
+        assert!(note_md.contains("  - \"work\""));
+        assert!(note_md.contains("See [plan](projects/roadmap.md#q3)."));
+        assert!(out.join("projects/index.md").exists());
+        let roadmap_md = std::fs::read_to_string(out.join("projects/roadmap.md")).unwrap();
+        assert!(roadmap_md.contains("## Q3\n\nShip **it**."));
+
+        // Re-export without changes rewrites nothing
+        let options = MarkdownExportOptions { incremental: true };
+        let report = MarkdownExporter::new(options.clone()).export(&mut store, root, &out).await.unwrap();
+        assert!(report.written.is_empty());
+        assert_eq!(report.unchanged, 4);
+
+        // Deleting a node removes its file on the next export
+        store.delete_node(note).await.unwrap();
+        let report = MarkdownExporter::new(options).export(&mut store, root, &out).await.unwrap();
+        assert_eq!(report.removed, vec![PathBuf::from("meeting-notes.md")]);
+        assert!(!out.join("meeting-notes.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_export_stays_in_output_dir() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = store.root_node_id();
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        std::fs::create_dir_all(store.assets_dir()).unwrap();
+        std::fs::write(store.assets_dir().join("pic.png"), "png").unwrap();
+
+        let note = store.create_node(Node::document("Note"), Some(root)).await.unwrap();
+        store
+            .update_node_content(note, content("![a](assets/pic.png) ![b](assets/../../secret.txt)"))
+            .await
+            .unwrap();
+
+        let out = dir.path().join("nested").join("out");
+        let report = MarkdownExporter::default().export(&mut store, root, &out).await.unwrap();
+        assert_eq!(report.assets_copied, 1);
+        assert!(out.join("assets/pic.png").exists());
+        let note_md = std::fs::read_to_string(out.join("note.md")).unwrap();
+        assert!(note_md.contains("![b](assets/../../secret.txt)"));
+
+        // A tampered manifest can't make an export delete outside its directory
+        let manifest = r#"{"version":1,"files":["../../secret.txt","stale.md"]}"#;
+        std::fs::write(out.join(".pimble-export.json"), manifest).unwrap();
+        std::fs::write(out.join("stale.md"), "old").unwrap();
+        let report = MarkdownExporter::default().export(&mut store, root, &out).await.unwrap();
+        assert!(report.removed.is_empty());
+        assert!(out.join("stale.md").exists());
+
+        let options = MarkdownExportOptions { incremental: true };
+        let report = MarkdownExporter::new(options).export(&mut store, root, &out).await.unwrap();
+        assert_eq!(report.removed, vec![PathBuf::from("stale.md")]);
+        assert!(dir.path().join("secret.txt").exists());
+    }
+}
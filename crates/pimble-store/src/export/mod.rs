@@ -0,0 +1,603 @@
+//! Exporters that write store subtrees out to other formats
+//!
+//! Shared helpers live here: loading a subtree into memory, extracting
+//! node text, slugging titles into file names and rewriting `pimble://`
+//! links found in content.
+
+pub mod html;
+pub mod markdown;
+
+pub use html::*;
+pub use markdown::*;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use pimble_core::{node_types, LinkTarget, Node, NodeId};
+use pimble_crdt::DocumentContent;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::debug;
+
+use crate::error::Result;
+use crate::local::LocalStore;
+
+/// Prefix of store-relative asset references in content
+const ASSETS_PREFIX: &str = "assets/";
+
+/// Directory assets are copied into, inside the output directory
+const ASSETS_DIR: &str = "assets";
+
+/// A subtree loaded into memory for export
+pub struct ExportTree {
+    /// The node the export starts from
+    pub root: NodeId,
+
+    /// Every node in the subtree, keyed by ID
+    nodes: HashMap<NodeId, Node>,
+}
+
+impl ExportTree {
+    /// Load the subtree rooted at `root` from a store
+    pub async fn load(store: &mut LocalStore, root: NodeId) -> Result<Self> {
+        let mut nodes = HashMap::new();
+        let mut stack = vec![root];
+
+        while let Some(node_id) = stack.pop() {
+            if nodes.contains_key(&node_id) {
+                continue;
+            }
+            let node = store.get_node(node_id).await?.clone();
+            stack.extend(node.children.iter().rev().copied());
+            nodes.insert(node_id, node);
+        }
+
+        Ok(Self { root, nodes })
+    }
+
+    /// Get a node in the subtree
+    pub fn get(&self, node_id: &NodeId) -> Option<&Node> {
+        self.nodes.get(node_id)
+    }
+
+    /// Check if a node is part of the subtree
+    pub fn contains(&self, node_id: &NodeId) -> bool {
+        self.nodes.contains_key(node_id)
+    }
+
+    /// Number of nodes in the subtree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the subtree is empty
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Node IDs in depth-first pre-order, following child order
+    pub fn depth_first(&self) -> Vec<NodeId> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![self.root];
+        while let Some(node_id) = stack.pop() {
+            if let Some(node) = self.nodes.get(&node_id) {
+                order.push(node_id);
+                stack.extend(node.children.iter().rev().copied());
+            }
+        }
+        order
+    }
+
+    /// Whether a node is exported as a directory rather than a single file
+    pub fn is_branch(&self, node_id: &NodeId) -> bool {
+        self.nodes
+            .get(node_id)
+            .is_some_and(|n| !n.children.is_empty() || n.node_type == node_types::FOLDER)
+    }
+}
+
+/// Plan an output path for every node in the subtree, relative to the export root
+///
+/// Branch nodes (folders and anything with children) become directories
+/// holding an `index.{extension}` file; leaves become `{slug}.{extension}`.
+/// Sibling name clashes are resolved with a numeric suffix.
+pub fn plan_paths(tree: &ExportTree, extension: &str) -> HashMap<NodeId, PathBuf> {
+    let mut paths = HashMap::new();
+    let Some(root) = tree.get(&tree.root) else {
+        return paths;
+    };
+
+    if tree.is_branch(&tree.root) {
+        paths.insert(tree.root, PathBuf::from(format!("index.{extension}")));
+    } else {
+        let name = file_stem(root);
+        paths.insert(tree.root, PathBuf::from(format!("{name}.{extension}")));
+        return paths;
+    }
+
+    let mut stack = vec![(tree.root, PathBuf::new())];
+    while let Some((parent_id, dir)) = stack.pop() {
+        let Some(parent) = tree.get(&parent_id) else {
+            continue;
+        };
+        let mut used = HashSet::new();
+        for child_id in &parent.children {
+            let Some(child) = tree.get(child_id) else {
+                continue;
+            };
+            let stem = file_stem(child);
+            let mut name = stem.clone();
+            let mut n = 2;
+            while !used.insert(name.clone()) {
+                name = format!("{stem}-{n}");
+                n += 1;
+            }
+
+            if tree.is_branch(child_id) {
+                let child_dir = dir.join(&name);
+                paths.insert(*child_id, child_dir.join(format!("index.{extension}")));
+                stack.push((*child_id, child_dir));
+            } else {
+                paths.insert(*child_id, dir.join(format!("{name}.{extension}")));
+            }
+        }
+    }
+
+    paths
+}
+
+fn file_stem(node: &Node) -> String {
+    // "index" is reserved for branch nodes' own content
+    match slugify(&display_title(node)).as_str() {
+        "" => "untitled".to_string(),
+        "index" => "index-page".to_string(),
+        slug => slug.to_string(),
+    }
+}
+
+/// Summary of an export run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportReport {
+    /// Files written (relative to the output directory)
+    pub written: Vec<PathBuf>,
+
+    /// Files skipped because their content was unchanged
+    pub unchanged: usize,
+
+    /// Stale files removed from a previous export
+    pub removed: Vec<PathBuf>,
+
+    /// Asset files copied
+    pub assets_copied: usize,
+}
+
+/// Record of what an export wrote, kept in the output directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ExportManifest {
+    /// Schema version for forward compatibility
+    version: u32,
+
+    /// Every file written by the export (relative paths)
+    files: BTreeSet<PathBuf>,
+}
+
+impl ExportManifest {
+    const FILE_NAME: &'static str = ".pimble-export.json";
+    const CURRENT_VERSION: u32 = 1;
+}
+
+/// Writes export output, skipping unchanged files and pruning stale ones
+pub(crate) struct ExportWriter {
+    out_dir: PathBuf,
+    incremental: bool,
+    previous: ExportManifest,
+    current: ExportManifest,
+    report: ExportReport,
+}
+
+impl ExportWriter {
+    /// Prepare to write into `out_dir`, reading the previous export's manifest
+    pub(crate) async fn open(out_dir: &Path, incremental: bool) -> Result<Self> {
+        fs::create_dir_all(out_dir).await?;
+
+        let manifest_path = out_dir.join(ExportManifest::FILE_NAME);
+        let previous = match fs::read_to_string(&manifest_path).await {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_default(),
+            Err(_) => ExportManifest::default(),
+        };
+
+        Ok(Self {
+            out_dir: out_dir.to_path_buf(),
+            incremental,
+            previous,
+            current: ExportManifest {
+                version: ExportManifest::CURRENT_VERSION,
+                files: BTreeSet::new(),
+            },
+            report: ExportReport::default(),
+        })
+    }
+
+    /// Write a file; in incremental mode identical files are left untouched
+    pub(crate) async fn write(&mut self, rel: &Path, bytes: &[u8]) -> Result<()> {
+        let path = self.out_dir.join(rel);
+        self.current.files.insert(rel.to_path_buf());
+
+        if self.incremental {
+            if let Ok(existing) = fs::read(&path).await {
+                if existing == bytes {
+                    self.report.unchanged += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, bytes).await?;
+        self.report.written.push(rel.to_path_buf());
+        Ok(())
+    }
+
+    /// Copy an asset file into the export
+    pub(crate) async fn copy_asset(&mut self, src: &Path, rel: &Path) -> Result<()> {
+        if self.current.files.contains(rel) {
+            return Ok(());
+        }
+        let bytes = fs::read(src).await?;
+        let before = self.report.written.len();
+        self.write(rel, &bytes).await?;
+        if self.report.written.len() > before {
+            self.report.written.pop();
+            self.report.assets_copied += 1;
+        }
+        Ok(())
+    }
+
+    /// Copy the assets a page references from the store's assets directory
+    ///
+    /// Assets that don't exist in the store are skipped.
+    pub(crate) async fn copy_assets(&mut self, assets_dir: &Path, assets: &[PathBuf]) -> Result<()> {
+        for asset in assets {
+            let src = assets_dir.join(asset);
+            if src.exists() {
+                self.copy_asset(&src, &exported_asset(asset)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// In incremental mode, remove files from the previous export that
+    /// weren't written this time; then record the new manifest and return
+    /// the report
+    ///
+    /// Only manifest entries that stay inside the output directory are
+    /// ever removed. Without `incremental` stale files are left alone and
+    /// kept in the manifest, so a later incremental export prunes them.
+    pub(crate) async fn finish(mut self) -> Result<ExportReport> {
+        let stale: Vec<PathBuf> = self
+            .previous
+            .files
+            .difference(&self.current.files)
+            .filter(|rel| is_confined(rel))
+            .cloned()
+            .collect();
+
+        if !self.incremental {
+            self.current.files.extend(stale);
+            let json = serde_json::to_string_pretty(&self.current)?;
+            fs::write(self.out_dir.join(ExportManifest::FILE_NAME), json).await?;
+            return Ok(self.report);
+        }
+
+        for rel in stale {
+            let path = self.out_dir.join(&rel);
+            if fs::remove_file(&path).await.is_ok() {
+                debug!("Removed stale export file {:?}", rel);
+                // Drop directories the removal left empty
+                let mut dir = path.parent();
+                while let Some(d) = dir {
+                    if d == self.out_dir || fs::remove_dir(d).await.is_err() {
+                        break;
+                    }
+                    dir = d.parent();
+                }
+                self.report.removed.push(rel);
+            }
+        }
+
+        let json = serde_json::to_string_pretty(&self.current)?;
+        fs::write(self.out_dir.join(ExportManifest::FILE_NAME), json).await?;
+        Ok(self.report)
+    }
+}
+
+/// Check that a relative path names something strictly below its base
+///
+/// Rejects absolute paths and any `..` or `.` component.
+fn is_confined(rel: &Path) -> bool {
+    rel.components().next().is_some() && rel.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// The asset a content URL refers to, as a path below the assets directory
+///
+/// Returns `None` for URLs that aren't asset references, and for asset
+/// paths that would leave the assets directory (`assets/../notes`).
+pub fn asset_path(url: &str) -> Option<PathBuf> {
+    let rel = Path::new(url.strip_prefix(ASSETS_PREFIX)?);
+    is_confined(rel).then(|| rel.to_path_buf())
+}
+
+/// Path of an asset within the export, relative to the output directory
+pub(crate) fn exported_asset(asset: &Path) -> PathBuf {
+    Path::new(ASSETS_DIR).join(asset)
+}
+
+/// Extract the markdown text of a node's content
+///
+/// Content is read as a `DocumentContent` and its blocks and marks rendered
+/// as markdown; anything that doesn't load is treated as raw UTF-8.
+pub fn node_text(node: &Node) -> String {
+    if node.content.is_empty() {
+        return String::new();
+    }
+    match DocumentContent::load(&node.content) {
+        Ok(doc) => doc
+            .to_markdown()
+            .unwrap_or_else(|_| String::from_utf8_lossy(&node.content).to_string()),
+        Err(_) => String::from_utf8_lossy(&node.content).to_string(),
+    }
+}
+
+/// Title used for a node in exported output
+pub fn display_title(node: &Node) -> String {
+    if !node.metadata.title.is_empty() {
+        return node.metadata.title.clone();
+    }
+    node_text(node)
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(|l| l.trim_start_matches('#').trim().to_string())
+        .filter(|l| !l.is_empty())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+/// Turn arbitrary text into a lowercase, hyphen-separated slug
+///
+/// Used both for file names and heading fragments, so it only keeps
+/// alphanumerics and collapses everything else into single hyphens.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    let mut pending_hyphen = false;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if pending_hyphen && !slug.is_empty() {
+                slug.push('-');
+            }
+            pending_hyphen = false;
+            slug.extend(c.to_lowercase());
+        } else {
+            pending_hyphen = true;
+        }
+    }
+    slug
+}
+
+/// Compute a `/`-separated path to `to` relative to the directory containing `from`
+///
+/// Both paths are relative to the same export root.
+pub fn relative_path(from: &Path, to: &Path) -> String {
+    let from_dir: Vec<Component> = from
+        .parent()
+        .map(|p| p.components().collect())
+        .unwrap_or_default();
+    let to_parts: Vec<Component> = to.components().collect();
+
+    let common = from_dir
+        .iter()
+        .zip(to_parts.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut parts: Vec<String> = Vec::new();
+    for _ in common..from_dir.len() {
+        parts.push("..".to_string());
+    }
+    for part in &to_parts[common..] {
+        parts.push(part.as_os_str().to_string_lossy().to_string());
+    }
+    parts.join("/")
+}
+
+/// A link target found inside markdown content
+#[derive(Debug, Clone)]
+pub struct ContentLink {
+    /// Byte range of the URL within the text
+    pub range: Range<usize>,
+
+    /// The raw URL as written
+    pub url: String,
+}
+
+impl ContentLink {
+    /// Parse the URL as an internal link target, if it is one
+    pub fn target(&self) -> Option<LinkTarget> {
+        LinkTarget::parse_uri(&self.url).filter(|t| t.node_id().is_some())
+    }
+}
+
+/// Find the URL portion of every inline markdown link or image in `text`
+///
+/// Only the `[label](url)` form is recognised; reference-style links are
+/// left alone.
+pub fn find_links(text: &str) -> Vec<ContentLink> {
+    let mut links = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(offset) = text[search_from..].find("](") {
+        let start = search_from + offset + 2;
+        let Some(len) = text[start..].find([')', '\n']) else {
+            break;
+        };
+        let end = start + len;
+        if text[end..].starts_with(')') {
+            // Strip an optional link title: [a](url "title")
+            let raw = &text[start..end];
+            let url_len = raw.find(' ').unwrap_or(raw.len());
+            links.push(ContentLink {
+                range: start..start + url_len,
+                url: raw[..url_len].to_string(),
+            });
+        }
+        search_from = end;
+    }
+
+    links
+}
+
+/// Rewrite link URLs in markdown text
+///
+/// `rewrite` is called for every link; returning `Some` replaces its URL.
+pub fn rewrite_links(text: &str, mut rewrite: impl FnMut(&ContentLink) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for link in find_links(text) {
+        if let Some(replacement) = rewrite(&link) {
+            out.push_str(&text[last..link.range.start]);
+            out.push_str(&replacement);
+            last = link.range.end;
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Resolve a deep-link anchor to a heading fragment in the target's text
+///
+/// Supported anchor forms:
+/// - `heading:{text}` — the heading itself
+/// - `text:{start}-{end}` — the nearest heading before character `start`
+/// - `paragraph:{n}` — the nearest heading before the n-th block (0-based)
+///
+/// Returns `None` when no heading precedes the anchored location.
+pub fn anchor_fragment(target_text: &str, anchor: &str) -> Option<String> {
+    let (kind, value) = anchor.split_once(':')?;
+    match kind {
+        "heading" => Some(slugify(value)).filter(|s| !s.is_empty()),
+        "text" => {
+            let start: usize = value.split('-').next()?.trim().parse().ok()?;
+            let byte_offset = target_text
+                .char_indices()
+                .nth(start)
+                .map(|(i, _)| i)
+                .unwrap_or(target_text.len());
+            heading_before(target_text, byte_offset)
+        }
+        "paragraph" => {
+            let index: usize = value.trim().parse().ok()?;
+            let mut byte_offset = None;
+            let mut block = 0;
+            let mut in_block = false;
+            let mut pos = 0;
+            for line in target_text.split_inclusive('\n') {
+                if line.trim().is_empty() {
+                    in_block = false;
+                } else if !in_block {
+                    if block == index {
+                        byte_offset = Some(pos);
+                        break;
+                    }
+                    block += 1;
+                    in_block = true;
+                }
+                pos += line.len();
+            }
+            heading_before(target_text, byte_offset? + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Slug of the last ATX heading that starts before `byte_offset`
+fn heading_before(text: &str, byte_offset: usize) -> Option<String> {
+    let mut pos = 0;
+    let mut found = None;
+    for line in text.split_inclusive('\n') {
+        if pos >= byte_offset {
+            break;
+        }
+        if let Some(title) = heading_text(line) {
+            found = Some(slugify(title));
+        }
+        pos += line.len();
+    }
+    found.filter(|s| !s.is_empty())
+}
+
+/// Text of an ATX heading line (`# Title`), if the line is one
+pub fn heading_text(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+    if hashes == 0 || hashes > 6 {
+        return None;
+    }
+    let rest = &trimmed[hashes..];
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim().trim_end_matches('#').trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Meeting notes 2024/05 "), "meeting-notes-2024-05");
+        assert_eq!(slugify("***"), "");
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let rel = relative_path(Path::new("a/b/note.md"), Path::new("a/c/other.md"));
+        assert_eq!(rel, "../c/other.md");
+        let rel = relative_path(Path::new("index.md"), Path::new("a/note.md"));
+        assert_eq!(rel, "a/note.md");
+        let rel = relative_path(Path::new("a/note.md"), Path::new("a/sibling.md"));
+        assert_eq!(rel, "sibling.md");
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let id = NodeId::new();
+        let text = format!("See [this](pimble://{id}#heading:Intro) and [web](https://example.com \"t\").");
+        let out = rewrite_links(&text, |link| {
+            link.target().map(|_| "other.md#intro".to_string())
+        });
+        assert_eq!(out, "See [this](other.md#intro) and [web](https://example.com \"t\").");
+    }
+
+    #[test]
+    fn test_asset_path() {
+        assert_eq!(asset_path("assets/img/a.png"), Some(PathBuf::from("img/a.png")));
+        assert_eq!(asset_path("assets/../../x"), None);
+        assert_eq!(asset_path("assets//etc/passwd"), None);
+        assert_eq!(asset_path("assets/"), None);
+        assert_eq!(asset_path("https://example.com/a.png"), None);
+    }
+
+    #[test]
+    fn test_anchor_fragment() {
+        let text = "# Intro\n\nFirst para.\n\n## Details\n\nSecond para.\n";
+        assert_eq!(anchor_fragment(text, "heading:Details").as_deref(), Some("details"));
+        assert_eq!(anchor_fragment(text, "paragraph:1").as_deref(), Some("intro"));
+        assert_eq!(anchor_fragment(text, "paragraph:3").as_deref(), Some("details"));
+        assert_eq!(anchor_fragment(text, "text:30-35").as_deref(), Some("details"));
+        assert_eq!(anchor_fragment("no headings", "text:3-4"), None);
+    }
+}
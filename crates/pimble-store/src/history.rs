@@ -0,0 +1,168 @@
+//! Browsing and restoring earlier versions of a node
+//!
+//! A version is named by the heads of the node's content at the time, as
+//! hex change hashes; each [`HistoryEntry`] hash names the version that
+//! change produced. Restoring writes the old content as a new change, so
+//! the restore itself shows up in history and replicates like any edit.
+//! Two versions can also be diffed, for reviewing what changed between them.
+
+use pimble_core::{HistoryEntry, Node, NodeDiff, NodeId};
+use pimble_crdt::{DocumentContent, MetadataContent};
+
+use crate::error::Result;
+use crate::local::LocalStore;
+
+/// List the changes to a node's content, oldest first
+///
+/// Each entry's author is the name registered for the device that made
+/// the change, if there is one.
+pub async fn node_history(store: &mut LocalStore, node_id: NodeId) -> Result<Vec<HistoryEntry>> {
+    let mut history = store.get_node_document(node_id).await?.history();
+    for entry in &mut history {
+        entry.author = store.author_of(&entry.actor);
+    }
+    Ok(history)
+}
+
+/// Get a node as it was at `heads`, along with its text at that version
+///
+/// The node's content is replaced by the content at that version and its
+/// metadata view is derived from it; everything else is as it is now.
+pub async fn node_at_version(store: &mut LocalStore, node_id: NodeId, heads: &[String]) -> Result<(Node, String)> {
+    let mut doc = store.get_node_document(node_id).await?;
+    let heads = doc.parse_heads(heads)?;
+    let mut node = store.get_node(node_id).await?.clone();
+    node.content = doc.at(&heads)?.save();
+    let text = DocumentContent::from_document(doc).text_at(&heads)?;
+    MetadataContent::load(&node.content)?.apply_to(&mut node.metadata)?;
+    Ok((node, text))
+}
+
+/// Restore a node's content to its state at `heads`
+///
+/// The node's metadata is left as it is now.
+pub async fn restore_node_version(store: &mut LocalStore, node_id: NodeId, heads: &[String]) -> Result<()> {
+    let mut content = DocumentContent::from_document(store.get_node_document(node_id).await?);
+    let heads = content.document_mut().parse_heads(heads)?;
+    content.restore(&heads)?;
+    store.update_node_content(node_id, content.save()).await
+}
+
+/// Diff a node's text between the versions at `from` and `to`
+///
+/// `to` defaults to the current version. The unified diff is labelled
+/// with the node's title.
+pub async fn diff_node_versions(
+    store: &mut LocalStore,
+    node_id: NodeId,
+    from: &[String],
+    to: Option<&[String]>,
+) -> Result<NodeDiff> {
+    let mut content = DocumentContent::from_document(store.get_node_document(node_id).await?);
+    let doc = content.document_mut();
+    let from = doc.parse_heads(from)?;
+    let to = match to {
+        Some(heads) => doc.parse_heads(heads)?,
+        None => doc.get_heads(),
+    };
+    let diff = content.diff(&from, &to)?;
+
+    let title = &store.get_node(node_id).await?.metadata.title;
+    Ok(NodeDiff {
+        unified: diff.unified(&format!("a/{}", title), &format!("b/{}", title)),
+        spans: diff.spans,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_browse_and_restore_versions() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("a.pimble"), "A").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Plan"), Some(root)).await.unwrap();
+
+        let mut content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        content.set_text("Draft").unwrap();
+        store.update_node_content(node_id, content.save()).await.unwrap();
+        let draft = vec![node_history(&mut store, node_id).await.unwrap().pop().unwrap().hash];
+
+        content.set_text("Final").unwrap();
+        store.update_node_content(node_id, content.save()).await.unwrap();
+        store.edit_node_metadata(node_id, &pimble_core::MetadataEdit::SetTitle { title: "Done".into() })
+            .await
+            .unwrap();
+
+        let (old, text) = node_at_version(&mut store, node_id, &draft).await.unwrap();
+        assert_eq!(text, "Draft");
+        assert_eq!(old.metadata.title, "Plan");
+        assert!(node_at_version(&mut store, node_id, &["nonsense".into()]).await.is_err());
+
+        restore_node_version(&mut store, node_id, &draft).await.unwrap();
+        let content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        assert_eq!(content.get_text().unwrap(), "Draft");
+        assert_eq!(store.get_node(node_id).await.unwrap().metadata.title, "Done");
+
+        let history = node_history(&mut store, node_id).await.unwrap();
+        assert!(history.last().unwrap().message.as_deref().unwrap().starts_with("Restore version"));
+    }
+
+    #[tokio::test]
+    async fn test_diff_versions() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("a.pimble"), "A").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Plan"), Some(root)).await.unwrap();
+
+        let mut content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        content.set_text("one\ntwo\n").unwrap();
+        store.update_node_content(node_id, content.save()).await.unwrap();
+        let first = vec![node_history(&mut store, node_id).await.unwrap().pop().unwrap().hash];
+
+        content.insert_text(4, "2\n").unwrap();
+        store.update_node_content(node_id, content.save()).await.unwrap();
+
+        let diff = diff_node_versions(&mut store, node_id, &first, None).await.unwrap();
+        assert_eq!(diff.spans.len(), 1);
+        assert_eq!(diff.spans[0].kind, pimble_core::DiffKind::Insert);
+        assert_eq!(diff.spans[0].text, "2\n");
+        assert_eq!(diff.spans[0].actors.len(), 1);
+        assert_eq!(diff.unified, "--- a/Plan\n+++ b/Plan\n@@ -1,2 +1,3 @@\n one\n+2\n two\n");
+
+        let empty = diff_node_versions(&mut store, node_id, &first, Some(&first)).await.unwrap();
+        assert!(empty.spans.is_empty() && empty.unified.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_history_names_authors() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.pimble");
+        let mut store = LocalStore::create(&path, "A").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Plan"), Some(root)).await.unwrap();
+
+        let device = pimble_crdt::DeviceId::random();
+        store.set_identity(device, Some("Laptop".into())).await.unwrap();
+        store.flush().await.unwrap();
+        let mut store = LocalStore::open(&path).await.unwrap();
+        assert_eq!(store.identities()[0].device_id, device.to_string());
+
+        let mut content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        content.document_mut().set_actor(device.new_actor());
+        content.set_text("Draft").unwrap();
+        store.update_node_content(node_id, content.save()).await.unwrap();
+
+        let history = node_history(&mut store, node_id).await.unwrap();
+        let last = history.last().unwrap();
+        assert_eq!(last.author.as_deref(), Some("Laptop"));
+        assert!(last.timestamp.is_some());
+
+        store.set_identity(device, None).await.unwrap();
+        assert!(store.identities().is_empty());
+        assert_eq!(node_history(&mut store, node_id).await.unwrap().last().unwrap().author, None);
+    }
+}
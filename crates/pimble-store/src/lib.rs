@@ -2,13 +2,39 @@
 //!
 //! This crate provides:
 //! - Local file-based store implementation
+//! - Remote stores proxied to other Pimble servers, with offline replicas
 //! - Store management (create, open, close)
-//! - Node persistence using Automerge documents
+//! - Node persistence using Automerge documents, saved incrementally and compacted
+//! - Export of subtrees to markdown folders and static HTML sites
+//! - OPML import and export of node outlines
+//! - Copying and moving subtrees between stores
+//! - Replicating stores with peers
+//! - Surfacing and resolving concurrent edits
+//! - Browsing and restoring earlier versions of nodes, with named authors
+//! - Per-session undo and redo of edits
 
+pub mod conflict;
 pub mod error;
+pub mod export;
+pub mod history;
 pub mod local;
 pub mod manager;
+pub mod opml;
+pub mod outbox;
+pub mod remote;
+pub mod replica;
+pub mod transfer;
+pub mod undo;
 
+pub use conflict::*;
 pub use error::*;
+pub use export::*;
+pub use history::*;
 pub use local::*;
 pub use manager::*;
+pub use opml::*;
+pub use outbox::*;
+pub use remote::*;
+pub use replica::*;
+pub use transfer::*;
+pub use undo::*;
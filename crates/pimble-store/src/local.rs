@@ -3,10 +3,18 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use pimble_core::{Node, NodeId, StoreId, StoreManifest};
-use pimble_crdt::CrdtDocument;
+use chrono::{DateTime, Utc};
+use pimble_core::{
+    ChangeType, CompactionReport, HistoryMode, Identity, MetadataEdit, MigrationReport, Node, NodeChange, NodeId,
+    NodeMetadata, NodeMigration, StoreId, StoreManifest, Tombstone,
+};
+use pimble_crdt::{
+    identify_format, CrdtDocument, DeviceId, MetadataContent, MigrationRegistry, PeerSyncState, TreeDocument,
+};
 use tokio::fs;
-use tracing::{debug, info};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
 
 use crate::error::{Result, StoreError};
 
@@ -16,13 +24,38 @@ use crate::error::{Result, StoreError};
 /// ```text
 /// store.pimble/
 /// ├── manifest.json           # Store metadata
+/// ├── tree.automerge          # Tree structure (parents and child order)
+/// ├── tombstones.json         # Deleted node IDs, for replication
+/// ├── identities.json         # Display names of devices writing to the store
 /// ├── nodes/
+/// │   ├── {node-id}.json      # Node record
 /// │   ├── {node-id}.automerge # One Automerge doc per node
 /// │   └── ...
 /// ├── assets/                 # Binary files
 /// │   └── {hash}.{ext}
+/// ├── sync/                   # Per-peer sync state (created on first sync)
+/// │   └── {peer-id}/{node-id}.state
 /// └── index/                  # Search indexes (future)
 /// ```
+///
+/// The tree structure is authoritative: each node's `parent_id` and
+/// `children` fields are kept in step with it, so concurrent moves made on
+/// different replicas resolve the same way once the trees are merged.
+/// Likewise a node's title, tags and custom fields live in its content
+/// document, and its `metadata` is refreshed from there whenever the
+/// content changes.
+///
+/// Content in an old format is upgraded as it's loaded, and written back
+/// on the next flush.
+///
+/// Flushing a node's content appends the changes made since its file was
+/// last written, rather than saving its whole history again. After enough
+/// appends the file is rewritten in full; [`LocalStore::compact_store`]
+/// does the same for every node, and drops the history of nodes whose
+/// [`HistoryMode`] asks for it.
+///
+/// Every change to a node is announced on the channel given to
+/// [`LocalStore::set_change_sender`], if any, as it is made.
 pub struct LocalStore {
     /// Store ID
     pub id: StoreId,
@@ -38,6 +71,47 @@ pub struct LocalStore {
 
     /// Dirty nodes that need saving
     dirty: std::collections::HashSet<NodeId>,
+
+    /// When each deleted node was deleted
+    tombstones: HashMap<NodeId, DateTime<Utc>>,
+
+    /// Display names of devices, by device ID
+    identities: HashMap<DeviceId, String>,
+
+    /// Tree structure
+    tree: TreeDocument,
+
+    /// Whether the tree has changes to save
+    tree_dirty: bool,
+
+    /// Upgrades for content in old formats
+    migrations: MigrationRegistry,
+
+    /// What each node's content file holds, for nodes written or read this
+    /// session
+    content_files: HashMap<NodeId, ContentFile>,
+
+    /// Nodes' content as documents, kept in step with the content bytes so
+    /// merges and saves don't have to load it again
+    documents: HashMap<NodeId, CrdtDocument>,
+
+    /// Where node changes are announced
+    changes: Option<broadcast::Sender<NodeChange>>,
+}
+
+/// The state of a node's content file on disk
+struct ContentFile {
+    /// Heads of the content in the file, in hex
+    heads: Vec<String>,
+
+    /// Size of the file when it was last written in full
+    base_len: u64,
+
+    /// Bytes appended since
+    appended_len: u64,
+
+    /// Change chunks appended since
+    chunks: usize,
 }
 
 impl LocalStore {
@@ -45,7 +119,18 @@ impl LocalStore {
     const NODES_DIR: &'static str = "nodes";
     const ASSETS_DIR: &'static str = "assets";
     const INDEX_DIR: &'static str = "index";
+    const SYNC_DIR: &'static str = "sync";
     const MANIFEST_FILE: &'static str = "manifest.json";
+    const TOMBSTONES_FILE: &'static str = "tombstones.json";
+    const IDENTITIES_FILE: &'static str = "identities.json";
+    const TREE_FILE: &'static str = "tree.automerge";
+
+    /// Appends to a content file before it is rewritten in full
+    const MAX_APPENDED_CHUNKS: usize = 32;
+
+    /// Bytes that may be appended to a content file before it is rewritten
+    /// in full, unless the file was bigger than this to begin with
+    const MAX_APPENDED_LEN: u64 = 64 * 1024;
 
     /// Create a new local store at the given path
     pub async fn create(path: impl AsRef<Path>, name: impl Into<String>) -> Result<Self> {
@@ -57,11 +142,7 @@ impl LocalStore {
             return Err(StoreError::StoreExists(path.display().to_string()));
         }
 
-        // Create directory structure
-        fs::create_dir_all(&path).await?;
-        fs::create_dir(path.join(Self::NODES_DIR)).await?;
-        fs::create_dir(path.join(Self::ASSETS_DIR)).await?;
-        fs::create_dir(path.join(Self::INDEX_DIR)).await?;
+        Self::create_dirs(&path).await?;
 
         // Create root node
         let root_node = Node::folder(&name);
@@ -80,9 +161,18 @@ impl LocalStore {
             manifest,
             nodes: HashMap::new(),
             dirty: std::collections::HashSet::new(),
+            tombstones: HashMap::new(),
+            identities: HashMap::new(),
+            tree: TreeDocument::new(),
+            tree_dirty: true,
+            migrations: MigrationRegistry::default(),
+            content_files: HashMap::new(),
+            documents: HashMap::new(),
+            changes: None,
         };
 
         // Save root node
+        store.tree.insert(root_node_id, None, None)?;
         store.nodes.insert(root_node_id, root_node);
         store.dirty.insert(root_node_id);
         store.flush().await?;
@@ -107,14 +197,92 @@ impl LocalStore {
         let manifest_json = fs::read_to_string(&manifest_path).await?;
         let manifest: StoreManifest = serde_json::from_str(&manifest_json)?;
 
-        info!("Opened local store '{}' from {:?}", manifest.name, path);
+        let tombstones_path = path.join(Self::TOMBSTONES_FILE);
+        let tombstones = if tombstones_path.exists() {
+            let json = fs::read_to_string(&tombstones_path).await?;
+            let list: Vec<Tombstone> = serde_json::from_str(&json)?;
+            list.into_iter().map(|t| (t.node_id, t.deleted_at)).collect()
+        } else {
+            HashMap::new()
+        };
 
-        Ok(Self {
+        let identities_path = path.join(Self::IDENTITIES_FILE);
+        let identities = if identities_path.exists() {
+            let json = fs::read_to_string(&identities_path).await?;
+            let list: Vec<Identity> = serde_json::from_str(&json)?;
+            list.into_iter()
+                .filter_map(|i| Some((DeviceId::parse(&i.device_id)?, i.name)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        let tree_path = path.join(Self::TREE_FILE);
+        let tree = if tree_path.exists() {
+            TreeDocument::load(&fs::read(&tree_path).await?)?
+        } else {
+            TreeDocument::new()
+        };
+
+        let mut store = Self {
             id: manifest.id,
             path,
             manifest,
             nodes: HashMap::new(),
             dirty: std::collections::HashSet::new(),
+            tombstones,
+            identities,
+            tree_dirty: false,
+            tree,
+            migrations: MigrationRegistry::default(),
+            content_files: HashMap::new(),
+            documents: HashMap::new(),
+            changes: None,
+        };
+        if !tree_path.exists() {
+            store.seed_tree().await?;
+        }
+
+        info!("Opened local store '{}' from {:?}", store.manifest.name, store.path);
+        Ok(store)
+    }
+
+    /// Create an empty replica of a store that lives elsewhere
+    ///
+    /// The replica shares the original's ID and root node ID but has no
+    /// nodes yet; replication fills it in.
+    pub async fn create_replica(
+        path: impl AsRef<Path>,
+        store_id: StoreId,
+        name: impl Into<String>,
+        root_node_id: NodeId,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            return Err(StoreError::StoreExists(path.display().to_string()));
+        }
+        Self::create_dirs(&path).await?;
+
+        let mut manifest = StoreManifest::new(name, root_node_id);
+        manifest.id = store_id;
+        let manifest_json = serde_json::to_string_pretty(&manifest)?;
+        fs::write(path.join(Self::MANIFEST_FILE), manifest_json).await?;
+
+        info!("Created replica of store {} at {:?}", store_id, path);
+        Ok(Self {
+            id: store_id,
+            path,
+            manifest,
+            nodes: HashMap::new(),
+            dirty: std::collections::HashSet::new(),
+            tombstones: HashMap::new(),
+            identities: HashMap::new(),
+            tree: TreeDocument::new(),
+            tree_dirty: false,
+            migrations: MigrationRegistry::default(),
+            content_files: HashMap::new(),
+            documents: HashMap::new(),
+            changes: None,
         })
     }
 
@@ -128,22 +296,30 @@ impl LocalStore {
         self.manifest.root_node_id
     }
 
+    /// Get the directory holding the store's binary assets
+    pub fn assets_dir(&self) -> PathBuf {
+        self.path.join(Self::ASSETS_DIR)
+    }
+
+    /// Announce changes to nodes on `sender` from now on
+    pub fn set_change_sender(&mut self, sender: broadcast::Sender<NodeChange>) {
+        self.changes = Some(sender);
+    }
+
     /// Get a node by ID (loads from disk if not cached)
     pub async fn get_node(&mut self, node_id: NodeId) -> Result<&Node> {
-        if !self.nodes.contains_key(&node_id) {
-            let node = self.load_node(node_id).await?;
-            self.nodes.insert(node_id, node);
-        }
+        self.ensure_loaded(node_id).await?;
         self.nodes.get(&node_id).ok_or(StoreError::NodeNotFound(node_id))
     }
 
     /// Get a mutable node by ID
+    ///
+    /// Its content may be replaced, so its document is loaded afresh when
+    /// next needed.
     pub async fn get_node_mut(&mut self, node_id: NodeId) -> Result<&mut Node> {
-        if !self.nodes.contains_key(&node_id) {
-            let node = self.load_node(node_id).await?;
-            self.nodes.insert(node_id, node);
-        }
+        self.ensure_loaded(node_id).await?;
         self.dirty.insert(node_id);
+        self.documents.remove(&node_id);
         self.nodes.get_mut(&node_id).ok_or(StoreError::NodeNotFound(node_id))
     }
 
@@ -152,15 +328,38 @@ impl LocalStore {
         let node_id = node.id;
         node.parent_id = parent_id;
 
-        // Add to parent's children
+        // Bring the content up to date first; it may not even be a CRDT
+        // document yet, such as plain text
+        if let Some(migrated) = self.migrations.migrate(&node.content, &node.node_type)? {
+            node.content = migrated.content;
+        }
+
+        // Metadata lives in the content; copies arrive with theirs already
+        let mut meta = MetadataContent::load(&node.content)?;
+        if meta.has_metadata() {
+            meta.apply_to(&mut node.metadata)?;
+        } else {
+            meta.write(&node.metadata)?;
+            node.content = meta.save();
+        }
+
+        // Validate the parent, then add to its children
         if let Some(pid) = parent_id {
-            let parent = self.get_node_mut(pid).await?;
-            parent.add_child(node_id);
+            self.get_node(pid).await?;
+            self.ensure_in_tree(pid).await?;
         }
+        self.tree.insert(node_id, parent_id, None)?;
+        self.tree_dirty = true;
 
+        self.documents.remove(&node_id);
         self.nodes.insert(node_id, node);
         self.dirty.insert(node_id);
+        if let Some(pid) = parent_id {
+            self.apply_tree_to(&[pid]).await?;
+            self.get_node_mut(pid).await?.touch();
+        }
 
+        self.notify(node_id, ChangeType::Created, parent_id, None);
         debug!("Created node {} in store {}", node_id, self.id);
         Ok(node_id)
     }
@@ -178,21 +377,59 @@ impl LocalStore {
             let parent = self.get_node_mut(pid).await?;
             parent.remove_child(&node_id);
         }
+        if self.tree.contains(node_id) {
+            self.tree.remove(node_id)?;
+            self.tree_dirty = true;
+        }
 
-        // Remove node file
+        // Remove node files
         let node_path = self.node_path(node_id);
         if node_path.exists() {
             fs::remove_file(&node_path).await?;
         }
+        let content_path = self.node_content_path(node_id);
+        if content_path.exists() {
+            fs::remove_file(&content_path).await?;
+        }
+        self.content_files.remove(&node_id);
 
         // Remove from cache
+        self.documents.remove(&node_id);
         self.nodes.remove(&node_id);
         self.dirty.remove(&node_id);
+        self.tombstones.insert(node_id, Utc::now());
 
+        self.notify(node_id, ChangeType::Deleted, parent_id, None);
         debug!("Deleted node {} from store {}", node_id, self.id);
         Ok(())
     }
 
+    /// Put a deleted node back under its parent, at a position among its
+    /// siblings
+    ///
+    /// Children it had when it was deleted come back with it.
+    pub async fn restore_node(&mut self, node: Node, position: usize) -> Result<()> {
+        let node_id = node.id;
+        let parent_id = node.parent_id;
+        if let Some(pid) = parent_id {
+            self.get_node(pid).await?;
+            self.ensure_in_tree(pid).await?;
+        }
+        self.tree.insert(node_id, parent_id, Some(position))?;
+        self.tree_dirty = true;
+
+        self.put_node(node);
+        let mut affected = vec![node_id];
+        affected.extend(parent_id);
+        self.apply_tree_to(&affected).await?;
+        if let Some(pid) = parent_id {
+            self.get_node_mut(pid).await?.touch();
+        }
+
+        debug!("Restored node {} in store {}", node_id, self.id);
+        Ok(())
+    }
+
     /// Move a node to a new parent, optionally at a specific position
     pub async fn move_node(&mut self, node_id: NodeId, new_parent_id: NodeId, position: Option<usize>) -> Result<()> {
         // Get old parent_id from the node
@@ -227,44 +464,118 @@ impl LocalStore {
             }
         }
 
-        // Remove from old parent's children
-        {
-            let old_parent = self.get_node_mut(old_parent_id).await?;
-            old_parent.remove_child(&node_id);
+        // Record the move in the tree, then bring the affected nodes in step
+        for id in [node_id, old_parent_id, new_parent_id] {
+            self.ensure_in_tree(id).await?;
         }
-
-        // Add to new parent's children at position
-        {
-            let new_parent = self.get_node_mut(new_parent_id).await?;
-            match position {
-                Some(pos) => {
-                    let pos = pos.min(new_parent.children.len());
-                    if !new_parent.children.contains(&node_id) {
-                        new_parent.children.insert(pos, node_id);
-                        new_parent.touch();
-                    }
-                }
-                None => new_parent.add_child(node_id),
-            }
-        }
-
-        // Update node's parent_id
-        {
-            let node = self.get_node_mut(node_id).await?;
-            node.parent_id = Some(new_parent_id);
+        self.tree.move_node(node_id, new_parent_id, position)?;
+        self.tree_dirty = true;
+        self.apply_tree_to(&[node_id, old_parent_id, new_parent_id]).await?;
+
+        self.get_node_mut(old_parent_id).await?.touch();
+        if new_parent_id != old_parent_id {
+            self.get_node_mut(new_parent_id).await?.touch();
+        } else {
+            // Applying the tree only announces changes of parent
+            self.notify(node_id, ChangeType::Moved, Some(new_parent_id), Some(old_parent_id));
         }
 
         Ok(())
     }
 
-    /// Update a node's CRDT content
-    pub async fn update_node_content(&mut self, node_id: NodeId, content: Vec<u8>) -> Result<()> {
+    /// Replace a node's metadata
+    ///
+    /// Only the fields that differ are written to the content document, so
+    /// this merges with concurrent edits to other fields.
+    pub async fn update_node_metadata(&mut self, node_id: NodeId, metadata: NodeMetadata) -> Result<()> {
         let node = self.get_node_mut(node_id).await?;
-        node.content = content;
+        let mut meta = MetadataContent::load(&node.content)?;
+        meta.write(&metadata)?;
+        node.content = meta.save();
+        node.metadata = metadata;
+        meta.apply_to(&mut node.metadata)?;
         node.touch();
+        self.notify_updated(node_id);
         Ok(())
     }
 
+    /// Change one field of a node's metadata, returning the result
+    pub async fn edit_node_metadata(&mut self, node_id: NodeId, edit: &MetadataEdit) -> Result<NodeMetadata> {
+        let node = self.get_node_mut(node_id).await?;
+        let mut meta = MetadataContent::load(&node.content)?;
+        let seeded = !meta.has_metadata() && meta.write(&node.metadata)?;
+        if !meta.apply(edit)? && !seeded {
+            return Ok(node.metadata.clone());
+        }
+        node.content = meta.save();
+        meta.apply_to(&mut node.metadata)?;
+        node.touch();
+        let metadata = node.metadata.clone();
+        self.notify_updated(node_id);
+        Ok(metadata)
+    }
+
+    /// Update a node's CRDT content
+    pub async fn update_node_content(&mut self, node_id: NodeId, content: Vec<u8>) -> Result<()> {
+        let doc = CrdtDocument::load(&content)?;
+        self.set_content(node_id, content, doc, true).await
+    }
+
+    /// Replace a node's content without changing its modified time
+    ///
+    /// Used when merging content from a peer, where the node record itself
+    /// hasn't changed.
+    pub async fn merge_node_content(&mut self, node_id: NodeId, content: Vec<u8>) -> Result<()> {
+        let doc = CrdtDocument::load(&content)?;
+        self.set_content(node_id, content, doc, false).await
+    }
+
+    /// Merge CRDT changes into a node's content, returning its new heads
+    ///
+    /// Unlike [`LocalStore::update_node_content`], edits made here since the
+    /// sender last caught up are kept rather than written over. Only the new
+    /// changes are added to the node's content.
+    ///
+    /// Fails without changing anything if some changes build on changes the
+    /// node doesn't have, such as history dropped since the sender loaded it.
+    pub async fn apply_node_changes(&mut self, node_id: NodeId, changes: &[Vec<u8>]) -> Result<Vec<String>> {
+        let mut doc = self.take_document(node_id).await?;
+        let before = doc.get_heads();
+        doc.apply_encoded_changes(changes)?;
+        let missing = doc.missing_deps();
+        if !missing.is_empty() {
+            // The document is dropped, and the changes held back with it
+            return Err(StoreError::MissingChanges(missing.iter().map(|h| h.to_string()).collect()));
+        }
+        let heads = doc.get_heads();
+        if heads == before {
+            self.documents.insert(node_id, doc);
+        } else {
+            let mut content = std::mem::take(&mut self.get_node_mut(node_id).await?.content);
+            content.extend_from_slice(&doc.save_after(&before));
+            self.set_content(node_id, content, doc, true).await?;
+        }
+        Ok(heads.iter().map(|h| h.to_string()).collect())
+    }
+
+    /// Encode the changes to a node's content beyond `heads`, with its current heads
+    ///
+    /// Heads this store doesn't have are ignored, so a caller with changes
+    /// of its own gets everything it might be missing; it already has the
+    /// rest, and applying those again does nothing.
+    pub async fn get_node_changes_since(
+        &mut self,
+        node_id: NodeId,
+        heads: &[String],
+    ) -> Result<(Vec<Vec<u8>>, Vec<String>)> {
+        let mut doc = self.take_document(node_id).await?;
+        let known = doc.known_heads(heads);
+        let changes = doc.encode_changes_since(&known);
+        let heads = doc.get_heads().iter().map(|h| h.to_string()).collect();
+        self.documents.insert(node_id, doc);
+        Ok((changes, heads))
+    }
+
     /// Get a node's CRDT document
     pub async fn get_node_document(&mut self, node_id: NodeId) -> Result<CrdtDocument> {
         let node = self.get_node(node_id).await?;
@@ -283,12 +594,30 @@ impl LocalStore {
 
         for node_id in dirty {
             if let Some(node) = self.nodes.get(&node_id) {
-                self.save_node_to_disk(node).await?;
+                let file = self.content_files.remove(&node_id);
+                let mut doc = self.documents.remove(&node_id);
+                let saved = self.save_node_to_disk(node, doc.as_mut(), file).await;
+                if let Some(doc) = doc {
+                    self.documents.insert(node_id, doc);
+                }
+                if let Some(file) = saved? {
+                    self.content_files.insert(node_id, file);
+                }
             }
         }
 
         self.dirty.clear();
 
+        if !self.tombstones.is_empty() {
+            let json = serde_json::to_string_pretty(&self.tombstones())?;
+            fs::write(self.path.join(Self::TOMBSTONES_FILE), json).await?;
+        }
+
+        if self.tree_dirty {
+            fs::write(self.path.join(Self::TREE_FILE), self.tree.save()).await?;
+            self.tree_dirty = false;
+        }
+
         // Update manifest modified time
         self.manifest.modified_at = chrono::Utc::now();
         let manifest_json = serde_json::to_string_pretty(&self.manifest)?;
@@ -298,6 +627,115 @@ impl LocalStore {
         Ok(())
     }
 
+    /// Upgrade every node's content to the current version of its format
+    ///
+    /// Nodes upgraded when they were loaded earlier count as current here.
+    pub async fn migrate_store(&mut self) -> Result<MigrationReport> {
+        let mut report = MigrationReport::default();
+        for node_id in self.list_node_ids().await? {
+            let mut node = match self.nodes.remove(&node_id) {
+                Some(node) => node,
+                None => self.load_node(node_id).await?,
+            };
+            match self.migrate_content(&mut node)? {
+                Some(migration) => {
+                    report.migrated.push(migration);
+                    self.dirty.insert(node_id);
+                    self.documents.remove(&node_id);
+                }
+                None => match identify_format(&node.content, &node.node_type) {
+                    Some(format) if self.migrations.current_version(&format.id).is_some() => report.current += 1,
+                    _ => report.unrecognized.push(node_id),
+                },
+            }
+            self.nodes.insert(node_id, node);
+        }
+        self.flush().await?;
+        for migration in &report.migrated {
+            self.notify_updated(migration.node_id);
+        }
+
+        info!(
+            "Migrated {} nodes in store {} ({} current, {} unrecognized)",
+            report.migrated.len(),
+            self.id,
+            report.current,
+            report.unrecognized.len()
+        );
+        Ok(report)
+    }
+
+    /// Rewrite every node's content file in full
+    ///
+    /// Nodes whose history mode is [`HistoryMode::Truncate`] have their
+    /// content replaced by a snapshot of its current state first, unless
+    /// `keep_history` is set or the store has replicated with a peer: a
+    /// snapshot shares no history with other copies of the content, so
+    /// their changes could no longer be merged. Content that isn't an
+    /// Automerge document is left as it is.
+    pub async fn compact_store(&mut self, keep_history: bool) -> Result<CompactionReport> {
+        self.flush().await?;
+        let keep_history = keep_history || self.has_peers().await?;
+
+        let mut report = CompactionReport::default();
+        for node_id in self.list_node_ids().await? {
+            let content_path = self.node_content_path(node_id);
+            if !content_path.exists() {
+                continue;
+            }
+            let bytes_before = fs::metadata(&content_path).await?.len();
+            let node = self.get_node(node_id).await?;
+            let truncate = node.history == HistoryMode::Truncate;
+            let Ok(mut doc) = CrdtDocument::load(&node.content) else {
+                continue;
+            };
+
+            // A snapshot is a single change, so there's nothing to drop
+            if truncate && doc.history().len() > 1 && keep_history {
+                report.kept.push(node_id);
+            } else if truncate && doc.history().len() > 1 {
+                doc = doc.snapshot()?;
+                let content = doc.save();
+                if let Some(node) = self.nodes.get_mut(&node_id) {
+                    node.content = content;
+                }
+                self.notify_updated(node_id);
+                report.truncated.push(node_id);
+            }
+
+            let file = Self::write_content(&content_path, &mut doc, None).await?;
+            report.compacted += 1;
+            report.bytes_before += bytes_before;
+            report.bytes_after += file.base_len;
+            self.content_files.insert(node_id, file);
+            self.documents.insert(node_id, doc);
+        }
+
+        info!(
+            "Compacted {} content files in store {} ({} truncated, {} kept), {} bytes down to {}",
+            report.compacted,
+            self.id,
+            report.truncated.len(),
+            report.kept.len(),
+            report.bytes_before,
+            report.bytes_after
+        );
+        Ok(report)
+    }
+
+    /// Choose how much of a node's content history to keep
+    ///
+    /// Dropping history waits until the store is next compacted.
+    pub async fn set_history_mode(&mut self, node_id: NodeId, mode: HistoryMode) -> Result<()> {
+        let node = self.get_node_mut(node_id).await?;
+        if node.history != mode {
+            node.history = mode;
+            node.touch();
+            self.notify_updated(node_id);
+        }
+        Ok(())
+    }
+
     /// List all node IDs in the store
     pub async fn list_node_ids(&self) -> Result<Vec<NodeId>> {
         let nodes_dir = self.path.join(Self::NODES_DIR);
@@ -334,8 +772,313 @@ impl LocalStore {
         Ok(children)
     }
 
+    /// Check whether a node exists, in the cache or on disk
+    pub fn contains_node(&self, node_id: NodeId) -> bool {
+        self.nodes.contains_key(&node_id) || self.node_path(node_id).exists()
+    }
+
+    /// Insert a node record as-is, replacing any existing record
+    ///
+    /// Unlike [`LocalStore::create_node`] this doesn't touch the parent;
+    /// callers are responsible for keeping the tree consistent.
+    pub fn put_node(&mut self, node: Node) {
+        let change_type = if self.contains_node(node.id) {
+            ChangeType::Updated
+        } else {
+            ChangeType::Created
+        };
+        self.notify(node.id, change_type, node.parent_id, None);
+        self.tombstones.remove(&node.id);
+        self.dirty.insert(node.id);
+        self.documents.remove(&node.id);
+        self.nodes.insert(node.id, node);
+    }
+
+    /// Deleted nodes and when they were deleted
+    pub fn tombstones(&self) -> Vec<Tombstone> {
+        let mut list: Vec<Tombstone> = self
+            .tombstones
+            .iter()
+            .map(|(&node_id, &deleted_at)| Tombstone { node_id, deleted_at })
+            .collect();
+        list.sort_by_key(|t| t.deleted_at);
+        list
+    }
+
+    /// When a node was deleted, if it has been
+    pub fn deleted_at(&self, node_id: NodeId) -> Option<DateTime<Utc>> {
+        self.tombstones.get(&node_id).copied()
+    }
+
+    /// Record a deletion made elsewhere, keeping the latest time seen
+    pub fn record_tombstone(&mut self, tombstone: Tombstone) {
+        self.tombstones
+            .entry(tombstone.node_id)
+            .and_modify(|t| *t = (*t).max(tombstone.deleted_at))
+            .or_insert(tombstone.deleted_at);
+    }
+
+    /// Display names registered for devices, sorted by name
+    pub fn identities(&self) -> Vec<Identity> {
+        let mut list: Vec<Identity> = self
+            .identities
+            .iter()
+            .map(|(device, name)| Identity {
+                device_id: device.to_string(),
+                name: name.clone(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.device_id.cmp(&b.device_id)));
+        list
+    }
+
+    /// Register a display name for a device, or remove it with `None`
+    ///
+    /// The registry is written straight away.
+    pub async fn set_identity(&mut self, device: DeviceId, name: Option<String>) -> Result<()> {
+        match name {
+            Some(name) => self.identities.insert(device, name),
+            None => self.identities.remove(&device),
+        };
+        let json = serde_json::to_string_pretty(&self.identities())?;
+        fs::write(self.path.join(Self::IDENTITIES_FILE), json).await?;
+        Ok(())
+    }
+
+    /// Display name of the device that made changes as `actor`, in hex
+    pub fn author_of(&self, actor: &str) -> Option<String> {
+        DeviceId::of_actor(actor).and_then(|device| self.identities.get(&device).cloned())
+    }
+
+    /// Get the store's tree structure
+    pub fn tree(&self) -> &TreeDocument {
+        &self.tree
+    }
+
+    /// Save the tree structure to bytes, for sending to a peer
+    pub fn save_tree(&mut self) -> Vec<u8> {
+        self.tree.save()
+    }
+
+    /// Merge a peer's copy of the tree structure into ours
+    ///
+    /// Every node's `parent_id` and `children` are brought in step with the
+    /// merged tree, without touching their modified times.
+    pub async fn merge_tree(&mut self, tree: &mut TreeDocument) -> Result<()> {
+        self.tree.merge(tree)?;
+        self.tree_dirty = true;
+        self.apply_tree().await
+    }
+
+    /// Bring every node's `parent_id` and `children` in step with the tree
+    ///
+    /// Nodes the tree doesn't know about keep their fields, and children
+    /// that haven't arrived yet are left out until they do.
+    pub async fn apply_tree(&mut self) -> Result<()> {
+        let nodes = self.tree.nodes();
+        self.apply_tree_to(&nodes).await
+    }
+
+    /// Load the saved sync state for a peer and node
+    ///
+    /// Returns fresh state if nothing has been saved yet.
+    pub async fn load_peer_sync_state(&self, peer_id: &str, node_id: NodeId) -> Result<PeerSyncState> {
+        let path = self.peer_sync_path(peer_id, node_id);
+        match fs::read(&path).await {
+            Ok(bytes) => Ok(PeerSyncState::decode(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PeerSyncState::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save sync state for a peer and node so the next session resumes from it
+    pub async fn save_peer_sync_state(&self, peer_id: &str, node_id: NodeId, state: &PeerSyncState) -> Result<()> {
+        let path = self.peer_sync_path(peer_id, node_id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(&path, state.encode()).await?;
+        Ok(())
+    }
+
+    /// Whether the store has replicated with a peer
+    pub async fn has_peers(&self) -> Result<bool> {
+        let sync_dir = self.path.join(Self::SYNC_DIR);
+        if !sync_dir.exists() {
+            return Ok(false);
+        }
+        Ok(fs::read_dir(&sync_dir).await?.next_entry().await?.is_some())
+    }
+
     // Private helpers
 
+    /// Take a node's content document out, loading it if need be
+    ///
+    /// Put it back in `documents` while it's in step with the content.
+    async fn take_document(&mut self, node_id: NodeId) -> Result<CrdtDocument> {
+        match self.documents.remove(&node_id) {
+            Some(doc) => Ok(doc),
+            None => self.get_node_document(node_id).await,
+        }
+    }
+
+    /// Replace a node's content with `content`, which `doc` was loaded from
+    /// or saved as, and announce it
+    async fn set_content(&mut self, node_id: NodeId, content: Vec<u8>, doc: CrdtDocument, touch: bool) -> Result<()> {
+        let node = self.get_node_mut(node_id).await?;
+        node.content = content;
+        let meta = MetadataContent::from_document(doc);
+        meta.apply_to(&mut node.metadata)?;
+        if touch {
+            node.touch();
+        }
+        self.documents.insert(node_id, meta.into_document());
+        self.notify_updated(node_id);
+        Ok(())
+    }
+
+    /// Load a node into the cache if it isn't there, upgrading its content
+    async fn ensure_loaded(&mut self, node_id: NodeId) -> Result<()> {
+        if !self.nodes.contains_key(&node_id) {
+            let mut node = self.load_node(node_id).await?;
+            if self.migrate_content(&mut node)?.is_some() {
+                self.dirty.insert(node_id);
+            }
+            self.nodes.insert(node_id, node);
+        }
+        Ok(())
+    }
+
+    /// Upgrade a node's content to the current version of its format
+    ///
+    /// Content that fails to upgrade is left as it is, so the node still
+    /// loads.
+    fn migrate_content(&self, node: &mut Node) -> Result<Option<NodeMigration>> {
+        let migrated = match self.migrations.migrate(&node.content, &node.node_type) {
+            Ok(Some(migrated)) => migrated,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                warn!("Could not upgrade content of node {}: {}", node.id, e);
+                return Ok(None);
+            }
+        };
+        info!("Upgraded content of node {} from {} to {}", node.id, migrated.from, migrated.to);
+        node.content = migrated.content;
+
+        // Content from before metadata moved into it gets the record's
+        let mut meta = MetadataContent::load(&node.content)?;
+        if !meta.has_metadata() {
+            meta.write(&node.metadata)?;
+            node.content = meta.save();
+        }
+
+        Ok(Some(NodeMigration {
+            node_id: node.id,
+            from: migrated.from,
+            to: migrated.to,
+        }))
+    }
+
+    /// Announce a change to a node, if anyone is listening
+    fn notify(&self, node_id: NodeId, change_type: ChangeType, parent_id: Option<NodeId>, old_parent_id: Option<NodeId>) {
+        if let Some(sender) = &self.changes {
+            // No receivers just means nobody is subscribed
+            let _ = sender.send(NodeChange {
+                store_id: self.id,
+                node_id,
+                change_type,
+                parent_id,
+                old_parent_id,
+            });
+        }
+    }
+
+    /// Announce that a cached node's record or content changed
+    fn notify_updated(&self, node_id: NodeId) {
+        let parent_id = self.nodes.get(&node_id).and_then(|node| node.parent_id);
+        self.notify(node_id, ChangeType::Updated, parent_id, None);
+    }
+
+    /// Build the tree from the nodes' own fields, for stores created before
+    /// the tree was kept
+    async fn seed_tree(&mut self) -> Result<()> {
+        let root = self.root_node_id();
+        if !self.contains_node(root) {
+            return Ok(());
+        }
+        let mut stack = vec![(root, None)];
+        while let Some((node_id, parent)) = stack.pop() {
+            self.tree.insert(node_id, parent, None)?;
+            let children = self.get_node(node_id).await?.children.clone();
+            for child in children.into_iter().rev() {
+                if self.contains_node(child) && !self.tree.contains(child) {
+                    stack.push((child, Some(node_id)));
+                }
+            }
+        }
+        self.tree_dirty = true;
+        Ok(())
+    }
+
+    /// Add a node to the tree where its own record says it is, if the tree
+    /// doesn't have it yet
+    async fn ensure_in_tree(&mut self, node_id: NodeId) -> Result<()> {
+        if !self.tree.contains(node_id) {
+            let parent = self.get_node(node_id).await?.parent_id;
+            self.tree.insert(node_id, parent, None)?;
+            self.tree_dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Copy tree structure into the given nodes' fields
+    async fn apply_tree_to(&mut self, node_ids: &[NodeId]) -> Result<()> {
+        for &node_id in node_ids {
+            if !self.tree.contains(node_id) || !self.contains_node(node_id) {
+                continue;
+            }
+            let parent_id = self.tree.parent(node_id);
+            let mut children: Vec<NodeId> = self
+                .tree
+                .children(node_id)
+                .into_iter()
+                .filter(|c| self.contains_node(*c))
+                .collect();
+            // Children the tree hasn't heard of yet stay, after the rest
+            let recorded = self.get_node(node_id).await?.children.clone();
+            children.extend(recorded.into_iter().filter(|c| !self.tree.contains(*c)));
+            let node = self.get_node(node_id).await?;
+            if node.parent_id != parent_id || node.children != children {
+                let old_parent_id = node.parent_id;
+                let node = self.get_node_mut(node_id).await?;
+                node.parent_id = parent_id;
+                node.children = children;
+                if old_parent_id.is_some() && old_parent_id != parent_id {
+                    self.notify(node_id, ChangeType::Moved, parent_id, old_parent_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_dirs(path: &Path) -> Result<()> {
+        fs::create_dir_all(path).await?;
+        fs::create_dir(path.join(Self::NODES_DIR)).await?;
+        fs::create_dir(path.join(Self::ASSETS_DIR)).await?;
+        fs::create_dir(path.join(Self::INDEX_DIR)).await?;
+        Ok(())
+    }
+
+    fn peer_sync_path(&self, peer_id: &str, node_id: NodeId) -> PathBuf {
+        // Peer IDs come from the network; hex keeps them safe file names
+        // without letting two peers share one
+        let peer_dir: String = peer_id.bytes().map(|b| format!("{b:02x}")).collect();
+        self.path
+            .join(Self::SYNC_DIR)
+            .join(peer_dir)
+            .join(format!("{}.state", node_id))
+    }
+
     fn node_path(&self, node_id: NodeId) -> PathBuf {
         self.path.join(Self::NODES_DIR).join(format!("{}.json", node_id))
     }
@@ -364,7 +1107,17 @@ impl LocalStore {
         Ok(node)
     }
 
-    async fn save_node_to_disk(&self, node: &Node) -> Result<()> {
+    /// Write a node to disk, given its content document if there is one
+    /// and what its content file last held
+    ///
+    /// Returns what the content file holds now, if it's an Automerge
+    /// document.
+    async fn save_node_to_disk(
+        &self,
+        node: &Node,
+        doc: Option<&mut CrdtDocument>,
+        mut file: Option<ContentFile>,
+    ) -> Result<Option<ContentFile>> {
         let node_path = self.node_path(node.id);
 
         // Save node metadata (without content for cleaner JSON)
@@ -377,17 +1130,85 @@ impl LocalStore {
         // Save content separately if not empty
         if !content.is_empty() {
             let content_path = self.node_content_path(node.id);
-            fs::write(&content_path, &content).await?;
+            let mut loaded = if doc.is_none() { CrdtDocument::load(&content).ok() } else { None };
+            let doc = doc.or(loaded.as_mut());
+            file = match doc {
+                Some(doc) => {
+                    if file.is_none() {
+                        file = Self::read_content_file(&content_path).await?;
+                    }
+                    Some(Self::write_content(&content_path, doc, file).await?)
+                }
+                None => {
+                    fs::write(&content_path, &content).await?;
+                    None
+                }
+            };
         }
 
         debug!("Saved node {} to disk", node.id);
-        Ok(())
+        Ok(file)
+    }
+
+    /// Read the state of a content file written in an earlier session
+    async fn read_content_file(path: &Path) -> Result<Option<ContentFile>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(path).await?;
+        Ok(CrdtDocument::load(&bytes).ok().map(|mut doc| ContentFile {
+            heads: doc.get_heads().iter().map(|h| h.to_string()).collect(),
+            base_len: bytes.len() as u64,
+            appended_len: 0,
+            chunks: 0,
+        }))
+    }
+
+    /// Bring a content file up to date with `doc`
+    ///
+    /// The changes since the file was written are appended, unless the file
+    /// has had enough appends or no longer shares history with `doc`, when
+    /// it is rewritten in full.
+    async fn write_content(path: &Path, doc: &mut CrdtDocument, file: Option<ContentFile>) -> Result<ContentFile> {
+        let heads: Vec<String> = doc.get_heads().iter().map(|h| h.to_string()).collect();
+        if let Some(file) = file {
+            if file.heads == heads {
+                return Ok(file);
+            }
+            let known = doc.known_heads(&file.heads);
+            let appendable = known.len() == file.heads.len()
+                && file.chunks < Self::MAX_APPENDED_CHUNKS
+                && file.appended_len < file.base_len.max(Self::MAX_APPENDED_LEN);
+            if appendable {
+                let chunk = doc.save_after(&known);
+                let mut out = fs::OpenOptions::new().append(true).open(path).await?;
+                out.write_all(&chunk).await?;
+                out.flush().await?;
+                return Ok(ContentFile {
+                    heads,
+                    appended_len: file.appended_len + chunk.len() as u64,
+                    chunks: file.chunks + 1,
+                    ..file
+                });
+            }
+        }
+
+        let bytes = doc.save();
+        fs::write(path, &bytes).await?;
+        Ok(ContentFile {
+            heads,
+            base_len: bytes.len() as u64,
+            appended_len: 0,
+            chunks: 0,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pimble_core::ContentFormat;
+    use pimble_crdt::{DocumentContent, DOCUMENT_FORMAT, TEXT_FORMAT};
     use tempfile::tempdir;
 
     #[tokio::test]
@@ -414,6 +1235,71 @@ mod tests {
         assert_eq!(store.id, store_id);
     }
 
+    #[tokio::test]
+    async fn test_apply_node_changes_merges_concurrent_edits() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Shared"), Some(root)).await.unwrap();
+
+        let mut alice = store.get_node_document(node_id).await.unwrap();
+        let mut bob = store.get_node_document(node_id).await.unwrap();
+        let known = alice.get_heads();
+        alice.set_string("a", "from alice").unwrap();
+        bob.set_string("b", "from bob").unwrap();
+
+        store.apply_node_changes(node_id, &alice.encode_changes_since(&known)).await.unwrap();
+        let heads = store.apply_node_changes(node_id, &bob.encode_changes_since(&known)).await.unwrap();
+
+        let mut merged = store.get_node_document(node_id).await.unwrap();
+        assert_eq!(merged.get_string("a").unwrap(), Some("from alice".to_string()));
+        assert_eq!(merged.get_string("b").unwrap(), Some("from bob".to_string()));
+        assert_eq!(heads, merged.get_heads().iter().map(|h| h.to_string()).collect::<Vec<_>>());
+
+        let known: Vec<String> = known.iter().map(|h| h.to_string()).collect();
+        let (changes, server_heads) = store.get_node_changes_since(node_id, &known).await.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(server_heads, heads);
+        alice.apply_encoded_changes(&changes).unwrap();
+        assert_eq!(alice.get_string("b").unwrap(), Some("from bob".to_string()));
+
+        // The appended changes are saved too
+        store.flush().await.unwrap();
+        let mut reopened = LocalStore::open(dir.path().join("test.pimble")).await.unwrap();
+        let mut saved = reopened.get_node_document(node_id).await.unwrap();
+        assert_eq!(saved.get_heads(), merged.get_heads());
+    }
+
+    #[tokio::test]
+    async fn test_peer_sync_state_persists() {
+        let dir = tempdir().unwrap();
+        let store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let node_id = store.root_node_id();
+
+        let mut doc = CrdtDocument::new();
+        doc.set_string("k", "v").unwrap();
+        let mut peer_doc = CrdtDocument::new();
+        let (mut ours, mut theirs) = (PeerSyncState::new(), PeerSyncState::new());
+        while let Some(msg) = doc.generate_sync_message(&mut ours) {
+            peer_doc.receive_sync_message(&mut theirs, &msg).unwrap();
+            if let Some(reply) = peer_doc.generate_sync_message(&mut theirs) {
+                doc.receive_sync_message(&mut ours, &reply).unwrap();
+            }
+        }
+
+        store.save_peer_sync_state("http://peer:9876", node_id, &ours).await.unwrap();
+        let loaded = store.load_peer_sync_state("http://peer:9876", node_id).await.unwrap();
+        assert_eq!(loaded.shared_heads(), doc.get_heads().as_slice());
+
+        let fresh = store.load_peer_sync_state("other-peer", node_id).await.unwrap();
+        assert!(fresh.shared_heads().is_empty());
+
+        // Peers whose ids differ only in punctuation keep separate state
+        store.save_peer_sync_state("a.b", node_id, &ours).await.unwrap();
+        let fresh = store.load_peer_sync_state("a_b", node_id).await.unwrap();
+        assert!(fresh.shared_heads().is_empty());
+    }
+
     #[tokio::test]
     async fn test_create_node() {
         let dir = tempdir().unwrap();
@@ -431,4 +1317,239 @@ mod tests {
         let root = store.get_node(root_id).await.unwrap();
         assert!(root.children.contains(&doc_id));
     }
+
+    #[tokio::test]
+    async fn test_create_node_from_plain_text() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test Store").await.unwrap();
+        let root_id = store.root_node_id();
+
+        let mut node = Node::document("Legacy");
+        node.content = b"plain text".to_vec();
+        let node_id = store.create_node(node, Some(root_id)).await.unwrap();
+
+        let node = store.get_node(node_id).await.unwrap();
+        assert_eq!(node.metadata.title, "Legacy");
+        let content = DocumentContent::load(&node.content).unwrap();
+        assert_eq!(content.get_text().unwrap(), "plain text");
+        assert_eq!(
+            content.document().content_format().unwrap(),
+            Some(ContentFormat::new(DOCUMENT_FORMAT, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_moves_go_through_the_tree() {
+        let dir = tempdir().unwrap();
+        let store_path = dir.path().join("test.pimble");
+        let mut store = LocalStore::create(&store_path, "Test Store").await.unwrap();
+        let root = store.root_node_id();
+        let a = store.create_node(Node::folder("A"), Some(root)).await.unwrap();
+        let b = store.create_node(Node::folder("B"), Some(root)).await.unwrap();
+        let c = store.create_node(Node::document("C"), Some(root)).await.unwrap();
+
+        // Reordering within a parent
+        store.move_node(c, root, Some(0)).await.unwrap();
+        assert_eq!(store.get_node(root).await.unwrap().children, vec![c, a, b]);
+
+        // A peer moves B under A while we move A under B
+        let mut peer = TreeDocument::load(&store.save_tree()).unwrap();
+        peer.move_node(b, a, None).unwrap();
+        store.move_node(a, b, None).await.unwrap();
+        store.merge_tree(&mut peer).await.unwrap();
+
+        let (pa, pb) = (store.get_node(a).await.unwrap().parent_id, store.get_node(b).await.unwrap().parent_id);
+        assert!((pa == Some(b)) ^ (pb == Some(a)), "exactly one move wins");
+        let top = store.get_node(root).await.unwrap().children.clone();
+        assert_eq!(top.len(), 2);
+
+        // The tree survives reopening
+        store.flush().await.unwrap();
+        let mut reopened = LocalStore::open(&store_path).await.unwrap();
+        assert_eq!(reopened.tree().children(root), top);
+        assert_eq!(reopened.get_node(root).await.unwrap().children, top);
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_announced() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test Store").await.unwrap();
+        let root = store.root_node_id();
+        let (sender, mut changes) = broadcast::channel(16);
+        store.set_change_sender(sender);
+        let mut next = || {
+            let change = changes.try_recv().unwrap();
+            (change.node_id, change.change_type, change.parent_id, change.old_parent_id)
+        };
+
+        let folder = store.create_node(Node::folder("Folder"), Some(root)).await.unwrap();
+        let doc = store.create_node(Node::document("Doc"), Some(root)).await.unwrap();
+        assert_eq!(next(), (folder, ChangeType::Created, Some(root), None));
+        assert_eq!(next(), (doc, ChangeType::Created, Some(root), None));
+
+        store.move_node(doc, folder, None).await.unwrap();
+        assert_eq!(next(), (doc, ChangeType::Moved, Some(folder), Some(root)));
+        store.move_node(doc, folder, Some(0)).await.unwrap();
+        assert_eq!(next(), (doc, ChangeType::Moved, Some(folder), Some(folder)));
+
+        store
+            .edit_node_metadata(doc, &MetadataEdit::SetTitle { title: "Renamed".into() })
+            .await
+            .unwrap();
+        assert_eq!(next(), (doc, ChangeType::Updated, Some(folder), None));
+
+        store.delete_node(doc).await.unwrap();
+        assert_eq!(next(), (doc, ChangeType::Deleted, Some(folder), None));
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metadata_edits_merge() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test Store").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Plan"), Some(root)).await.unwrap();
+
+        // Another device renames the node from the same starting point
+        let mut theirs = CrdtDocument::load(&store.get_node(node_id).await.unwrap().content).unwrap().fork();
+        let mut meta = MetadataContent::from_document(theirs.fork());
+        meta.apply(&MetadataEdit::SetTitle { title: "Roadmap".into() }).unwrap();
+        theirs = CrdtDocument::load(&meta.save()).unwrap();
+
+        // Meanwhile we tag it
+        let view = store
+            .edit_node_metadata(node_id, &MetadataEdit::AddTag { tag: "work".into() })
+            .await
+            .unwrap();
+        assert_eq!(view.title, "Plan");
+
+        let mut ours = store.get_node_document(node_id).await.unwrap();
+        ours.merge(&mut theirs).unwrap();
+        store.merge_node_content(node_id, ours.save()).await.unwrap();
+
+        let node = store.get_node(node_id).await.unwrap();
+        assert_eq!(node.metadata.title, "Roadmap");
+        assert_eq!(node.metadata.tags, vec!["work".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_old_content_is_upgraded() {
+        let dir = tempdir().unwrap();
+        let store_path = dir.path().join("test.pimble");
+        let mut store = LocalStore::create(&store_path, "Test Store").await.unwrap();
+        let root = store.root_node_id();
+        let loaded = store.create_node(Node::document("Loaded"), Some(root)).await.unwrap();
+        let migrated = store.create_node(Node::document("Migrated"), Some(root)).await.unwrap();
+        store.flush().await.unwrap();
+        drop(store);
+
+        // Content written as plain bytes, before it was kept in Automerge
+        for node_id in [loaded, migrated] {
+            std::fs::write(store_path.join("nodes").join(format!("{}.automerge", node_id)), "Old note").unwrap();
+        }
+
+        // Loading a node upgrades it
+        let mut store = LocalStore::open(&store_path).await.unwrap();
+        let content = DocumentContent::load(&store.get_node(loaded).await.unwrap().content).unwrap();
+        assert_eq!(content.get_text().unwrap(), "Old note");
+        assert_eq!(
+            content.document().content_format().unwrap(),
+            Some(ContentFormat::new(DOCUMENT_FORMAT, 1))
+        );
+
+        // Migrating the store upgrades the rest
+        let report = store.migrate_store().await.unwrap();
+        let upgraded = report.migrated.iter().find(|m| m.node_id == migrated).unwrap();
+        assert_eq!(upgraded.from, ContentFormat::new(TEXT_FORMAT, 0));
+        assert_eq!(upgraded.to, ContentFormat::new(DOCUMENT_FORMAT, 1));
+        assert!(report.migrated.iter().all(|m| m.node_id != loaded));
+        assert!(report.unrecognized.is_empty());
+
+        // Upgrades are saved, and keep the node's metadata
+        drop(store);
+        let mut store = LocalStore::open(&store_path).await.unwrap();
+        let report = store.migrate_store().await.unwrap();
+        assert!(report.migrated.is_empty());
+        assert_eq!(report.current, 3);
+        let node = store.get_node(migrated).await.unwrap();
+        assert_eq!(node.metadata.title, "Migrated");
+        assert_eq!(MetadataContent::load(&node.content).unwrap().title().unwrap(), "Migrated");
+    }
+
+    #[tokio::test]
+    async fn test_content_appends_then_compacts() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.pimble");
+        let mut store = LocalStore::create(&path, "Test").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Log"), Some(root)).await.unwrap();
+        store.flush().await.unwrap();
+
+        let content_path = store.node_content_path(node_id);
+        let mut content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        for i in 0..5 {
+            let before = std::fs::read(&content_path).unwrap();
+            content.insert_text(0, &format!("line {}\n", i)).unwrap();
+            store.update_node_content(node_id, content.save()).await.unwrap();
+            store.flush().await.unwrap();
+            let after = std::fs::read(&content_path).unwrap();
+            assert!(after.len() > before.len() && after.starts_with(&before));
+        }
+
+        let mut reopened = LocalStore::open(&path).await.unwrap();
+        let text = DocumentContent::from_document(reopened.get_node_document(node_id).await.unwrap());
+        assert_eq!(text.get_text().unwrap(), content.get_text().unwrap());
+
+        let report = reopened.compact_store(false).await.unwrap();
+        assert_eq!(report.compacted, 1);
+        assert!(report.truncated.is_empty());
+        assert!(report.bytes_after < report.bytes_before);
+        let mut history = reopened.get_node_document(node_id).await.unwrap();
+        assert!(history.history().len() > 5);
+    }
+
+    #[tokio::test]
+    async fn test_compaction_truncates_history() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.pimble");
+        let mut store = LocalStore::create(&path, "Test").await.unwrap();
+        let root = store.root_node_id();
+        let node_id = store.create_node(Node::document("Scratch"), Some(root)).await.unwrap();
+
+        let mut content = DocumentContent::from_document(store.get_node_document(node_id).await.unwrap());
+        for word in ["one", "two", "three"] {
+            content.set_text(word).unwrap();
+            store.update_node_content(node_id, content.save()).await.unwrap();
+        }
+        store.set_history_mode(node_id, HistoryMode::Truncate).await.unwrap();
+        let mut stale = store.get_node_document(node_id).await.unwrap();
+        let known = stale.get_heads();
+
+        let report = store.compact_store(true).await.unwrap();
+        assert_eq!(report.kept, vec![node_id]);
+        assert!(report.truncated.is_empty());
+
+        let report = store.compact_store(false).await.unwrap();
+        assert_eq!(report.truncated, vec![node_id]);
+        assert!(store.compact_store(false).await.unwrap().truncated.is_empty());
+
+        let mut reopened = LocalStore::open(&path).await.unwrap();
+        let node = reopened.get_node(node_id).await.unwrap();
+        assert_eq!(node.history, HistoryMode::Truncate);
+        assert_eq!(node.metadata.title, "Scratch");
+        let mut doc = reopened.get_node_document(node_id).await.unwrap();
+        assert_eq!(doc.history().len(), 1);
+        assert_eq!(DocumentContent::from_document(doc).get_text().unwrap(), "three");
+
+        // Edits to a copy loaded before the history was dropped no longer apply
+        stale.set_string("late", "edit").unwrap();
+        let err = store
+            .apply_node_changes(node_id, &stale.encode_changes_since(&known))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StoreError::MissingChanges(_)));
+        let mut doc = store.get_node_document(node_id).await.unwrap();
+        assert!(!doc.contains_key("late"));
+        assert_eq!(doc.history().len(), 1);
+    }
 }
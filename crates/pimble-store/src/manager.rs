@@ -1,35 +1,101 @@
 //! Store manager - handles multiple open stores
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use pimble_core::{Node, NodeId, Store, StoreId, StoreLocation, SyncState};
-use pimble_crdt::CrdtDocument;
-use tracing::info;
+use pimble_core::{
+    AuthMethod, CompactionReport, ConflictInfo, HistoryEntry, HistoryMode, Identity, LinkResolution, LinkTarget, MetadataEdit, MigrationReport, Node,
+    NodeChange, NodeDiff, NodeId, NodeMetadata, NodeVersion, Store, StoreId, StoreLocation, SyncState, Tombstone, UndoneEdit,
+};
+use pimble_client::ClientError;
+use pimble_crdt::{CrdtDocument, DeviceId, PeerSyncState, UndoManager};
+use tokio::sync::{broadcast, Mutex, MutexGuard};
+use tracing::{debug, info, warn};
+use url::Url;
 
+use crate::conflict;
+use crate::history;
 use crate::error::{Result, StoreError};
+use crate::export::{
+    ExportReport, HtmlExportOptions, HtmlExporter, MarkdownExportOptions, MarkdownExporter,
+};
 use crate::local::LocalStore;
+use crate::opml;
+use crate::remote::{DroppedChange, RemoteStore};
+use crate::replica;
+use crate::transfer::{collect_subtree, duplicate_subtree, relink_store, rewrite_node_links, write_links};
+use crate::undo::{UndoCheckpoint, UndoEntry};
 
 /// Manages multiple open stores
 pub struct StoreManager {
     /// Open local stores
     local_stores: HashMap<StoreId, LocalStore>,
+
+    /// Open stores served by other Pimble servers
+    remote_stores: HashMap<StoreId, SharedRemote>,
+
+    /// Replication state of stores paired with a peer
+    sync_states: HashMap<StoreId, SyncState>,
+
+    /// Undo history of each client session, for edits to local stores
+    undo_sessions: HashMap<String, UndoSession>,
+
+    /// Changes to nodes in local stores, as they are made
+    changes: broadcast::Sender<NodeChange>,
+}
+
+/// A client session's undo history and when the session last used it
+struct UndoSession {
+    history: UndoManager<UndoEntry>,
+    last_used: Instant,
 }
 
 impl StoreManager {
+    /// Changes kept for subscribers that fall behind
+    const CHANGE_CAPACITY: usize = 1024;
+
+    /// Sessions whose undo history is kept; the least recently used is
+    /// forgotten to make room for a new one
+    pub const MAX_UNDO_SESSIONS: usize = 64;
+
+    /// How long an unused session's undo history is kept
+    pub const UNDO_SESSION_IDLE: Duration = Duration::from_secs(60 * 60);
+
     /// Create a new store manager
     pub fn new() -> Self {
+        let (changes, _) = broadcast::channel(Self::CHANGE_CAPACITY);
         Self {
             local_stores: HashMap::new(),
+            remote_stores: HashMap::new(),
+            sync_states: HashMap::new(),
+            undo_sessions: HashMap::new(),
+            changes,
         }
     }
 
+    /// Receive every change made to a node in a local store from now on
+    ///
+    /// Remote stores are left out: their changes are announced by the
+    /// server that holds them. A receiver that falls too far behind misses
+    /// changes and is told how many.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<NodeChange> {
+        self.changes.subscribe()
+    }
+
+    fn add_local_store(&mut self, mut store: LocalStore) -> StoreId {
+        let id = store.id;
+        store.set_change_sender(self.changes.clone());
+        self.local_stores.insert(id, store);
+        id
+    }
+
     /// Create a new local store
     pub async fn create_local_store(&mut self, path: impl AsRef<Path>, name: impl Into<String>) -> Result<StoreId> {
         let store = LocalStore::create(path.as_ref(), name).await?;
-        let id = store.id;
-        self.local_stores.insert(id, store);
-        Ok(id)
+        Ok(self.add_local_store(store))
     }
 
     /// Open an existing local store
@@ -42,12 +108,115 @@ impl StoreManager {
             return Ok(id);
         }
 
-        self.local_stores.insert(id, store);
-        Ok(id)
+        Ok(self.add_local_store(store))
+    }
+
+    /// Open a store served by another Pimble server
+    pub async fn open_remote_store(&mut self, url: Url, auth: AuthMethod) -> Result<StoreId> {
+        let store = RemoteStore::connect(url, auth).await?;
+        Ok(self.add_remote_store(store))
+    }
+
+    /// Open a store served by another Pimble server, working offline-first
+    /// from a local replica at `replica_path`
+    pub async fn open_remote_store_with_replica(
+        &mut self,
+        url: Url,
+        auth: AuthMethod,
+        replica_path: impl AsRef<Path>,
+    ) -> Result<StoreId> {
+        let store = RemoteStore::connect_with_replica(url, auth, replica_path).await?;
+        Ok(self.add_remote_store(store))
+    }
+
+    /// Add a remote store that is already connected
+    ///
+    /// Connecting talks to the remote server, so callers sharing the
+    /// manager can connect first and only lock the manager to add it.
+    pub fn add_remote_store(&mut self, store: RemoteStore) -> StoreId {
+        let id = store.id;
+
+        if self.is_open(id) {
+            info!("Store {} is already open", id);
+            return id;
+        }
+
+        self.remote_stores.insert(id, SharedRemote::new(store));
+        id
+    }
+
+    /// A manager holding just the remote store `store_id`, if it is one
+    ///
+    /// The view shares the store with this manager, so calls on it can be
+    /// made without holding whatever lock guards this one: they do network
+    /// I/O, which would otherwise stall everything else behind a slow
+    /// remote, or deadlock on a remote that is served from the same lock.
+    pub fn remote_view(&self, store_id: StoreId) -> Option<StoreManager> {
+        let remote = self.remote_stores.get(&store_id)?;
+        Some(Self {
+            local_stores: HashMap::new(),
+            remote_stores: HashMap::from([(store_id, remote.clone())]),
+            sync_states: HashMap::new(),
+            undo_sessions: HashMap::new(),
+            changes: self.changes.clone(),
+        })
+    }
+
+    /// Open a store wherever it lives
+    pub async fn open_location(&mut self, location: &StoreLocation) -> Result<StoreId> {
+        match location {
+            StoreLocation::Local { path } => self.open_local_store(path).await,
+            StoreLocation::Remote { url, auth } => self.open_remote_store(url.clone(), auth.clone()).await,
+            StoreLocation::Mounted { .. } => Err(StoreError::InvalidOperation(
+                "Mounted stores can't be opened directly".into(),
+            )),
+        }
+    }
+
+    /// Check every remote store's connectivity
+    ///
+    /// Unreachable stores are marked Offline (or Disconnected, with a
+    /// replica); the rest are marked Synced. Stores with a replica also
+    /// send their queued changes and pull the server's.
+    pub async fn refresh_remote_stores(&mut self) {
+        for (id, store) in &self.remote_stores {
+            // Connectivity is recorded in the store's sync state
+            if let Err(e) = store.lock().await.refresh().await {
+                debug!("Refreshing remote store {} failed: {}", id, e);
+            }
+        }
+    }
+
+    /// Collect the queued changes to a remote store that its server
+    /// refused since last asked
+    pub async fn take_dropped_changes(&mut self, store_id: StoreId) -> Vec<DroppedChange> {
+        match self.remote_stores.get(&store_id) {
+            Some(remote) => remote.lock().await.take_dropped_changes(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Create an empty local replica of a store held by a peer
+    pub async fn create_replica(
+        &mut self,
+        path: impl AsRef<Path>,
+        store_id: StoreId,
+        name: impl Into<String>,
+        root_node_id: NodeId,
+    ) -> Result<StoreId> {
+        if self.local_stores.contains_key(&store_id) {
+            return Err(StoreError::InvalidOperation(format!("Store {} is already open", store_id)));
+        }
+        let store = LocalStore::create_replica(path.as_ref(), store_id, name, root_node_id).await?;
+        Ok(self.add_local_store(store))
     }
 
     /// Close a store
     pub async fn close_store(&mut self, store_id: StoreId) -> Result<()> {
+        self.sync_states.remove(&store_id);
+        if self.remote_stores.remove(&store_id).is_some() {
+            info!("Closed remote store {}", store_id);
+        }
         if let Some(mut store) = self.local_stores.remove(&store_id) {
             store.flush().await?;
             info!("Closed store {}", store_id);
@@ -57,6 +226,9 @@ impl StoreManager {
 
     /// Get store info
     pub fn get_store_info(&self, store_id: StoreId) -> Result<Store> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return Ok(remote.info());
+        }
         if let Some(store) = self.local_stores.get(&store_id) {
             let manifest = store.manifest();
             Ok(Store {
@@ -66,42 +238,347 @@ impl StoreManager {
                     path: store.path.clone(),
                 },
                 root_node_id: manifest.root_node_id,
-                sync_state: SyncState::Offline,
+                sync_state: self.sync_state(store_id),
             })
         } else {
             Err(StoreError::StoreNotFound(store_id))
         }
     }
 
+    /// Get a store's replication state (Offline unless paired with a peer)
+    pub fn sync_state(&self, store_id: StoreId) -> SyncState {
+        self.sync_states.get(&store_id).cloned().unwrap_or(SyncState::Offline)
+    }
+
+    /// Record a store's replication state
+    pub fn set_sync_state(&mut self, store_id: StoreId, state: SyncState) {
+        self.sync_states.insert(store_id, state);
+    }
+
+    /// List nodes with concurrent edits that haven't been reconciled
+    pub async fn list_conflicts(&mut self, store_id: StoreId) -> Result<Vec<ConflictInfo>> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.list_conflicts().await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        conflict::list_conflicts(store).await
+    }
+
+    /// Settle a conflicting key of a node on a picked or merged value
+    ///
+    /// Returns the store's remaining conflicts. A store in the Conflict
+    /// state goes back to Synced once none remain.
+    pub async fn resolve_conflict(
+        &mut self,
+        store_id: StoreId,
+        node_id: NodeId,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<Vec<ConflictInfo>> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.resolve_conflict(node_id, key, value).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        conflict::resolve_conflict(store, node_id, key, value).await?;
+        let remaining = conflict::list_conflicts(store).await?;
+
+        if self.sync_state(store_id).has_conflicts() {
+            self.set_sync_state(store_id, Self::state_after_sync(remaining.clone()));
+        }
+        Ok(remaining)
+    }
+
+    /// List the changes to a node's content, oldest first
+    pub async fn get_node_history(&mut self, store_id: StoreId, node_id: NodeId) -> Result<Vec<HistoryEntry>> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.get_node_history(node_id).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        history::node_history(store, node_id).await
+    }
+
+    /// Get a node as it was at `heads`, along with its text at that version
+    pub async fn get_node_at_version(
+        &mut self,
+        store_id: StoreId,
+        node_id: NodeId,
+        heads: &[String],
+    ) -> Result<(Node, String)> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.get_node_at_version(node_id, heads).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        history::node_at_version(store, node_id, heads).await
+    }
+
+    /// Restore a node's content to its state at `heads`, as a new change
+    pub async fn restore_node_version(&mut self, store_id: StoreId, node_id: NodeId, heads: &[String]) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.restore_node_version(node_id, heads).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        history::restore_node_version(store, node_id, heads).await
+    }
+
+    /// Diff a node's text between the versions at `from` and `to`
+    ///
+    /// `to` defaults to the current version.
+    pub async fn diff_node_versions(
+        &mut self,
+        store_id: StoreId,
+        node_id: NodeId,
+        from: &[String],
+        to: Option<&[String]>,
+    ) -> Result<NodeDiff> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.diff_node_versions(node_id, from, to).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        history::diff_node_versions(store, node_id, from, to).await
+    }
+
+    /// Register a display name for a device writing to a store, or remove it
+    pub async fn set_identity(&mut self, store_id: StoreId, device: DeviceId, name: Option<String>) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.set_identity(device, name).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.set_identity(device, name).await
+    }
+
+    /// List the devices named in a store
+    pub async fn list_identities(&mut self, store_id: StoreId) -> Result<Vec<Identity>> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.identities().await;
+        }
+        let store = self.local_stores.get(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        Ok(store.identities())
+    }
+
+    /// Rewrite a store's content files in full, dropping history where asked
+    ///
+    /// History is kept when `keep_history` is set, for callers holding
+    /// copies of the content, and for stores paired with a peer.
+    pub async fn compact_store(&mut self, store_id: StoreId, keep_history: bool) -> Result<CompactionReport> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.compact_store().await;
+        }
+        let keep_history = keep_history || self.sync_states.contains_key(&store_id);
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.compact_store(keep_history).await
+    }
+
+    /// Choose how much of a node's content history to keep
+    pub async fn set_history_mode(&mut self, store_id: StoreId, node_id: NodeId, mode: HistoryMode) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.set_history_mode(node_id, mode).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.set_history_mode(node_id, mode).await
+    }
+
+    /// Upgrade every node's content in a store to current formats
+    pub async fn migrate_store(&mut self, store_id: StoreId) -> Result<MigrationReport> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.migrate_store().await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.migrate_store().await
+    }
+
+    /// Checkpoint a node before a session edits it, for recording the
+    /// edit with [`StoreManager::record_undo`]
+    ///
+    /// Returns `None` without a session, and for remote stores, which keep
+    /// their own history.
+    pub async fn undo_checkpoint(
+        &mut self,
+        session: Option<&str>,
+        store_id: StoreId,
+        node_id: NodeId,
+    ) -> Result<Option<UndoCheckpoint>> {
+        let (Some(session), Some(store)) = (session, self.local_stores.get_mut(&store_id)) else {
+            return Ok(None);
+        };
+        UndoCheckpoint::take(session, store, node_id).await.map(Some)
+    }
+
+    /// Record what a session did to a node since a checkpoint
+    pub async fn record_undo(&mut self, checkpoint: Option<UndoCheckpoint>) -> Result<()> {
+        let Some(checkpoint) = checkpoint else {
+            return Ok(());
+        };
+        let Some(store) = self.local_stores.get_mut(&checkpoint.store_id()) else {
+            return Ok(());
+        };
+        let session = checkpoint.session.clone();
+        if let Some(entry) = checkpoint.finish(store).await? {
+            debug!("Recorded {:?} edit of node {} for session {}", entry.edit().kind, entry.node_id(), session);
+            self.session_history(&session).record(entry);
+        }
+        Ok(())
+    }
+
+    /// Undo a session's latest edit that can still be undone
+    ///
+    /// Edits to stores that have since closed, and edits overtaken by
+    /// later changes, are skipped. Returns the edit undone, if any.
+    pub async fn undo(&mut self, session: &str) -> Result<Option<UndoneEdit>> {
+        self.step_history(session, true).await
+    }
+
+    /// Redo a session's latest undone edit that can still be redone
+    pub async fn redo(&mut self, session: &str) -> Result<Option<UndoneEdit>> {
+        self.step_history(session, false).await
+    }
+
+    /// Whether a session has edits to undo and to redo
+    pub fn undo_state(&self, session: &str) -> (bool, bool) {
+        self.undo_sessions
+            .get(session)
+            .map_or((false, false), |s| (s.history.can_undo(), s.history.can_redo()))
+    }
+
+    /// Forget a session's undo history
+    pub fn close_session(&mut self, session: &str) {
+        if self.undo_sessions.remove(session).is_some() {
+            debug!("Closed session {}", session);
+        }
+    }
+
+    /// A session's undo history, started if need be
+    ///
+    /// Forgets the histories of sessions that have gone idle, and the
+    /// least recently used one when a new session would be one too many.
+    fn session_history(&mut self, session: &str) -> &mut UndoManager<UndoEntry> {
+        let now = Instant::now();
+        self.undo_sessions
+            .retain(|_, s| now.duration_since(s.last_used) < Self::UNDO_SESSION_IDLE);
+        if !self.undo_sessions.contains_key(session) && self.undo_sessions.len() >= Self::MAX_UNDO_SESSIONS {
+            let oldest = self
+                .undo_sessions
+                .iter()
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                debug!("Forgetting the undo history of session {}", oldest);
+                self.undo_sessions.remove(&oldest);
+            }
+        }
+        let entry = self.undo_sessions.entry(session.to_string()).or_insert_with(|| UndoSession {
+            history: UndoManager::new(),
+            last_used: now,
+        });
+        entry.last_used = now;
+        &mut entry.history
+    }
+
+    async fn step_history(&mut self, session: &str, undo: bool) -> Result<Option<UndoneEdit>> {
+        let Some(session) = self.undo_sessions.get_mut(session) else {
+            return Ok(None);
+        };
+        session.last_used = Instant::now();
+        let history = &mut session.history;
+        loop {
+            let entry = if undo { history.pop_undo() } else { history.pop_redo() };
+            let Some(entry) = entry else {
+                return Ok(None);
+            };
+            let Some(store) = self.local_stores.get_mut(&entry.store_id()) else {
+                continue;
+            };
+            if let Some(reverse) = entry.revert(store).await? {
+                history.for_each_mut(|other| other.follow(&reverse));
+                if undo {
+                    history.push_redo(reverse);
+                } else {
+                    history.push_undo(reverse);
+                }
+                return Ok(Some(entry.edit()));
+            }
+        }
+    }
+
+    /// Synced, or Conflict if a sync left conflicts behind
+    pub fn state_after_sync(conflicts: Vec<ConflictInfo>) -> SyncState {
+        if conflicts.is_empty() {
+            SyncState::Synced { last_sync: chrono::Utc::now() }
+        } else {
+            SyncState::Conflict { details: conflicts }
+        }
+    }
+
     /// List all open stores
     pub fn list_stores(&self) -> Vec<StoreId> {
-        self.local_stores.keys().copied().collect()
+        self.local_stores.keys().chain(self.remote_stores.keys()).copied().collect()
     }
 
     /// Check if a store is open
     pub fn is_open(&self, store_id: StoreId) -> bool {
-        self.local_stores.contains_key(&store_id)
+        self.local_stores.contains_key(&store_id) || self.remote_stores.contains_key(&store_id)
+    }
+
+    /// Check if a store is served by another Pimble server
+    pub fn is_remote(&self, store_id: StoreId) -> bool {
+        self.remote_stores.contains_key(&store_id)
+    }
+
+    /// Refuse an operation that only works on local stores for a remote one
+    fn ensure_local(&self, store_id: StoreId, operation: &str) -> Result<()> {
+        if self.remote_stores.contains_key(&store_id) {
+            return Err(StoreError::InvalidOperation(format!(
+                "{} is not supported for remote stores", operation
+            )));
+        }
+        Ok(())
     }
 
     /// Get a node from a store
     pub async fn get_node(&mut self, store_id: StoreId, node_id: NodeId) -> Result<Node> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.get_node(node_id).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.get_node(node_id).await.map(|n| n.clone())
     }
 
     /// Update a node's metadata in-place and mark it dirty
-    pub async fn update_node_metadata(&mut self, store_id: StoreId, node_id: NodeId, metadata: pimble_core::NodeMetadata) -> Result<()> {
+    pub async fn update_node_metadata(&mut self, store_id: StoreId, node_id: NodeId, metadata: NodeMetadata) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.update_node_metadata(node_id, metadata).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
-        let node = store.get_node_mut(node_id).await?;
-        node.metadata = metadata;
-        node.touch();
-        Ok(())
+        store.update_node_metadata(node_id, metadata).await
+    }
+
+    /// Change one field of a node's metadata, returning the result
+    pub async fn edit_node_metadata(&mut self, store_id: StoreId, node_id: NodeId, edit: &MetadataEdit) -> Result<NodeMetadata> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.edit_node_metadata(node_id, edit).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.edit_node_metadata(node_id, edit).await
     }
 
     /// Create a node in a store
     pub async fn create_node(&mut self, store_id: StoreId, node: Node, parent_id: Option<NodeId>) -> Result<NodeId> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.create_node(node, parent_id).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.create_node(node, parent_id).await
@@ -109,13 +586,196 @@ impl StoreManager {
 
     /// Move a node to a new parent in a store
     pub async fn move_node(&mut self, store_id: StoreId, node_id: NodeId, new_parent_id: NodeId, position: Option<usize>) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.move_node(node_id, new_parent_id, position).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.move_node(node_id, new_parent_id, position).await
     }
 
+    /// Deep-copy a subtree under `new_parent_id`, possibly in another store
+    ///
+    /// Every copied node gets a fresh ID and forked content; links inside
+    /// the subtree are remapped to the copies. Returns the new root's ID.
+    /// Both stores must be local.
+    pub async fn copy_node(
+        &mut self,
+        source_store_id: StoreId,
+        node_id: NodeId,
+        target_store_id: StoreId,
+        new_parent_id: NodeId,
+        position: Option<usize>,
+    ) -> Result<NodeId> {
+        self.ensure_local(source_store_id, "Copying")?;
+        self.ensure_local(target_store_id, "Copying")?;
+        let source = self.local_stores.get_mut(&source_store_id)
+            .ok_or(StoreError::NotOpen(source_store_id))?;
+        let nodes = collect_subtree(source, node_id).await?;
+        let copies = duplicate_subtree(&nodes)?;
+
+        let target = self.local_stores.get_mut(&target_store_id)
+            .ok_or(StoreError::NotOpen(target_store_id))?;
+        let new_root = insert_subtree(target, copies, new_parent_id, position).await?;
+
+        info!(
+            "Copied subtree {} ({} nodes) from store {} to {} in store {}",
+            node_id, nodes.len(), source_store_id, new_root, target_store_id
+        );
+        Ok(new_root)
+    }
+
+    /// Move a subtree from one store to another
+    ///
+    /// Node IDs are preserved. Links are re-qualified so they keep resolving:
+    /// links left behind in the source store that point into the subtree
+    /// become cross-store links, links inside the subtree back to the source
+    /// store gain its store ID, and cross-store links that now point into
+    /// their own store become plain links. Returns the IDs of every moved node.
+    ///
+    /// Both stores must be local. If any step fails, both stores are put
+    /// back as they were.
+    pub async fn move_node_across_stores(
+        &mut self,
+        source_store_id: StoreId,
+        node_id: NodeId,
+        target_store_id: StoreId,
+        new_parent_id: NodeId,
+        position: Option<usize>,
+    ) -> Result<Vec<NodeId>> {
+        if source_store_id == target_store_id {
+            self.move_node(source_store_id, node_id, new_parent_id, position).await?;
+            return Ok(vec![node_id]);
+        }
+
+        self.ensure_local(source_store_id, "Moving between stores")?;
+        self.ensure_local(target_store_id, "Moving between stores")?;
+        let source = self.local_stores.get_mut(&source_store_id)
+            .ok_or(StoreError::NotOpen(source_store_id))?;
+        if source.root_node_id() == node_id {
+            return Err(StoreError::InvalidOperation("Cannot move root node".into()));
+        }
+        let originals = collect_subtree(source, node_id).await?;
+        let moved: Vec<NodeId> = originals.iter().map(|n| n.id).collect();
+        let moved_set: HashSet<NodeId> = moved.iter().copied().collect();
+        let is_moved = |t: &LinkTarget| t.node_id().is_some_and(|id| moved_set.contains(&id));
+
+        // Work out everything the source side needs before the target changes
+        let old_parent_id = originals[0].parent_id
+            .ok_or_else(|| StoreError::InvalidOperation("Cannot move root node".into()))?;
+        let old_position = source.get_node(old_parent_id).await?
+            .children.iter().position(|id| *id == node_id).unwrap_or(0);
+        let left_behind = |t: &LinkTarget| {
+            (t.store_id().is_none() && is_moved(t)).then(|| t.in_store(target_store_id))
+        };
+        let mut source_links = relink_store(source, &left_behind).await?;
+        source_links.retain(|n| !moved_set.contains(&n.id));
+
+        let target = self.local_stores.get_mut(&target_store_id)
+            .ok_or(StoreError::NotOpen(target_store_id))?;
+        for id in &moved {
+            if target.get_node(*id).await.is_ok() {
+                return Err(StoreError::InvalidOperation(format!(
+                    "Node {} already exists in store {}", id, target_store_id
+                )));
+            }
+        }
+        let into_target = |t: &LinkTarget| {
+            (t.store_id() == Some(source_store_id) && is_moved(t)).then(|| t.local())
+        };
+        let target_links = relink_store(target, &into_target).await?;
+
+        let outgoing = |t: &LinkTarget| match t.store_id() {
+            None if t.node_id().is_some() && !is_moved(t) => Some(t.in_store(source_store_id)),
+            Some(store) if store == target_store_id => Some(t.local()),
+            Some(store) if store == source_store_id && is_moved(t) => Some(t.local()),
+            _ => None,
+        };
+        let mut nodes = originals.clone();
+        nodes[0].parent_id = None;
+        for node in &mut nodes {
+            rewrite_node_links(node, &outgoing)?;
+        }
+
+        // From here on, a failure takes back what the target was given
+        if let Err(e) = insert_subtree(target, nodes, new_parent_id, position).await {
+            remove_inserted(target, &moved).await;
+            return Err(e);
+        }
+        let target_previous = match write_links(target, target_links).await {
+            Ok(previous) => previous,
+            Err(e) => {
+                remove_inserted(target, &moved).await;
+                return Err(e);
+            }
+        };
+
+        let source = self.local_stores.get_mut(&source_store_id)
+            .ok_or(StoreError::NotOpen(source_store_id))?;
+        if let Err(e) = remove_subtree(source, &originals, old_position, source_links).await {
+            if let Some(target) = self.local_stores.get_mut(&target_store_id) {
+                if let Err(e) = write_links(target, target_previous).await {
+                    warn!("Failed to restore links in store {}: {}", target_store_id, e);
+                }
+                remove_inserted(target, &moved).await;
+            }
+            return Err(e);
+        }
+
+        info!(
+            "Moved subtree {} ({} nodes) from store {} to store {}",
+            node_id, moved.len(), source_store_id, target_store_id
+        );
+        Ok(moved)
+    }
+
+    /// Resolve a link found in `from_store_id` against the open stores
+    ///
+    /// Store-qualified links resolve in their own store, and report
+    /// [`LinkResolution::Unavailable`] when that store isn't open. Plain links
+    /// are looked up in the source store first, then in every other open
+    /// store, local or remote; remote stores that can't be reached are skipped.
+    pub async fn resolve_link(&mut self, from_store_id: StoreId, target: &LinkTarget) -> Result<LinkResolution> {
+        let (node_id, anchor) = match target {
+            LinkTarget::External(url) => return Ok(LinkResolution::External { url: url.clone() }),
+            _ => (
+                target.node_id().ok_or_else(|| StoreError::InvalidOperation("Link has no target".into()))?,
+                target.anchor().map(str::to_string),
+            ),
+        };
+
+        let candidates: Vec<StoreId> = match target.store_id() {
+            Some(store_id) if !self.is_open(store_id) => {
+                return Ok(LinkResolution::Unavailable { store_id, node_id });
+            }
+            Some(store_id) => vec![store_id],
+            None => std::iter::once(from_store_id)
+                .chain(self.list_stores().into_iter().filter(|id| *id != from_store_id))
+                .collect(),
+        };
+        let searching = target.store_id().is_none();
+
+        for store_id in candidates {
+            if !self.is_open(store_id) {
+                continue;
+            }
+            match self.get_node(store_id, node_id).await {
+                Ok(node) => {
+                    return Ok(LinkResolution::Resolved { store_id, node: Box::new(node), anchor });
+                }
+                Err(StoreError::NodeNotFound(_) | StoreError::Remote(ClientError::NodeNotFound(_))) => continue,
+                Err(StoreError::Remote(e)) if searching && e.is_connection_error() => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(LinkResolution::Missing { node_id })
+    }
+
     /// Delete a node from a store
     pub async fn delete_node(&mut self, store_id: StoreId, node_id: NodeId) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.delete_node(node_id).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.delete_node(node_id).await
@@ -123,13 +783,49 @@ impl StoreManager {
 
     /// Update a node's raw content bytes
     pub async fn update_node_content(&mut self, store_id: StoreId, node_id: NodeId, content: Vec<u8>) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.update_node_content(node_id, content).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.update_node_content(node_id, content).await
     }
 
+    /// Merge CRDT changes into a node's content, returning its new heads
+    pub async fn apply_node_changes(
+        &mut self,
+        store_id: StoreId,
+        node_id: NodeId,
+        changes: &[Vec<u8>],
+    ) -> Result<Vec<String>> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.apply_node_changes(node_id, changes).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.apply_node_changes(node_id, changes).await
+    }
+
+    /// Encode the changes to a node's content beyond `heads`, with its current heads
+    pub async fn get_node_changes_since(
+        &mut self,
+        store_id: StoreId,
+        node_id: NodeId,
+        heads: &[String],
+    ) -> Result<(Vec<Vec<u8>>, Vec<String>)> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.get_node_changes_since(node_id, heads).await;
+        }
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.get_node_changes_since(node_id, heads).await
+    }
+
     /// Get a node's CRDT document
     pub async fn get_node_document(&mut self, store_id: StoreId, node_id: NodeId) -> Result<CrdtDocument> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.get_node_document(node_id).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.get_node_document(node_id).await
@@ -137,6 +833,9 @@ impl StoreManager {
 
     /// Save a node's CRDT document
     pub async fn save_node_document(&mut self, store_id: StoreId, node_id: NodeId, doc: &mut CrdtDocument) -> Result<()> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.save_node_document(node_id, doc).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.save_node_document(node_id, doc).await
@@ -144,13 +843,19 @@ impl StoreManager {
 
     /// Get children of a node
     pub async fn get_children(&mut self, store_id: StoreId, node_id: NodeId) -> Result<Vec<Node>> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return remote.lock().await.get_children(node_id).await;
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.get_children(node_id).await
     }
 
-    /// Flush a store to disk
+    /// Flush a store to disk (remote stores write through, so there is nothing to flush)
     pub async fn flush(&mut self, store_id: StoreId) -> Result<()> {
+        if self.remote_stores.contains_key(&store_id) {
+            return Ok(());
+        }
         let store = self.local_stores.get_mut(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         store.flush().await
@@ -164,14 +869,259 @@ impl StoreManager {
         Ok(())
     }
 
+    /// Export a subtree (or the whole store) to a folder of markdown files
+    pub async fn export_markdown(
+        &mut self,
+        store_id: StoreId,
+        node_id: Option<NodeId>,
+        out_dir: impl AsRef<Path>,
+        options: MarkdownExportOptions,
+    ) -> Result<ExportReport> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        let root = node_id.unwrap_or_else(|| store.root_node_id());
+        MarkdownExporter::new(options).export(store, root, out_dir).await
+    }
+
+    /// Publish a subtree (or the whole store when `node_id` is None) as a static HTML site
+    pub async fn export_html(
+        &mut self,
+        store_id: StoreId,
+        node_id: Option<NodeId>,
+        out_dir: impl AsRef<Path>,
+        options: HtmlExportOptions,
+    ) -> Result<ExportReport> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        let root = node_id.unwrap_or_else(|| store.root_node_id());
+        HtmlExporter::new(options).export(store, root, out_dir).await
+    }
+
+    /// Export a subtree (or the whole store when `node_id` is None) as OPML
+    pub async fn export_opml(&mut self, store_id: StoreId, node_id: Option<NodeId>) -> Result<String> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        let root = node_id.unwrap_or_else(|| store.root_node_id());
+        opml::export_opml(store, root).await
+    }
+
+    /// Import OPML outlines under `parent_id` (or the store root when None)
+    pub async fn import_opml(&mut self, store_id: StoreId, parent_id: Option<NodeId>, xml: &str) -> Result<Vec<NodeId>> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        let parent = parent_id.unwrap_or_else(|| store.root_node_id());
+        opml::import_opml(store, parent, xml).await
+    }
+
+    /// Summarise a store's nodes and deletions for replication
+    pub async fn replication_manifest(&mut self, store_id: StoreId) -> Result<(Vec<NodeVersion>, Vec<Tombstone>)> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        let versions = replica::node_versions(store).await?;
+        Ok((versions, store.tombstones()))
+    }
+
+    /// Apply node records and tombstones received from a peer
+    pub async fn apply_node_records(&mut self, store_id: StoreId, nodes: Vec<Node>, tombstones: &[Tombstone]) -> Result<usize> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        replica::apply_node_records(store, nodes, tombstones).await
+    }
+
+    /// Get a store's tree structure, for sending to a peer
+    pub fn save_store_tree(&mut self, store_id: StoreId) -> Result<Vec<u8>> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        Ok(store.save_tree())
+    }
+
+    /// Merge a peer's tree structure into a store's, returning the result
+    pub async fn merge_store_tree(&mut self, store_id: StoreId, tree: &[u8]) -> Result<Vec<u8>> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        replica::merge_tree(store, tree).await
+    }
+
+    /// Answer a content sync message from a peer
+    pub async fn sync_node_content(
+        &mut self,
+        store_id: StoreId,
+        peer_id: &str,
+        node_id: NodeId,
+        message: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        replica::sync_node_content(store, peer_id, node_id, message).await
+    }
+
+    /// Merge a document synced with a peer into a node's current content
+    pub async fn merge_synced_content(&mut self, store_id: StoreId, node_id: NodeId, doc: &mut CrdtDocument) -> Result<()> {
+        let store = self.local_stores.get_mut(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        replica::merge_synced_content(store, node_id, doc).await
+    }
+
+    /// Load the saved sync state for a peer and node
+    pub async fn load_peer_sync_state(&self, store_id: StoreId, peer_id: &str, node_id: NodeId) -> Result<PeerSyncState> {
+        let store = self.local_stores.get(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.load_peer_sync_state(peer_id, node_id).await
+    }
+
+    /// Save sync state for a peer and node
+    pub async fn save_peer_sync_state(&self, store_id: StoreId, peer_id: &str, node_id: NodeId, state: &PeerSyncState) -> Result<()> {
+        let store = self.local_stores.get(&store_id)
+            .ok_or(StoreError::NotOpen(store_id))?;
+        store.save_peer_sync_state(peer_id, node_id, state).await
+    }
+
     /// Get the root node ID for a store
     pub fn root_node_id(&self, store_id: StoreId) -> Result<NodeId> {
+        if let Some(remote) = self.remote_stores.get(&store_id) {
+            return Ok(remote.info().root_node_id);
+        }
         let store = self.local_stores.get(&store_id)
             .ok_or(StoreError::NotOpen(store_id))?;
         Ok(store.root_node_id())
     }
 }
 
+/// Attach a pre-ordered subtree under `parent_id`, keeping child order
+async fn insert_subtree(
+    store: &mut LocalStore,
+    nodes: Vec<Node>,
+    parent_id: NodeId,
+    position: Option<usize>,
+) -> Result<NodeId> {
+    // Validate the parent before writing anything
+    store.get_node(parent_id).await?;
+
+    let mut nodes = nodes.into_iter();
+    let root = nodes.next()
+        .ok_or_else(|| StoreError::InvalidOperation("Empty subtree".into()))?;
+    let root_id = store.create_node(root, Some(parent_id)).await?;
+    if position.is_some() {
+        store.move_node(root_id, parent_id, position).await?;
+    }
+
+    for node in nodes {
+        let parent = node.parent_id;
+        store.create_node(node, parent).await?;
+    }
+    Ok(root_id)
+}
+
+/// Delete a pre-ordered subtree from a store, leaves first, then store
+/// the links rewritten to follow it
+///
+/// `position` is the subtree root's place among its siblings. On failure
+/// the nodes already deleted are put back.
+async fn remove_subtree(store: &mut LocalStore, nodes: &[Node], position: usize, links: Vec<Node>) -> Result<()> {
+    for (i, node) in nodes.iter().enumerate().rev() {
+        if let Err(e) = store.delete_node(node.id).await {
+            restore_subtree(store, nodes, i, position).await;
+            return Err(e);
+        }
+    }
+    if let Err(e) = write_links(store, links).await {
+        restore_subtree(store, nodes, 0, position).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Put back the nodes of a pre-ordered subtree from index `from` on,
+/// parents first
+///
+/// Runs while another error is being returned, so failures are only logged.
+async fn restore_subtree(store: &mut LocalStore, nodes: &[Node], from: usize, position: usize) {
+    for (i, node) in nodes.iter().enumerate().skip(from) {
+        let position = if i == 0 {
+            position
+        } else {
+            nodes.iter()
+                .find(|n| Some(n.id) == node.parent_id)
+                .and_then(|parent| parent.children.iter().position(|id| *id == node.id))
+                .unwrap_or(0)
+        };
+        if let Err(e) = store.restore_node(node.clone(), position).await {
+            warn!("Failed to restore node {}: {}", node.id, e);
+        }
+    }
+}
+
+/// Delete nodes a failed transfer inserted into a store, leaves first
+async fn remove_inserted(store: &mut LocalStore, ids: &[NodeId]) {
+    for id in ids.iter().rev() {
+        if !store.contains_node(*id) {
+            continue;
+        }
+        if let Err(e) = store.delete_node(*id).await {
+            warn!("Failed to remove node {}: {}", id, e);
+        }
+    }
+}
+
+/// A remote store, locked apart from the manager holding it
+///
+/// Calls on it do network I/O while holding only its own lock. Its
+/// description is kept beside it, so the store can still be described
+/// while a call is in flight.
+#[derive(Clone)]
+struct SharedRemote {
+    store: Arc<Mutex<RemoteStore>>,
+    info: Arc<RwLock<Store>>,
+}
+
+impl SharedRemote {
+    fn new(store: RemoteStore) -> Self {
+        Self {
+            info: Arc::new(RwLock::new(store.info())),
+            store: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    /// Lock the store for a call, waiting for any call in flight
+    async fn lock(&self) -> RemoteGuard<'_> {
+        RemoteGuard {
+            store: self.store.lock().await,
+            info: &self.info,
+        }
+    }
+
+    /// The store's description as of its last call
+    fn info(&self) -> Store {
+        self.info.read().unwrap().clone()
+    }
+}
+
+/// A locked remote store, which updates its description when released
+struct RemoteGuard<'a> {
+    store: MutexGuard<'a, RemoteStore>,
+    info: &'a RwLock<Store>,
+}
+
+impl Deref for RemoteGuard<'_> {
+    type Target = RemoteStore;
+
+    fn deref(&self) -> &RemoteStore {
+        &self.store
+    }
+}
+
+impl DerefMut for RemoteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut RemoteStore {
+        &mut self.store
+    }
+}
+
+impl Drop for RemoteGuard<'_> {
+    fn drop(&mut self) {
+        *self.info.write().unwrap() = self.store.info();
+    }
+}
+
 impl Default for StoreManager {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,375 @@
+//! OPML 2.0 import and export of node outlines
+//!
+//! Outlines map onto the node tree directly:
+//! - `text` ↔ node title
+//! - `_note` ↔ document text content
+//! - `category` ↔ tags (comma-separated)
+//! - `created` ↔ creation time (RFC 822)
+//! - `pimbleId` / `pimbleType` preserve the node ID and type for round-trips
+
+use chrono::{DateTime, Utc};
+use pimble_core::{node_types, Node, NodeId};
+use pimble_crdt::DocumentContent;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use tracing::info;
+
+use crate::error::{Result, StoreError};
+use crate::export::{node_text, ExportTree};
+use crate::local::LocalStore;
+
+/// A single `<outline>` element and its children
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpmlOutline {
+    /// The outline text (node title)
+    pub text: String,
+
+    /// The `_note` attribute (document content)
+    pub note: Option<String>,
+
+    /// Node ID preserved by a previous export
+    pub id: Option<NodeId>,
+
+    /// Node type preserved by a previous export
+    pub node_type: Option<String>,
+
+    /// Tags from the `category` attribute
+    pub tags: Vec<String>,
+
+    /// Creation time from the `created` attribute
+    pub created: Option<DateTime<Utc>>,
+
+    /// Nested outlines
+    pub children: Vec<OpmlOutline>,
+}
+
+/// A parsed OPML document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpmlDocument {
+    /// Title from `<head><title>`
+    pub title: Option<String>,
+
+    /// Top-level outlines in `<body>`
+    pub outlines: Vec<OpmlOutline>,
+}
+
+impl OpmlDocument {
+    /// Parse an OPML document
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut doc = OpmlDocument::default();
+        // Outlines that have been opened but not yet closed
+        let mut stack: Vec<OpmlOutline> = Vec::new();
+        let mut in_title = false;
+        let mut saw_opml = false;
+
+        loop {
+            let event = reader
+                .read_event()
+                .map_err(|e| invalid(format!("OPML parse error at {}: {}", reader.buffer_position(), e)))?;
+            match event {
+                Event::Start(e) => match e.name().as_ref() {
+                    b"opml" => saw_opml = true,
+                    b"title" => in_title = true,
+                    b"outline" => stack.push(parse_outline(&e)?),
+                    _ => {}
+                },
+                Event::Empty(e) => match e.name().as_ref() {
+                    b"opml" => saw_opml = true,
+                    b"outline" => {
+                        let outline = parse_outline(&e)?;
+                        push_outline(&mut doc, &mut stack, outline);
+                    }
+                    _ => {}
+                },
+                Event::Text(t) if in_title => {
+                    let title = t.unescape().map_err(|e| invalid(e.to_string()))?;
+                    doc.title = Some(title.trim().to_string());
+                }
+                Event::End(e) => match e.name().as_ref() {
+                    b"title" => in_title = false,
+                    b"outline" => {
+                        let outline = stack
+                            .pop()
+                            .ok_or_else(|| invalid("Unbalanced </outline>".to_string()))?;
+                        push_outline(&mut doc, &mut stack, outline);
+                    }
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if !saw_opml {
+            return Err(invalid("Missing <opml> element".to_string()));
+        }
+        if !stack.is_empty() {
+            return Err(invalid("Unclosed <outline> element".to_string()));
+        }
+        Ok(doc)
+    }
+
+    /// Build an OPML document from a subtree
+    ///
+    /// With `include_root` the subtree root becomes the single top-level
+    /// outline; otherwise its children are the top-level outlines and its
+    /// title becomes the document title (used when exporting a whole store).
+    pub fn from_tree(tree: &ExportTree, include_root: bool) -> Self {
+        let root = tree.get(&tree.root);
+        let (title, top) = match root {
+            Some(root) if !include_root => (Some(root.metadata.title.clone()), root.children.clone()),
+            Some(root) => (Some(root.metadata.title.clone()), vec![root.id]),
+            None => (None, Vec::new()),
+        };
+
+        Self {
+            title,
+            outlines: top.iter().filter_map(|id| outline_from_tree(tree, id)).collect(),
+        }
+    }
+
+    /// Serialise to an OPML 2.0 XML string
+    pub fn to_xml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<opml version=\"2.0\">\n  <head>\n");
+        if let Some(title) = &self.title {
+            out.push_str(&format!("    <title>{}</title>\n", escape(title)));
+        }
+        out.push_str(&format!(
+            "    <dateCreated>{}</dateCreated>\n",
+            Utc::now().to_rfc2822()
+        ));
+        out.push_str("  </head>\n  <body>\n");
+        for outline in &self.outlines {
+            write_outline(&mut out, outline, 2);
+        }
+        out.push_str("  </body>\n</opml>\n");
+        out
+    }
+}
+
+/// Import OPML outlines as children of `parent_id`
+///
+/// Outlines with children and no note become folders, everything else a
+/// document. Preserved node IDs are reused unless already taken in the
+/// store. Returns the IDs of the top-level imported nodes.
+pub async fn import_opml(store: &mut LocalStore, parent_id: NodeId, xml: &str) -> Result<Vec<NodeId>> {
+    let doc = OpmlDocument::parse(xml)?;
+    let mut top_level = Vec::with_capacity(doc.outlines.len());
+    let mut count = 0;
+
+    // Depth-first, creating parents before their children
+    let mut stack: Vec<(&OpmlOutline, NodeId, bool)> =
+        doc.outlines.iter().rev().map(|o| (o, parent_id, true)).collect();
+    while let Some((outline, parent, is_top)) = stack.pop() {
+        let node = node_from_outline(store, outline).await?;
+        let node_id = store.create_node(node, Some(parent)).await?;
+        if is_top {
+            top_level.push(node_id);
+        }
+        count += 1;
+        stack.extend(outline.children.iter().rev().map(|c| (c, node_id, false)));
+    }
+
+    store.flush().await?;
+    info!("Imported {} nodes from OPML into store {}", count, store.id);
+    Ok(top_level)
+}
+
+/// Export the subtree rooted at `root` as an OPML string
+pub async fn export_opml(store: &mut LocalStore, root: NodeId) -> Result<String> {
+    let tree = ExportTree::load(store, root).await?;
+    let include_root = root != store.root_node_id();
+    Ok(OpmlDocument::from_tree(&tree, include_root).to_xml())
+}
+
+async fn node_from_outline(store: &mut LocalStore, outline: &OpmlOutline) -> Result<Node> {
+    let node_type = outline.node_type.clone().unwrap_or_else(|| {
+        if !outline.children.is_empty() && outline.note.is_none() {
+            node_types::FOLDER.to_string()
+        } else {
+            node_types::DOCUMENT.to_string()
+        }
+    });
+
+    let mut node = Node::new(node_type);
+    if let Some(id) = outline.id {
+        if store.get_node(id).await.is_err() {
+            node.id = id;
+        }
+    }
+    node.metadata.title = outline.text.clone();
+    node.metadata.tags = outline.tags.clone();
+    if let Some(created) = outline.created {
+        node.metadata.created_at = created;
+    }
+    if let Some(note) = outline.note.as_deref().filter(|n| !n.is_empty()) {
+        let mut content = DocumentContent::new();
+        content.set_text(note)?;
+        node.content = content.save();
+    }
+    Ok(node)
+}
+
+fn outline_from_tree(tree: &ExportTree, node_id: &NodeId) -> Option<OpmlOutline> {
+    let node = tree.get(node_id)?;
+    let text = node_text(node);
+    Some(OpmlOutline {
+        text: node.metadata.title.clone(),
+        note: (!text.is_empty()).then_some(text),
+        id: Some(node.id),
+        node_type: Some(node.node_type.clone()),
+        tags: node.metadata.tags.clone(),
+        created: Some(node.metadata.created_at),
+        children: node
+            .children
+            .iter()
+            .filter_map(|c| outline_from_tree(tree, c))
+            .collect(),
+    })
+}
+
+fn parse_outline(e: &BytesStart) -> Result<OpmlOutline> {
+    let mut outline = OpmlOutline::default();
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| invalid(e.to_string()))?;
+        let value = attr.unescape_value().map_err(|e| invalid(e.to_string()))?;
+        match attr.key.as_ref() {
+            b"text" => outline.text = value.to_string(),
+            b"_note" => outline.note = Some(value.to_string()),
+            b"pimbleId" => outline.id = NodeId::parse(&value).ok(),
+            b"pimbleType" => outline.node_type = Some(value.to_string()),
+            b"category" => {
+                outline.tags = value
+                    .split(',')
+                    .map(|t| t.trim().trim_start_matches('/').to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect();
+            }
+            b"created" => {
+                outline.created = DateTime::parse_from_rfc2822(&value)
+                    .ok()
+                    .map(|d| d.with_timezone(&Utc));
+            }
+            _ => {}
+        }
+    }
+    Ok(outline)
+}
+
+fn push_outline(doc: &mut OpmlDocument, stack: &mut [OpmlOutline], outline: OpmlOutline) {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(outline),
+        None => doc.outlines.push(outline),
+    }
+}
+
+fn write_outline(out: &mut String, outline: &OpmlOutline, depth: usize) {
+    let indent = "  ".repeat(depth);
+    out.push_str(&format!("{}<outline text=\"{}\"", indent, escape(&outline.text)));
+    if let Some(note) = &outline.note {
+        out.push_str(&format!(" _note=\"{}\"", escape(note)));
+    }
+    if !outline.tags.is_empty() {
+        out.push_str(&format!(" category=\"{}\"", escape(&outline.tags.join(","))));
+    }
+    if let Some(created) = outline.created {
+        out.push_str(&format!(" created=\"{}\"", escape(&created.to_rfc2822())));
+    }
+    if let Some(id) = outline.id {
+        out.push_str(&format!(" pimbleId=\"{}\"", id));
+    }
+    if let Some(node_type) = &outline.node_type {
+        out.push_str(&format!(" pimbleType=\"{}\"", escape(node_type)));
+    }
+
+    if outline.children.is_empty() {
+        out.push_str("/>\n");
+    } else {
+        out.push_str(">\n");
+        for child in &outline.children {
+            write_outline(out, child, depth + 1);
+        }
+        out.push_str(&format!("{}</outline>\n", indent));
+    }
+}
+
+/// Escape text for use in XML content and attribute values
+///
+/// Whitespace control characters are written as character references so
+/// multi-line notes survive attribute-value normalisation.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\n' => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            '\t' => out.push_str("&#9;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn invalid(message: String) -> StoreError {
+    StoreError::InvalidFormat(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Projects &amp; Ideas</title></head>
+  <body>
+    <outline text="Work" category="/work,urgent">
+      <outline text="Roadmap" _note="Line one&#10;Line two"/>
+    </outline>
+    <outline text="Loose note"/>
+  </body>
+</opml>"#;
+
+    #[test]
+    fn test_parse_opml() {
+        let doc = OpmlDocument::parse(SAMPLE).unwrap();
+        assert_eq!(doc.title.as_deref(), Some("Projects & Ideas"));
+        assert_eq!(doc.outlines.len(), 2);
+        assert_eq!(doc.outlines[0].tags, vec!["work", "urgent"]);
+        assert_eq!(doc.outlines[0].children[0].note.as_deref(), Some("Line one\nLine two"));
+    }
+
+    #[test]
+    fn test_parse_rejects_non_opml() {
+        assert!(OpmlDocument::parse("<html></html>").is_err());
+        assert!(OpmlDocument::parse("<opml><body><outline text=\"a\"></body></opml>").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_export_round_trip() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = store.root_node_id();
+
+        let imported = import_opml(&mut store, root, SAMPLE).await.unwrap();
+        assert_eq!(imported.len(), 2);
+
+        let work = store.get_node(imported[0]).await.unwrap().clone();
+        assert_eq!(work.node_type, node_types::FOLDER);
+        let roadmap = store.get_node(work.children[0]).await.unwrap().clone();
+        assert_eq!(node_text(&roadmap), "Line one\nLine two");
+
+        let xml = export_opml(&mut store, work.id).await.unwrap();
+        let doc = OpmlDocument::parse(&xml).unwrap();
+        assert_eq!(doc.outlines.len(), 1);
+        assert_eq!(doc.outlines[0].id, Some(work.id));
+        assert_eq!(doc.outlines[0].tags, vec!["work", "urgent"]);
+        assert_eq!(doc.outlines[0].children[0].note.as_deref(), Some("Line one\nLine two"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Durable queue of changes waiting to be sent to a remote store
+//!
+//! Edits made to a remote store's local replica are queued here and
+//! replayed in order once the server is reachable. The queue is rewritten
+//! on every change, so nothing queued is lost if the app exits while
+//! offline.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use pimble_core::{Node, NodeId, NodeMetadata};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::error::Result;
+
+/// A change made locally that the remote server hasn't seen yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum QueuedOp {
+    /// Create a node, keeping its ID
+    CreateNode { node: Box<Node> },
+
+    /// Replace a node's metadata
+    UpdateMetadata { node_id: NodeId, metadata: NodeMetadata },
+
+    /// Merge the replica's content for a node into the server's
+    ///
+    /// The content itself is read from the replica when the change is
+    /// replayed, so repeated edits collapse into one send.
+    UpdateContent { node_id: NodeId },
+
+    /// Delete a node
+    DeleteNode { node_id: NodeId },
+
+    /// Move a node to a new parent
+    MoveNode {
+        node_id: NodeId,
+        new_parent_id: NodeId,
+        position: Option<usize>,
+    },
+}
+
+impl QueuedOp {
+    /// The node the change applies to
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            QueuedOp::CreateNode { node } => node.id,
+            QueuedOp::UpdateMetadata { node_id, .. }
+            | QueuedOp::UpdateContent { node_id }
+            | QueuedOp::DeleteNode { node_id }
+            | QueuedOp::MoveNode { node_id, .. } => *node_id,
+        }
+    }
+}
+
+/// On-disk form of the outbox
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OutboxFile {
+    last_sync: Option<DateTime<Utc>>,
+    ops: VecDeque<QueuedOp>,
+}
+
+/// Ordered, durable queue of changes for a remote store
+pub struct Outbox {
+    path: PathBuf,
+    file: OutboxFile,
+}
+
+impl Outbox {
+    /// Load the outbox at `path`, or start an empty one
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path).await?)?
+        } else {
+            OutboxFile::default()
+        };
+        Ok(Self { path, file })
+    }
+
+    /// Number of queued changes
+    pub fn len(&self) -> usize {
+        self.file.ops.len()
+    }
+
+    /// Check whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.file.ops.is_empty()
+    }
+
+    /// The next change to send
+    pub fn front(&self) -> Option<&QueuedOp> {
+        self.file.ops.front()
+    }
+
+    /// Check whether any queued change touches a node
+    pub fn touches(&self, node_id: NodeId) -> bool {
+        self.file.ops.iter().any(|op| op.node_id() == node_id)
+    }
+
+    /// When the queue was last fully sent
+    pub fn last_sync(&self) -> Option<DateTime<Utc>> {
+        self.file.last_sync
+    }
+
+    /// Queue a change
+    ///
+    /// A content update for a node that already has one queued is dropped,
+    /// since the queued one will send the latest content anyway.
+    pub async fn push(&mut self, op: QueuedOp) -> Result<()> {
+        if let QueuedOp::UpdateContent { node_id } = op {
+            let queued = self.file.ops.iter().any(
+                |q| matches!(q, QueuedOp::UpdateContent { node_id: n } if *n == node_id),
+            );
+            if queued {
+                return Ok(());
+            }
+        }
+        self.file.ops.push_back(op);
+        self.save().await
+    }
+
+    /// Remove the change at the front, once it has been sent
+    pub async fn pop_front(&mut self) -> Result<Option<QueuedOp>> {
+        let op = self.file.ops.pop_front();
+        self.save().await?;
+        Ok(op)
+    }
+
+    /// Record that the queue has been fully sent
+    pub async fn mark_synced(&mut self) -> Result<()> {
+        self.file.last_sync = Some(Utc::now());
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.file)?;
+        fs::write(&self.path, json).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_outbox_persists_in_order() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("outbox.json");
+        let node = Node::document("Queued");
+        let node_id = node.id;
+
+        let mut outbox = Outbox::open(&path).await.unwrap();
+        outbox.push(QueuedOp::CreateNode { node: Box::new(node) }).await.unwrap();
+        outbox.push(QueuedOp::UpdateContent { node_id }).await.unwrap();
+        outbox.push(QueuedOp::UpdateContent { node_id }).await.unwrap();
+        outbox.push(QueuedOp::DeleteNode { node_id }).await.unwrap();
+        assert_eq!(outbox.len(), 3);
+
+        let mut reopened = Outbox::open(&path).await.unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert!(reopened.touches(node_id));
+        assert!(matches!(reopened.pop_front().await.unwrap(), Some(QueuedOp::CreateNode { .. })));
+        assert!(matches!(reopened.front(), Some(QueuedOp::UpdateContent { node_id: n }) if *n == node_id));
+        assert_eq!(reopened.last_sync(), None);
+
+        reopened.mark_synced().await.unwrap();
+        assert!(Outbox::open(&path).await.unwrap().last_sync().is_some());
+    }
+}
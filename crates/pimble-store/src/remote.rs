@@ -0,0 +1,888 @@
+//! Remote store implementation, proxied to another Pimble server
+//!
+//! A remote store is a store opened on another server. Node operations are
+//! forwarded over RPC with the store's configured [`AuthMethod`].
+//!
+//! Without a replica, every node read is cached in memory so the tree stays
+//! browsable while the server is unreachable, but writes need the server.
+//! With a replica (see [`RemoteStore::connect_with_replica`]) the store works
+//! offline-first: the whole store is mirrored into a local store, edits are
+//! applied there and queued in an [`Outbox`], and the queue is replayed when
+//! the server is reachable again. Queued content edits are merged into the
+//! server's content rather than written over it.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use pimble_client::PimbleClient;
+use pimble_core::{
+    AuthMethod, CompactionReport, ConflictInfo, HistoryEntry, HistoryMode, Identity, MetadataEdit, MigrationReport, Node, NodeDiff, NodeId, NodeMetadata,
+    Store, StoreId, StoreLocation, SyncState,
+};
+use pimble_crdt::{CrdtDocument, DeviceId};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::error::{Result, StoreError};
+use crate::local::LocalStore;
+use crate::outbox::{Outbox, QueuedOp};
+use crate::replica;
+
+/// A store served by another Pimble server
+///
+/// The store is named by a `store` query parameter on its URL, e.g.
+/// `http://host:9876/?store=<store-id>`. Without one, the server must have
+/// exactly one store open.
+pub struct RemoteStore {
+    /// Store ID (the same as on the server)
+    pub id: StoreId,
+
+    /// URL the store was opened with
+    pub url: Url,
+
+    /// Credentials sent with every request
+    auth: AuthMethod,
+
+    /// Store name, as reported by the server
+    name: String,
+
+    /// Root node ID
+    root_node_id: NodeId,
+
+    /// Client for the remote server
+    client: PimbleClient,
+
+    /// Nodes read from the server, kept for offline reads (without a replica)
+    cache: HashMap<NodeId, Node>,
+
+    /// Local mirror and outgoing queue, for offline-first use
+    replica: Option<Replica>,
+
+    /// When the server last answered; None while it is unreachable
+    last_contact: Option<DateTime<Utc>>,
+
+    /// Queued changes the server refused, not yet collected
+    dropped: Vec<DroppedChange>,
+}
+
+/// A queued change the server refused when it was replayed
+///
+/// The change stays in the replica only until the next pull replaces the
+/// node with the server's copy.
+#[derive(Debug)]
+pub struct DroppedChange {
+    /// The change, as it was queued
+    pub op: QueuedOp,
+
+    /// Why the server refused it
+    pub error: StoreError,
+}
+
+/// Local mirror of a remote store, with the changes not yet sent
+struct Replica {
+    store: LocalStore,
+    outbox: Outbox,
+}
+
+impl RemoteStore {
+    /// Outbox file inside a replica's directory
+    const OUTBOX_FILE: &'static str = "outbox.json";
+
+    /// Connect to a remote store
+    pub async fn connect(url: Url, auth: AuthMethod) -> Result<Self> {
+        let (server_url, store_id) = split_store_url(&url)?;
+        let client = PimbleClient::new_with_auth(server_url.as_str(), &auth)?;
+        let store = find_store(&client, &server_url, store_id).await?;
+
+        info!("Opened remote store '{}' at {}", store.name, server_url);
+        Ok(Self {
+            id: store.id,
+            url,
+            auth,
+            name: store.name,
+            root_node_id: store.root_node_id,
+            client,
+            cache: HashMap::new(),
+            replica: None,
+            last_contact: Some(Utc::now()),
+            dropped: Vec::new(),
+        })
+    }
+
+    /// Connect to a remote store, mirroring it into a local replica
+    ///
+    /// The replica is created at `replica_path` on first use. Once it
+    /// exists the store can be opened while the server is unreachable, and
+    /// edits are queued until it comes back.
+    pub async fn connect_with_replica(url: Url, auth: AuthMethod, replica_path: impl AsRef<Path>) -> Result<Self> {
+        let replica_path = replica_path.as_ref();
+        let (server_url, store_id) = split_store_url(&url)?;
+        let client = PimbleClient::new_with_auth(server_url.as_str(), &auth)?;
+
+        let existing = if replica_path.exists() {
+            Some(LocalStore::open(replica_path).await?)
+        } else {
+            None
+        };
+        let wanted = store_id.or(existing.as_ref().map(|s| s.id));
+        let remote = match find_store(&client, &server_url, wanted).await {
+            Ok(store) => Some(store),
+            Err(StoreError::Remote(e)) if e.is_connection_error() && existing.is_some() => {
+                warn!("{} is unreachable; opening replica offline", server_url);
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        let store = match (existing, &remote) {
+            (Some(store), Some(remote)) if store.id != remote.id => {
+                return Err(StoreError::InvalidOperation(format!(
+                    "Replica at {} belongs to store {}, not {}",
+                    replica_path.display(),
+                    store.id,
+                    remote.id
+                )));
+            }
+            (Some(store), _) => store,
+            (None, Some(remote)) => {
+                LocalStore::create_replica(replica_path, remote.id, &remote.name, remote.root_node_id).await?
+            }
+            (None, None) => unreachable!("an unreachable server without a replica is an error above"),
+        };
+        let outbox = Outbox::open(store.path.join(Self::OUTBOX_FILE)).await?;
+
+        let mut remote_store = Self {
+            id: store.id,
+            url,
+            auth,
+            name: store.manifest().name.clone(),
+            root_node_id: store.root_node_id(),
+            client,
+            cache: HashMap::new(),
+            replica: Some(Replica { store, outbox }),
+            last_contact: remote.is_some().then(Utc::now),
+            dropped: Vec::new(),
+        };
+        if remote_store.is_online() {
+            remote_store.sync().await?;
+        }
+
+        info!("Opened remote store '{}' at {} with a local replica", remote_store.name, server_url);
+        Ok(remote_store)
+    }
+
+    /// Describe the store, including its connectivity
+    ///
+    /// The credentials are left out: the description is sent to clients.
+    pub fn info(&self) -> Store {
+        Store {
+            id: self.id,
+            name: self.name.clone(),
+            location: StoreLocation::Remote {
+                url: self.url.clone(),
+                auth: self.auth.redacted(),
+            },
+            root_node_id: self.root_node_id,
+            sync_state: self.sync_state(),
+        }
+    }
+
+    /// Connectivity and, with a replica, how much is waiting to be sent
+    pub fn sync_state(&self) -> SyncState {
+        match (&self.replica, self.last_contact) {
+            (Some(replica), None) => SyncState::Disconnected {
+                queued: replica.outbox.len(),
+                last_sync: replica.outbox.last_sync(),
+            },
+            (Some(replica), Some(_)) if !replica.outbox.is_empty() => SyncState::Syncing,
+            (Some(replica), Some(contact)) => SyncState::Synced {
+                last_sync: replica.outbox.last_sync().unwrap_or(contact),
+            },
+            (None, Some(last_sync)) => SyncState::Synced { last_sync },
+            (None, None) => SyncState::Offline,
+        }
+    }
+
+    /// Check whether the server answered the last request
+    pub fn is_online(&self) -> bool {
+        self.last_contact.is_some()
+    }
+
+    /// Number of local changes waiting to be sent
+    pub fn queued_changes(&self) -> usize {
+        self.replica.as_ref().map_or(0, |r| r.outbox.len())
+    }
+
+    /// Collect the queued changes the server has refused since last asked
+    pub fn take_dropped_changes(&mut self) -> Vec<DroppedChange> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Get the root node ID
+    pub fn root_node_id(&self) -> NodeId {
+        self.root_node_id
+    }
+
+    /// Get the cached copy of a node, without asking the server
+    pub fn cached_node(&self, node_id: NodeId) -> Option<&Node> {
+        self.cache.get(&node_id)
+    }
+
+    /// Check whether the server is reachable again
+    ///
+    /// With a replica, queued changes are replayed and the replica is
+    /// brought up to date.
+    pub async fn refresh(&mut self) -> Result<()> {
+        if self.replica.is_some() {
+            return self.sync().await;
+        }
+        let result = self.client.list_stores().await;
+        self.track(result).map(|_| ())
+    }
+
+    /// Get a node, falling back to the cache or replica while offline
+    pub async fn get_node(&mut self, node_id: NodeId) -> Result<Node> {
+        if self.replica.is_none() {
+            let result = self.client.get_node(self.id, node_id).await;
+            return match self.track(result) {
+                Ok(node) => {
+                    self.cache.insert(node_id, node.clone());
+                    Ok(node)
+                }
+                Err(e) => self.cache_fallback(e, |cache| cache.get(&node_id).cloned()),
+            };
+        }
+
+        if self.is_online() && !self.has_pending(node_id) {
+            let result = self.client.get_node(self.id, node_id).await;
+            match self.track(result) {
+                Ok(node) => self.store_fetched(std::slice::from_ref(&node)).await?,
+                Err(StoreError::Remote(e)) if e.is_connection_error() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let replica = self.replica_mut()?;
+        replica.store.get_node(node_id).await.cloned()
+    }
+
+    /// Get children of a node, falling back to the cache or replica while offline
+    pub async fn get_children(&mut self, node_id: NodeId) -> Result<Vec<Node>> {
+        if self.replica.is_none() {
+            let result = self.client.get_children(self.id, node_id).await;
+            return match self.track(result) {
+                Ok(children) => {
+                    for child in &children {
+                        self.cache.insert(child.id, child.clone());
+                    }
+                    Ok(children)
+                }
+                Err(e) => self.cache_fallback(e, |cache| {
+                    cache
+                        .get(&node_id)?
+                        .children
+                        .iter()
+                        .map(|id| cache.get(id).cloned())
+                        .collect()
+                }),
+            };
+        }
+
+        // Refresh the parent's child list, then the children themselves
+        self.get_node(node_id).await?;
+        if self.is_online() {
+            let result = self.client.get_children(self.id, node_id).await;
+            match self.track(result) {
+                Ok(children) => self.store_fetched(&children).await?,
+                Err(StoreError::Remote(e)) if e.is_connection_error() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let replica = self.replica_mut()?;
+        replica.store.get_children(node_id).await
+    }
+
+    /// Get a node's CRDT document
+    pub async fn get_node_document(&mut self, node_id: NodeId) -> Result<CrdtDocument> {
+        let node = self.get_node(node_id).await?;
+        CrdtDocument::load(&node.content).map_err(StoreError::from)
+    }
+
+    /// Create a node on the server, keeping its ID
+    ///
+    /// The whole record is sent, so metadata, links and content arrive
+    /// with the node.
+    pub async fn create_node(&mut self, mut node: Node, parent_id: Option<NodeId>) -> Result<NodeId> {
+        let node_id = node.id;
+        node.parent_id = parent_id;
+
+        if let Some(replica) = &mut self.replica {
+            replica.store.create_node(node.clone(), parent_id).await?;
+            replica.store.flush().await?;
+            self.queue(QueuedOp::CreateNode { node: Box::new(node) }).await?;
+            return Ok(node_id);
+        }
+
+        let result = self
+            .client
+            .apply_node_records(self.id, vec![node.clone()], Vec::new())
+            .await;
+        self.track(result)?;
+
+        if let Some(pid) = parent_id {
+            self.cache.remove(&pid);
+        }
+        self.cache.insert(node_id, node);
+        debug!("Created node {} in remote store {}", node_id, self.id);
+        Ok(node_id)
+    }
+
+    /// Replace a node's metadata
+    ///
+    /// With a replica, metadata travels inside the node's content, so it is
+    /// merged with the server's rather than written over it.
+    pub async fn update_node_metadata(&mut self, node_id: NodeId, metadata: NodeMetadata) -> Result<()> {
+        if let Some(replica) = &mut self.replica {
+            replica.store.update_node_metadata(node_id, metadata).await?;
+            replica.store.flush().await?;
+            return self.queue(QueuedOp::UpdateContent { node_id }).await;
+        }
+
+        let result = self
+            .client
+            .update_node_metadata(self.id, node_id, metadata.clone())
+            .await;
+        self.track(result)?;
+
+        if let Some(node) = self.cache.get_mut(&node_id) {
+            node.metadata = metadata;
+            node.touch();
+        }
+        Ok(())
+    }
+
+    /// Change one field of a node's metadata, returning the result
+    pub async fn edit_node_metadata(&mut self, node_id: NodeId, edit: &MetadataEdit) -> Result<NodeMetadata> {
+        if let Some(replica) = &mut self.replica {
+            let metadata = replica.store.edit_node_metadata(node_id, edit).await?;
+            replica.store.flush().await?;
+            self.queue(QueuedOp::UpdateContent { node_id }).await?;
+            return Ok(metadata);
+        }
+
+        let result = self.client.edit_node_metadata(self.id, node_id, edit).await;
+        let metadata = self.track(result)?;
+
+        if let Some(node) = self.cache.get_mut(&node_id) {
+            node.metadata = metadata.clone();
+        }
+        Ok(metadata)
+    }
+
+    /// List the server's unreconciled concurrent edits in this store
+    pub async fn list_conflicts(&mut self) -> Result<Vec<ConflictInfo>> {
+        let result = self.client.list_conflicts(self.id).await;
+        self.track(result)
+    }
+
+    /// Settle a conflicting key on the server, returning what remains
+    pub async fn resolve_conflict(
+        &mut self,
+        node_id: NodeId,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<Vec<ConflictInfo>> {
+        let result = self.client.resolve_conflict(self.id, node_id, key, value.clone()).await;
+        let remaining = self.track(result)?;
+        self.cache.remove(&node_id);
+        Ok(remaining)
+    }
+
+    /// List the changes to a node's content on the server
+    pub async fn get_node_history(&mut self, node_id: NodeId) -> Result<Vec<HistoryEntry>> {
+        let result = self.client.get_node_history(self.id, node_id).await;
+        self.track(result)
+    }
+
+    /// Get a node as it was at `heads` on the server
+    pub async fn get_node_at_version(&mut self, node_id: NodeId, heads: &[String]) -> Result<(Node, String)> {
+        let result = self.client.get_node_at_version(self.id, node_id, heads.to_vec()).await;
+        self.track(result)
+    }
+
+    /// Restore a node's content on the server to its state at `heads`
+    pub async fn restore_node_version(&mut self, node_id: NodeId, heads: &[String]) -> Result<()> {
+        let result = self.client.restore_node_version(self.id, node_id, heads.to_vec()).await;
+        self.track(result)?;
+        self.cache.remove(&node_id);
+        Ok(())
+    }
+
+    /// Register or remove a device's display name on the server
+    pub async fn set_identity(&mut self, device: DeviceId, name: Option<String>) -> Result<()> {
+        let result = self.client.set_identity(self.id, device.to_string(), name).await;
+        self.track(result)
+    }
+
+    /// List the devices named in the store on the server
+    pub async fn identities(&mut self) -> Result<Vec<Identity>> {
+        let result = self.client.list_identities(self.id).await;
+        self.track(result)
+    }
+
+    /// Diff a node's text on the server between the versions at `from` and `to`
+    pub async fn diff_node_versions(
+        &mut self,
+        node_id: NodeId,
+        from: &[String],
+        to: Option<&[String]>,
+    ) -> Result<NodeDiff> {
+        let result = self
+            .client
+            .diff_node_versions(self.id, node_id, from.to_vec(), to.map(<[String]>::to_vec))
+            .await;
+        self.track(result)
+    }
+
+    /// Upgrade every node's content on the server to current formats
+    pub async fn migrate_store(&mut self) -> Result<MigrationReport> {
+        let result = self.client.migrate_store(self.id).await;
+        let report = self.track(result)?;
+        for migration in &report.migrated {
+            self.cache.remove(&migration.node_id);
+        }
+        Ok(report)
+    }
+
+    /// Compact the store's content files on the server
+    ///
+    /// The server is asked to keep all history, which the content cached
+    /// here builds on.
+    pub async fn compact_store(&mut self) -> Result<CompactionReport> {
+        let result = self.client.compact_store(self.id, true).await;
+        let report = self.track(result)?;
+        for node_id in &report.truncated {
+            self.cache.remove(node_id);
+        }
+        Ok(report)
+    }
+
+    /// Choose how much of a node's history the server keeps
+    pub async fn set_history_mode(&mut self, node_id: NodeId, mode: HistoryMode) -> Result<()> {
+        let result = self.client.set_history_mode(self.id, node_id, mode).await;
+        self.track(result)?;
+        self.cache.remove(&node_id);
+        Ok(())
+    }
+
+    /// Replace a node's CRDT content
+    pub async fn update_node_content(&mut self, node_id: NodeId, content: Vec<u8>) -> Result<()> {
+        if let Some(replica) = &mut self.replica {
+            replica.store.update_node_content(node_id, content).await?;
+            replica.store.flush().await?;
+            return self.queue(QueuedOp::UpdateContent { node_id }).await;
+        }
+
+        let result = self
+            .client
+            .set_node_content_bytes(self.id, node_id, content.clone())
+            .await;
+        self.track(result)?;
+
+        if let Some(node) = self.cache.get_mut(&node_id) {
+            node.content = content;
+            node.touch();
+        }
+        Ok(())
+    }
+
+    /// Save a node's CRDT document
+    pub async fn save_node_document(&mut self, node_id: NodeId, doc: &mut CrdtDocument) -> Result<()> {
+        self.update_node_content(node_id, doc.save()).await
+    }
+
+    /// Merge CRDT changes into a node's content, returning its new heads
+    pub async fn apply_node_changes(&mut self, node_id: NodeId, changes: &[Vec<u8>]) -> Result<Vec<String>> {
+        if let Some(replica) = &mut self.replica {
+            let heads = replica.store.apply_node_changes(node_id, changes).await?;
+            replica.store.flush().await?;
+            self.queue(QueuedOp::UpdateContent { node_id }).await?;
+            return Ok(heads);
+        }
+
+        let result = self.client.apply_node_changes(self.id, node_id, changes.to_vec()).await;
+        let heads = self.track(result)?;
+        self.cache.remove(&node_id);
+        Ok(heads)
+    }
+
+    /// Encode the changes to a node's content beyond `heads`, with its current heads
+    ///
+    /// With a replica, the node is refreshed first when online and the
+    /// changes come from the replica.
+    pub async fn get_node_changes_since(
+        &mut self,
+        node_id: NodeId,
+        heads: &[String],
+    ) -> Result<(Vec<Vec<u8>>, Vec<String>)> {
+        if self.replica.is_none() {
+            let result = self.client.get_node_changes_since(self.id, node_id, heads.to_vec()).await;
+            return self.track(result);
+        }
+
+        self.get_node(node_id).await?;
+        let replica = self.replica_mut()?;
+        replica.store.get_node_changes_since(node_id, heads).await
+    }
+
+    /// Delete a node
+    pub async fn delete_node(&mut self, node_id: NodeId) -> Result<()> {
+        if let Some(replica) = &mut self.replica {
+            replica.store.delete_node(node_id).await?;
+            replica.store.flush().await?;
+            return self.queue(QueuedOp::DeleteNode { node_id }).await;
+        }
+
+        let result = self.client.delete_node(self.id, node_id).await;
+        self.track(result)?;
+
+        if let Some(node) = self.cache.remove(&node_id) {
+            if let Some(pid) = node.parent_id {
+                self.cache.remove(&pid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move a node to a new parent, optionally at a specific position
+    pub async fn move_node(&mut self, node_id: NodeId, new_parent_id: NodeId, position: Option<usize>) -> Result<()> {
+        if let Some(replica) = &mut self.replica {
+            replica.store.move_node(node_id, new_parent_id, position).await?;
+            replica.store.flush().await?;
+            return self
+                .queue(QueuedOp::MoveNode {
+                    node_id,
+                    new_parent_id,
+                    position,
+                })
+                .await;
+        }
+
+        let result = self
+            .client
+            .move_node(self.id, node_id, new_parent_id, position)
+            .await;
+        self.track(result)?;
+
+        // The old parent, new parent and node all changed on the server
+        if let Some(node) = self.cache.remove(&node_id) {
+            if let Some(pid) = node.parent_id {
+                self.cache.remove(&pid);
+            }
+        }
+        self.cache.remove(&new_parent_id);
+        Ok(())
+    }
+
+    // Private helpers
+
+    fn replica_mut(&mut self) -> Result<&mut Replica> {
+        self.replica
+            .as_mut()
+            .ok_or_else(|| StoreError::InvalidOperation(format!("Remote store {} has no replica", self.id)))
+    }
+
+    fn has_pending(&self, node_id: NodeId) -> bool {
+        self.replica.as_ref().is_some_and(|r| r.outbox.touches(node_id))
+    }
+
+    /// Queue a change already applied to the replica, then try to send it
+    ///
+    /// Changes queued earlier are sent first. The server refusing one of
+    /// those isn't this change's failure, so it is recorded as dropped
+    /// rather than returned.
+    async fn queue(&mut self, op: QueuedOp) -> Result<()> {
+        self.replay().await?;
+        let outbox = &mut self.replica_mut()?.outbox;
+        let behind = !outbox.is_empty();
+        outbox.push(op).await?;
+        if behind {
+            // The server is unreachable; the change waits its turn
+            return Ok(());
+        }
+
+        match self.send_next().await? {
+            Some((_, answer)) => {
+                self.replica_mut()?.outbox.mark_synced().await?;
+                answer
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Replay queued changes, then bring the replica up to date
+    async fn sync(&mut self) -> Result<()> {
+        // A replica opened offline hasn't checked the server's protocol yet
+        let result = self.client.handshake().await.map(|_| ());
+        match self.track(result) {
+            Err(StoreError::Remote(e)) if e.is_connection_error() => return Ok(()),
+            result => result?,
+        }
+        self.replay().await?;
+        if self.is_online() && self.queued_changes() == 0 {
+            self.pull().await?;
+        }
+        Ok(())
+    }
+
+    /// Send queued changes in order until the queue is empty or the
+    /// server can't be reached
+    ///
+    /// A change the server refuses is dropped from the queue and kept for
+    /// [`RemoteStore::take_dropped_changes`]; the next pull replaces the
+    /// replica's copy with the server's.
+    async fn replay(&mut self) -> Result<()> {
+        let mut sent = false;
+        while let Some((op, answer)) = self.send_next().await? {
+            sent = true;
+            if let Err(error) = answer {
+                warn!("Server rejected queued change to node {}: {}", op.node_id(), error);
+                self.dropped.push(DroppedChange { op, error });
+            }
+        }
+
+        if sent && self.queued_changes() == 0 {
+            self.replica_mut()?.outbox.mark_synced().await?;
+        }
+        Ok(())
+    }
+
+    /// Send the oldest queued change and take it off the queue
+    ///
+    /// Returns the change and the server's answer, or None if the queue is
+    /// empty or the server can't be reached, in which case the change stays
+    /// queued.
+    async fn send_next(&mut self) -> Result<Option<(QueuedOp, Result<()>)>> {
+        let Some(op) = self.replica_mut()?.outbox.front().cloned() else {
+            return Ok(None);
+        };
+        let result = self.send(op.clone()).await;
+        let answer = match self.track(result) {
+            Err(StoreError::Remote(e)) if e.is_connection_error() => return Ok(None),
+            answer => answer,
+        };
+        self.replica_mut()?.outbox.pop_front().await?;
+        Ok(Some((op, answer)))
+    }
+
+    /// Send one queued change to the server
+    async fn send(&mut self, op: QueuedOp) -> Result<()> {
+        let store_id = self.id;
+        match op {
+            QueuedOp::CreateNode { node } => {
+                self.client.apply_node_records(store_id, vec![*node], Vec::new()).await?;
+            }
+            QueuedOp::UpdateMetadata { node_id, metadata } => {
+                self.client.update_node_metadata(store_id, node_id, metadata).await?;
+            }
+            QueuedOp::UpdateContent { node_id } => {
+                let local = match self.replica_mut()?.store.get_node(node_id).await {
+                    Ok(node) => node.content.clone(),
+                    // Deleted locally since; a queued delete follows
+                    Err(StoreError::NodeNotFound(_)) => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+
+                // Exchange only the changes each side is missing, so the
+                // server's own edits survive and the whole note isn't resent
+                let mut doc = CrdtDocument::load(&local)?;
+                let heads: Vec<String> = doc.get_heads().iter().map(|h| h.to_string()).collect();
+                let (theirs, their_heads) = self.client.get_node_changes_since(store_id, node_id, heads).await?;
+                doc.apply_encoded_changes(&theirs)?;
+                let their_heads = doc.parse_heads(&their_heads)?;
+                let ours = doc.encode_changes_since(&their_heads);
+                if !ours.is_empty() {
+                    self.client.apply_node_changes(store_id, node_id, ours).await?;
+                }
+                let merged = doc.save();
+
+                let replica = self.replica_mut()?;
+                replica.store.merge_node_content(node_id, merged).await?;
+                replica.store.flush().await?;
+            }
+            QueuedOp::DeleteNode { node_id } => {
+                self.client.delete_node(store_id, node_id).await?;
+            }
+            QueuedOp::MoveNode {
+                node_id,
+                new_parent_id,
+                position,
+            } => {
+                self.client.move_node(store_id, node_id, new_parent_id, position).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the replica match the server, except for nodes with queued changes
+    async fn pull(&mut self) -> Result<()> {
+        let result = self.client.replication_manifest(self.id).await;
+        let remote = self.track(result)?;
+
+        let replica = self.replica_mut()?;
+        let local: HashMap<NodeId, _> = replica::node_versions(&mut replica.store)
+            .await?
+            .into_iter()
+            .map(|v| (v.node_id, v))
+            .collect();
+        let remote_ids: HashSet<NodeId> = remote.nodes.iter().map(|v| v.node_id).collect();
+
+        let stale: Vec<NodeId> = remote
+            .nodes
+            .iter()
+            .filter(|v| local.get(&v.node_id) != Some(*v) && !replica.outbox.touches(v.node_id))
+            .map(|v| v.node_id)
+            .collect();
+        let gone: Vec<NodeId> = local
+            .keys()
+            .filter(|id| !remote_ids.contains(id) && !replica.outbox.touches(**id))
+            .copied()
+            .collect();
+
+        if !stale.is_empty() {
+            let result = self.client.get_nodes(self.id, stale).await;
+            let nodes = self.track(result)?;
+            self.store_fetched(&nodes).await?;
+        }
+        let result = self.client.merge_store_tree(self.id, None).await;
+        let tree = self.track(result)?;
+        let replica = self.replica_mut()?;
+        replica::merge_tree(&mut replica.store, &tree).await?;
+        for node_id in &gone {
+            // Its parent may be going too; only detach from one that stays
+            let node = replica.store.get_node_mut(*node_id).await?;
+            if node.parent_id.is_some_and(|pid| gone.contains(&pid)) {
+                node.parent_id = None;
+            }
+            replica.store.delete_node(*node_id).await?;
+        }
+        replica.store.flush().await?;
+        replica.outbox.mark_synced().await?;
+
+        debug!("Pulled remote store {} into its replica ({} removed)", self.id, gone.len());
+        Ok(())
+    }
+
+    /// Write nodes fetched from the server into the replica
+    ///
+    /// Nodes with queued changes keep their local copy.
+    async fn store_fetched(&mut self, nodes: &[Node]) -> Result<()> {
+        let replica = self.replica_mut()?;
+        for node in nodes {
+            if !replica.outbox.touches(node.id) {
+                replica.store.put_node(node.clone());
+            }
+        }
+        replica.store.flush().await
+    }
+
+    /// Record whether the server answered, and convert the error
+    ///
+    /// Any answer, even an error, means the server is reachable.
+    fn track<T, E: Into<StoreError>>(&mut self, result: std::result::Result<T, E>) -> Result<T> {
+        let result = result.map_err(Into::into);
+        let unreachable = matches!(&result, Err(StoreError::Remote(e)) if e.is_connection_error());
+        if unreachable {
+            if self.last_contact.take().is_some() {
+                warn!("Remote store {} went offline", self.id);
+            }
+        } else if result.is_ok() || matches!(&result, Err(StoreError::Remote(_))) {
+            if self.last_contact.is_none() {
+                info!("Remote store {} is back online", self.id);
+            }
+            self.last_contact = Some(Utc::now());
+        }
+        result
+    }
+
+    /// Answer from the cache if the error was a lost connection
+    fn cache_fallback<T>(
+        &self,
+        error: StoreError,
+        read: impl FnOnce(&HashMap<NodeId, Node>) -> Option<T>,
+    ) -> Result<T> {
+        match &error {
+            StoreError::Remote(e) if e.is_connection_error() => read(&self.cache).ok_or(error),
+            _ => Err(error),
+        }
+    }
+}
+
+/// Find the named store among those the server has open, once the
+/// handshake shows the server speaks a compatible protocol
+///
+/// Without a name, the server must have exactly one store open.
+async fn find_store(client: &PimbleClient, server_url: &Url, store_id: Option<StoreId>) -> Result<Store> {
+    client.handshake().await?;
+    let stores = client.list_stores().await?;
+    match store_id {
+        Some(id) => stores.into_iter().find(|s| s.id == id).ok_or_else(|| {
+            StoreError::InvalidPath(format!("Store {} is not open on {}", id, server_url))
+        }),
+        None => {
+            if stores.len() != 1 {
+                return Err(StoreError::InvalidPath(format!(
+                    "{} has {} stores open; name one with ?store=<id>",
+                    server_url,
+                    stores.len()
+                )));
+            }
+            Ok(stores.into_iter().next().unwrap())
+        }
+    }
+}
+
+/// Split a remote store URL into the server URL and the store it names
+pub fn split_store_url(url: &Url) -> Result<(Url, Option<StoreId>)> {
+    let store_id = url
+        .query_pairs()
+        .find(|(key, _)| key == "store")
+        .map(|(_, value)| {
+            StoreId::parse(&value)
+                .map_err(|e| StoreError::InvalidPath(format!("Invalid store ID in {}: {}", url, e)))
+        })
+        .transpose()?;
+
+    let mut server_url = url.clone();
+    server_url.set_query(None);
+    server_url.set_fragment(None);
+    Ok((server_url, store_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_store_url() {
+        let id = StoreId::new();
+        let url: Url = format!("http://example.com:9876/?store={}", id).parse().unwrap();
+        let (server, store) = split_store_url(&url).unwrap();
+        assert_eq!(server.as_str(), "http://example.com:9876/");
+        assert_eq!(store, Some(id));
+
+        let url: Url = "http://example.com:9876".parse().unwrap();
+        assert_eq!(split_store_url(&url).unwrap().1, None);
+
+        let url: Url = "http://example.com/?store=nope".parse().unwrap();
+        assert!(split_store_url(&url).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_unreachable_server_fails() {
+        // Nothing listens on port 9 (discard) on localhost
+        let url: Url = "http://127.0.0.1:9/".parse().unwrap();
+        let err = RemoteStore::connect(url, AuthMethod::None).await.err().unwrap();
+        assert!(matches!(err, StoreError::Remote(e) if e.is_connection_error()));
+    }
+}
@@ -0,0 +1,383 @@
+//! Replicating a store with a copy of it held by a peer
+//!
+//! Replication works in two layers. Node records (metadata, links and tree
+//! position) are reconciled last-writer-wins on `modified_at`, and deletions
+//! travel as tombstones so they aren't undone by the other side. Node content
+//! is an Automerge document and is merged with the sync protocol instead, so
+//! concurrent edits on both sides survive.
+//!
+//! After records are applied the tree is repaired: a node's `parent_id` is
+//! authoritative and its parent's child list is made to agree with it.
+//! Where the store's tree structure knows a node, the tree wins; peers
+//! exchange their trees with [`merge_tree`] so concurrent moves resolve the
+//! same way on both sides.
+
+use std::collections::{HashMap, HashSet};
+
+use pimble_core::{Node, NodeId, NodeVersion, Tombstone};
+use pimble_crdt::{CrdtDocument, TreeDocument};
+
+use crate::error::Result;
+use crate::local::LocalStore;
+
+/// What to exchange with a peer, worked out from both sides' summaries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicationPlan {
+    /// Nodes whose records should be fetched from the peer
+    pub pull: Vec<NodeId>,
+
+    /// Nodes whose records should be sent to the peer
+    pub push: Vec<NodeId>,
+
+    /// Nodes present on both sides whose content heads differ
+    pub content: Vec<NodeId>,
+}
+
+impl ReplicationPlan {
+    /// Compare local and remote summaries
+    pub fn new(
+        local: &[NodeVersion],
+        local_tombstones: &[Tombstone],
+        remote: &[NodeVersion],
+        remote_tombstones: &[Tombstone],
+    ) -> Self {
+        let local_map: HashMap<NodeId, &NodeVersion> = local.iter().map(|v| (v.node_id, v)).collect();
+        let remote_map: HashMap<NodeId, &NodeVersion> = remote.iter().map(|v| (v.node_id, v)).collect();
+        let deleted_here: HashMap<NodeId, _> =
+            local_tombstones.iter().map(|t| (t.node_id, t.deleted_at)).collect();
+        let deleted_there: HashMap<NodeId, _> =
+            remote_tombstones.iter().map(|t| (t.node_id, t.deleted_at)).collect();
+
+        let mut plan = Self::default();
+        for theirs in remote {
+            match local_map.get(&theirs.node_id) {
+                Some(ours) => {
+                    if theirs.modified_at > ours.modified_at {
+                        plan.pull.push(theirs.node_id);
+                    } else if ours.modified_at > theirs.modified_at {
+                        plan.push.push(theirs.node_id);
+                    }
+                    if theirs.heads != ours.heads {
+                        plan.content.push(theirs.node_id);
+                    }
+                }
+                None => {
+                    // A deletion here at least as new as their edit wins
+                    let deleted = deleted_here
+                        .get(&theirs.node_id)
+                        .is_some_and(|&at| at >= theirs.modified_at);
+                    if !deleted {
+                        plan.pull.push(theirs.node_id);
+                    }
+                }
+            }
+        }
+        for ours in local {
+            if remote_map.contains_key(&ours.node_id) {
+                continue;
+            }
+            let deleted = deleted_there
+                .get(&ours.node_id)
+                .is_some_and(|&at| at >= ours.modified_at);
+            if !deleted {
+                plan.push.push(ours.node_id);
+            }
+        }
+        plan
+    }
+
+    /// Check whether the two sides already agree
+    pub fn is_empty(&self) -> bool {
+        self.pull.is_empty() && self.push.is_empty() && self.content.is_empty()
+    }
+}
+
+/// Heads of a node's CRDT content as hex strings
+///
+/// Content that isn't a CRDT document has no heads.
+pub fn content_heads(content: &[u8]) -> Vec<String> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    match CrdtDocument::load(content) {
+        Ok(mut doc) => doc.get_heads().iter().map(|h| h.to_string()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Summarise every node in a store
+///
+/// Flushes the store first so nodes created since the last flush are seen.
+pub async fn node_versions(store: &mut LocalStore) -> Result<Vec<NodeVersion>> {
+    store.flush().await?;
+    let mut versions = Vec::new();
+    for node_id in store.list_node_ids().await? {
+        let node = store.get_node(node_id).await?;
+        versions.push(NodeVersion {
+            node_id,
+            modified_at: node.metadata.modified_at,
+            heads: content_heads(&node.content),
+        });
+    }
+    Ok(versions)
+}
+
+/// Apply node records and tombstones received from a peer
+///
+/// Records older than the local copy are ignored. When an existing node is
+/// replaced its local content is kept, since content is merged separately
+/// through the sync protocol. Returns the number of nodes added, updated or
+/// deleted.
+pub async fn apply_node_records(
+    store: &mut LocalStore,
+    nodes: Vec<Node>,
+    tombstones: &[Tombstone],
+) -> Result<usize> {
+    let root = store.root_node_id();
+    let mut applied = 0;
+
+    for tombstone in tombstones {
+        if tombstone.node_id == root {
+            continue;
+        }
+        if store.contains_node(tombstone.node_id) {
+            let node = store.get_node(tombstone.node_id).await?;
+            if tombstone.deleted_at < node.metadata.modified_at {
+                // Edited after the peer deleted it; our edit wins
+                continue;
+            }
+            // Detach without touching the parent, so its record stays in step
+            // with the peer's
+            if let Some(parent_id) = node.parent_id {
+                if store.contains_node(parent_id) {
+                    store.get_node_mut(parent_id).await?.children.retain(|c| *c != tombstone.node_id);
+                }
+            }
+            store.delete_node(tombstone.node_id).await?;
+            applied += 1;
+        }
+        store.record_tombstone(*tombstone);
+    }
+
+    // Previous records of replaced nodes, for repairing the tree afterwards
+    let mut previous: HashMap<NodeId, Option<Node>> = HashMap::new();
+    for mut node in nodes {
+        if store
+            .deleted_at(node.id)
+            .is_some_and(|at| at >= node.metadata.modified_at)
+        {
+            continue;
+        }
+        let existing = if store.contains_node(node.id) {
+            let local = store.get_node(node.id).await?.clone();
+            if node.metadata.modified_at <= local.metadata.modified_at {
+                continue;
+            }
+            node.content = local.content.clone();
+            Some(local)
+        } else {
+            None
+        };
+        previous.insert(node.id, existing);
+        store.put_node(node);
+        applied += 1;
+    }
+
+    repair_tree(store, &previous).await?;
+    store.apply_tree().await?;
+    Ok(applied)
+}
+
+/// Merge a peer's tree structure into the store's
+///
+/// Returns the merged tree, for sending back to the peer.
+pub async fn merge_tree(store: &mut LocalStore, tree: &[u8]) -> Result<Vec<u8>> {
+    let mut theirs = TreeDocument::load(tree)?;
+    store.merge_tree(&mut theirs).await?;
+    Ok(store.save_tree())
+}
+
+/// Answer one content sync message from a peer
+///
+/// Pass `None` to start a session. Merged content is written back without
+/// touching the node's modified time, and the peer's sync state is saved so
+/// the next session resumes from it. Returns the reply, if any.
+pub async fn sync_node_content(
+    store: &mut LocalStore,
+    peer_id: &str,
+    node_id: NodeId,
+    message: Option<&[u8]>,
+) -> Result<Option<Vec<u8>>> {
+    let mut state = store.load_peer_sync_state(peer_id, node_id).await?;
+    let content = store.get_node(node_id).await?.content.clone();
+    let mut doc = if content.is_empty() {
+        CrdtDocument::new()
+    } else {
+        CrdtDocument::load(&content)?
+    };
+
+    let before = doc.get_heads();
+    if let Some(message) = message {
+        doc.receive_sync_message(&mut state, message)?;
+    }
+    let reply = doc.generate_sync_message(&mut state);
+
+    if doc.get_heads() != before {
+        store.merge_node_content(node_id, doc.save()).await?;
+    }
+    store.save_peer_sync_state(peer_id, node_id, &state).await?;
+    Ok(reply)
+}
+
+/// Merge a document synced with a peer into a node's current content
+///
+/// The node may have been edited locally while the sync was in progress,
+/// so the synced document is merged rather than written over it.
+pub async fn merge_synced_content(store: &mut LocalStore, node_id: NodeId, doc: &mut CrdtDocument) -> Result<()> {
+    let content = store.get_node(node_id).await?.content.clone();
+    if content.is_empty() {
+        return store.merge_node_content(node_id, doc.save()).await;
+    }
+    let mut current = CrdtDocument::load(&content)?;
+    let before = current.get_heads();
+    current.merge(doc)?;
+    if current.get_heads() != before {
+        store.merge_node_content(node_id, current.save()).await?;
+    }
+    Ok(())
+}
+
+/// Make parents' child lists agree with replaced nodes' `parent_id`
+///
+/// `previous` maps each replaced node to its record before replacement
+/// (None for nodes that are new here).
+async fn repair_tree(store: &mut LocalStore, previous: &HashMap<NodeId, Option<Node>>) -> Result<()> {
+    for (&node_id, old) in previous {
+        let node = store.get_node(node_id).await?.clone();
+
+        // Leave the old parent if the node moved
+        if let Some(old_parent) = old.as_ref().and_then(|o| o.parent_id) {
+            if node.parent_id != Some(old_parent) && store.contains_node(old_parent) {
+                store.get_node_mut(old_parent).await?.children.retain(|c| *c != node_id);
+            }
+        }
+
+        // Join the new parent, unless it hasn't arrived yet
+        if let Some(parent_id) = node.parent_id {
+            if store.contains_node(parent_id) {
+                let parent = store.get_node_mut(parent_id).await?;
+                if !parent.children.contains(&node_id) {
+                    parent.children.push(node_id);
+                }
+            }
+        }
+
+        // Keep local children that still point here, drop those that moved
+        let mut children = node.children.clone();
+        if let Some(old) = old {
+            for child in &old.children {
+                if !children.contains(child) {
+                    children.push(*child);
+                }
+            }
+        }
+        let mut kept = Vec::with_capacity(children.len());
+        let mut seen = HashSet::new();
+        for child in children {
+            if !seen.insert(child) || (store.deleted_at(child).is_some() && !store.contains_node(child)) {
+                continue;
+            }
+            if store.contains_node(child) {
+                let child_parent = store.get_node(child).await?.parent_id;
+                if child_parent != Some(node_id) {
+                    continue;
+                }
+            }
+            kept.push(child);
+        }
+        if kept != node.children {
+            store.get_node_mut(node_id).await?.children = kept;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use tempfile::tempdir;
+
+    fn version(node_id: NodeId, age_secs: i64, heads: &[&str]) -> NodeVersion {
+        let base = chrono::DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z").unwrap();
+        NodeVersion {
+            node_id,
+            modified_at: base.with_timezone(&Utc) - Duration::seconds(age_secs),
+            heads: heads.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_plan_compares_versions_and_tombstones() {
+        let (shared, newer_here, only_there, only_here, deleted_here) =
+            (NodeId::new(), NodeId::new(), NodeId::new(), NodeId::new(), NodeId::new());
+        let local = vec![
+            version(shared, 10, &["a"]),
+            version(newer_here, 1, &[]),
+            version(only_here, 5, &[]),
+        ];
+        let remote = vec![
+            version(shared, 10, &["b"]),
+            version(newer_here, 20, &[]),
+            version(only_there, 5, &[]),
+            version(deleted_here, 30, &[]),
+        ];
+        let tombstones = vec![Tombstone {
+            node_id: deleted_here,
+            deleted_at: version(deleted_here, 0, &[]).modified_at,
+        }];
+
+        let plan = ReplicationPlan::new(&local, &tombstones, &remote, &[]);
+        assert_eq!(plan.pull, vec![only_there]);
+        assert_eq!(plan.push, vec![newer_here, only_here]);
+        assert_eq!(plan.content, vec![shared]);
+    }
+
+    #[tokio::test]
+    async fn test_apply_records_repairs_tree() {
+        let dir = tempdir().unwrap();
+        let mut store = LocalStore::create(dir.path().join("a.pimble"), "A").await.unwrap();
+        let root = store.root_node_id();
+        let folder = store.create_node(Node::folder("Folder"), Some(root)).await.unwrap();
+        let ours = store.create_node(Node::document("Ours"), Some(folder)).await.unwrap();
+        let doomed = store.create_node(Node::document("Doomed"), Some(root)).await.unwrap();
+
+        // The peer renamed the folder and added a child of its own
+        let mut remote_folder = store.get_node(folder).await.unwrap().clone();
+        let theirs = Node::document("Theirs").with_parent(folder);
+        remote_folder.metadata.title = "Renamed".into();
+        remote_folder.children = vec![theirs.id];
+        remote_folder.metadata.modified_at = Utc::now() + Duration::seconds(5);
+        let tombstone = Tombstone {
+            node_id: doomed,
+            deleted_at: Utc::now() + Duration::seconds(5),
+        };
+
+        let applied = apply_node_records(&mut store, vec![remote_folder, theirs.clone()], &[tombstone])
+            .await
+            .unwrap();
+        assert_eq!(applied, 3);
+
+        let folder_node = store.get_node(folder).await.unwrap();
+        assert_eq!(folder_node.metadata.title, "Renamed");
+        assert_eq!(folder_node.children, vec![ours, theirs.id]);
+        assert!(!store.contains_node(doomed));
+        assert_eq!(store.get_node(root).await.unwrap().children, vec![folder]);
+        assert!(store.deleted_at(doomed).is_some());
+
+        // Tombstones survive reopening
+        store.flush().await.unwrap();
+        let reopened = LocalStore::open(dir.path().join("a.pimble")).await.unwrap();
+        assert!(reopened.deleted_at(doomed).is_some());
+    }
+}
@@ -0,0 +1,334 @@
+//! Copying and moving subtrees within and between stores
+//!
+//! A copy gives every node a fresh ID, forks its CRDT content so the copy
+//! keeps the original's history but edits independently, and remaps links
+//! that point inside the copied subtree. Links to nodes outside the subtree
+//! are left untouched.
+
+use std::collections::HashMap;
+
+use pimble_core::{LinkTarget, Node, NodeId};
+use pimble_crdt::{CrdtDocument, DocumentContent};
+
+use crate::error::Result;
+use crate::export::find_links;
+use crate::local::LocalStore;
+
+/// Load a subtree in depth-first pre-order, starting with `root`
+pub async fn collect_subtree(store: &mut LocalStore, root: NodeId) -> Result<Vec<Node>> {
+    let mut nodes = Vec::new();
+    let mut stack = vec![root];
+    while let Some(node_id) = stack.pop() {
+        let node = store.get_node(node_id).await?.clone();
+        stack.extend(node.children.iter().rev().copied());
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// Duplicate a pre-ordered subtree with fresh IDs
+///
+/// The first node is the subtree root; its parent is cleared so the copy
+/// can be attached anywhere. Returns the copies in the same order.
+pub fn duplicate_subtree(nodes: &[Node]) -> Result<Vec<Node>> {
+    let id_map: HashMap<NodeId, NodeId> = nodes.iter().map(|n| (n.id, NodeId::new())).collect();
+
+    let mut copies = Vec::with_capacity(nodes.len());
+    for (i, node) in nodes.iter().enumerate() {
+        let mut copy = node.clone();
+        copy.id = id_map[&node.id];
+        copy.parent_id = if i == 0 {
+            None
+        } else {
+            node.parent_id.map(|p| id_map.get(&p).copied().unwrap_or(p))
+        };
+        copy.children = node.children.iter().filter_map(|c| id_map.get(c).copied()).collect();
+        for link in &mut copy.links {
+            link.target = remap_target(&link.target, &id_map);
+        }
+        copy.content = fork_content(&node.content, &id_map)?;
+        copies.push(copy);
+    }
+    Ok(copies)
+}
+
+/// Point a link target at the remapped node, if its node was remapped
+///
+/// Store-qualified links point outside the copied subtree and are kept.
+pub fn remap_target(target: &LinkTarget, id_map: &HashMap<NodeId, NodeId>) -> LinkTarget {
+    match target {
+        LinkTarget::Node(id) => LinkTarget::Node(id_map.get(id).copied().unwrap_or(*id)),
+        LinkTarget::Deep { node_id, anchor } => LinkTarget::Deep {
+            node_id: id_map.get(node_id).copied().unwrap_or(*node_id),
+            anchor: anchor.clone(),
+        },
+        other => other.clone(),
+    }
+}
+
+/// Fork CRDT content and rewrite `pimble://` links in it
+///
+/// Content that isn't a CRDT document is copied verbatim.
+fn fork_content(content: &[u8], id_map: &HashMap<NodeId, NodeId>) -> Result<Vec<u8>> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Ok(mut doc) = CrdtDocument::load(content) else {
+        return Ok(content.to_vec());
+    };
+    let mut content = DocumentContent::from_document(doc.fork());
+    rewrite_content_links(&mut content, &|target| {
+        let remapped = remap_target(target, id_map);
+        (remapped != *target).then_some(remapped)
+    })?;
+    Ok(content.save())
+}
+
+/// Rewrite a node's link targets, both metadata links and links in its content
+///
+/// `rewrite` returns the new target for links that should change. Returns
+/// whether anything changed.
+pub fn rewrite_node_links(
+    node: &mut Node,
+    rewrite: &dyn Fn(&LinkTarget) -> Option<LinkTarget>,
+) -> Result<bool> {
+    let mut changed = false;
+    for link in &mut node.links {
+        if let Some(target) = rewrite(&link.target) {
+            link.target = target;
+            changed = true;
+        }
+    }
+
+    if !node.content.is_empty() {
+        if let Ok(mut content) = DocumentContent::load(&node.content) {
+            if rewrite_content_links(&mut content, rewrite)? {
+                node.content = content.save();
+                changed = true;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Rewrite link targets on every node in a store's tree, without storing them
+///
+/// Returns rewritten copies of the nodes that changed, for [`write_links`].
+pub async fn relink_store(
+    store: &mut LocalStore,
+    rewrite: &(dyn Fn(&LinkTarget) -> Option<LinkTarget> + Sync),
+) -> Result<Vec<Node>> {
+    let root = store.root_node_id();
+    let mut relinked = Vec::new();
+    for mut node in collect_subtree(store, root).await? {
+        if rewrite_node_links(&mut node, rewrite)? {
+            relinked.push(node);
+        }
+    }
+    Ok(relinked)
+}
+
+/// Store the links and content of rewritten nodes
+///
+/// Every node is looked up before any is changed. Returns the nodes as they
+/// were, so writing those back undoes the rewrite.
+pub async fn write_links(store: &mut LocalStore, nodes: Vec<Node>) -> Result<Vec<Node>> {
+    let mut previous = Vec::with_capacity(nodes.len());
+    for node in &nodes {
+        previous.push(store.get_node(node.id).await?.clone());
+    }
+    for node in nodes {
+        let stored = store.get_node_mut(node.id).await?;
+        stored.links = node.links;
+        stored.content = node.content;
+        stored.touch();
+    }
+    Ok(previous)
+}
+
+/// Rewrite `pimble://` links in document content
+///
+/// Both link marks and markdown links written out in the text are
+/// rewritten; the text is spliced only where a URI changes.
+fn rewrite_content_links(
+    content: &mut DocumentContent,
+    rewrite: &dyn Fn(&LinkTarget) -> Option<LinkTarget>,
+) -> Result<bool> {
+    let mut changed = content.rewrite_link_marks(|url| {
+        let target = LinkTarget::parse_uri(url).filter(|t| t.node_id().is_some())?;
+        rewrite(&target).map(|t| t.to_uri())
+    })?;
+
+    let text = content.get_text().unwrap_or_default();
+
+    // Splice from the end so earlier offsets stay valid
+    for link in find_links(&text).iter().rev() {
+        let Some(target) = link.target().and_then(|t| rewrite(&t)) else {
+            continue;
+        };
+        let start = text[..link.range.start].chars().count();
+        let len = text[link.range.clone()].chars().count();
+        content.delete_text(start, len)?;
+        content.insert_text(start, &target.to_uri())?;
+        changed = true;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager::StoreManager;
+    use pimble_core::{LinkResolution, NodeLink};
+    use pimble_crdt::TextMark;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_duplicate_subtree_remaps_internal_links() {
+        let outside = NodeId::new();
+        let mut root = Node::folder("Root");
+        let mut child = Node::document("Child").with_parent(root.id);
+        root.children.push(child.id);
+        child.add_link(NodeLink::reference(root.id));
+        child.add_link(NodeLink::reference(outside));
+
+        let mut doc = DocumentContent::new();
+        let text = format!(
+            "Up to [root]({}) and [out]({}).",
+            LinkTarget::Node(root.id).to_uri(),
+            LinkTarget::Node(outside).to_uri()
+        );
+        doc.set_text(&text).unwrap();
+        child.content = doc.save();
+
+        let copies = duplicate_subtree(&[root.clone(), child.clone()]).unwrap();
+        let (new_root, new_child) = (&copies[0], &copies[1]);
+
+        assert_ne!(new_root.id, root.id);
+        assert_eq!(new_root.parent_id, None);
+        assert_eq!(new_root.children, vec![new_child.id]);
+        assert_eq!(new_child.parent_id, Some(new_root.id));
+        assert_eq!(new_child.links[0].target.node_id(), Some(new_root.id));
+        assert_eq!(new_child.links[1].target.node_id(), Some(outside));
+
+        let copied_text = DocumentContent::load(&new_child.content).unwrap().get_text().unwrap();
+        assert_eq!(
+            copied_text,
+            format!(
+                "Up to [root]({}) and [out]({}).",
+                LinkTarget::Node(new_root.id).to_uri(),
+                LinkTarget::Node(outside).to_uri()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewrite_node_links_rewrites_link_marks() {
+        let (target, outside, store) = (NodeId::new(), NodeId::new(), pimble_core::StoreId::new());
+        let mut content = DocumentContent::new();
+        content.set_text("See this and that").unwrap();
+        let link = |id| TextMark::Link { url: LinkTarget::Node(id).to_uri() };
+        content.mark(4, 8, &link(target)).unwrap();
+        content.mark(13, 17, &link(outside)).unwrap();
+        let mut node = Node::document("Note");
+        node.content = content.save();
+
+        let rewrite = |t: &LinkTarget| (t.node_id() == Some(target)).then(|| t.in_store(store));
+        assert!(rewrite_node_links(&mut node, &rewrite).unwrap());
+
+        let urls: Vec<String> = DocumentContent::load(&node.content)
+            .unwrap()
+            .blocks()
+            .unwrap()
+            .into_iter()
+            .flat_map(|block| block.spans)
+            .flat_map(|span| span.marks)
+            .filter_map(|mark| match mark {
+                TextMark::Link { url } => Some(url),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(urls, vec![LinkTarget::Node(target).in_store(store).to_uri(), LinkTarget::Node(outside).to_uri()]);
+    }
+
+    #[tokio::test]
+    async fn test_copy_and_move_across_stores() {
+        let dir = tempdir().unwrap();
+        let mut manager = StoreManager::new();
+        let a = manager.create_local_store(dir.path().join("a.pimble"), "A").await.unwrap();
+        let b = manager.create_local_store(dir.path().join("b.pimble"), "B").await.unwrap();
+        let (root_a, root_b) = (manager.root_node_id(a).unwrap(), manager.root_node_id(b).unwrap());
+
+        let folder = manager.create_node(a, Node::folder("Folder"), Some(root_a)).await.unwrap();
+        let leaf = manager.create_node(a, Node::document("Leaf"), Some(folder)).await.unwrap();
+        let mut note = Node::document("Note");
+        note.add_link(NodeLink::reference(leaf));
+        let note = manager.create_node(a, note, Some(root_a)).await.unwrap();
+
+        let copy = manager.copy_node(a, folder, b, root_b, None).await.unwrap();
+        let copied = manager.get_node(b, copy).await.unwrap();
+        assert_eq!(copied.metadata.title, "Folder");
+        assert_eq!(copied.children.len(), 1);
+        assert_ne!(copied.children[0], leaf);
+        // The original is untouched
+        assert_eq!(manager.get_node(a, folder).await.unwrap().children, vec![leaf]);
+
+        let moved = manager.move_node_across_stores(a, folder, b, root_b, Some(0)).await.unwrap();
+        assert_eq!(moved, vec![folder, leaf]);
+        assert_eq!(manager.get_node(b, root_b).await.unwrap().children, vec![folder, copy]);
+        assert_eq!(manager.get_node(b, leaf).await.unwrap().parent_id, Some(folder));
+        assert!(manager.get_node(a, folder).await.is_err());
+        assert_eq!(manager.get_node(a, root_a).await.unwrap().children, vec![note]);
+
+        // The link left behind now names the store the leaf moved to
+        let link = manager.get_node(a, note).await.unwrap().links[0].target.clone();
+        assert_eq!(link, LinkTarget::Node(leaf).in_store(b));
+        match manager.resolve_link(a, &link).await.unwrap() {
+            LinkResolution::Resolved { store_id, node, .. } => {
+                assert_eq!(store_id, b);
+                assert_eq!(node.id, leaf);
+            }
+            other => panic!("unexpected resolution: {:?}", other),
+        }
+
+        manager.close_store(b).await.unwrap();
+        assert!(matches!(
+            manager.resolve_link(a, &link).await.unwrap(),
+            LinkResolution::Unavailable { store_id, node_id } if store_id == b && node_id == leaf
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_move_across_stores_changes_neither_store() {
+        let dir = tempdir().unwrap();
+        let mut manager = StoreManager::new();
+        let a = manager.create_local_store(dir.path().join("a.pimble"), "A").await.unwrap();
+        let b = manager.create_local_store(dir.path().join("b.pimble"), "B").await.unwrap();
+        let (root_a, root_b) = (manager.root_node_id(a).unwrap(), manager.root_node_id(b).unwrap());
+
+        let first = manager.create_node(a, Node::document("First"), Some(root_a)).await.unwrap();
+        let folder = manager.create_node(a, Node::folder("Folder"), Some(root_a)).await.unwrap();
+        let leaf = manager.create_node(a, Node::document("Leaf"), Some(folder)).await.unwrap();
+        let mut note = Node::document("Note");
+        note.add_link(NodeLink::reference(leaf));
+        let note = manager.create_node(a, note, Some(root_a)).await.unwrap();
+        manager.flush(a).await.unwrap();
+
+        // The folder's file can't be removed, so deleting it from the
+        // source fails after the leaf is already gone
+        let folder_file = dir.path().join("a.pimble").join("nodes").join(format!("{}.json", folder));
+        std::fs::remove_file(&folder_file).unwrap();
+        std::fs::create_dir(&folder_file).unwrap();
+
+        assert!(manager.move_node_across_stores(a, folder, b, root_b, None).await.is_err());
+
+        assert!(manager.get_node(b, root_b).await.unwrap().children.is_empty());
+        assert!(manager.get_node(b, folder).await.is_err());
+        assert!(manager.get_node(b, leaf).await.is_err());
+        assert_eq!(manager.get_node(a, root_a).await.unwrap().children, vec![first, folder, note]);
+        assert_eq!(manager.get_node(a, folder).await.unwrap().children, vec![leaf]);
+        assert_eq!(manager.get_node(a, leaf).await.unwrap().parent_id, Some(folder));
+        let link = manager.get_node(a, note).await.unwrap().links[0].target.clone();
+        assert_eq!(link, LinkTarget::Node(leaf));
+    }
+}
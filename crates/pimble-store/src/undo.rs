@@ -0,0 +1,362 @@
+//! Undo history for edits made to local stores
+//!
+//! Each client session has its own history. Content edits are undone with
+//! an [`UndoStep`], which reverses only what the session itself changed;
+//! creates, deletes and moves are undone by the opposite tree operation.
+//! An entry is skipped when what it would reverse has changed since, for
+//! example a move of a node someone else has moved again.
+
+use pimble_core::{EditKind, Node, NodeId, StoreId, UndoneEdit};
+use pimble_crdt::{CrdtDocument, UndoStep};
+
+use crate::error::Result;
+use crate::local::LocalStore;
+
+/// An edit that can be undone, or an undone edit that can be redone
+#[derive(Debug, Clone)]
+pub enum UndoEntry {
+    /// A change to a node's content or metadata
+    Content {
+        store_id: StoreId,
+        node_id: NodeId,
+        step: UndoStep,
+    },
+
+    /// A node was created
+    Create { store_id: StoreId, node_id: NodeId },
+
+    /// A node was deleted from a position among its siblings
+    Delete {
+        store_id: StoreId,
+        node: Box<Node>,
+        position: usize,
+    },
+
+    /// A node was moved away from a position under `from_parent`
+    Move {
+        store_id: StoreId,
+        node_id: NodeId,
+        from_parent: NodeId,
+        from_position: usize,
+        to_parent: NodeId,
+    },
+}
+
+impl UndoEntry {
+    /// The store the edit was made in
+    pub fn store_id(&self) -> StoreId {
+        match self {
+            Self::Content { store_id, .. }
+            | Self::Create { store_id, .. }
+            | Self::Delete { store_id, .. }
+            | Self::Move { store_id, .. } => *store_id,
+        }
+    }
+
+    /// The node the edit was made to
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            Self::Content { node_id, .. } | Self::Create { node_id, .. } | Self::Move { node_id, .. } => *node_id,
+            Self::Delete { node, .. } => node.id,
+        }
+    }
+
+    /// Describe the edit for clients
+    pub fn edit(&self) -> UndoneEdit {
+        let kind = match self {
+            Self::Content { .. } => EditKind::Content,
+            Self::Create { .. } => EditKind::Create,
+            Self::Delete { .. } => EditKind::Delete,
+            Self::Move { .. } => EditKind::Move,
+        };
+        UndoneEdit {
+            store_id: self.store_id(),
+            node_id: self.node_id(),
+            kind,
+        }
+    }
+
+    /// Reverse the edit, returning the entry that reverses the reversal
+    ///
+    /// Returns `None` if the edit can no longer be reversed.
+    pub async fn revert(&self, store: &mut LocalStore) -> Result<Option<UndoEntry>> {
+        let store_id = store.id;
+        match self {
+            Self::Content { node_id, step, .. } => {
+                if !store.contains_node(*node_id) {
+                    return Ok(None);
+                }
+                let mut doc = store.get_node_document(*node_id).await?;
+                let Some(step) = step.revert(&mut doc)? else {
+                    return Ok(None);
+                };
+                store.save_node_document(*node_id, &mut doc).await?;
+                Ok(Some(Self::Content {
+                    store_id,
+                    node_id: *node_id,
+                    step,
+                }))
+            }
+            Self::Create { node_id, .. } => {
+                // A node that has gained children since is left alone
+                if !store.contains_node(*node_id) || !store.get_node(*node_id).await?.children.is_empty() {
+                    return Ok(None);
+                }
+                let position = position_of(store, *node_id).await?;
+                let node = store.get_node(*node_id).await?.clone();
+                store.delete_node(*node_id).await?;
+                Ok(Some(Self::Delete {
+                    store_id,
+                    node: Box::new(node),
+                    position,
+                }))
+            }
+            Self::Delete { node, position, .. } => {
+                let parent_present = node.parent_id.is_some_and(|pid| store.contains_node(pid));
+                if store.contains_node(node.id) || !parent_present {
+                    return Ok(None);
+                }
+                store.restore_node((**node).clone(), *position).await?;
+                Ok(Some(Self::Create {
+                    store_id,
+                    node_id: node.id,
+                }))
+            }
+            Self::Move {
+                node_id,
+                from_parent,
+                from_position,
+                to_parent,
+                ..
+            } => {
+                if !store.contains_node(*node_id) || !store.contains_node(*from_parent) {
+                    return Ok(None);
+                }
+                if store.get_node(*node_id).await?.parent_id != Some(*to_parent) {
+                    return Ok(None);
+                }
+                let position = position_of(store, *node_id).await?;
+                store.move_node(*node_id, *from_parent, Some(*from_position)).await?;
+                Ok(Some(Self::Move {
+                    store_id,
+                    node_id: *node_id,
+                    from_parent: *to_parent,
+                    from_position: position,
+                    to_parent: *from_parent,
+                }))
+            }
+        }
+    }
+
+    /// Follow a content edit of the same node that was just reversed
+    pub fn follow(&mut self, reverted: &UndoEntry) {
+        if let (
+            Self::Content { store_id, node_id, step },
+            Self::Content {
+                store_id: other_store,
+                node_id: other_node,
+                step: other,
+            },
+        ) = (self, reverted)
+        {
+            if store_id == other_store && node_id == other_node {
+                step.follow(other);
+            }
+        }
+    }
+}
+
+/// A node as it was before an edit, for working out what the edit did
+#[derive(Debug, Clone)]
+pub struct UndoCheckpoint {
+    /// Session the edit belongs to
+    pub session: String,
+
+    store_id: StoreId,
+    node_id: NodeId,
+    node: Option<Node>,
+    position: usize,
+}
+
+impl UndoCheckpoint {
+    /// Take a checkpoint of a node, which may not exist yet
+    pub async fn take(session: &str, store: &mut LocalStore, node_id: NodeId) -> Result<Self> {
+        let (node, position) = if store.contains_node(node_id) {
+            let position = position_of(store, node_id).await?;
+            (Some(store.get_node(node_id).await?.clone()), position)
+        } else {
+            (None, 0)
+        };
+        Ok(Self {
+            session: session.to_string(),
+            store_id: store.id,
+            node_id,
+            node,
+            position,
+        })
+    }
+
+    /// The store the checkpoint was taken in
+    pub fn store_id(&self) -> StoreId {
+        self.store_id
+    }
+
+    /// Work out what was done to the node since the checkpoint
+    ///
+    /// Returns `None` if nothing was.
+    pub async fn finish(self, store: &mut LocalStore) -> Result<Option<UndoEntry>> {
+        let store_id = self.store_id;
+        let exists = store.contains_node(self.node_id);
+        let Some(before) = self.node else {
+            return Ok(exists.then_some(UndoEntry::Create {
+                store_id,
+                node_id: self.node_id,
+            }));
+        };
+        if !exists {
+            return Ok(Some(UndoEntry::Delete {
+                store_id,
+                node: Box::new(before),
+                position: self.position,
+            }));
+        }
+
+        let parent_id = store.get_node(self.node_id).await?.parent_id;
+        let position = position_of(store, self.node_id).await?;
+        if let (Some(from_parent), Some(to_parent)) = (before.parent_id, parent_id) {
+            if from_parent != to_parent || position != self.position {
+                return Ok(Some(UndoEntry::Move {
+                    store_id,
+                    node_id: self.node_id,
+                    from_parent,
+                    from_position: self.position,
+                    to_parent,
+                }));
+            }
+        }
+
+        let heads = CrdtDocument::load(&before.content)?.get_heads();
+        let mut doc = store.get_node_document(self.node_id).await?;
+        Ok(UndoStep::record(&mut doc, &heads)?.map(|step| UndoEntry::Content {
+            store_id,
+            node_id: self.node_id,
+            step,
+        }))
+    }
+}
+
+/// Where a node is among its siblings
+async fn position_of(store: &mut LocalStore, node_id: NodeId) -> Result<usize> {
+    let Some(parent_id) = store.get_node(node_id).await?.parent_id else {
+        return Ok(0);
+    };
+    let siblings = &store.get_node(parent_id).await?.children;
+    Ok(siblings.iter().position(|id| *id == node_id).unwrap_or(siblings.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pimble_crdt::DocumentContent;
+    use tempfile::tempdir;
+
+    use crate::manager::StoreManager;
+
+    #[tokio::test]
+    async fn test_undo_tree_and_content_edits() {
+        let dir = tempdir().unwrap();
+        let mut manager = StoreManager::new();
+        let store_id = manager.create_local_store(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = manager.root_node_id(store_id).unwrap();
+        let folder = manager.create_node(store_id, Node::folder("Folder"), Some(root)).await.unwrap();
+        let session = Some("session-a");
+
+        let note = Node::document("Note");
+        let note_id = note.id;
+        let checkpoint = manager.undo_checkpoint(session, store_id, note_id).await.unwrap();
+        manager.create_node(store_id, note, Some(root)).await.unwrap();
+        manager.record_undo(checkpoint).await.unwrap();
+
+        let checkpoint = manager.undo_checkpoint(session, store_id, note_id).await.unwrap();
+        let doc = manager.get_node_document(store_id, note_id).await.unwrap();
+        let mut content = DocumentContent::from_document(doc);
+        content.set_text("Draft").unwrap();
+        manager.save_node_document(store_id, note_id, content.document_mut()).await.unwrap();
+        manager.record_undo(checkpoint).await.unwrap();
+
+        let checkpoint = manager.undo_checkpoint(session, store_id, note_id).await.unwrap();
+        manager.move_node(store_id, note_id, folder, None).await.unwrap();
+        manager.record_undo(checkpoint).await.unwrap();
+
+        // Another session's history is its own
+        assert_eq!(manager.undo("session-b").await.unwrap(), None);
+
+        let edit = manager.undo("session-a").await.unwrap().unwrap();
+        assert_eq!(edit.kind, EditKind::Move);
+        assert_eq!(manager.get_node(store_id, note_id).await.unwrap().parent_id, Some(root));
+
+        let edit = manager.undo("session-a").await.unwrap().unwrap();
+        assert_eq!(edit.kind, EditKind::Content);
+        let doc = manager.get_node_document(store_id, note_id).await.unwrap();
+        assert_eq!(DocumentContent::from_document(doc).get_text().unwrap(), "");
+
+        let edit = manager.undo("session-a").await.unwrap().unwrap();
+        assert_eq!(edit.kind, EditKind::Create);
+        assert!(manager.get_node(store_id, note_id).await.is_err());
+        assert_eq!(manager.undo_state("session-a"), (false, true));
+
+        // Redo brings the node back, then its text, then the move
+        for kind in [EditKind::Delete, EditKind::Content, EditKind::Move] {
+            assert_eq!(manager.redo("session-a").await.unwrap().unwrap().kind, kind);
+        }
+        let node = manager.get_node(store_id, note_id).await.unwrap();
+        assert_eq!(node.parent_id, Some(folder));
+        let doc = manager.get_node_document(store_id, note_id).await.unwrap();
+        assert_eq!(DocumentContent::from_document(doc).get_text().unwrap(), "Draft");
+        assert_eq!(manager.undo_state("session-a"), (true, false));
+    }
+
+    #[tokio::test]
+    async fn test_undo_skips_moves_made_since() {
+        let dir = tempdir().unwrap();
+        let mut manager = StoreManager::new();
+        let store_id = manager.create_local_store(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = manager.root_node_id(store_id).unwrap();
+        let a = manager.create_node(store_id, Node::folder("A"), Some(root)).await.unwrap();
+        let b = manager.create_node(store_id, Node::folder("B"), Some(root)).await.unwrap();
+        let note = manager.create_node(store_id, Node::document("Note"), Some(root)).await.unwrap();
+
+        let checkpoint = manager.undo_checkpoint(Some("ours"), store_id, note).await.unwrap();
+        manager.move_node(store_id, note, a, None).await.unwrap();
+        manager.record_undo(checkpoint).await.unwrap();
+
+        // Someone else moves it on; our move is no longer ours to undo
+        manager.move_node(store_id, note, b, None).await.unwrap();
+        assert_eq!(manager.undo("ours").await.unwrap(), None);
+        assert_eq!(manager.get_node(store_id, note).await.unwrap().parent_id, Some(b));
+    }
+
+    #[tokio::test]
+    async fn test_undo_sessions_are_forgotten() {
+        let dir = tempdir().unwrap();
+        let mut manager = StoreManager::new();
+        let store_id = manager.create_local_store(dir.path().join("test.pimble"), "Test").await.unwrap();
+        let root = manager.root_node_id(store_id).unwrap();
+
+        for index in 0..=StoreManager::MAX_UNDO_SESSIONS {
+            let session = format!("session-{}", index);
+            let node = Node::folder("Folder");
+            let checkpoint = manager.undo_checkpoint(Some(&session), store_id, node.id).await.unwrap();
+            manager.create_node(store_id, node, Some(root)).await.unwrap();
+            manager.record_undo(checkpoint).await.unwrap();
+        }
+
+        // The least recently used session made room for the last
+        assert_eq!(manager.undo_state("session-0"), (false, false));
+        assert_eq!(manager.undo_state("session-1"), (true, false));
+
+        manager.close_session("session-1");
+        assert_eq!(manager.undo_state("session-1"), (false, false));
+        assert_eq!(manager.undo("session-1").await.unwrap(), None);
+    }
+}